@@ -0,0 +1,258 @@
+use std::{collections::HashMap, fmt};
+
+use sodiumoxide::crypto::sign;
+
+use security::{proof::verify_proof, report::verify_report};
+use server::{server::validator::{ReportValidator, Rule}, storage::Timeline};
+
+#[derive(Debug, PartialEq)]
+pub enum Issue {
+    UnknownUser { idx : usize, epoch : usize },
+    BadReportSignature { idx : usize, epoch : usize },
+    MismatchedReport { idx : usize, epoch : usize }, // signed content differs from where it is stored
+    MissingFromGrid { idx : usize, epoch : usize },
+    InvalidReport { idx : usize, epoch : usize }, // fails the proof and neighbour checks
+    BadProofSignature { witness : usize, epoch : usize },
+    MismatchedProof { witness : usize, epoch : usize },
+    OnlyInOne { idx : usize, epoch : usize },
+    OnlyInOther { idx : usize, epoch : usize },
+    DifferentReport { idx : usize, epoch : usize },
+    BadCommitment { epoch : usize }, // root does not match the committed leaves
+    UncommittedReport { idx : usize, epoch : usize },
+    DifferentRoot { epoch : usize },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Issue::UnknownUser { idx, epoch } =>
+                write!(f, "epoch {:}: report of unknown user {:}", epoch, idx),
+            Issue::BadReportSignature { idx, epoch } =>
+                write!(f, "epoch {:}: report of user {:} has a bad signature", epoch, idx),
+            Issue::MismatchedReport { idx, epoch } =>
+                write!(f, "epoch {:}: report of user {:} does not match where it is stored", epoch, idx),
+            Issue::MissingFromGrid { idx, epoch } =>
+                write!(f, "epoch {:}: user {:} is missing from the grid", epoch, idx),
+            Issue::InvalidReport { idx, epoch } =>
+                write!(f, "epoch {:}: report of user {:} does not have enough valid proofs", epoch, idx),
+            Issue::BadProofSignature { witness, epoch } =>
+                write!(f, "epoch {:}: proof given by user {:} has a bad signature", epoch, witness),
+            Issue::MismatchedProof { witness, epoch } =>
+                write!(f, "epoch {:}: proof given by user {:} does not match where it is stored", epoch, witness),
+            Issue::OnlyInOne { idx, epoch } =>
+                write!(f, "epoch {:}: report of user {:} only in the first storage", epoch, idx),
+            Issue::OnlyInOther { idx, epoch } =>
+                write!(f, "epoch {:}: report of user {:} only in the second storage", epoch, idx),
+            Issue::DifferentReport { idx, epoch } =>
+                write!(f, "epoch {:}: report of user {:} differs between storages", epoch, idx),
+            Issue::BadCommitment { epoch } =>
+                write!(f, "epoch {:}: root does not match the committed reports", epoch),
+            Issue::UncommittedReport { idx, epoch } =>
+                write!(f, "epoch {:}: report of user {:} is not in the committed reports", epoch, idx),
+            Issue::DifferentRoot { epoch } =>
+                write!(f, "epoch {:}: committed root differs between storages", epoch),
+        }
+    }
+}
+
+pub fn audit_storage(
+    client_keys : &HashMap<usize, sign::PublicKey>,
+    storage : &Timeline,
+    f_line : usize,
+    f_servers : usize,
+) -> Vec<Issue> {
+    let mut issues = vec![];
+    // Reports of users blacklisted after delivery are kept as evidence, and closed epochs keep what they took
+    let validator = ReportValidator::new(Rule::all().into_iter().filter(|&rule| rule != Rule::Blacklisted && rule != Rule::EpochWindow).collect(), f_line, f_servers, None);
+
+    for (epoch, idx, loc, signed_report) in storage.get_reports() {
+        let sign_key = match client_keys.get(&idx) {
+            Some(sign_key) => sign_key,
+            None => { issues.push(Issue::UnknownUser { idx, epoch }); continue; }
+        };
+
+        let report = match verify_report(sign_key, &signed_report) {
+            Ok(report) => report,
+            Err(_) => { issues.push(Issue::BadReportSignature { idx, epoch }); continue; }
+        };
+
+        if report.idx() != idx || report.epoch() != epoch || report.loc() != loc {
+            issues.push(Issue::MismatchedReport { idx, epoch });
+        }
+
+        match storage.get_users_at_epoch_at_location(epoch, loc) {
+            Some(users) if users.iter().any(|(user, _)| *user == idx) => {}
+            _ => issues.push(Issue::MissingFromGrid { idx, epoch }),
+        }
+
+        // Reports carry their own proofs, so those of a witness that was erased since still count,
+        // and the witness' own report being gone is no different from it never reporting
+        if validator.validate(client_keys, storage, idx, &report).is_err() {
+            issues.push(Issue::InvalidReport { idx, epoch });
+        }
+    }
+
+    for (witness, epoch, signed_proof) in storage.get_all_proofs() {
+        let sign_key = match client_keys.get(&witness) {
+            Some(sign_key) => sign_key,
+            None => { issues.push(Issue::UnknownUser { idx : witness, epoch }); continue; }
+        };
+
+        match verify_proof(sign_key, &signed_proof) {
+            Ok(proof) => {
+                if proof.idx_ass() != witness || proof.epoch() != epoch {
+                    issues.push(Issue::MismatchedProof { witness, epoch });
+                }
+            }
+            Err(_) => issues.push(Issue::BadProofSignature { witness, epoch }),
+        }
+    }
+
+    for (epoch, idx) in storage.check_commitments() {
+        match idx {
+            Some(idx) => issues.push(Issue::UncommittedReport { idx, epoch }),
+            None => issues.push(Issue::BadCommitment { epoch }),
+        }
+    }
+
+    issues
+}
+
+pub fn diff_storage(storage : &Timeline, other : &Timeline) -> Vec<Issue> {
+    let mut issues = vec![];
+
+    let reports : HashMap<(usize, usize), Vec<u8>> = storage.get_reports().into_iter()
+        .map(|(epoch, idx, _, report)| ((idx, epoch), report)).collect();
+    let mut other_reports : HashMap<(usize, usize), Vec<u8>> = other.get_reports().into_iter()
+        .map(|(epoch, idx, _, report)| ((idx, epoch), report)).collect();
+
+    for ((idx, epoch), report) in reports.iter() {
+        match other_reports.remove(&(*idx, *epoch)) {
+            Some(other_report) => {
+                if *report != other_report {
+                    issues.push(Issue::DifferentReport { idx : *idx, epoch : *epoch });
+                }
+            }
+            None => issues.push(Issue::OnlyInOne { idx : *idx, epoch : *epoch }),
+        }
+    }
+    for (idx, epoch) in other_reports.keys() {
+        issues.push(Issue::OnlyInOther { idx : *idx, epoch : *epoch });
+    }
+
+    // Epochs only one of them closed can't be compared yet
+    for root in storage.get_roots() {
+        match other.get_root(root.epoch) {
+            Some(other_root) if !root.agrees_with(&other_root) => issues.push(Issue::DifferentRoot { epoch : root.epoch }),
+            _ => {}
+        }
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use security::{proof::Proof, report::sign_report};
+    use server::fixtures::{self, FILENAME};
+
+    const SIZE : usize = 5;
+    const EPOCH : usize = 2;
+    const F_LINE : usize = 1;
+    const F_SERVERS : usize = 1;
+    const IDX : usize = 0;
+    const WITNESSES : [usize; 2] = [1, 2];
+    const LOC : (usize, usize) = (2, 2);
+
+    fn keys() -> fixtures::SignKeys {
+        fixtures::keys(3)
+    }
+
+    // Storage that delivered a complete beacon for EPOCH
+    fn storage() -> (Timeline, Vec<u8>) {
+        fixtures::beacon_storage(SIZE, EPOCH, F_SERVERS)
+    }
+
+    fn signed_report(secret_keys : &HashMap<usize, sign::SecretKey>, beacon : &[u8], loc_ass : (usize, usize)) -> (Vec<u8>, Vec<(Proof, Vec<u8>)>) {
+        let witnesses : Vec<_> = WITNESSES.iter().map(|&witness| (witness, loc_ass)).collect();
+        let report = fixtures::report(secret_keys, EPOCH, (IDX, LOC), &witnesses, beacon);
+        (sign_report(&secret_keys[&IDX], &report), fixtures::proofs(secret_keys, EPOCH, IDX, &witnesses, beacon))
+    }
+
+    #[test]
+    fn audit_consistent_storage() {
+        let (public_keys, secret_keys) = keys();
+        let (storage, beacon) = storage();
+
+        let (report, proofs) = signed_report(&secret_keys, &beacon, LOC);
+        storage.add_user_location_at_epoch(EPOCH, LOC, IDX, report).unwrap();
+        storage.add_proofs(proofs);
+
+        assert!(audit_storage(&public_keys, &storage, F_LINE, F_SERVERS).is_empty());
+
+        storage.close_before(EPOCH + 1, 0, &secret_keys[&IDX]);
+        assert!(audit_storage(&public_keys, &storage, F_LINE, F_SERVERS).is_empty());
+    }
+
+    #[test]
+    fn audit_erased_witness() {
+        let (public_keys, secret_keys) = keys();
+        let (storage, beacon) = storage();
+
+        let (report, proofs) = signed_report(&secret_keys, &beacon, LOC);
+        storage.add_user_location_at_epoch(EPOCH, LOC, IDX, report).unwrap();
+        storage.add_user_location_at_epoch(EPOCH, LOC, WITNESSES[0], b"report".to_vec()).unwrap();
+        storage.add_proofs(proofs);
+        storage.erase_user(WITNESSES[0], EPOCH + 1);
+
+        assert!(audit_storage(&public_keys, &storage, F_LINE, F_SERVERS).is_empty());
+    }
+
+    #[test]
+    fn audit_far_away_witnesses() {
+        let (public_keys, secret_keys) = keys();
+        let (storage, beacon) = storage();
+
+        let (report, _) = signed_report(&secret_keys, &beacon, (0, 0));
+        storage.add_user_location_at_epoch(EPOCH, LOC, IDX, report).unwrap();
+
+        assert_eq!(vec![Issue::InvalidReport { idx : IDX, epoch : EPOCH }], audit_storage(&public_keys, &storage, F_LINE, F_SERVERS));
+    }
+
+    #[test]
+    fn audit_forged_report() {
+        let (public_keys, secret_keys) = keys();
+        let (storage, beacon) = storage();
+
+        let (report, _) = signed_report(&secret_keys, &beacon, LOC);
+        storage.add_user_location_at_epoch(EPOCH, (0, 0), IDX, report).unwrap();
+        storage.add_proofs(vec![(Proof::new(EPOCH, IDX, WITNESSES[0], LOC, beacon), b"forged".to_vec())]);
+
+        let issues = audit_storage(&public_keys, &storage, F_LINE, F_SERVERS);
+
+        assert!(issues.contains(&Issue::MismatchedReport { idx : IDX, epoch : EPOCH }));
+        assert!(issues.contains(&Issue::BadProofSignature { witness : WITNESSES[0], epoch : EPOCH }));
+    }
+
+    #[test]
+    fn diff_two_storages() {
+        let (_, secret_keys) = keys();
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+        let other = Timeline::new(SIZE, FILENAME.to_string());
+
+        let (report, _) = signed_report(&secret_keys, &[], LOC);
+        storage.add_user_location_at_epoch(EPOCH, LOC, IDX, report.clone()).unwrap();
+        other.add_user_location_at_epoch(EPOCH, LOC, IDX, report).unwrap();
+        other.add_user_location_at_epoch(EPOCH, LOC, WITNESSES[0], b"report".to_vec()).unwrap();
+
+        assert_eq!(vec![Issue::OnlyInOther { idx : WITNESSES[0], epoch : EPOCH }], diff_storage(&storage, &other));
+
+        storage.close_before(EPOCH + 1, 0, &secret_keys[&IDX]);
+        other.close_before(EPOCH + 1, 1, &secret_keys[&IDX]);
+        assert_eq!(vec![
+            Issue::OnlyInOther { idx : WITNESSES[0], epoch : EPOCH },
+            Issue::DifferentRoot { epoch : EPOCH },
+        ], diff_storage(&storage, &other));
+    }
+}
@@ -0,0 +1,91 @@
+mod audit;
+
+use eyre::eyre;
+use color_eyre::eyre::Result;
+use structopt::StructOpt;
+
+use security::key_management::{retrieve_server_keys, retrieve_servers_public_keys};
+use server::storage::{retrieve_storage, audit::{retrieve_audit_log, verify_chain, verify_head}};
+
+#[derive(StructOpt)]
+#[structopt(name = "Auditor", about = "Verifies the data accepted by a server, without the server")]
+struct Opt {
+
+    #[structopt(name = "storage", long, required_unless = "audit")]
+    storage_file : Option<String>,
+
+    #[structopt(name = "keys", long, default_value = "security/keys")]
+    keys_dir : String,
+
+    #[structopt(name = "id", long, help = "Server whose keys are used to know the clients")]
+    server_id : usize,
+
+    #[structopt(name = "fline", long, default_value = "3")]
+    f_line : usize,
+
+    #[structopt(name = "diff", long, help = "Storage of another server to compare against")]
+    other_storage_file : Option<String>,
+
+    #[structopt(name = "audit", long, help = "Audit log of HA queries to verify against the server's signing key")]
+    audit_file : Option<String>,
+
+    #[structopt(name = "heads", long, requires = "audit", help = "Storages of other servers, holding the latest audit log head they were given")]
+    head_storage_files : Vec<String>,
+
+    #[structopt(name = "export", long, requires = "audit", help = "Writes the verified audit log to this file as JSON")]
+    export_file : Option<String>,
+}
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    let opt = Opt::from_args();
+
+    sodiumoxide::init().expect("Unable to make sodiumoxide thread safe");
+
+    if let Some(audit_file) = &opt.audit_file {
+        let entries = retrieve_audit_log(audit_file)?;
+        let servers_keys = retrieve_servers_public_keys(&opt.keys_dir)?;
+        verify_chain(&entries, servers_keys.public_sign_key(opt.server_id))?;
+        for head_storage_file in opt.head_storage_files.iter() {
+            match retrieve_storage(head_storage_file)?.get_audit_head(opt.server_id) {
+                Some(head) => verify_head(&entries, &head, servers_keys.public_sign_key(opt.server_id))
+                    .map_err(|err| eyre!("{:} (head kept in '{:}')", err, head_storage_file))?,
+                None => println!("Storage '{:}' holds no audit log head of server {:}.", head_storage_file, opt.server_id),
+            }
+        }
+        println!("Audit log '{:}' is intact, with {:} queries.", audit_file, entries.len());
+
+        if let Some(export_file) = &opt.export_file {
+            std::fs::write(export_file, serde_json::to_string_pretty(&entries)?)?;
+        }
+    }
+
+    let storage_file = match &opt.storage_file {
+        Some(storage_file) => storage_file,
+        None => return Ok(()),
+    };
+
+    let server_keys = retrieve_server_keys(&opt.keys_dir, opt.server_id)?;
+    let servers_keys = retrieve_servers_public_keys(&opt.keys_dir)?;
+    let f_servers = (servers_keys.public_sign_keys().len() - 1) / 3;
+    let storage = retrieve_storage(storage_file)?;
+
+    let mut issues = audit::audit_storage(server_keys.client_sign_keys(), &storage, opt.f_line, f_servers);
+
+    if let Some(other_storage_file) = &opt.other_storage_file {
+        let other = retrieve_storage(other_storage_file)?;
+        issues.extend(audit::diff_storage(&storage, &other));
+    }
+
+    for issue in issues.iter() {
+        println!("{:}", issue);
+    }
+
+    if issues.is_empty() {
+        println!("Storage '{:}' is consistent.", storage_file);
+        Ok(())
+    } else {
+        Err(eyre!("Found {:} inconsistencies in '{:}'", issues.len(), storage_file))
+    }
+}
@@ -7,12 +7,12 @@ use color_eyre::eyre::Result;
 use futures::stream::{FuturesUnordered, StreamExt};
 use futures::select;
 
-use std::{collections::HashSet, sync::Arc, usize};
+use std::{collections::HashSet, fs, sync::Arc, usize};
 use structopt::StructOpt;
 use regex::Regex;
 
 
-use tokio::{sync::Mutex, time::{interval_at, Duration, Instant}};
+use tokio::{sync::Mutex, time::{sleep, Duration}};
 use tokio::io::{self, AsyncBufReadExt, BufReader};
 use tonic::transport::Uri;
 
@@ -22,7 +22,16 @@ use security::{key_management::{
     ServerPublicKey,
     retrieve_client_keys,
     retrieve_servers_public_keys,
-}, proof::Proof, report::Report};
+}, proof::Proof, receipt::{Receipt, save_receipt}, report::{Report, sign_report, timestamp_now}, status::UserData};
+use security::certificate::{DeliveryCertificate, save_certificate};
+use security::beacon::EpochBeacon;
+use security::clock::EpochClock;
+use security::status::EXPOSURE_WINDOWS;
+
+const RECEIPTS_DIR : &str = "client/receipts/";
+const CERTIFICATE_ATTEMPTS : usize = 5;
+const BEACON_ATTEMPTS : usize = 10;
+const EPOCH_OFFSET : u64 = 2_000; // ms into an epoch before reporting, so the servers opened it too
 
 #[derive(StructOpt)]
 #[structopt(name = "Client", about = "Reporting and verifying locations since 99.")]
@@ -38,7 +47,13 @@ struct Opt {
     keys_dir : String,
 
     #[structopt(name = "n_servers", long, default_value = "1")]
-    n_servers : usize
+    n_servers : usize,
+
+    #[structopt(name = "epoch_start", long, help = "Unix time at which epoch 0 starts, the same as on the servers")]
+    epoch_start : u64,
+
+    #[structopt(name = "epoch_duration", long, default_value = "15", help = "Seconds each epoch lasts, the same as on the servers")]
+    epoch_duration : u64,
 }
 
 #[tokio::main]
@@ -61,14 +76,17 @@ async fn main() -> Result<()> {
 
     sodiumoxide::init().expect("Unable to make sodiumoxide thread safe");
 
+    fs::create_dir_all(RECEIPTS_DIR)?;
+
     let proofer =
-        tokio::spawn(proofing_system::start_proofer(opt.idx, timeline.clone(), client_keys.sign_key().clone()));
+        tokio::spawn(proofing_system::start_proofer(opt.idx, timeline.clone(), client_keys.sign_key().clone(), server_keys.clone(), f_servers));
 
     let server_urls  = get_servers_url(opt.n_servers);
 
     let atomic = Arc::new(Mutex::new(()));
 
-    tokio::spawn(epochs_generator(timeline.clone(), opt.idx, server_urls.clone(), client_keys.clone(), server_keys.clone(), necessary_res, atomic.clone()));
+    let clock = EpochClock::new(opt.epoch_start, opt.epoch_duration, 0);
+    tokio::spawn(epochs_generator(timeline.clone(), opt.idx, clock, server_urls.clone(), client_keys.clone(), server_keys.clone(), necessary_res, atomic.clone()));
 
     read_commands(opt.idx, server_urls, client_keys, server_keys, necessary_res, atomic).await;
 
@@ -89,7 +107,14 @@ async fn reports_generator(
 ) {
 
     if let Some((loc_x, loc_y)) = timeline.get_location_at_epoch(idx, epoch) {
-        let (proofs, idxs_ass) = proofing_system::get_proofs(timeline.clone(), idx, epoch).await;
+        let beacon = match obtain_beacon(idx, epoch, &server_urls, &client_keys, &server_key).await {
+            Some(beacon) => beacon,
+            None => {
+                println!("Client {:} unable to obtain the beacon of epoch {:}.", idx, epoch);
+                return;
+            }
+        };
+        let (proofs, idxs_ass) = proofing_system::get_proofs(timeline.clone(), idx, epoch, &beacon).await;
         if proofs.len() > timeline.f_line && proofs.len() == idxs_ass.len() {
             atomic.lock().await;
 
@@ -102,6 +127,7 @@ async fn reports_generator(
                     url,
                     client_keys.sign_key(),
                     server_key.public_key(server_id),
+                    server_key.public_sign_key(server_id),
                 )
             ).collect();
 
@@ -109,7 +135,8 @@ async fn reports_generator(
             loop {
                 select! {
                     res = responses.select_next_some() => {
-                        if res.is_ok() {
+                        if let Ok(receipt) = res {
+                            keep_receipt(idx, &receipt);
                             counter += 1;
                         }
 
@@ -120,6 +147,12 @@ async fn reports_generator(
                     complete => break,
                 }
             }
+            drop(responses);
+
+            // Apart from the report, so a slow certificate holds up nothing else
+            if counter > necessary_res {
+                tokio::spawn(obtain_certificate(idx, report, server_urls, client_keys, server_key, necessary_res));
+            }
         } else {
             println!("Client {:} unable to generate report for epoch {:}.", idx, epoch);
         }
@@ -128,9 +161,77 @@ async fn reports_generator(
     }
 }
 
+// Servers release the beacon once the epoch opens on their clock and f_servers + 1 of them agreed on the shares, so it may take a moment
+async fn obtain_beacon(
+    idx : usize,
+    epoch : usize,
+    server_urls : &[Uri],
+    client_keys : &ClientKeys,
+    server_keys : &ServerPublicKey,
+) -> Option<EpochBeacon> {
+    let f_servers = (server_urls.len() - 1) / 3;
+    for _ in 0..BEACON_ATTEMPTS {
+        for (server_id, url) in server_urls.iter().enumerate() {
+            let res = reports::obtain_beacon(
+                idx,
+                epoch,
+                url.clone(),
+                client_keys.sign_key(),
+                server_keys.public_key(server_id),
+                server_keys.public_sign_key(server_id),
+            ).await;
+
+            if let Ok((Some(beacon), receipt)) = res {
+                keep_receipt(idx, &receipt);
+                if beacon.epoch == epoch && beacon.verify(server_keys.public_sign_keys(), f_servers) {
+                    return Some(beacon);
+                }
+            }
+        }
+        sleep(Duration::from_millis(1000)).await;
+    }
+    None
+}
+
+// Statements reach the other servers after delivery, so the certificate may take a moment to form
+async fn obtain_certificate(
+    idx : usize,
+    report : Report,
+    server_urls: Arc<Vec<Uri>>,
+    client_keys : Arc<ClientKeys>,
+    server_keys : Arc<ServerPublicKey>,
+    necessary_res : usize,
+) {
+    let signed_report = sign_report(client_keys.sign_key(), &report);
+
+    for _ in 0..CERTIFICATE_ATTEMPTS {
+        sleep(Duration::from_millis(1000)).await;
+        for (server_id, url) in server_urls.iter().enumerate() {
+            let res = reports::obtain_location_report(
+                idx,
+                report.epoch(),
+                url.clone(),
+                client_keys.sign_key(),
+                server_keys.public_key(server_id),
+                client_keys.public_key(),
+                server_keys.public_sign_key(server_id),
+            ).await;
+
+            if let Some(certificate) = res.ok().and_then(|(_, res)| res.certificate) {
+                if certificate.certifies(idx, report.epoch(), &signed_report) && certificate.verify(server_keys.public_sign_keys(), necessary_res) {
+                    keep_certificate(idx, &certificate);
+                    return;
+                }
+            }
+        }
+    }
+    println!("Client {:} unable to obtain a delivery certificate for epoch {:}.", idx, report.epoch());
+}
+
 async fn epochs_generator(
     timeline : Arc<Timeline>,
     idx : usize,
+    clock : EpochClock,
     server_urls : Arc<Vec<Uri>>,
     client_keys : Arc<ClientKeys>,
     server_keys : Arc<ServerPublicKey>,
@@ -138,11 +239,12 @@ async fn epochs_generator(
     atomic : Arc<Mutex<()>>,
 ) -> Result<()> {
 
-    let start = Instant::now() + Duration::from_millis(2000);
-    let mut interval = interval_at(start, Duration::from_millis(15000));
-
-    for epoch in 0..timeline.epochs() {
-        interval.tick().await;
+    // Epochs already over are skipped, the servers no longer take their reports
+    let first = clock.current().unwrap_or(0);
+    for epoch in first..timeline.epochs() {
+        let now = timestamp_now();
+        let opens = clock.start_of(epoch);
+        sleep(Duration::from_secs(opens.saturating_sub(now)) + Duration::from_millis(EPOCH_OFFSET)).await;
 
         println!("Client {:} entered epoch {:}/{:}.", idx, epoch, timeline.epochs()-1);
 
@@ -165,6 +267,7 @@ async fn do_get_report_command(
     server_urls :  Arc<Vec<Uri>>,
     client_keys : Arc<ClientKeys>,
     server_keys : Arc<ServerPublicKey>,
+    necessary_res : usize,
     epoch : usize,
     atomic : Arc<Mutex<()>>,
 ) {
@@ -177,15 +280,30 @@ async fn do_get_report_command(
                 url.clone(),
                 client_keys.sign_key(),
                 server_keys.public_key(server_id),
-                client_keys.public_key()
+                client_keys.public_key(),
+                server_keys.public_sign_key(server_id),
             )
         ).collect();
 
     loop {
         select! {
             res = responses.select_next_some() => {
-                if let Ok(loc) = res {
-                    println!("location {:?}", loc);
+                if let Ok((loc, res)) = res {
+                    keep_receipt(idx, &res.receipt);
+                    if res.disputed {
+                        println!("location {:?} (disputed)", loc);
+                    } else {
+                        println!("location {:?}", loc);
+                    }
+                    match res.certificate {
+                        Some(certificate) if certificate.verify(server_keys.public_sign_keys(), necessary_res) =>
+                            println!("delivered by servers {:?}", certificate.signatures.keys().collect::<Vec<_>>()),
+                        _ => println!("no delivery certificate yet"),
+                    }
+                    match res.inclusion {
+                        Some(inclusion) => println!("committed among {:} reports of epoch {:}", inclusion.root.leaves, epoch),
+                        None => println!("epoch {:} is not closed yet", epoch),
+                    }
                     break;
                 }
             }
@@ -213,7 +331,8 @@ async fn do_get_proofs_command(
                 url.clone(),
                 client_keys.sign_key(),
                 server_keys.public_key(server_id),
-                client_keys.public_key()
+                client_keys.public_key(),
+                server_keys.public_sign_key(server_id),
             )
         ).collect();
 
@@ -222,7 +341,8 @@ async fn do_get_proofs_command(
     loop {
         select! {
             res = responses.select_next_some() => {
-                if let Ok(proofs) = res {
+                if let Ok((proofs, receipt)) = res {
+                    keep_receipt(idx, &receipt);
                     if proofs.len() > proofs_res.len(){
                         proofs_res = proofs;
                     }
@@ -242,6 +362,208 @@ async fn do_get_proofs_command(
     }
 }
 
+async fn do_check_exposure_command(
+    idx : usize,
+    server_urls :  Arc<Vec<Uri>>,
+    client_keys : Arc<ClientKeys>,
+    server_keys : Arc<ServerPublicKey>,
+    necessary_res : usize,
+    last_epochs : usize,
+) {
+    let mut responses : FuturesUnordered<_> = server_urls.iter().enumerate().map(
+        |(server_id, url)|
+            reports::check_exposure(
+                idx,
+                last_epochs,
+                url.clone(),
+                client_keys.sign_key(),
+                server_keys.public_key(server_id),
+                server_keys.public_sign_key(server_id),
+            )
+        ).collect();
+
+    let mut exposed : usize = 0;
+    let mut counter : usize = 0;
+    loop {
+        select! {
+            res = responses.select_next_some() => {
+                if let Ok((was_exposed, receipt)) = res {
+                    keep_receipt(idx, &receipt);
+                    if was_exposed {
+                        exposed += 1;
+                    }
+                    counter += 1;
+                }
+
+                if counter > necessary_res {
+                    break ;
+                }
+            }
+            complete => {
+                println!("Quorum not achieved, might be incomplete.");
+                break;
+            }
+        }
+    }
+
+    if counter == 0 {
+        println!("Unable to check exposure");
+    } else if exposed * 2 > counter {
+        println!("You were in contact with a flagged user in the last {:} epochs", last_epochs);
+    } else {
+        println!("No contact with a flagged user in the last {:} epochs", last_epochs);
+    }
+}
+
+// Servers should hold the same data, the copy most of them agree on is saved
+async fn do_my_data_command(
+    idx : usize,
+    server_urls :  Arc<Vec<Uri>>,
+    client_keys : Arc<ClientKeys>,
+    server_keys : Arc<ServerPublicKey>,
+    necessary_res : usize,
+    file : String,
+) {
+    let mut responses : FuturesUnordered<_> = server_urls.iter().enumerate().map(
+        |(server_id, url)|
+            reports::obtain_my_data(
+                idx,
+                url.clone(),
+                client_keys.sign_key(),
+                server_keys.public_key(server_id),
+                server_keys.public_sign_key(server_id),
+            )
+        ).collect();
+
+    let mut answers : Vec<(UserData, usize)> = vec![];
+    let mut counter : usize = 0;
+    loop {
+        select! {
+            res = responses.select_next_some() => {
+                if let Ok((data, receipt)) = res {
+                    keep_receipt(idx, &receipt);
+                    match answers.iter_mut().find(|(known, _)| *known == data) {
+                        Some((_, votes)) => *votes += 1,
+                        None => answers.push((data, 1)),
+                    }
+                    counter += 1;
+                }
+
+                if counter > necessary_res {
+                    break ;
+                }
+            }
+            complete => {
+                println!("Quorum not achieved, might be incomplete.");
+                break;
+            }
+        }
+    }
+
+    match answers.into_iter().max_by_key(|(_, votes)| *votes) {
+        Some((data, votes)) => {
+            let written = serde_json::to_vec_pretty(&data).map_err(|err| err.to_string())
+                .and_then(|json| std::fs::write(&file, json).map_err(|err| err.to_string()));
+            match written {
+                Ok(_) => println!("Saved {:} reports, {:} proofs given and {:} proofs received to '{:}' ({:}/{:} servers agree)",
+                    data.reports.len(), data.proofs_given.len(), data.proofs_received.len(), file, votes, counter),
+                Err(err) => println!("Unable to save data to '{:}': {:}", file, err),
+            }
+        }
+        None => println!("Unable to obtain data"),
+    }
+}
+
+async fn do_erasure_command(
+    idx : usize,
+    server_urls :  Arc<Vec<Uri>>,
+    client_keys : Arc<ClientKeys>,
+    server_keys : Arc<ServerPublicKey>,
+    necessary_res : usize,
+    before : usize,
+) {
+    let mut responses : FuturesUnordered<_> = server_urls.iter().enumerate().map(
+        |(server_id, url)|
+            reports::request_erasure(
+                idx,
+                before,
+                url.clone(),
+                client_keys.sign_key(),
+                server_keys.public_key(server_id),
+                server_keys.public_sign_key(server_id),
+            )
+        ).collect();
+
+    let mut counter : usize = 0;
+    let mut refusal = None;
+    loop {
+        select! {
+            res = responses.select_next_some() => {
+                match res {
+                    Ok(receipt) => {
+                        keep_receipt(idx, &receipt);
+                        counter += 1;
+                    }
+                    Err(err) => refusal = Some(err),
+                }
+
+                if counter > necessary_res {
+                    break ;
+                }
+            }
+            complete => break,
+        }
+    }
+
+    if counter > necessary_res {
+        println!("Your data before epoch {:} was erased", before);
+    } else if let Some(err) = refusal {
+        println!("Erasure not confirmed by enough servers: {:}", err);
+    } else {
+        println!("Erasure not confirmed by enough servers");
+    }
+}
+
+// Every server keeps its own audit log, so each answer is shown on its own
+async fn do_accesses_command(
+    idx : usize,
+    server_urls :  Arc<Vec<Uri>>,
+    client_keys : Arc<ClientKeys>,
+    server_keys : Arc<ServerPublicKey>,
+    epochs : (usize, usize),
+) {
+    let mut responses : FuturesUnordered<_> = server_urls.iter().enumerate().map(
+        |(server_id, url)| {
+            let client_keys = client_keys.clone();
+            let server_keys = server_keys.clone();
+            async move {
+                (server_id, reports::obtain_my_accesses(
+                    idx,
+                    epochs,
+                    url.clone(),
+                    client_keys.sign_key(),
+                    server_keys.public_key(server_id),
+                    server_keys.public_sign_key(server_id),
+                ).await)
+            }
+        }).collect();
+
+    while let Some((server_id, res)) = responses.next().await {
+        match res {
+            Ok((accesses, receipt)) => {
+                keep_receipt(idx, &receipt);
+                if accesses.is_empty() {
+                    println!("Server {:}: no queries touched your data", server_id);
+                }
+                for access in accesses {
+                    println!("Server {:}: at {:} (unix time) a query read your epochs {:?}", server_id, access.timestamp, access.epochs);
+                }
+            }
+            Err(_) => println!("Server {:}: unable to obtain accesses", server_id),
+        }
+    }
+}
+
 async fn read_commands(
     idx : usize,
     server_urls :  Arc<Vec<Uri>>,
@@ -252,6 +574,10 @@ async fn read_commands(
 ){
     print_command_msg();
 
+    let exposed_pat = Regex::new(r"^e(xposed)? [+]?(\d+)$").unwrap();
+    let accesses_pat = Regex::new(r"^a(ccesses)? [+]?(\d+) [+]?(\d+)$").unwrap();
+    let data_pat = Regex::new(r"^d(ata)? (\S+)$").unwrap();
+    let erase_pat = Regex::new(r"^erase [+]?(\d+)$").unwrap();
     let orep_pat = Regex::new(r"r(eport)? [+]?(\d+)").unwrap();
     let rproofs_pat = Regex::new(r"p(roofs)?( [+]?(\d)+)+").unwrap(); // FIX TODO
 
@@ -264,7 +590,56 @@ async fn read_commands(
             break;
         }
         {
-            if let Some(cap) = orep_pat.captures(buffer.trim_end()) {
+            if let Some(cap) = exposed_pat.captures(buffer.trim_end()) {
+                let last_epochs  = cap[2].parse::<usize>();
+                if !matches!(last_epochs, Ok(last_epochs) if EXPOSURE_WINDOWS.contains(&last_epochs)) { print_command_msg(); continue; }
+
+                do_check_exposure_command(
+                    idx,
+                    server_urls.clone(),
+                    client_keys.clone(),
+                    server_keys.clone(),
+                    necessary_res,
+                    last_epochs.unwrap(),
+                ).await
+
+            } else if let Some(cap) = data_pat.captures(buffer.trim_end()) {
+                do_my_data_command(
+                    idx,
+                    server_urls.clone(),
+                    client_keys.clone(),
+                    server_keys.clone(),
+                    necessary_res,
+                    cap[2].to_string(),
+                ).await
+
+            } else if let Some(cap) = erase_pat.captures(buffer.trim_end()) {
+                let before = cap[1].parse::<usize>();
+                if before.is_err() { print_command_msg(); continue; }
+
+                do_erasure_command(
+                    idx,
+                    server_urls.clone(),
+                    client_keys.clone(),
+                    server_keys.clone(),
+                    necessary_res,
+                    before.unwrap(),
+                ).await
+
+            } else if let Some(cap) = accesses_pat.captures(buffer.trim_end()) {
+                let first = cap[2].parse::<usize>();
+                let last = cap[3].parse::<usize>();
+                if first.is_err() || last.is_err() { print_command_msg(); continue; }
+
+                do_accesses_command(
+                    idx,
+                    server_urls.clone(),
+                    client_keys.clone(),
+                    server_keys.clone(),
+                    (first.unwrap(), last.unwrap()),
+                ).await
+
+            } else if let Some(cap) = orep_pat.captures(buffer.trim_end()) {
                 let epoch  = cap[2].parse::<usize>();
                 if epoch.is_err() { print_command_msg(); continue; }
 
@@ -273,6 +648,7 @@ async fn read_commands(
                     server_urls.clone(),
                     client_keys.clone(),
                     server_keys.clone(),
+                    necessary_res,
                     epoch.unwrap(),
                     atomic.clone(),
                 ).await
@@ -300,9 +676,29 @@ async fn read_commands(
     }
 }
 
+// Signed answers from the servers, kept as evidence of what each one said
+fn keep_receipt(idx : usize, receipt : &Receipt) {
+    let receipts_file = format!("{:}client_{:}.receipts", RECEIPTS_DIR, idx);
+    if let Err(err) = save_receipt(&receipts_file, receipt) {
+        println!("Unable to keep receipt from server {:}: {:}", receipt.server_id, err);
+    }
+}
+
+// Proof that a quorum of servers delivered the report, any server can hand it out again
+fn keep_certificate(idx : usize, certificate : &DeliveryCertificate) {
+    let certificates_file = format!("{:}client_{:}.certificates", RECEIPTS_DIR, idx);
+    if let Err(err) = save_certificate(&certificates_file, certificate) {
+        println!("Unable to keep certificate for epoch {:}: {:}", certificate.epoch, err);
+    }
+}
+
 fn print_command_msg() {
     println!("To obtain a report use: report <epoch>");
     println!("To obtain proofs recieved by server use: proof <epoch>");
+    println!("To check for contacts with a flagged user use: exposed <last epochs>, one of {:?}", EXPOSURE_WINDOWS);
+    println!("To see which HA queries read your data use: accesses <from epoch> <to epoch>");
+    println!("To download everything the servers hold about you use: data <file>");
+    println!("To erase your data before an epoch past the retention period use: erase <epoch>");
 }
 
 fn get_servers_url(n_servers : usize ) -> Arc<Vec<Uri>> {
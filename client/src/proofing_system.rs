@@ -16,6 +16,7 @@ use protos::location_proof::location_proof_server::{LocationProof, LocationProof
 
 use protos::location_proof::{RequestLocationProofRequest, RequestLocationProofResponse};
 use security::proof::{self, sign_proof};
+use security::{beacon::EpochBeacon, key_management::ServerPublicKey};
 
 use sodiumoxide::crypto::sign;
 
@@ -32,14 +33,18 @@ struct Proofer {
     idx : usize,
     timeline : Arc<Timeline>,
     sign_key : sign::SecretKey,
+    server_keys : Arc<ServerPublicKey>,
+    f_servers : usize,
 }
 
 impl Proofer {
-    fn new(idx : usize, timeline : Arc<Timeline>, sign_key : sign::SecretKey) -> Proofer {
+    fn new(idx : usize, timeline : Arc<Timeline>, sign_key : sign::SecretKey, server_keys : Arc<ServerPublicKey>, f_servers : usize) -> Proofer {
         Proofer {
             idx,
             timeline,
             sign_key,
+            server_keys,
+            f_servers,
         }
     }
 }
@@ -63,6 +68,12 @@ impl LocationProof for Proofer {
         }
         let epoch = result_req_epoch.unwrap();
 
+        // Servers only release the beacon once the epoch opens, so the proof can't be signed ahead of time
+        let beacon = match serde_json::from_slice::<EpochBeacon>(&request.get_ref().beacon) {
+            Ok(beacon) if beacon.epoch == epoch && beacon.verify(self.server_keys.public_sign_keys(), self.f_servers) => beacon,
+            _ => return Err(Status::invalid_argument(format!("Not the beacon of epoch {:}.", epoch))),
+        };
+
         // Maybe this verification is armful because it wont allow testing with byzantine users
         // And the request can only be recieved by a neighbour
         match self.timeline.get_neighbours_at_epoch(self.idx, epoch) {
@@ -75,6 +86,7 @@ impl LocationProof for Proofer {
                                 req_idx,
                                 self.idx,
                                 (x, y),
+                                beacon.value(),
                             )),
                         idx_ass : self.idx as u64,
 
@@ -88,9 +100,15 @@ impl LocationProof for Proofer {
     }
 }
 
-pub async fn start_proofer(idx : usize, timeline : Arc<Timeline>, sign_key : sign::SecretKey) -> Result<()> {
+pub async fn start_proofer(
+    idx : usize,
+    timeline : Arc<Timeline>,
+    sign_key : sign::SecretKey,
+    server_keys : Arc<ServerPublicKey>,
+    f_servers : usize,
+) -> Result<()> {
     let addr = get_address(idx).parse()?;
-    let proofer = Proofer::new(idx, timeline, sign_key);
+    let proofer = Proofer::new(idx, timeline, sign_key, server_keys, f_servers);
 
     println!("LocationProofServer listening on {}\n", addr);
 
@@ -104,7 +122,7 @@ pub async fn start_proofer(idx : usize, timeline : Arc<Timeline>, sign_key : sig
 
 // As Client
 
-pub async fn request_location_proof(idx : usize, epoch : usize, id_dest : usize) -> Result<(Vec<u8>, u64)> {
+pub async fn request_location_proof(idx : usize, epoch : usize, beacon : &EpochBeacon, id_dest : usize) -> Result<(Vec<u8>, u64)> {
 
     let mut client = LocationProofClient::connect(get_url(id_dest)).await.wrap_err_with(
         || format!("Failed to connect to client with id: {:}.", id_dest)
@@ -113,6 +131,7 @@ pub async fn request_location_proof(idx : usize, epoch : usize, id_dest : usize)
     let request = tonic::Request::new(RequestLocationProofRequest {
         idx: idx as u64,
         epoch: epoch as u64,
+        beacon: serde_json::to_vec(beacon).unwrap(),
     });
     match client.request_location_proof(request).await {
 
@@ -124,7 +143,7 @@ pub async fn request_location_proof(idx : usize, epoch : usize, id_dest : usize)
     }
 }
 
-pub async fn get_proofs(timeline : Arc<Timeline>, idx : usize, epoch : usize) -> (Vec<Vec<u8>>, Vec<usize>) {
+pub async fn get_proofs(timeline : Arc<Timeline>, idx : usize, epoch : usize, beacon : &EpochBeacon) -> (Vec<Vec<u8>>, Vec<usize>) {
 
     let nec_proofs = timeline.f_line * 2;
 
@@ -134,7 +153,7 @@ pub async fn get_proofs(timeline : Arc<Timeline>, idx : usize, epoch : usize) ->
     };
 
     let mut responses : FuturesUnordered<_> = neighbours.iter().map(
-        |&id_dest| request_location_proof(idx, epoch, id_dest)
+        |&id_dest| request_location_proof(idx, epoch, beacon, id_dest)
     ).collect();
 
     let mut report : Vec<Vec<u8>> = Vec::with_capacity(nec_proofs + 1);
@@ -4,13 +4,20 @@ use color_eyre::eyre::Result;
 use std::{collections::HashSet};
 use tonic::transport::Uri;
 
-use protos::{location_storage::{ObtainLocationReportRequest, SubmitLocationReportRequest, RequestMyProofsRequest}};
+use protos::{location_storage::{ObtainLocationReportRequest, SubmitLocationReportRequest, RequestMyProofsRequest, CheckExposureRequest, ObtainMyAccessesRequest}};
+use protos::location_storage::{ObtainMyDataRequest, RequestErasureRequest, ObtainBeaconRequest};
 use protos::location_storage::location_storage_client::LocationStorageClient;
 
 use sodiumoxide::crypto::sign;
 use sodiumoxide::crypto::box_;
-use security::{proof::Proof, report::verify_report, status::{LocationReportRequest, MyProofsRequest, decode_my_proofs_response, decode_response_location, encode_location_report, encode_my_proofs_request}};
-use security::report::{self, Report, success_report};
+use security::{proof::{Proof, verify_proof}, report::verify_report, status::{LocationReportRequest, LocationReportResponse, MyProofsRequest, MyProofsResponse}};
+use security::report::{self, Report, sign_report};
+use security::receipt::{ACCEPTED, Receipt, Receipted, decode_receipt};
+use security::status::{self, encode_request, decode_response, sign_request, CheckExposureResponse};
+use security::status::{DataAccess, MyAccessesRequest, MyAccessesResponse};
+use security::status::{UserData, UserDataRequest, ErasureRequest};
+use security::status::{BeaconRequest, BeaconResponse};
+use security::beacon::EpochBeacon;
 
 pub async fn submit_location_report(
     idx : usize,
@@ -18,8 +25,11 @@ pub async fn submit_location_report(
     url : &Uri,
     sign_key : &sign::SecretKey,
     server_key : &box_::PublicKey,
-) -> Result<()> {
+    server_sign_key : &sign::PublicKey,
+) -> Result<Receipt> {
 
+    let epoch = report.epoch();
+    let signed_report = sign_report(sign_key, report);
     let (report_info, report, key, pow) = report::encode_report(sign_key, server_key, report, idx);
 
     let mut client = LocationStorageClient::connect(url.clone()).await?;
@@ -33,10 +43,9 @@ pub async fn submit_location_report(
     match client.submit_location_report(request).await {
         Ok(response) => {
             let response = response.get_ref();
-            if success_report(&key, &response.nonce, &response.ok) {
-                Ok(())
-            } else {
-                Err(eyre!("submit_location_report unable to validate server response "))
+            match decode_receipt(&key, &response.nonce, &response.ok) {
+                Ok(receipt) if receipt.confirms(server_sign_key, idx, epoch, &signed_report, &ACCEPTED) => Ok(receipt),
+                _ => Err(eyre!("submit_location_report unable to validate server response ")),
             }
         }
         Err(status) => {
@@ -56,10 +65,12 @@ pub async fn obtain_location_report(
     sign_key : &sign::SecretKey,
     server_key : &box_::PublicKey,
     public_key : &sign::PublicKey,
-)-> Result<(usize, usize)> {
+    server_sign_key : &sign::PublicKey,
+)-> Result<((usize, usize), LocationReportResponse)> { // location, the answer with everything in it checked
 
     let loc_report = LocationReportRequest::new(idx, epoch);
-    let (user_info, user, key, pow) = encode_location_report(sign_key, server_key, &loc_report, idx);
+    let signed = sign_request(sign_key, &loc_report);
+    let (user_info, user, key, pow) = encode_request(sign_key, server_key, &loc_report, idx);
 
     let mut client = LocationStorageClient::connect(url).await?;
 
@@ -69,12 +80,21 @@ pub async fn obtain_location_report(
         pow,
     });
 
-    let report = match client.obtain_location_report(request).await {
+    let (report, res) = match client.obtain_location_report(request).await {
         Ok(response) => {
             let response = response.get_ref();
-            if let Ok(res) = decode_response_location(&key, &response.nonce, &response.location) {
+            if let Ok(res) = decode_response::<LocationReportResponse>(&key, &response.nonce, &response.location) {
+                if !res.receipt.confirms(server_sign_key, idx, epoch, &signed, &(&res.report, res.disputed)) {
+                    return Err(eyre!("obtain_location_report unable to validate server receipt"));
+                }
+                if matches!(&res.certificate, Some(certificate) if !certificate.certifies(idx, epoch, &res.report)) {
+                    return Err(eyre!("obtain_location_report certificate is about another report"));
+                }
+                if matches!(&res.inclusion, Some(inclusion) if !inclusion.verify(server_sign_key, idx, epoch, &res.report)) {
+                    return Err(eyre!("obtain_location_report report is not in the committed reports"));
+                }
                 if let Ok(report) = verify_report(public_key, &res.report) {
-                    report
+                    (report, res)
                 } else {
                     return  Err(eyre!("obtain_location_report unable to verify report"));
                 }
@@ -87,7 +107,7 @@ pub async fn obtain_location_report(
     };
 
     if epoch == report.epoch(){
-        Ok(report.loc())
+        Ok((report.loc(), res))
     } else {
         Err(eyre!("Not the requested epoch: {:}", report.epoch()))
     }
@@ -100,10 +120,12 @@ pub async fn request_my_proofs(
     sign_key : &sign::SecretKey,
     server_key : &box_::PublicKey,
     public_key : &sign::PublicKey,
-) -> Result<HashSet<Proof>> {
+    server_sign_key : &sign::PublicKey,
+) -> Result<(HashSet<Proof>, Receipt)> {
 
     let proofs_req = MyProofsRequest::new(epochs.clone());
-    let (user_info, vec_epochs, key,pow) = encode_my_proofs_request(sign_key, server_key, &proofs_req, idx);
+    let signed = sign_request(sign_key, &proofs_req);
+    let (user_info, vec_epochs, key,pow) = encode_request(sign_key, server_key, &proofs_req, idx);
 
     let mut client = LocationStorageClient::connect(url).await?;
 
@@ -113,13 +135,15 @@ pub async fn request_my_proofs(
         pow,
     });
 
-    let proofs = match client.request_my_proofs(request).await {
+    let (proofs, receipt) = match client.request_my_proofs(request).await {
         Ok(response) => {
             let response = response.get_ref();
-            if let Ok(proofs) = decode_my_proofs_response(&key, &public_key, &response.nonce, &response.proofs) {
-                proofs
-            } else {
-                return Err(eyre!("obtain_location_report unable to validate server response "));
+            match decode_response::<Receipted<MyProofsResponse>>(&key, &response.nonce, &response.proofs) {
+                Ok(res) if res.confirms(server_sign_key, &signed) => {
+                    let proofs : Result<Vec<Proof>> = res.response.proofs.iter().map(|proof| verify_proof(public_key, proof)).collect();
+                    (proofs.map_err(|_| eyre!("request_my_proofs unable to verify proofs"))?, res.receipt)
+                }
+                _ => return Err(eyre!("obtain_location_report unable to validate server response ")),
             }
         }
         Err(status) => return Err(eyre!("ObtainLocationReport failed with code {:?} and message {:?}.",
@@ -131,5 +155,178 @@ pub async fn request_my_proofs(
             return Err(eyre!("obtain_location_report unable to validate server response"));
         }
     }
-    Ok(proofs.into_iter().collect())
+    Ok((proofs.into_iter().collect(), receipt))
+}
+
+pub async fn check_exposure(
+    idx : usize,
+    last_epochs : usize,
+    url : Uri,
+    sign_key : &sign::SecretKey,
+    server_key : &box_::PublicKey,
+    server_sign_key : &sign::PublicKey,
+) -> Result<(bool, Receipt)> {
+
+    let check_req = status::CheckExposureRequest::new(last_epochs);
+    let signed = sign_request(sign_key, &check_req);
+    let (user_info, request, key, pow) = encode_request(sign_key, server_key, &check_req, idx);
+
+    let mut client = LocationStorageClient::connect(url).await?;
+
+    let request = tonic::Request::new(CheckExposureRequest {
+        request,
+        user_info,
+        pow,
+    });
+
+    match client.check_exposure(request).await {
+        Ok(response) => {
+            let response = response.get_ref();
+            match decode_response::<Receipted<CheckExposureResponse>>(&key, &response.nonce, &response.exposed) {
+                Ok(res) if res.confirms(server_sign_key, &signed) => Ok((res.response.exposed, res.receipt)),
+                _ => Err(eyre!("check_exposure unable to validate server response ")),
+            }
+        }
+        Err(status) => Err(eyre!("CheckExposure failed with code {:?} and message {:?}.",
+                            status.code(), status.message())),
+    }
+}
+
+pub async fn obtain_my_accesses(
+    idx : usize,
+    epochs : (usize, usize),
+    url : Uri,
+    sign_key : &sign::SecretKey,
+    server_key : &box_::PublicKey,
+    server_sign_key : &sign::PublicKey,
+) -> Result<(Vec<DataAccess>, Receipt)> {
+
+    let accesses_req = MyAccessesRequest::new(epochs);
+    let signed = sign_request(sign_key, &accesses_req);
+    let (user_info, request, key, pow) = encode_request(sign_key, server_key, &accesses_req, idx);
+
+    let mut client = LocationStorageClient::connect(url).await?;
+
+    let request = tonic::Request::new(ObtainMyAccessesRequest {
+        request,
+        user_info,
+        pow,
+    });
+
+    match client.obtain_my_accesses(request).await {
+        Ok(response) => {
+            let response = response.get_ref();
+            let res = match decode_response::<Receipted<MyAccessesResponse>>(&key, &response.nonce, &response.accesses) {
+                Ok(res) if res.confirms(server_sign_key, &signed) => res,
+                _ => return Err(eyre!("obtain_my_accesses unable to validate server response ")),
+            };
+            let accesses = res.response.accesses;
+            if accesses.iter().flat_map(|access| access.epochs.iter()).any(|epoch| *epoch < epochs.0 || *epoch > epochs.1) {
+                return Err(eyre!("obtain_my_accesses unable to validate server response"));
+            }
+            Ok((accesses, res.receipt))
+        }
+        Err(status) => Err(eyre!("ObtainMyAccesses failed with code {:?} and message {:?}.",
+                            status.code(), status.message())),
+    }
+}
+
+pub async fn obtain_my_data(
+    idx : usize,
+    url : Uri,
+    sign_key : &sign::SecretKey,
+    server_key : &box_::PublicKey,
+    server_sign_key : &sign::PublicKey,
+) -> Result<(UserData, Receipt)> {
+
+    let signed = sign_request(sign_key, &UserDataRequest::new());
+    let (user_info, request, key, pow) = encode_request(sign_key, server_key, &UserDataRequest::new(), idx);
+
+    let mut client = LocationStorageClient::connect(url).await?;
+
+    let request = tonic::Request::new(ObtainMyDataRequest {
+        request,
+        user_info,
+        pow,
+    });
+
+    match client.obtain_my_data(request).await {
+        Ok(response) => {
+            let response = response.get_ref();
+            match decode_response::<Receipted<UserData>>(&key, &response.nonce, &response.data) {
+                Ok(res) if res.confirms(server_sign_key, &signed) => Ok((res.response, res.receipt)),
+                _ => Err(eyre!("obtain_my_data unable to validate server response ")),
+            }
+        }
+        Err(status) => Err(eyre!("ObtainMyData failed with code {:?} and message {:?}.",
+                            status.code(), status.message())),
+    }
+}
+
+// None while the server does not have the beacon of the epoch yet
+pub async fn obtain_beacon(
+    idx : usize,
+    epoch : usize,
+    url : Uri,
+    sign_key : &sign::SecretKey,
+    server_key : &box_::PublicKey,
+    server_sign_key : &sign::PublicKey,
+) -> Result<(Option<EpochBeacon>, Receipt)> {
+
+    let signed = sign_request(sign_key, &BeaconRequest::new(epoch));
+    let (user_info, request, key, pow) = encode_request(sign_key, server_key, &BeaconRequest::new(epoch), idx);
+
+    let mut client = LocationStorageClient::connect(url).await?;
+
+    let request = tonic::Request::new(ObtainBeaconRequest {
+        request,
+        user_info,
+        pow,
+    });
+
+    match client.obtain_beacon(request).await {
+        Ok(response) => {
+            let response = response.get_ref();
+            match decode_response::<Receipted<BeaconResponse>>(&key, &response.nonce, &response.beacon) {
+                Ok(res) if res.confirms(server_sign_key, &signed) => Ok((res.response.beacon, res.receipt)),
+                _ => Err(eyre!("obtain_beacon unable to validate server response ")),
+            }
+        }
+        Err(status) => Err(eyre!("ObtainBeacon failed with code {:?} and message {:?}.",
+                            status.code(), status.message())),
+    }
+}
+
+pub async fn request_erasure(
+    idx : usize,
+    before : usize,
+    url : Uri,
+    sign_key : &sign::SecretKey,
+    server_key : &box_::PublicKey,
+    server_sign_key : &sign::PublicKey,
+) -> Result<Receipt> {
+
+    let erasure = ErasureRequest::new(idx, before, sign_key);
+    let signed = sign_request(sign_key, &erasure);
+    let (user_info, erasure, key, pow) = encode_request(sign_key, server_key, &erasure, idx);
+
+    let mut client = LocationStorageClient::connect(url).await?;
+
+    let request = tonic::Request::new(RequestErasureRequest {
+        erasure,
+        user_info,
+        pow,
+    });
+
+    match client.request_erasure(request).await {
+        Ok(response) => {
+            let response = response.get_ref();
+            match decode_receipt(&key, &response.nonce, &response.ok) {
+                Ok(receipt) if receipt.confirms(server_sign_key, idx, before, &signed, &ACCEPTED) => Ok(receipt),
+                _ => Err(eyre!("request_erasure unable to validate server response ")),
+            }
+        }
+        Err(status) => Err(eyre!("RequestErasure failed with code {:?} and message {:?}.",
+                            status.code(), status.message())),
+    }
 }
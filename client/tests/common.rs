@@ -1,6 +1,7 @@
 use std::sync::Arc;
 
 use grid::grid::{Timeline, retrieve_timeline};
+use security::beacon::{BeaconShare, EpochBeacon};
 use security::key_management::{
     ClientKeys,
     retrieve_client_keys,
@@ -39,3 +40,19 @@ pub fn get_pub_sign_key(idx : usize) -> sign::PublicKey  {
 pub fn get_pub_server_key() ->  Vec<box_::PublicKey> {
     retrieve_servers_public_keys(KEYS_DIR).unwrap().public_keys().to_vec()
 }
+
+#[allow(dead_code)]
+pub fn get_pub_server_sign_keys() ->  Vec<sign::PublicKey> {
+    retrieve_servers_public_keys(KEYS_DIR).unwrap().public_sign_keys().to_vec()
+}
+
+// A beacon made of the shares of the first f_servers + 1 servers
+#[allow(dead_code)]
+pub fn get_beacon(epoch : usize, f_servers : usize) -> EpochBeacon {
+    let mut beacon = EpochBeacon::new(epoch);
+    for server_id in 0..=f_servers {
+        let server_keys = retrieve_server_keys(KEYS_DIR, server_id).expect("Unhable to get server keys");
+        beacon.add(BeaconShare::new(server_id, epoch, server_keys.sign_key()));
+    }
+    beacon
+}
@@ -25,6 +25,7 @@ pub async fn get_submited_report () {
 
     let client_keys = common::get_client_keys(IDX);
     let server_key = common::get_pub_server_key();
+    let server_sign_key = common::get_pub_server_sign_keys();
 
     let timeline = common::get_timeline();
 
@@ -39,10 +40,11 @@ pub async fn get_submited_report () {
                 client_keys.sign_key(),
                 &server_key[0],
                 client_keys.public_key(),
+                &server_sign_key[0],
             ).await;
 
         assert!(loc_res.is_ok());
-        assert_eq!(location, loc_res.unwrap());
+        assert_eq!(location, loc_res.unwrap().0);
     } else {
         panic!("Error: reports_generator! (Should never happen)");
     }
@@ -58,6 +60,7 @@ pub async fn get_not_mine_submitted_report () {
 
     let client_keys = common::get_client_keys(IDX);
     let server_key = common::get_pub_server_key();
+    let server_sign_key = common::get_pub_server_sign_keys();
 
     sleep(Duration::from_millis(2000)).await; //allow time for user to have submited report
 
@@ -69,6 +72,7 @@ pub async fn get_not_mine_submitted_report () {
             client_keys.sign_key(),
             &server_key[0],
             client_keys.public_key(),
+            &server_sign_key[0],
         ).await;
 
     assert!(loc_res.is_err());
@@ -83,6 +87,7 @@ pub async fn get_not_submitted_yet_report () {
 
     let client_keys = common::get_client_keys(IDX);
     let server_key = common::get_pub_server_key();
+    let server_sign_key = common::get_pub_server_sign_keys();
 
     let loc_res =
         reports::obtain_location_report(
@@ -92,6 +97,7 @@ pub async fn get_not_submitted_yet_report () {
             client_keys.sign_key(),
             &server_key[0],
             client_keys.public_key(),
+            &server_sign_key[0],
         ).await;
 
     assert!(loc_res.is_err());
@@ -106,6 +112,7 @@ pub async fn get_submited_proofs () {
 
     let client_keys = common::get_client_keys(IDX);
     let server_key = common::get_pub_server_key();
+    let server_sign_key = common::get_pub_server_sign_keys();
 
     sleep(Duration::from_millis(2000)).await; //allow time for user to have submited report
 
@@ -121,6 +128,7 @@ pub async fn get_submited_proofs () {
             client_keys.sign_key(),
             &server_key[0],
             client_keys.public_key(),
+            &server_sign_key[0],
         ).await;
 
     assert!(proof_res.is_ok());
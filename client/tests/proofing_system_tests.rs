@@ -1,6 +1,6 @@
 mod common;
 
-/// Requires ./sbin/proofing_test_setup.sh
+// Requires ./sbin/proofing_test_setup.sh
 
 use client::proofing_system::request_location_proof;
 use security::proof;
@@ -8,6 +8,7 @@ use security::proof;
 const IDX : usize = 19;
 const EPOCH : usize = 1;
 const N_EPOCHS : usize = 10;
+const F_SERVERS : usize = 1;
 
 #[tokio::test]
 #[ignore]
@@ -18,7 +19,7 @@ pub async fn get_proof () {
 
     let id_proofer = neighbours.expect("Need a neighbour to request a proof")[0];
 
-    let (sign_proof, idx_ass) = request_location_proof(IDX, EPOCH, id_proofer).await.unwrap();
+    let (sign_proof, idx_ass) = request_location_proof(IDX, EPOCH, &common::get_beacon(EPOCH, F_SERVERS), id_proofer).await.unwrap();
 
     assert_eq!(id_proofer, idx_ass as usize,"Proofer id does not match id of the responder");
 
@@ -33,6 +34,21 @@ pub async fn get_proof () {
     assert_eq!(id_proofer, poof.idx_ass());
     assert_eq!(EPOCH, poof.epoch());
     assert_eq!(timeline.get_location_at_epoch(id_proofer, EPOCH).unwrap(), poof.loc_ass());
+    assert_eq!(common::get_beacon(EPOCH, F_SERVERS).value(), poof.beacon());
+}
+
+#[tokio::test]
+#[ignore]
+pub async fn bad_beacon_get_proof () {
+    let timeline = common::get_timeline();
+
+    let neighbours = timeline.get_neighbours_at_epoch(IDX, EPOCH);
+
+    let id_proofer = neighbours.expect("Need a neighbour to request a proof")[0];
+
+    // Beacon of a later epoch, or one with too few shares
+    request_location_proof(IDX, EPOCH, &common::get_beacon(EPOCH + 1, F_SERVERS), id_proofer).await.expect_err("Got a proof, when i shouln't have");
+    request_location_proof(IDX, EPOCH, &common::get_beacon(EPOCH, F_SERVERS - 1), id_proofer).await.expect_err("Got a proof, when i shouln't have");
 }
 
 #[tokio::test]
@@ -44,13 +60,13 @@ pub async fn bad_id_get_proof () {
 
     let id_proofer = neighbours.expect("Need a neighbour to request a proof")[0];
 
-    request_location_proof(IDX*5, EPOCH, id_proofer).await.expect_err("Got a proof, when i shouln't have");
+    request_location_proof(IDX*5, EPOCH, &common::get_beacon(EPOCH, F_SERVERS), id_proofer).await.expect_err("Got a proof, when i shouln't have");
 }
 
 #[tokio::test]
 #[ignore]
 pub async fn bad_epoch_get_proof () {
-    request_location_proof(IDX, N_EPOCHS, 5).await.expect_err("Got a proof, when i shouln't have");
-    request_location_proof(IDX, N_EPOCHS + 2, 6).await.expect_err("Got a proof, when i shouln't have");
+    request_location_proof(IDX, N_EPOCHS, &common::get_beacon(N_EPOCHS, F_SERVERS), 5).await.expect_err("Got a proof, when i shouln't have");
+    request_location_proof(IDX, N_EPOCHS + 2, &common::get_beacon(N_EPOCHS + 2, F_SERVERS), 6).await.expect_err("Got a proof, when i shouln't have");
 }
 
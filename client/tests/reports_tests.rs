@@ -12,6 +12,7 @@ const IDX : usize = 19;
 const EPOCH : usize = 1;
 const SIZE : usize = 3;
 const N_SERVERS : usize = 5;
+const F_SERVERS : usize = (N_SERVERS - 1) / 3;
 
 #[tokio::test]
 #[ignore]
@@ -22,10 +23,11 @@ pub async fn submit_correct_report () {
 
     let client_keys = common::get_client_keys(IDX);
     let server_key = common::get_pub_server_key();
+    let server_sign_key = common::get_pub_server_sign_keys();
 
     let timeline = common::get_timeline();
     if let Some((loc_x, loc_y)) = timeline.get_location_at_epoch(IDX, EPOCH) {
-        let (proofs, idxs_ass) = proofing_system::get_proofs(timeline, IDX, EPOCH).await;
+        let (proofs, idxs_ass) = proofing_system::get_proofs(timeline, IDX, EPOCH, &common::get_beacon(EPOCH, F_SERVERS)).await;
         if proofs.len() > 0 && proofs.len() == idxs_ass.len() {
             let report = Report::new(EPOCH, (loc_x, loc_y), IDX, idxs_ass, proofs);
 
@@ -36,6 +38,7 @@ pub async fn submit_correct_report () {
                     url,
                     client_keys.sign_key(),
                     &server_key[server_id],
+                    &server_sign_key[server_id],
                 )
             ).collect();
 
@@ -69,6 +72,7 @@ pub async fn submit_empty_report () {
 
     let client_keys = common::get_client_keys(IDX);
     let server_key = common::get_pub_server_key();
+    let server_sign_key = common::get_pub_server_sign_keys();
 
     let timeline = common::get_timeline();
     if let Some((loc_x, loc_y)) = timeline.get_location_at_epoch(IDX, EPOCH) {
@@ -83,6 +87,7 @@ pub async fn submit_empty_report () {
                     url,
                     client_keys.sign_key(),
                     &server_key[server_id],
+                    &server_sign_key[server_id],
                 )
             ).collect();
 
@@ -112,10 +117,11 @@ pub async fn submit_bad_location_report () {
 
     let client_keys = common::get_client_keys(IDX);
     let server_key = common::get_pub_server_key();
+    let server_sign_key = common::get_pub_server_sign_keys();
 
     let timeline = common::get_timeline();
     if let Some((_, loc_y)) = timeline.get_location_at_epoch(IDX, EPOCH) {
-        let (proofs, idxs_ass) = proofing_system::get_proofs(timeline, IDX, EPOCH).await;
+        let (proofs, idxs_ass) = proofing_system::get_proofs(timeline, IDX, EPOCH, &common::get_beacon(EPOCH, F_SERVERS)).await;
         if proofs.len() > 0 && proofs.len() == idxs_ass.len() {
             let report = Report::new(EPOCH, (SIZE, loc_y), IDX, idxs_ass, proofs);
 
@@ -126,6 +132,7 @@ pub async fn submit_bad_location_report () {
                     url,
                     client_keys.sign_key(),
                     &server_key[server_id],
+                    &server_sign_key[server_id],
                 )
             ).collect();
 
@@ -160,10 +167,11 @@ pub async fn submit_only_my_proof_report () {
 
     let client_keys = common::get_client_keys(IDX);
     let server_key = common::get_pub_server_key();
+    let server_sign_key = common::get_pub_server_sign_keys();
 
     let timeline = common::get_timeline();
     if let Some((loc_x, loc_y)) = timeline.get_location_at_epoch(IDX, EPOCH) {
-        let proof = Proof::new(EPOCH, IDX, IDX, (loc_x, loc_y));
+        let proof = Proof::new(EPOCH, IDX, IDX, (loc_x, loc_y), vec![]);
         let proofs = vec![sign_proof(&client_keys.sign_key(), proof)];
         let idxs_ass = vec![IDX];
         let report = Report::new(EPOCH, (loc_x, loc_y), IDX, idxs_ass, proofs);
@@ -175,6 +183,7 @@ pub async fn submit_only_my_proof_report () {
                     url,
                     client_keys.sign_key(),
                     &server_key[server_id],
+                    &server_sign_key[server_id],
                 )
             ).collect();
 
@@ -204,10 +213,11 @@ pub async fn submit_not_enough_proofs_report () {
 
     let client_keys = common::get_client_keys(IDX);
     let server_key = common::get_pub_server_key();
+    let server_sign_key = common::get_pub_server_sign_keys();
 
     let timeline = common::get_timeline();
     if let Some((loc_x, loc_y)) = timeline.get_location_at_epoch(IDX, EPOCH) {
-        let (proofs, idxs_ass) = proofing_system::get_proofs(timeline.clone(), IDX, EPOCH).await;
+        let (proofs, idxs_ass) = proofing_system::get_proofs(timeline.clone(), IDX, EPOCH, &common::get_beacon(EPOCH, F_SERVERS)).await;
         let less_proos = Vec::from_iter(proofs[..timeline.f_line].iter().cloned());
         let less_idxs_ass = Vec::from_iter(idxs_ass[..timeline.f_line].iter().cloned());
 
@@ -221,6 +231,7 @@ pub async fn submit_not_enough_proofs_report () {
                     url,
                     client_keys.sign_key(),
                     &server_key[server_id],
+                    &server_sign_key[server_id],
                 )
             ).collect();
 
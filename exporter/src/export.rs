@@ -0,0 +1,187 @@
+use std::{collections::HashMap, io::Write};
+
+use color_eyre::eyre::Result;
+use serde_derive::Serialize;
+use sodiumoxide::crypto::sign;
+
+use security::{proof::verify_proof, report::verify_report};
+use server::storage::Timeline;
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ProofRow {
+    witness : usize,
+    x : usize,
+    y : usize,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Row {
+    epoch : usize,
+    user : usize,
+    x : usize,
+    y : usize,
+    witnesses : Vec<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proofs : Option<Vec<ProofRow>>,
+}
+
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub from : Option<usize>,
+    pub to : Option<usize>,
+    pub region : Option<((usize, usize), (usize, usize))>, // lower corner, upper corner
+}
+
+impl Filter {
+    fn accepts(&self, epoch : usize, (x, y) : (usize, usize)) -> bool {
+        if matches!(self.from, Some(from) if epoch < from) || matches!(self.to, Some(to) if epoch > to) {
+            return false;
+        }
+        match self.region {
+            Some(((lower_x, lower_y), (upper_x, upper_y))) =>
+                lower_x <= x && x <= upper_x && lower_y <= y && y <= upper_y,
+            None => true,
+        }
+    }
+}
+
+pub fn rows(
+    client_keys : &HashMap<usize, sign::PublicKey>,
+    storage : &Timeline,
+    filter : &Filter,
+    with_proofs : bool,
+) -> Vec<Row> {
+    let mut rows = vec![];
+
+    for (epoch, user, (x, y), signed_report) in storage.get_reports() {
+        if !filter.accepts(epoch, (x, y)) {
+            continue;
+        }
+
+        let report = match client_keys.get(&user).map(|sign_key| verify_report(sign_key, &signed_report)) {
+            Some(Ok(report)) => report,
+            _ => {
+                eprintln!("Skipping report of user {:} at epoch {:}: unable to verify it", user, epoch);
+                continue;
+            }
+        };
+
+        let proofs = if with_proofs {
+            Some(report.proofs().iter().filter_map(
+                |(witness, proof)| client_keys.get(witness)
+                    .and_then(|sign_key| verify_proof(sign_key, proof).ok())
+                    .map(|proof| ProofRow { witness : *witness, x : proof.loc_ass().0, y : proof.loc_ass().1 })
+            ).collect())
+        } else {
+            None
+        };
+
+        rows.push(Row {
+            epoch,
+            user,
+            x,
+            y,
+            witnesses : report.proofs().iter().map(|(witness, _)| *witness).collect(),
+            proofs,
+        });
+    }
+
+    rows.sort_by_key(|row| (row.epoch, row.user));
+    rows
+}
+
+pub fn write_csv<W : Write>(out : &mut W, rows : &[Row]) -> Result<()> {
+    write!(out, "epoch,user,x,y,witnesses")?;
+    if rows.iter().any(|row| row.proofs.is_some()) {
+        write!(out, ",proofs")?;
+    }
+    writeln!(out)?;
+
+    for row in rows {
+        let witnesses : Vec<String> = row.witnesses.iter().map(|witness| witness.to_string()).collect();
+        write!(out, "{:},{:},{:},{:},{:}", row.epoch, row.user, row.x, row.y, witnesses.join(";"))?;
+        if let Some(proofs) = &row.proofs {
+            let proofs : Vec<String> = proofs.iter().map(|proof| format!("{:}@{:}:{:}", proof.witness, proof.x, proof.y)).collect();
+            write!(out, ",{:}", proofs.join(";"))?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+pub fn write_ndjson<W : Write>(out : &mut W, rows : &[Row]) -> Result<()> {
+    for row in rows {
+        serde_json::to_writer(&mut *out, row)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use security::report::sign_report;
+    use server::fixtures::{self, FILENAME};
+
+    const SIZE : usize = 5;
+    const EPOCH : usize = 2;
+    const OTHER_EPOCH : usize = 4;
+    const IDX : usize = 0;
+    const WITNESS : usize = 1;
+    const LOC : (usize, usize) = (2, 2);
+    const OTHER_LOC : (usize, usize) = (0, 0);
+
+    fn storage() -> (HashMap<usize, sign::PublicKey>, Timeline) {
+        let (public_keys, secret_keys) = fixtures::keys(2);
+
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+        for &(epoch, loc) in [(EPOCH, LOC), (OTHER_EPOCH, OTHER_LOC)].iter() {
+            let report = fixtures::report(&secret_keys, epoch, (IDX, loc), &[(WITNESS, loc)], &[]);
+            storage.add_user_location_at_epoch(epoch, loc, IDX, sign_report(&secret_keys[&IDX], &report)).unwrap();
+        }
+        (public_keys, storage)
+    }
+
+    #[test]
+    fn export_all() {
+        let (keys, storage) = storage();
+
+        let rows = rows(&keys, &storage, &Filter::default(), false);
+
+        assert_eq!(2, rows.len());
+        assert_eq!(Row { epoch : EPOCH, user : IDX, x : LOC.0, y : LOC.1, witnesses : vec![WITNESS], proofs : None }, rows[0]);
+    }
+
+    #[test]
+    fn export_filtered() {
+        let (keys, storage) = storage();
+
+        let by_epoch = Filter { from : Some(OTHER_EPOCH), ..Filter::default() };
+        let by_region = Filter { region : Some((LOC, LOC)), ..Filter::default() };
+
+        assert_eq!(OTHER_EPOCH, rows(&keys, &storage, &by_epoch, false)[0].epoch);
+        assert_eq!(EPOCH, rows(&keys, &storage, &by_region, false)[0].epoch);
+        assert_eq!(1, rows(&keys, &storage, &by_region, false).len());
+    }
+
+    #[test]
+    fn export_csv_with_proofs() {
+        let (keys, storage) = storage();
+        let by_epoch = Filter { to : Some(EPOCH), ..Filter::default() };
+
+        let mut out = vec![];
+        write_csv(&mut out, &rows(&keys, &storage, &by_epoch, true)).unwrap();
+
+        assert_eq!("epoch,user,x,y,witnesses,proofs\n2,0,2,2,1,1@2:2\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn export_ndjson() {
+        let (keys, storage) = storage();
+
+        let mut out = vec![];
+        write_ndjson(&mut out, &rows(&keys, &storage, &Filter::default(), false)).unwrap();
+
+        assert_eq!(2, String::from_utf8(out).unwrap().lines().count());
+    }
+}
@@ -0,0 +1,81 @@
+mod export;
+
+use std::{fs::File, io::{self, BufWriter, Write}};
+
+use eyre::eyre;
+use color_eyre::eyre::Result;
+use structopt::StructOpt;
+
+use security::key_management::retrieve_server_keys;
+use server::storage::retrieve_storage;
+
+#[derive(StructOpt)]
+#[structopt(name = "Exporter", about = "Dumps the locations accepted by a server for analysis")]
+struct Opt {
+
+    #[structopt(name = "storage", long)]
+    storage_file : String,
+
+    #[structopt(name = "keys", long, default_value = "security/keys")]
+    keys_dir : String,
+
+    #[structopt(name = "id", long, help = "Server whose keys are used to know the clients")]
+    server_id : usize,
+
+    #[structopt(name = "format", long, default_value = "csv", possible_values = &["csv", "ndjson"])]
+    format : String,
+
+    #[structopt(name = "proofs", long, help = "Include the decoded witness proofs")]
+    with_proofs : bool,
+
+    #[structopt(name = "from", long)]
+    from : Option<usize>,
+
+    #[structopt(name = "to", long)]
+    to : Option<usize>,
+
+    #[structopt(name = "region", long, parse(try_from_str = parse_region), help = "Rectangle as <x_min>,<y_min>,<x_max>,<y_max>")]
+    region : Option<((usize, usize), (usize, usize))>,
+
+    #[structopt(name = "output", long, help = "File to write to (stdout if absent)")]
+    output : Option<String>,
+}
+
+fn parse_region(region : &str) -> Result<((usize, usize), (usize, usize))> {
+    let coords = region.split(',').map(|c| c.trim().parse::<usize>()).collect::<Result<Vec<usize>, _>>()?;
+    match coords[..] {
+        [x_min, y_min, x_max, y_max] if x_min <= x_max && y_min <= y_max => Ok(((x_min, y_min), (x_max, y_max))),
+        _ => Err(eyre!("Region must be <x_min>,<y_min>,<x_max>,<y_max>")),
+    }
+}
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    let opt = Opt::from_args();
+
+    sodiumoxide::init().expect("Unable to make sodiumoxide thread safe");
+
+    let server_keys = retrieve_server_keys(&opt.keys_dir, opt.server_id)?;
+    let storage = retrieve_storage(&opt.storage_file)?;
+
+    let filter = export::Filter {
+        from : opt.from,
+        to : opt.to,
+        region : opt.region,
+    };
+    let rows = export::rows(server_keys.client_sign_keys(), &storage, &filter, opt.with_proofs);
+
+    let mut out : Box<dyn Write> = match &opt.output {
+        Some(output) => Box::new(BufWriter::new(File::create(output)?)),
+        None => Box::new(BufWriter::new(io::stdout())),
+    };
+
+    match opt.format.as_str() {
+        "ndjson" => export::write_ndjson(&mut out, &rows)?,
+        _ => export::write_csv(&mut out, &rows)?,
+    }
+    out.flush()?;
+
+    Ok(())
+}
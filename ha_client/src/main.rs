@@ -1,6 +1,6 @@
 mod verifying;
 
-use std::{collections::{HashMap, HashSet}, sync::Arc};
+use std::{collections::{BTreeMap, BTreeSet, HashMap, HashSet}, sync::Arc};
 
 use futures::stream::{FuturesUnordered, StreamExt};
 use futures::select;
@@ -13,6 +13,12 @@ use tonic::transport::Uri;
 use tokio::io::{self, AsyncBufReadExt, BufReader};
 
 use security::key_management::{HAClientKeys, ServerPublicKey, retrieve_ha_client_keys, retrieve_servers_public_keys};
+use security::status::{BlacklistRequest, BlacklistResponse, UsersInRegionRequest, TrajectoryRequest, ContactsRequest, ExposureRequest, InfectionRequest, HeatmapRequest, RootsRequest};
+use security::status::HEATMAP_EPSILONS;
+use security::status::{ExposureLink, RootsResponse};
+use security::receipt::{Receipt, save_receipt};
+
+const RECEIPTS_DIR : &str = "ha_client/receipts/";
 
 #[derive(StructOpt)]
 #[structopt(name = "HA_Client", about = "Checking on server satus")]
@@ -26,6 +32,9 @@ struct Opt {
 
     #[structopt(name = "keys", long, default_value = "security/keys")]
     keys_dir : String,
+
+    #[structopt(name = "operator", long, default_value = "0")]
+    operator : usize,
 }
 
 #[tokio::main]
@@ -39,7 +48,8 @@ async fn main() -> Result<()> {
     let f_servers = (opt.n_servers - 1) / 3;
     let necessary_res= f_servers + opt.n_servers / 2;
 
-    let ha_keys = retrieve_ha_client_keys(&opt.keys_dir)?;
+    let ha_keys = retrieve_ha_client_keys(&opt.keys_dir, opt.operator)?;
+    println!("HA operator {:} ({:})", ha_keys.operator(), ha_keys.role());
     let server_keys = retrieve_servers_public_keys(&opt.keys_dir)?;
 
     let server_urls  = get_servers_url(opt.n_servers);
@@ -75,17 +85,24 @@ async fn do_report_command(
                 url.clone(),
                 ha_keys.sign_key(),
                 server_keys.public_key(server_id),
+                server_keys.public_sign_key(server_id),
                 client_pub_key,
             )
         ).collect();
 
+    // Servers vote on the location only, a dispute may reach some of them before the others
     let mut locations : HashMap<(usize, usize), usize> = HashMap::new();
+    let mut disputes : HashMap<(usize, usize), usize> = HashMap::new();
     let mut location : (usize, usize) = (usize::MAX, usize::MAX);
     let mut max : usize = 0;
     loop {
         select! {
             res = responses.select_next_some() => {
-                if let Ok(loc) = res {
+                if let Ok((loc, disputed, receipt)) = res {
+                    keep_receipt(ha_keys.operator(), &receipt);
+                    if disputed {
+                        *disputes.entry(loc).or_default() += 1;
+                    }
                     match locations.get_mut(&loc) {
                         Some(n) => {
                             *n += 1;
@@ -106,7 +123,10 @@ async fn do_report_command(
 
 
                 if max > f_servers {
-                    println!("{:?}" ,location);
+                    match disputes.get(&location) {
+                        Some(n) => println!("{:?} (disputed by {:} of {:} servers)", location, n, max),
+                        None => println!("{:?}", location),
+                    }
                     break ;
                 }
             }
@@ -134,16 +154,22 @@ async fn do_get_users_at_loc_command(
                 url.clone(),
                 ha_keys.sign_key(),
                 server_keys.public_key(server_id),
+                server_keys.public_sign_key(server_id),
                 ha_keys.clients_public_keys()
             )
         ).collect();
 
     let mut all_users : Vec<HashSet<usize>> = Vec::with_capacity(necessary_res + 1);
+    let mut disputes : BTreeMap<usize, usize> = BTreeMap::new(); // user -> servers that dispute its report
     loop {
         select! {
             res = responses.select_next_some() => {
-                if let Ok(users) = res {
+                if let Ok((users, disputed, receipt)) = res {
+                    keep_receipt(ha_keys.operator(), &receipt);
                     all_users.push(users);
+                    for idx in disputed {
+                        *disputes.entry(idx).or_default() += 1;
+                    }
                 }
 
                 if all_users.len() > necessary_res {
@@ -174,7 +200,473 @@ async fn do_get_users_at_loc_command(
     }
 
     println!("{:?}", max_occurencies_set);
+    for (idx, n) in disputes.iter().filter(|(idx, _)| max_occurencies_set.contains(idx)) {
+        println!("user {:} disputed by {:} of {:} servers", idx, n, all_users.len());
+    }
+
+}
+
+async fn do_trajectory_command(
+    grid_size : usize,
+    server_urls :  Arc<Vec<Uri>>,
+    ha_keys : &HAClientKeys,
+    server_keys : &ServerPublicKey,
+    f_servers : usize,
+    trajectory_req : TrajectoryRequest,
+) {
+
+    let client_pub_key = match ha_keys.client_public_key(trajectory_req.idx()) {
+        Some(client_pub_key) => client_pub_key,
+        None => { println!("Invalid idx for client"); return; }
+    };
+
+    let mut responses : FuturesUnordered<_> = server_urls.iter().enumerate().map(
+        |(server_id, url)|
+            verifying::obtain_trajectory(
+                &trajectory_req,
+                grid_size,
+                url.clone(),
+                ha_keys.sign_key(),
+                server_keys.public_key(server_id),
+                server_keys.public_sign_key(server_id),
+                client_pub_key,
+            )
+        ).collect();
+
+    // The whole trajectory must be the same in more than f_servers answers
+    let mut trajectories : Vec<(verifying::Trajectory, usize)> = vec![];
+    let mut agreed = None;
+    loop {
+        select! {
+            res = responses.select_next_some() => {
+                if let Ok((trajectory, receipt)) = res {
+                    keep_receipt(ha_keys.operator(), &receipt);
+                    let position = match trajectories.iter().position(|(other, _)| *other == trajectory) {
+                        Some(position) => position,
+                        None => { trajectories.push((trajectory, 0)); trajectories.len() - 1 }
+                    };
+                    trajectories[position].1 += 1;
+                    if trajectories[position].1 > f_servers {
+                        agreed = Some(position);
+                        break;
+                    }
+                }
+            }
+            complete => break,
+        }
+    }
+
+    let trajectory = match agreed {
+        Some(position) => &trajectories[position].0,
+        None => { println!("Servers did not agree on a trajectory"); return; }
+    };
+
+    let (first, last) = trajectory_req.epochs();
+    let mut missing = vec![];
+    for epoch in first..=last {
+        match trajectory.get(&epoch) {
+            Some((loc, true)) => println!("epoch {:}: {:?} (disputed)", epoch, loc),
+            Some((loc, false)) => println!("epoch {:}: {:?}", epoch, loc),
+            None => missing.push(epoch),
+        }
+    }
+    if !missing.is_empty() {
+        println!("missing epochs: {:}", format_epochs(&missing));
+    }
+}
+
+// Sorted epochs as ranges, e.g. "1, 3-5"
+fn format_epochs(epochs : &[usize]) -> String {
+    let mut ranges : Vec<(usize, usize)> = vec![];
+    for &epoch in epochs {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == epoch => *end = epoch,
+            _ => ranges.push((epoch, epoch)),
+        }
+    }
+    ranges.iter().map(|&(start, end)| if start == end { start.to_string() } else { format!("{:}-{:}", start, end) }).collect::<Vec<_>>().join(", ")
+}
+
+async fn do_contacts_command(
+    server_urls :  Arc<Vec<Uri>>,
+    ha_keys : &HAClientKeys,
+    server_keys : &ServerPublicKey,
+    f_servers : usize,
+    necessary_res : usize,
+    contacts_req : ContactsRequest,
+) {
+
+    let mut responses : FuturesUnordered<_> = server_urls.iter().enumerate().map(
+        |(server_id, url)|
+            verifying::obtain_contacts(
+                &contacts_req,
+                url.clone(),
+                ha_keys.sign_key(),
+                server_keys.public_key(server_id),
+                server_keys.public_sign_key(server_id),
+                ha_keys.clients_public_keys()
+            )
+        ).collect();
+
+    let mut all_results : Vec<verifying::Contacts> = Vec::with_capacity(necessary_res + 1);
+    loop {
+        select! {
+            res = responses.select_next_some() => {
+                if let Ok((result, receipt)) = res {
+                    keep_receipt(ha_keys.operator(), &receipt);
+                    all_results.push(result);
+                }
+
+                if all_results.len() > necessary_res {
+                    break ;
+                }
+            }
+            complete => break,
+        }
+    }
+
+    if all_results.len() <= f_servers {
+        println!("Unable to get contacts");
+        return;
+    }
+
+    // A contact is only kept if more than f_servers servers vouch for it
+    type Contact = (usize, (usize, usize), usize, (usize, usize)); // epoch, location, contact id, contact location
+    let mut vouched : BTreeMap<Contact, usize> = BTreeMap::new();
+    for result in all_results.iter() {
+        for (epoch, (loc, near_users)) in result.iter() {
+            for (idx, near_loc) in near_users.iter() {
+                *vouched.entry((*epoch, *loc, *idx, *near_loc)).or_default() += 1;
+            }
+        }
+    }
+
+    let mut contacts : verifying::Contacts = BTreeMap::new();
+    for ((epoch, loc, idx, near_loc), _) in vouched.into_iter().filter(|(_, servers)| *servers > f_servers) {
+        contacts.entry(epoch).or_insert_with(|| (loc, BTreeMap::new())).1.insert(idx, near_loc);
+    }
+
+    if contacts.is_empty() {
+        println!("No contacts found");
+    }
+    for (epoch, (loc, near_users)) in contacts.iter() {
+        println!("epoch {:}: at {:?} near {:?}", epoch, loc, near_users);
+    }
+}
+
+async fn do_exposure_command(
+    grid_size : usize,
+    server_urls :  Arc<Vec<Uri>>,
+    ha_keys : &HAClientKeys,
+    server_keys : &ServerPublicKey,
+    f_servers : usize,
+    exposure_req : ExposureRequest,
+) {
+
+    let mut responses : FuturesUnordered<_> = server_urls.iter().enumerate().map(
+        |(server_id, url)|
+            verifying::obtain_exposure_chains(
+                &exposure_req,
+                grid_size,
+                url.clone(),
+                ha_keys.sign_key(),
+                server_keys.public_key(server_id),
+                server_keys.public_sign_key(server_id),
+            )
+        ).collect();
 
+    // The same chains must be returned by more than f_servers servers
+    let mut all_chains : Vec<(Vec<Vec<ExposureLink>>, usize)> = vec![];
+    let mut agreed = None;
+    loop {
+        select! {
+            res = responses.select_next_some() => {
+                if let Ok((chains, receipt)) = res {
+                    keep_receipt(ha_keys.operator(), &receipt);
+                    let position = match all_chains.iter().position(|(other, _)| *other == chains) {
+                        Some(position) => position,
+                        None => { all_chains.push((chains, 0)); all_chains.len() - 1 }
+                    };
+                    all_chains[position].1 += 1;
+                    if all_chains[position].1 > f_servers {
+                        agreed = Some(position);
+                        break;
+                    }
+                }
+            }
+            complete => break,
+        }
+    }
+
+    match agreed.map(|position| &all_chains[position].0) {
+        Some(chains) if chains.is_empty() => println!("No users exposed"),
+        Some(chains) => {
+            for chain in chains.iter() {
+                let mut line = chain[0].from.to_string();
+                for link in chain.iter() {
+                    line.push_str(&format!(" -[epoch {:} {:?}->{:?}]-> {:}", link.epoch, link.from_cell, link.to_cell, link.to));
+                }
+                println!("{:}", line);
+            }
+        }
+        None => println!("Servers did not agree on the exposure chains"),
+    }
+}
+
+async fn do_heatmap_command(
+    grid_size : usize,
+    server_urls :  Arc<Vec<Uri>>,
+    ha_keys : &HAClientKeys,
+    server_keys : &ServerPublicKey,
+    necessary_res : usize,
+    heatmap_req : HeatmapRequest,
+) {
+
+    let mut responses : FuturesUnordered<_> = server_urls.iter().enumerate().map(
+        |(server_id, url)|
+            verifying::obtain_heatmap(
+                &heatmap_req,
+                grid_size,
+                url.clone(),
+                ha_keys.sign_key(),
+                server_keys.public_key(server_id),
+                server_keys.public_sign_key(server_id),
+            )
+        ).collect();
+
+    let mut all_results : Vec<BTreeMap<(usize, usize), usize>> = Vec::with_capacity(necessary_res + 1);
+    loop {
+        select! {
+            res = responses.select_next_some() => {
+                if let Ok((result, receipt)) = res {
+                    keep_receipt(ha_keys.operator(), &receipt);
+                    all_results.push(result);
+                }
+
+                if all_results.len() > necessary_res {
+                    break ;
+                }
+            }
+            complete => break,
+        }
+    }
+
+    if all_results.is_empty() {
+        println!("Unable to get heatmap");
+        return;
+    }
+
+    // Noisy counts never match, so each cell takes the median of the servers that did not suppress it
+    // and is only shown if most servers did not
+    let mut heatmap : BTreeMap<(usize, usize), usize> = BTreeMap::new();
+    let cells : BTreeSet<(usize, usize)> = all_results.iter().flat_map(|result| result.keys().copied()).collect();
+    for cell in cells {
+        let mut counts : Vec<usize> = all_results.iter().filter_map(|result| result.get(&cell).copied()).collect();
+        if counts.len() * 2 > all_results.len() {
+            counts.sort_unstable();
+            heatmap.insert(cell, counts[counts.len() / 2]);
+        }
+    }
+
+    for y in (0..grid_size).rev() {
+        let row : Vec<String> = (0..grid_size).map(|x| match heatmap.get(&(x, y)) {
+            Some(count) => format!("{:>4}", count),
+            None => format!("{:>4}", "."),
+        }).collect();
+        println!("{:>3} |{:}", y, row.join(""));
+    }
+    println!("    +{:}", "-".repeat(4 * grid_size));
+    println!("     {:}", (0..grid_size).map(|x| format!("{:>4}", x)).collect::<Vec<_>>().join(""));
+}
+
+async fn do_flag_infected_command(
+    server_urls :  Arc<Vec<Uri>>,
+    ha_keys : &HAClientKeys,
+    server_keys : &ServerPublicKey,
+    necessary_res : usize,
+    infection_req : InfectionRequest,
+) {
+
+    let mut responses : FuturesUnordered<_> = server_urls.iter().enumerate().map(
+        |(server_id, url)|
+            verifying::flag_infected(
+                &infection_req,
+                url.clone(),
+                ha_keys.sign_key(),
+                server_keys.public_key(server_id),
+                server_keys.public_sign_key(server_id),
+            )
+        ).collect();
+
+    let mut counter : usize = 0;
+    while let Some(res) = responses.next().await {
+        if let Ok(receipt) = res {
+            keep_receipt(ha_keys.operator(), &receipt);
+            counter += 1;
+        }
+    }
+
+    if counter > necessary_res {
+        println!("User {:} flagged as infected from epoch {:} on {:} servers.", infection_req.idx(), infection_req.epoch(), counter);
+    } else {
+        println!("Quorum not achieved, user {:} only flagged on {:} servers.", infection_req.idx(), counter);
+    }
+}
+
+async fn do_get_users_in_region_command(
+    server_urls :  Arc<Vec<Uri>>,
+    ha_keys : &HAClientKeys,
+    server_keys : &ServerPublicKey,
+    f_servers : usize,
+    region_req : UsersInRegionRequest,
+) {
+
+    let mut responses : FuturesUnordered<_> = server_urls.iter().enumerate().map(
+        |(server_id, url)|
+            verifying::obtain_users_in_region(
+                &region_req,
+                url.clone(),
+                ha_keys.sign_key(),
+                server_keys.public_key(server_id),
+                server_keys.public_sign_key(server_id),
+                ha_keys.clients_public_keys()
+            )
+        ).collect();
+
+    // The same users must be returned by more than f_servers servers
+    let mut all_results : Vec<(verifying::RegionUsers, usize)> = vec![];
+    let mut agreed = None;
+    loop {
+        select! {
+            res = responses.select_next_some() => {
+                if let Ok((result, receipt)) = res {
+                    keep_receipt(ha_keys.operator(), &receipt);
+                    let position = match all_results.iter().position(|(other, _)| *other == result) {
+                        Some(position) => position,
+                        None => { all_results.push((result, 0)); all_results.len() - 1 }
+                    };
+                    all_results[position].1 += 1;
+                    if all_results[position].1 > f_servers {
+                        agreed = Some(position);
+                        break;
+                    }
+                }
+            }
+            complete => break,
+        }
+    }
+
+    match agreed.map(|position| &all_results[position].0) {
+        Some((users, _)) if users.is_empty() => println!("No users in the region"),
+        Some((users, disputed)) => {
+            for (epoch, at_epoch) in users.iter() {
+                let at_epoch_disputed : BTreeSet<usize> = disputed.iter().filter(|(e, _)| e == epoch).map(|(_, idx)| *idx).collect();
+                if at_epoch_disputed.is_empty() {
+                    println!("epoch {:}: {:?}", epoch, at_epoch);
+                } else {
+                    println!("epoch {:}: {:?} disputed {:?}", epoch, at_epoch, at_epoch_disputed);
+                }
+            }
+        }
+        None => println!("Servers did not agree on the users in the region"),
+    }
+}
+
+async fn do_blacklist_command(
+    server_urls :  Arc<Vec<Uri>>,
+    ha_keys : &HAClientKeys,
+    server_keys : &ServerPublicKey,
+    necessary_res : usize,
+    blacklist_req : BlacklistRequest,
+) {
+
+    let mut responses : FuturesUnordered<_> = server_urls.iter().enumerate().map(
+        |(server_id, url)|
+            verifying::administer_blacklist(
+                &blacklist_req,
+                url.clone(),
+                ha_keys.sign_key(),
+                server_keys.public_key(server_id),
+                server_keys.public_sign_key(server_id),
+            )
+        ).collect();
+
+    // user id -> (epoch, reason, servers)
+    let mut listed : HashMap<usize, (usize, String, usize)> = HashMap::new();
+    let mut counter : usize = 0;
+    let mut cleared : usize = 0;
+    // epoch -> servers that sent evidence of the witness contradicting itself
+    let mut flags : BTreeMap<usize, usize> = BTreeMap::new();
+    loop {
+        select! {
+            res = responses.select_next_some() => {
+                let res = match res {
+                    Ok((res, receipt)) => {
+                        keep_receipt(ha_keys.operator(), &receipt);
+                        res
+                    }
+                    Err(_) => continue,
+                };
+                match res {
+                    BlacklistResponse::Listed(entries) => {
+                        for (idx, epoch, reason, _) in entries {
+                            listed.entry(idx).or_insert((epoch, reason, 0)).2 += 1;
+                        }
+                    }
+                    BlacklistResponse::Entry(Some(entry)) => {
+                        // The evidence speaks for itself, no need for a quorum
+                        match ha_keys.client_public_key(entry.idx) {
+                            Some(public_key) if entry.verify(public_key) => {
+                                println!("User {:} blacklisted at epoch {:} ({:}) since {:}, evidence verified.",
+                                    entry.idx, entry.epoch, entry.reason, entry.timestamp);
+                                return;
+                            }
+                            _ => println!("Server sent evidence that does not verify."),
+                        }
+                    }
+                    BlacklistResponse::Cleared(true) => cleared += 1,
+                    BlacklistResponse::Flags(flagged) => {
+                        for flag in flagged {
+                            match ha_keys.client_public_key(flag.idx) {
+                                Some(public_key) if flag.verify(public_key) => *flags.entry(flag.epoch).or_default() += 1,
+                                _ => println!("Server sent evidence that does not verify."),
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+                counter += 1;
+
+                if counter > necessary_res {
+                    break ;
+                }
+            }
+            complete => {
+                println!("Quorum not achieved, might be incomplete.");
+                break;
+            }
+        }
+    }
+
+    match blacklist_req {
+        BlacklistRequest::List => {
+            let mut listed : Vec<_> = listed.into_iter().collect();
+            listed.sort_by_key(|(idx, _)| *idx);
+            for (idx, (epoch, reason, servers)) in listed {
+                println!("User {:} at epoch {:}: {:} (reported by {:} servers)", idx, epoch, reason, servers);
+            }
+        }
+        BlacklistRequest::Inspect(idx) => println!("User {:} is not blacklisted.", idx),
+        BlacklistRequest::Clear(idx) | BlacklistRequest::Unflag(idx) => println!("User {:} cleared on {:} servers.", idx, cleared),
+        BlacklistRequest::Flags(idx) => {
+            if flags.is_empty() {
+                println!("User {:} never vouched for contradictory locations.", idx);
+            }
+            for (epoch, servers) in flags {
+                println!("User {:} vouched for contradictory locations at epoch {:}, evidence verified (sent by {:} servers)", idx, epoch, servers);
+            }
+        }
+    }
 }
 
 async fn read_commands(
@@ -189,6 +681,17 @@ async fn read_commands(
 
     let o_rep_pat = Regex::new(r"r(eport)? [+]?(\d+) [+]?(\d+)").unwrap();
     let o_users_pat = Regex::new(r"u(sers)? [+]?(\d+) [+]?(\d+) [+]?(\d+)").unwrap();
+    let unban_pat = Regex::new(r"^unban [+]?(\d+)$").unwrap();
+    let flags_pat = Regex::new(r"^flags [+]?(\d+)$").unwrap();
+    let unflag_pat = Regex::new(r"^unflag [+]?(\d+)$").unwrap();
+    let blacklist_pat = Regex::new(r"^b(lacklist)?( [+]?(\d+))?$").unwrap();
+    let trajectory_pat = Regex::new(r"^t(rajectory)? [+]?(\d+) [+]?(\d+) [+]?(\d+)$").unwrap();
+    let contacts_pat = Regex::new(r"^c(ontacts)? [+]?(\d+) [+]?(\d+) [+]?(\d+)$").unwrap();
+    let exposure_pat = Regex::new(r"^e(xposure)? [+]?(\d+) [+]?(\d+) [+]?(\d+) [+]?(\d+)$").unwrap();
+    let infected_pat = Regex::new(r"^i(nfected)? [+]?(\d+) [+]?(\d+)$").unwrap();
+    let heatmap_pat = Regex::new(r"^h(eatmap)? [+]?(\d+) [+]?(\d+) [+]?(\d+)( [+]?(\d+(\.\d+)?))?$").unwrap();
+    let roots_pat = Regex::new(r"^roots [+]?(\d+)$").unwrap();
+    let region_pat = Regex::new(r"^region [+]?(\d+) [+]?(\d+) [+]?(\d+) [+]?(\d+) [+]?(\d+) [+]?(\d+)$").unwrap();
 
     let mut reader = BufReader::new(io::stdin());
     let mut buffer = String::new();
@@ -197,7 +700,162 @@ async fn read_commands(
         buffer.clear();
         reader.read_line(&mut buffer).await.unwrap();
         {
-            if let Some(cap) = o_rep_pat.captures(buffer.trim_end()) {
+            if let Some(cap) = unban_pat.captures(buffer.trim_end()) {
+                let idx  = cap[1].parse::<usize>();
+                if idx.is_err() { print_command_msg(); continue; }
+
+                do_blacklist_command(
+                    server_urls.clone(),
+                    ha_keys,
+                    server_keys,
+                    necessary_res,
+                    BlacklistRequest::Clear(idx.unwrap()),
+                ).await;
+
+            } else if let Some(cap) = flags_pat.captures(buffer.trim_end()) {
+                let idx  = cap[1].parse::<usize>();
+                if idx.is_err() { print_command_msg(); continue; }
+
+                do_blacklist_command(
+                    server_urls.clone(),
+                    ha_keys,
+                    server_keys,
+                    necessary_res,
+                    BlacklistRequest::Flags(idx.unwrap()),
+                ).await;
+
+            } else if let Some(cap) = unflag_pat.captures(buffer.trim_end()) {
+                let idx  = cap[1].parse::<usize>();
+                if idx.is_err() { print_command_msg(); continue; }
+
+                do_blacklist_command(
+                    server_urls.clone(),
+                    ha_keys,
+                    server_keys,
+                    necessary_res,
+                    BlacklistRequest::Unflag(idx.unwrap()),
+                ).await;
+
+            } else if let Some(cap) = blacklist_pat.captures(buffer.trim_end()) {
+                let blacklist_req = match cap.get(3) {
+                    Some(idx) => match idx.as_str().parse::<usize>() {
+                        Ok(idx) => BlacklistRequest::Inspect(idx),
+                        Err(_) => { print_command_msg(); continue; }
+                    },
+                    None => BlacklistRequest::List,
+                };
+
+                do_blacklist_command(
+                    server_urls.clone(),
+                    ha_keys,
+                    server_keys,
+                    necessary_res,
+                    blacklist_req,
+                ).await;
+
+            } else if let Some(cap) = trajectory_pat.captures(buffer.trim_end()) {
+                let idx  = cap[2].parse::<usize>();
+                let first  = cap[3].parse::<usize>();
+                let last  = cap[4].parse::<usize>();
+                if idx.is_err() || first.is_err() || last.is_err() { print_command_msg(); continue; }
+
+                do_trajectory_command(
+                    grid_size,
+                    server_urls.clone(),
+                    ha_keys,
+                    server_keys,
+                    f_servers,
+                    TrajectoryRequest::new(idx.unwrap(), (first.unwrap(), last.unwrap())),
+                ).await;
+
+            } else if let Some(cap) = contacts_pat.captures(buffer.trim_end()) {
+                let idx  = cap[2].parse::<usize>();
+                let first  = cap[3].parse::<usize>();
+                let last  = cap[4].parse::<usize>();
+                if idx.is_err() || first.is_err() || last.is_err() { print_command_msg(); continue; }
+
+                do_contacts_command(
+                    server_urls.clone(),
+                    ha_keys,
+                    server_keys,
+                    f_servers,
+                    necessary_res,
+                    ContactsRequest::new(idx.unwrap(), (first.unwrap(), last.unwrap())),
+                ).await;
+
+            } else if let Some(cap) = exposure_pat.captures(buffer.trim_end()) {
+                let idx  = cap[2].parse::<usize>();
+                let first  = cap[3].parse::<usize>();
+                let last  = cap[4].parse::<usize>();
+                let hops  = cap[5].parse::<usize>();
+                if idx.is_err() || first.is_err() || last.is_err() || hops.is_err() { print_command_msg(); continue; }
+
+                do_exposure_command(
+                    grid_size,
+                    server_urls.clone(),
+                    ha_keys,
+                    server_keys,
+                    f_servers,
+                    ExposureRequest::new(idx.unwrap(), (first.unwrap(), last.unwrap()), hops.unwrap()),
+                ).await;
+
+            } else if let Some(cap) = infected_pat.captures(buffer.trim_end()) {
+                let idx  = cap[2].parse::<usize>();
+                let epoch  = cap[3].parse::<usize>();
+                if idx.is_err() || epoch.is_err() { print_command_msg(); continue; }
+
+                do_flag_infected_command(
+                    server_urls.clone(),
+                    ha_keys,
+                    server_keys,
+                    necessary_res,
+                    InfectionRequest::new(idx.unwrap(), epoch.unwrap()),
+                ).await;
+
+            } else if let Some(cap) = heatmap_pat.captures(buffer.trim_end()) {
+                let first  = cap[2].parse::<usize>();
+                let last  = cap[3].parse::<usize>();
+                let k  = cap[4].parse::<usize>();
+                let epsilon = cap.get(6).map(|epsilon| epsilon.as_str().parse::<f64>());
+                if first.is_err() || last.is_err() || k.is_err() || matches!(epsilon, Some(Err(_))) { print_command_msg(); continue; }
+                let epsilon = epsilon.map(|epsilon| epsilon.unwrap());
+                if matches!(epsilon, Some(epsilon) if !HEATMAP_EPSILONS.contains(&epsilon)) { print_command_msg(); continue; }
+
+                do_heatmap_command(
+                    grid_size,
+                    server_urls.clone(),
+                    ha_keys,
+                    server_keys,
+                    necessary_res,
+                    HeatmapRequest::new((first.unwrap(), last.unwrap()), k.unwrap(), epsilon),
+                ).await;
+
+            } else if let Some(cap) = roots_pat.captures(buffer.trim_end()) {
+                let epoch  = cap[1].parse::<usize>();
+                if epoch.is_err() { print_command_msg(); continue; }
+
+                do_roots_command(
+                    server_urls.clone(),
+                    ha_keys,
+                    server_keys,
+                    epoch.unwrap(),
+                ).await;
+
+            } else if let Some(cap) = region_pat.captures(buffer.trim_end()) {
+                let values : Vec<usize> = match (1..=6).map(|i| cap[i].parse::<usize>()).collect() {
+                    Ok(values) => values,
+                    Err(_) => { print_command_msg(); continue; }
+                };
+
+                do_get_users_in_region_command(
+                    server_urls.clone(),
+                    ha_keys,
+                    server_keys,
+                    f_servers,
+                    UsersInRegionRequest::new((values[0], values[1]), (values[2], values[3]), (values[4], values[5])),
+                ).await;
+
+            } else if let Some(cap) = o_rep_pat.captures(buffer.trim_end()) {
                 let idx  = cap[2].parse::<usize>();
                 let epoch  = cap[3].parse::<usize>();
                 if idx.is_err() || epoch.is_err() { print_command_msg(); continue; }
@@ -235,7 +893,89 @@ async fn read_commands(
     }
 }
 
-fn print_command_msg() { println!("To obtain a report use: report <id> <epoch>\nTo obtain users ate location use: users <epoch> <pos_x> <pos_y>"); }
+async fn do_roots_command(
+    server_urls :  Arc<Vec<Uri>>,
+    ha_keys : &HAClientKeys,
+    server_keys : &ServerPublicKey,
+    epoch : usize,
+) {
+
+    let roots_req = RootsRequest::new(epoch);
+    let mut responses : FuturesUnordered<_> = server_urls.iter().enumerate().map(
+        |(server_id, url)| {
+            let roots_req = &roots_req;
+            async move {
+                (server_id, verifying::obtain_roots(
+                    roots_req,
+                    url.clone(),
+                    ha_keys.sign_key(),
+                    server_keys.public_key(server_id),
+                    server_keys.public_sign_key(server_id),
+                ).await)
+            }
+        }).collect();
+
+    // Each server answers for itself, a divergence only shows by putting the answers side by side
+    let mut all_results : BTreeMap<usize, RootsResponse> = BTreeMap::new();
+    while let Some((server_id, res)) = responses.next().await {
+        match res {
+            Ok((result, receipt)) => {
+                keep_receipt(ha_keys.operator(), &receipt);
+                all_results.insert(server_id, result);
+            }
+            Err(error) => println!("Server {:} : {:}", server_id, error),
+        }
+    }
+
+    if all_results.is_empty() {
+        println!("Unable to get roots");
+        return;
+    }
+
+    for (server_id, result) in all_results.iter() {
+        match &result.root {
+            Some(root) => println!("Server {:} committed to {:} reports with root {:}", server_id, root.leaves, hex(&root.root)),
+            None => println!("Server {:} committed to no reports", server_id),
+        }
+        for peer_root in result.peer_roots.iter() {
+            let verified = server_keys.public_sign_keys().get(peer_root.server_id).map(|key| peer_root.verify(key)).unwrap_or(false);
+            println!("    told server {:} committed to {:} reports with root {:}{:}",
+                peer_root.server_id, peer_root.leaves, hex(&peer_root.root), if verified { "" } else { " (bad signature)" });
+        }
+        if !result.divergent.is_empty() {
+            println!("    diverges from servers {:?}", result.divergent);
+        }
+    }
+
+    let roots : Vec<&Vec<u8>> = all_results.values().filter_map(|result| result.root.as_ref().map(|root| &root.root)).collect();
+    if roots.windows(2).any(|pair| pair[0] != pair[1]) {
+        println!("Servers did not commit to the same reports for epoch {:}", epoch);
+    }
+}
+
+fn hex(bytes : &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn print_command_msg() {
+    println!("To obtain a report use: report <id> <epoch>\nTo obtain users ate location use: users <epoch> <pos_x> <pos_y>");
+    println!("To obtain the trajectory of a user use: trajectory <id> <from> <to>");
+    println!("To obtain the users near a user use: contacts <id> <from> <to>");
+    println!("To obtain the exposure chains starting at a user use: exposure <id> <from> <to> <hops>");
+    println!("To flag a user as infected from an epoch on use: infected <id> <epoch>");
+    println!("To obtain the number of users per cell use: heatmap <from> <to> <k> [epsilon], epsilon one of {:?}", HEATMAP_EPSILONS);
+    println!("To compare what each server committed to for an epoch use: roots <epoch>");
+    println!("To obtain users inside a rectangle during a range of epochs use: region <from> <to> <x0> <y0> <x1> <y1>");
+    println!("To list the blacklist use: blacklist\nTo inspect a blacklisted user use: blacklist <id>\nTo lift a ban use: unban <id>");
+    println!("To see where a witness vouched for contradictory locations use: flags <id>\nTo trust its proofs again use: unflag <id>");
+}
+
+fn keep_receipt(operator : usize, receipt : &Receipt) {
+    let receipts_file = format!("{:}operator_{:}.receipts", RECEIPTS_DIR, operator);
+    if let Err(err) = save_receipt(&receipts_file, receipt) {
+        println!("Unable to keep receipt from server {:}: {:}", receipt.server_id, err);
+    }
+}
 
 fn get_servers_url(n_servers : usize ) -> Arc<Vec<Uri>> {
     let mut server_urls = vec![];
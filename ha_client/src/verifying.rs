@@ -1,16 +1,22 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
 use eyre::eyre;
 use color_eyre::eyre::Result;
 
 use sodiumoxide::crypto::{box_, sign};
-use status::{UsersAtLocationRequest, encode_location_report, encode_users_at_location_report};
+use status::{encode_request, decode_response, sign_request, LocationReportResponse, UsersAtLocationRequest, UsersAtLocationResponse};
+use status::{BlacklistRequest, BlacklistResponse, UsersInRegionRequest, UsersInRegionResponse};
+use status::{TrajectoryRequest, TrajectoryResponse, ContactsRequest, ContactsResponse};
+use status::{ExposureRequest, ExposureResponse, ExposureLink, InfectionRequest, HeatmapRequest, HeatmapResponse, RootsRequest, RootsResponse};
 use tonic::transport::Uri;
 
 use security::{report, status::{self, LocationReportRequest}};
+use security::receipt::{ACCEPTED, Receipt, Receipted, decode_receipt};
 
 use protos::location_master::location_master_client::LocationMasterClient;
-use protos::location_master::{ObtainLocationReportRequest, ObtainUsersAtLocationRequest};
+use protos::location_master::{ObtainLocationReportRequest, ObtainUsersAtLocationRequest, AdministerBlacklistRequest, ObtainUsersInRegionRequest};
+use protos::location_master::{ObtainTrajectoryRequest, ObtainContactsRequest, ObtainExposureChainsRequest, FlagInfectedRequest};
+use protos::location_master::{ObtainHeatmapRequest, ObtainRootsRequest};
 
 
 pub async fn obtain_location_report(
@@ -20,13 +26,15 @@ pub async fn obtain_location_report(
     url : Uri,
     sign_key : &sign::SecretKey,
     server_key : &box_::PublicKey,
+    server_sign_key : &sign::PublicKey,
     client_public_key : &sign::PublicKey
-) -> Result<(usize, usize)> {
+) -> Result<((usize, usize), bool, Receipt)> { // location, disputed, receipt
 
     let mut client = LocationMasterClient::connect(url).await?;
 
     let loc_report = LocationReportRequest::new(idx, epoch);
-    let (info, user, key, pow) = encode_location_report(&sign_key, server_key, &loc_report, idx);
+    let signed = sign_request(sign_key, &loc_report);
+    let (info, user, key, pow) = encode_request(sign_key, server_key, &loc_report, idx);
 
     let request = tonic::Request::new(ObtainLocationReportRequest {
         user,
@@ -34,12 +42,15 @@ pub async fn obtain_location_report(
         pow,
     });
 
-    let report = match client.obtain_location_report(request).await {
+    let (report, disputed, receipt) = match client.obtain_location_report(request).await {
         Ok(response) => {
             let response = response.get_ref();
-            if let Ok(res) = status::decode_response_location(&key, &response.nonce, &response.location) {
+            if let Ok(res) = decode_response::<LocationReportResponse>(&key, &response.nonce, &response.location) {
+                if !res.receipt.confirms(server_sign_key, idx, epoch, &signed, &(&res.report, res.disputed)) {
+                    return Err(eyre!("obtain_location_report unable to validate server receipt"));
+                }
                 if let Ok(report) = report::verify_report(client_public_key, &res.report) {
-                    report
+                    (report, res.disputed, res.receipt)
                 } else {
                     return  Err(eyre!("obtain_location_report unable to verify report"));
                 }
@@ -53,7 +64,7 @@ pub async fn obtain_location_report(
 
     let (x, y) = report.loc();
     if x < grid_size && y < grid_size && epoch == report.epoch(){
-        Ok((x, y))
+        Ok(((x, y), disputed, receipt))
     } else {
         Err(eyre!("Response : Not a valid position (x : {:}, y : {:}) or not the same epoch!", x, y))
     }
@@ -66,13 +77,15 @@ pub async fn obtain_users_at_location(
     url : Uri,
     sign_key : &sign::SecretKey,
     server_key : &box_::PublicKey,
+    server_sign_key : &sign::PublicKey,
     clients_public_keys : &HashMap<usize, sign::PublicKey>
-) -> Result<HashSet<usize>> {
+) -> Result<(HashSet<usize>, HashSet<usize>, Receipt)> { // users, disputed, receipt
 
     let mut client = LocationMasterClient::connect(url).await?;
 
     let loc_report = UsersAtLocationRequest::new((pos_x, pos_y), epoch);
-    let (info, place, key, pow) = encode_users_at_location_report(&sign_key, server_key, &loc_report, 0);
+    let signed = sign_request(sign_key, &loc_report);
+    let (info, place, key, pow) = encode_request(sign_key, server_key, &loc_report, 0);
 
     let request = tonic::Request::new(ObtainUsersAtLocationRequest {
         place,
@@ -83,7 +96,10 @@ pub async fn obtain_users_at_location(
     match client.obtain_users_at_location(request).await {
         Ok(response) => {
             let response = response.get_ref();
-            if let Ok(res) = status::decode_users_at_loc_response(&key, &response.nonce, &response.idxs) {
+            if let Ok(Receipted { response : res, receipt }) = decode_response::<Receipted<UsersAtLocationResponse>>(&key, &response.nonce, &response.idxs) {
+                if !receipt.covers(&signed, &res) || !receipt.verify(server_sign_key) {
+                    return Err(eyre!("obtain_users_at_location unable to validate server receipt"));
+                }
                 let mut idxs : HashSet<usize> = HashSet::new();
                 for (idx, report) in res.idxs_reports.iter() {
                     if !clients_public_keys.contains_key(idx) {
@@ -99,7 +115,8 @@ pub async fn obtain_users_at_location(
                         return Err(eyre!("obtain_location_report unable to validate all users reports"));
                     }
                 }
-                Ok(idxs)
+                let disputed = res.disputed.into_iter().filter(|idx| idxs.contains(idx)).collect();
+                Ok((idxs, disputed, receipt))
             } else {
                 return Err(eyre!("obtain_location_report unable to validate server response "));
             }
@@ -107,4 +124,374 @@ pub async fn obtain_users_at_location(
         Err(status) => return Err(eyre!("ObtainUsersAtLocation failed with code {:?} and message {:?}.",
                             status.code(), status.message())),
     }
-}
\ No newline at end of file
+}
+
+// epoch -> users, (epoch, disputed user)
+pub type RegionUsers = (BTreeMap<usize, BTreeSet<usize>>, BTreeSet<(usize, usize)>);
+
+pub async fn obtain_users_in_region(
+    region_req : &UsersInRegionRequest,
+    url : Uri,
+    sign_key : &sign::SecretKey,
+    server_key : &box_::PublicKey,
+    server_sign_key : &sign::PublicKey,
+    clients_public_keys : &HashMap<usize, sign::PublicKey>
+) -> Result<(RegionUsers, Receipt)> {
+
+    let mut client = LocationMasterClient::connect(url).await?;
+
+    let signed = sign_request(sign_key, region_req);
+    let (info, region, key, pow) = encode_request(sign_key, server_key, region_req, 0);
+
+    let request = tonic::Request::new(ObtainUsersInRegionRequest {
+        region,
+        info,
+        pow,
+    });
+
+    match client.obtain_users_in_region(request).await {
+        Ok(response) => {
+            let response = response.get_ref();
+            let (res, receipt) = match decode_response::<Receipted<UsersInRegionResponse>>(&key, &response.nonce, &response.reports) {
+                Ok(res) if res.confirms(server_sign_key, &signed) => (res.response, res.receipt),
+                _ => return Err(eyre!("obtain_users_in_region unable to validate server response ")),
+            };
+
+            let mut users : BTreeMap<usize, BTreeSet<usize>> = BTreeMap::new();
+            for (epoch, idx, report) in res.reports.iter() {
+                let public_key = clients_public_keys.get(idx).ok_or_else(|| eyre!("obtain_users_in_region unable to find user"))?;
+                match report::verify_report(public_key, report) {
+                    Ok(report) if report.idx() == *idx && report.epoch() == *epoch && region_req.contains(*epoch, report.loc()) => {
+                        users.entry(*epoch).or_default().insert(*idx);
+                    }
+                    _ => return Err(eyre!("obtain_users_in_region unable to validate all users reports")),
+                }
+            }
+            let disputed = res.disputed.into_iter().filter(
+                |(epoch, idx)| matches!(users.get(epoch), Some(at_epoch) if at_epoch.contains(idx))
+            ).collect();
+            Ok(((users, disputed), receipt))
+        }
+        Err(status) => Err(eyre!("ObtainUsersInRegion failed with code {:?} and message {:?}.",
+                            status.code(), status.message())),
+    }
+}
+
+// epoch -> (location, disputed)
+pub type Trajectory = BTreeMap<usize, ((usize, usize), bool)>;
+
+pub async fn obtain_trajectory(
+    trajectory_req : &TrajectoryRequest,
+    grid_size : usize,
+    url : Uri,
+    sign_key : &sign::SecretKey,
+    server_key : &box_::PublicKey,
+    server_sign_key : &sign::PublicKey,
+    client_public_key : &sign::PublicKey
+) -> Result<(Trajectory, Receipt)> {
+
+    let mut client = LocationMasterClient::connect(url).await?;
+
+    let signed = sign_request(sign_key, trajectory_req);
+    let (info, trajectory, key, pow) = encode_request(sign_key, server_key, trajectory_req, 0);
+
+    let request = tonic::Request::new(ObtainTrajectoryRequest {
+        trajectory,
+        info,
+        pow,
+    });
+
+    match client.obtain_trajectory(request).await {
+        Ok(response) => {
+            let response = response.get_ref();
+            let (res, receipt) = match decode_response::<Receipted<TrajectoryResponse>>(&key, &response.nonce, &response.trajectory) {
+                Ok(res) if res.confirms(server_sign_key, &signed) => (res.response, res.receipt),
+                _ => return Err(eyre!("obtain_trajectory unable to validate server response ")),
+            };
+
+            let (first, last) = trajectory_req.epochs();
+            let mut trajectory = Trajectory::new();
+            for (epoch, report) in res.reports.iter() {
+                match report::verify_report(client_public_key, report) {
+                    Ok(report) if report.idx() == trajectory_req.idx() && report.epoch() == *epoch
+                        && first <= *epoch && *epoch <= last
+                        && report.loc().0 < grid_size && report.loc().1 < grid_size => {
+                        trajectory.insert(*epoch, (report.loc(), res.disputed.contains(epoch)));
+                    }
+                    _ => return Err(eyre!("obtain_trajectory unable to validate all reports")),
+                }
+            }
+            Ok((trajectory, receipt))
+        }
+        Err(status) => Err(eyre!("ObtainTrajectory failed with code {:?} and message {:?}.",
+                            status.code(), status.message())),
+    }
+}
+
+// epoch -> (location, contact id -> location)
+pub type Contacts = BTreeMap<usize, ((usize, usize), BTreeMap<usize, (usize, usize)>)>;
+
+fn near((x, y) : (usize, usize), (other_x, other_y) : (usize, usize)) -> bool {
+    x.max(other_x) - x.min(other_x) <= 1 && y.max(other_y) - y.min(other_y) <= 1
+}
+
+pub async fn obtain_contacts(
+    contacts_req : &ContactsRequest,
+    url : Uri,
+    sign_key : &sign::SecretKey,
+    server_key : &box_::PublicKey,
+    server_sign_key : &sign::PublicKey,
+    clients_public_keys : &HashMap<usize, sign::PublicKey>
+) -> Result<(Contacts, Receipt)> {
+
+    let mut client = LocationMasterClient::connect(url).await?;
+
+    let signed = sign_request(sign_key, contacts_req);
+    let (info, contacts, key, pow) = encode_request(sign_key, server_key, contacts_req, 0);
+
+    let request = tonic::Request::new(ObtainContactsRequest {
+        contacts,
+        info,
+        pow,
+    });
+
+    match client.obtain_contacts(request).await {
+        Ok(response) => {
+            let response = response.get_ref();
+            let (res, receipt) = match decode_response::<Receipted<ContactsResponse>>(&key, &response.nonce, &response.contacts) {
+                Ok(res) if res.confirms(server_sign_key, &signed) => (res.response, res.receipt),
+                _ => return Err(eyre!("obtain_contacts unable to validate server response ")),
+            };
+
+            let (first, last) = contacts_req.epochs();
+            let public_key = clients_public_keys.get(&contacts_req.idx()).ok_or_else(|| eyre!("obtain_contacts unable to find user"))?;
+            let mut contacts = Contacts::new();
+            for at_epoch in res.contacts.iter() {
+                let loc = match report::verify_report(public_key, &at_epoch.report) {
+                    Ok(report) if report.idx() == contacts_req.idx() && report.epoch() == at_epoch.epoch
+                        && first <= at_epoch.epoch && at_epoch.epoch <= last => report.loc(),
+                    _ => return Err(eyre!("obtain_contacts unable to validate the user's reports")),
+                };
+                let mut near_users = BTreeMap::new();
+                for (idx, report) in at_epoch.contacts.iter() {
+                    let public_key = clients_public_keys.get(idx).ok_or_else(|| eyre!("obtain_contacts unable to find user"))?;
+                    match report::verify_report(public_key, report) {
+                        Ok(report) if report.idx() == *idx && *idx != contacts_req.idx()
+                            && report.epoch() == at_epoch.epoch && near(loc, report.loc()) => {
+                            near_users.insert(*idx, report.loc());
+                        }
+                        _ => return Err(eyre!("obtain_contacts unable to validate all contacts reports")),
+                    }
+                }
+                contacts.insert(at_epoch.epoch, (loc, near_users));
+            }
+            Ok((contacts, receipt))
+        }
+        Err(status) => Err(eyre!("ObtainContacts failed with code {:?} and message {:?}.",
+                            status.code(), status.message())),
+    }
+}
+
+// Each chain starts at the source, links consecutive contacts forward in time and stays within range
+fn valid_chain(exposure_req : &ExposureRequest, grid_size : usize, chain : &[ExposureLink]) -> bool {
+    let (first, last) = exposure_req.epochs();
+    let mut current = (exposure_req.idx(), first);
+    !chain.is_empty() && chain.len() <= exposure_req.hops() && chain.iter().all(|link| {
+        let valid = link.from == current.0 && current.1 <= link.epoch && link.epoch <= last && link.from != link.to
+            && link.to_cell.0 < grid_size && link.to_cell.1 < grid_size && near(link.from_cell, link.to_cell);
+        current = (link.to, link.epoch + 1);
+        valid
+    })
+}
+
+pub async fn obtain_exposure_chains(
+    exposure_req : &ExposureRequest,
+    grid_size : usize,
+    url : Uri,
+    sign_key : &sign::SecretKey,
+    server_key : &box_::PublicKey,
+    server_sign_key : &sign::PublicKey,
+) -> Result<(Vec<Vec<ExposureLink>>, Receipt)> {
+
+    let mut client = LocationMasterClient::connect(url).await?;
+
+    let signed = sign_request(sign_key, exposure_req);
+    let (info, exposure, key, pow) = encode_request(sign_key, server_key, exposure_req, 0);
+
+    let request = tonic::Request::new(ObtainExposureChainsRequest {
+        exposure,
+        info,
+        pow,
+    });
+
+    match client.obtain_exposure_chains(request).await {
+        Ok(response) => {
+            let response = response.get_ref();
+            let (res, receipt) = match decode_response::<Receipted<ExposureResponse>>(&key, &response.nonce, &response.chains) {
+                Ok(res) if res.confirms(server_sign_key, &signed) => (res.response, res.receipt),
+                _ => return Err(eyre!("obtain_exposure_chains unable to validate server response ")),
+            };
+
+            if res.chains.iter().all(|chain| valid_chain(exposure_req, grid_size, chain)) {
+                Ok((res.chains, receipt))
+            } else {
+                Err(eyre!("obtain_exposure_chains unable to validate all chains"))
+            }
+        }
+        Err(status) => Err(eyre!("ObtainExposureChains failed with code {:?} and message {:?}.",
+                            status.code(), status.message())),
+    }
+}
+
+pub async fn obtain_heatmap(
+    heatmap_req : &HeatmapRequest,
+    grid_size : usize,
+    url : Uri,
+    sign_key : &sign::SecretKey,
+    server_key : &box_::PublicKey,
+    server_sign_key : &sign::PublicKey,
+) -> Result<(BTreeMap<(usize, usize), usize>, Receipt)> {
+
+    let mut client = LocationMasterClient::connect(url).await?;
+
+    let signed = sign_request(sign_key, heatmap_req);
+    let (info, heatmap, key, pow) = encode_request(sign_key, server_key, heatmap_req, 0);
+
+    let request = tonic::Request::new(ObtainHeatmapRequest {
+        heatmap,
+        info,
+        pow,
+    });
+
+    match client.obtain_heatmap(request).await {
+        Ok(response) => {
+            let response = response.get_ref();
+            let (res, receipt) = match decode_response::<Receipted<HeatmapResponse>>(&key, &response.nonce, &response.heatmap) {
+                Ok(res) if res.confirms(server_sign_key, &signed) => (res.response, res.receipt),
+                _ => return Err(eyre!("obtain_heatmap unable to validate server response ")),
+            };
+
+            if res.cells.iter().any(|((x, y), _)| *x >= grid_size || *y >= grid_size) {
+                return Err(eyre!("obtain_heatmap cell outside the grid"));
+            }
+            Ok((res.cells.into_iter().collect(), receipt))
+        }
+        Err(status) => Err(eyre!("ObtainHeatmap failed with code {:?} and message {:?}.",
+                            status.code(), status.message())),
+    }
+}
+
+pub async fn obtain_roots(
+    roots_req : &RootsRequest,
+    url : Uri,
+    sign_key : &sign::SecretKey,
+    server_key : &box_::PublicKey,
+    server_sign_key : &sign::PublicKey,
+) -> Result<(RootsResponse, Receipt)> {
+
+    let mut client = LocationMasterClient::connect(url).await?;
+
+    let signed = sign_request(sign_key, roots_req);
+    let (info, request, key, pow) = encode_request(sign_key, server_key, roots_req, 0);
+
+    let request = tonic::Request::new(ObtainRootsRequest {
+        request,
+        info,
+        pow,
+    });
+
+    match client.obtain_roots(request).await {
+        Ok(response) => {
+            let response = response.get_ref();
+            let (res, receipt) = match decode_response::<Receipted<RootsResponse>>(&key, &response.nonce, &response.roots) {
+                Ok(res) if res.confirms(server_sign_key, &signed) => (res.response, res.receipt),
+                _ => return Err(eyre!("obtain_roots unable to validate server response ")),
+            };
+
+            let epoch = roots_req.epoch();
+            if res.root.iter().chain(res.peer_roots.iter()).any(|root| root.epoch != epoch) {
+                return Err(eyre!("obtain_roots root for another epoch"));
+            }
+            if let Some(root) = &res.root {
+                if !root.verify(server_sign_key) {
+                    return Err(eyre!("obtain_roots unable to verify the server root"));
+                }
+            }
+            Ok((res, receipt))
+        }
+        Err(status) => Err(eyre!("ObtainRoots failed with code {:?} and message {:?}.",
+                            status.code(), status.message())),
+    }
+}
+
+pub async fn flag_infected(
+    infection_req : &InfectionRequest,
+    url : Uri,
+    sign_key : &sign::SecretKey,
+    server_key : &box_::PublicKey,
+    server_sign_key : &sign::PublicKey,
+) -> Result<Receipt> {
+
+    let mut client = LocationMasterClient::connect(url).await?;
+
+    let signed = sign_request(sign_key, infection_req);
+    let (info, infection, key, pow) = encode_request(sign_key, server_key, infection_req, 0);
+
+    let request = tonic::Request::new(FlagInfectedRequest {
+        infection,
+        info,
+        pow,
+    });
+
+    match client.flag_infected(request).await {
+        Ok(response) => {
+            let response = response.get_ref();
+            match decode_receipt(&key, &response.nonce, &response.ok) {
+                Ok(receipt) if receipt.confirms(server_sign_key, infection_req.idx(), infection_req.epoch(), &signed, &ACCEPTED) => Ok(receipt),
+                _ => Err(eyre!("flag_infected unable to validate server response ")),
+            }
+        }
+        Err(status) => Err(eyre!("FlagInfected failed with code {:?} and message {:?}.",
+                            status.code(), status.message())),
+    }
+}
+
+pub async fn administer_blacklist(
+    blacklist_req : &BlacklistRequest,
+    url : Uri,
+    sign_key : &sign::SecretKey,
+    server_key : &box_::PublicKey,
+    server_sign_key : &sign::PublicKey,
+) -> Result<(BlacklistResponse, Receipt)> {
+
+    let mut client = LocationMasterClient::connect(url).await?;
+
+    let signed = sign_request(sign_key, blacklist_req);
+    let (info, request, key, pow) = encode_request(sign_key, server_key, blacklist_req, 0);
+
+    let request = tonic::Request::new(AdministerBlacklistRequest {
+        request,
+        info,
+        pow,
+    });
+
+    match client.administer_blacklist(request).await {
+        Ok(response) => {
+            let response = response.get_ref();
+            let (blacklist_res, receipt) = match decode_response::<Receipted<BlacklistResponse>>(&key, &response.nonce, &response.response) {
+                Ok(res) if res.confirms(server_sign_key, &signed) => (res.response, res.receipt),
+                _ => return Err(eyre!("administer_blacklist unable to validate server response ")),
+            };
+            match (blacklist_req, &blacklist_res) {
+                (BlacklistRequest::List, BlacklistResponse::Listed(_))
+                | (BlacklistRequest::Inspect(_), BlacklistResponse::Entry(_))
+                | (BlacklistRequest::Clear(_), BlacklistResponse::Cleared(_))
+                | (BlacklistRequest::Flags(_), BlacklistResponse::Flags(_))
+                | (BlacklistRequest::Unflag(_), BlacklistResponse::Cleared(_)) => Ok((blacklist_res, receipt)),
+                _ => Err(eyre!("administer_blacklist response does not match the request")),
+            }
+        }
+        Err(status) => Err(eyre!("AdministerBlacklist failed with code {:?} and message {:?}.",
+                            status.code(), status.message())),
+    }
+}
@@ -11,7 +11,7 @@ use security::key_management::{
     retrieve_ha_client_keys,
 };
 
-use sodiumoxide::crypto::box_;
+use sodiumoxide::crypto::{box_, sign};
 
 const KEYS_DIR : &str = "../security/keys";
 const GRID_FILE : &str = "../grid/grid.txt";
@@ -32,6 +32,10 @@ pub fn get_pub_server_key() -> Vec<box_::PublicKey> {
     retrieve_servers_public_keys(KEYS_DIR).unwrap().public_keys().to_vec()
 }
 
+pub fn get_pub_server_sign_keys() -> Vec<sign::PublicKey> {
+    retrieve_servers_public_keys(KEYS_DIR).unwrap().public_sign_keys().to_vec()
+}
+
 pub fn get_ha_client_keys() -> Arc<HAClientKeys> {
-    Arc::new(retrieve_ha_client_keys(KEYS_DIR).expect("Failed to retrieve ha_client keys"))
+    Arc::new(retrieve_ha_client_keys(KEYS_DIR, 0).expect("Failed to retrieve ha_client keys"))
 }
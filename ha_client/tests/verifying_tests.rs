@@ -26,6 +26,7 @@ pub async fn get_submited_report () {
 
     let ha_client_keys = common::get_ha_client_keys();
     let server_key = common::get_pub_server_key();
+    let server_sign_key = common::get_pub_server_sign_keys();
 
     let timeline = common::get_timeline();
 
@@ -40,11 +41,12 @@ pub async fn get_submited_report () {
                 server_url,
                 &ha_client_keys.sign_key(),
                 &server_key[0],
+                &server_sign_key[0],
                 ha_client_keys.client_public_key(IDX).unwrap(),
             ).await;
 
         assert!(loc_res.is_ok());
-        assert_eq!(location, loc_res.unwrap());
+        assert_eq!(location, loc_res.unwrap().0);
     } else {
         panic!("Error: reports_generator! (Should never happen)");
     }
@@ -59,6 +61,7 @@ pub async fn get_not_submited_report () {
 
     let ha_client_keys = common::get_ha_client_keys();
     let server_key = common::get_pub_server_key();
+    let server_sign_key = common::get_pub_server_sign_keys();
 
     let loc_res =
         obtain_location_report(
@@ -68,6 +71,7 @@ pub async fn get_not_submited_report () {
             server_url,
             &ha_client_keys.sign_key(),
             &server_key[0],
+            &server_sign_key[0],
             ha_client_keys.client_public_key(0).unwrap(),
         ).await;
 
@@ -83,6 +87,7 @@ pub async fn get_invalid_id_report () {
 
     let ha_client_keys = common::get_ha_client_keys();
     let server_key = common::get_pub_server_key();
+    let server_sign_key = common::get_pub_server_sign_keys();
 
     let loc_res =
         obtain_location_report(
@@ -92,6 +97,7 @@ pub async fn get_invalid_id_report () {
             server_url,
             ha_client_keys.sign_key(),
             &server_key[0],
+            &server_sign_key[0],
             ha_client_keys.client_public_key(0).unwrap(),
         ).await;
 
@@ -107,6 +113,7 @@ pub async fn get_location_report_invalid_signature () {
 
     let client_keys = common::get_client_keys(IDX);
     let server_key = common::get_pub_server_key();
+    let server_sign_key = common::get_pub_server_sign_keys();
 
     let loc_res =
         obtain_location_report(
@@ -116,6 +123,7 @@ pub async fn get_location_report_invalid_signature () {
             server_url,
             &client_keys.sign_key(),
             &server_key[0],
+            &server_sign_key[0],
             client_keys.public_key(),
         ).await;
 
@@ -131,6 +139,7 @@ pub async fn get_users_at_location_at_epoch () {
 
     let ha_client_keys = common::get_ha_client_keys();
     let server_key = common::get_pub_server_key();
+    let server_sign_key = common::get_pub_server_sign_keys();
 
     let timeline = common::get_timeline();
 
@@ -154,12 +163,13 @@ pub async fn get_users_at_location_at_epoch () {
             server_url,
             ha_client_keys.sign_key(),
             &server_key[0],
+            &server_sign_key[0],
             ha_client_keys.clients_public_keys(),
         ).await;
 
     assert!(users_res.is_ok());
 
-    let users_res = users_res.unwrap();
+    let (users_res, _, _) = users_res.unwrap();
 
     assert_eq!(users.len(), users_res.len());
 
@@ -177,6 +187,7 @@ pub async fn get_users_bad_location () {
 
     let ha_client_keys = common::get_ha_client_keys();
     let server_key = common::get_pub_server_key();
+    let server_sign_key = common::get_pub_server_sign_keys();
 
     let users_res =
         obtain_users_at_location(
@@ -186,6 +197,7 @@ pub async fn get_users_bad_location () {
             server_url,
             ha_client_keys.sign_key(),
             &server_key[0],
+            &server_sign_key[0],
             ha_client_keys.clients_public_keys(),
         ).await;
 
@@ -201,6 +213,7 @@ pub async fn get_users_not_existent_epoch () {
 
     let ha_client_keys = common::get_ha_client_keys();
     let server_key = common::get_pub_server_key();
+    let server_sign_key = common::get_pub_server_sign_keys();
 
     let users_res =
         obtain_users_at_location(
@@ -210,6 +223,7 @@ pub async fn get_users_not_existent_epoch () {
             server_url,
             ha_client_keys.sign_key(),
             &server_key[0],
+            &server_sign_key[0],
             ha_client_keys.clients_public_keys(),
         ).await;
 
@@ -225,6 +239,7 @@ pub async fn get_users_invalid_signature () {
 
     let client_keys = common::get_client_keys(IDX);
     let server_key = common::get_pub_server_key();
+    let server_sign_key = common::get_pub_server_sign_keys();
 
     let ha_client_keys = common::get_ha_client_keys();
 
@@ -236,6 +251,7 @@ pub async fn get_users_invalid_signature () {
             server_url,
             client_keys.sign_key(),
             &server_key[0],
+            &server_sign_key[0],
             ha_client_keys.clients_public_keys(),
         ).await;
 
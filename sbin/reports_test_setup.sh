@@ -26,16 +26,17 @@ echo
 echo "Starting Servers"
 echo
 rm server/storage/* 2> /dev/null
+epoch_start=$(date +%s)
 for ((idx=0;idx<n_servers;idx++))
 do
-    gnome-terminal -- ./target/$dir/server --id $idx --size $grid_size --keys $keys_dir --fline $f_line --n_servers $n_servers
+    gnome-terminal -- ./target/$dir/server --id $idx --size $grid_size --keys $keys_dir --fline $f_line --n_servers $n_servers --epoch_start $epoch_start
 done
 
 echo "Starting Clients"
 echo
 for ((idx=0;idx<n_points-1;idx++))
 do
-    gnome-terminal -- ./target/$dir/client --n_servers $n_servers --id $idx --grid $grid_file --keys $keys_dir
+    gnome-terminal -- ./target/$dir/client --n_servers $n_servers --id $idx --grid $grid_file --keys $keys_dir --epoch_start $epoch_start
 done
 
 echo "Starting ha_client"
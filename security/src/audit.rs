@@ -0,0 +1,52 @@
+use serde_derive::{Deserialize, Serialize};
+use sodiumoxide::crypto::sign;
+
+// Latest entry of a server's audit log, handed to the other servers so a shortened log shows
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AuditHead {
+    pub server_id : usize,
+    pub seq : usize,
+    pub hash : Vec<u8>,
+    pub signature : Vec<u8>,
+}
+
+impl AuditHead {
+    pub fn new(server_id : usize, seq : usize, hash : Vec<u8>, sign_key : &sign::SecretKey) -> AuditHead {
+        let mut head = AuditHead {
+            server_id,
+            seq,
+            hash,
+            signature : vec![],
+        };
+        head.signature = sign::sign_detached(&head.signed_content(), sign_key).0.to_vec();
+        head
+    }
+
+    fn signed_content(&self) -> Vec<u8> {
+        serde_json::to_vec(&("audit", self.server_id, self.seq, &self.hash)).unwrap()
+    }
+
+    pub fn verify(&self, public_key : &sign::PublicKey) -> bool {
+        match sign::Signature::from_slice(&self.signature) {
+            Some(signature) => sign::verify_detached(&signature, &self.signed_content(), public_key),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_head() {
+        let (public_key, sign_key) = sign::gen_keypair();
+        let head = AuditHead::new(1, 4, vec![7; 32], &sign_key);
+
+        assert!(head.verify(&public_key));
+        assert!(!head.verify(&sign::gen_keypair().0));
+
+        let shorter = AuditHead { seq : 3, ..head };
+        assert!(!shorter.verify(&public_key));
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+
+use serde_derive::{Deserialize, Serialize};
+use sodiumoxide::crypto::{hash::sha256, sign};
+
+// A server's contribution to the beacon of an epoch, released once the epoch opens
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BeaconShare {
+    pub server_id : usize,
+    pub epoch : usize,
+    pub signature : Vec<u8>,
+}
+
+impl BeaconShare {
+    // Signatures are deterministic, so a server has a single share per epoch and nobody can guess it beforehand
+    pub fn new(server_id : usize, epoch : usize, sign_key : &sign::SecretKey) -> BeaconShare {
+        BeaconShare {
+            server_id,
+            epoch,
+            signature : sign::sign_detached(&signed_content(epoch), sign_key).0.to_vec(),
+        }
+    }
+
+    pub fn verify(&self, public_key : &sign::PublicKey) -> bool {
+        verify_signature(&self.signature, &signed_content(self.epoch), public_key)
+    }
+}
+
+// Shares gathered for an epoch, servers agree on which ones make the beacon through the double echo
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EpochBeacon {
+    pub epoch : usize,
+    pub shares : BTreeMap<usize, Vec<u8>>, // server id -> signature
+}
+
+impl EpochBeacon {
+    pub fn new(epoch : usize) -> EpochBeacon {
+        EpochBeacon {
+            epoch,
+            shares : BTreeMap::new(),
+        }
+    }
+
+    // Returns false if the share is for another epoch or the server had already sent it
+    pub fn add(&mut self, share : BeaconShare) -> bool {
+        if share.epoch != self.epoch || self.shares.contains_key(&share.server_id) {
+            return false;
+        }
+        self.shares.insert(share.server_id, share.signature);
+        true
+    }
+
+    // Any f_servers + 1 servers will do, so at least one of them is correct and a crashed server stalls nothing
+    pub fn is_complete(&self, f_servers : usize) -> bool {
+        self.shares.len() > f_servers
+    }
+
+    // The first f_servers + 1 shares, what a server proposes as the beacon of the epoch
+    pub fn proposal(&self, f_servers : usize) -> EpochBeacon {
+        EpochBeacon {
+            epoch : self.epoch,
+            shares : self.shares.iter().take(f_servers + 1).map(|(server_id, signature)| (*server_id, signature.clone())).collect(),
+        }
+    }
+
+    pub fn value(&self) -> Vec<u8> {
+        sha256::hash(&serde_json::to_vec(&("beacon", self.epoch, &self.shares)).unwrap()).0.to_vec()
+    }
+
+    pub fn verify(&self, public_sign_keys : &[sign::PublicKey], f_servers : usize) -> bool {
+        let content = signed_content(self.epoch);
+        self.is_complete(f_servers) && self.shares.iter().all(|(server_id, signature)|
+            match public_sign_keys.get(*server_id) {
+                Some(public_key) => verify_signature(signature, &content, public_key),
+                None => false,
+            }
+        )
+    }
+}
+
+fn signed_content(epoch : usize) -> Vec<u8> {
+    serde_json::to_vec(&("beacon", epoch)).unwrap()
+}
+
+fn verify_signature(signature : &[u8], content : &[u8], public_key : &sign::PublicKey) -> bool {
+    match sign::Signature::from_slice(signature) {
+        Some(signature) => sign::verify_detached(&signature, content, public_key),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPOCH : usize = 6;
+    const F_SERVERS : usize = 1;
+
+    #[test]
+    fn verify_beacon() {
+        let keys : Vec<(sign::PublicKey, sign::SecretKey)> = (0..4).map(|_| sign::gen_keypair()).collect();
+        let public_sign_keys : Vec<sign::PublicKey> = keys.iter().map(|(public_key, _)| *public_key).collect();
+        let shares : Vec<BeaconShare> = keys.iter().enumerate()
+            .map(|(server_id, (_, sign_key))| BeaconShare::new(server_id, EPOCH, sign_key))
+            .collect();
+
+        assert!(shares[0].verify(&public_sign_keys[0]));
+        assert!(!shares[0].verify(&public_sign_keys[1]));
+        assert_eq!(shares[0], BeaconShare::new(0, EPOCH, &keys[0].1));
+
+        let mut beacon = EpochBeacon::new(EPOCH);
+        assert!(beacon.add(shares[0].clone()));
+        assert!(!beacon.add(shares[0].clone()));
+        assert!(!beacon.add(BeaconShare::new(1, EPOCH + 1, &keys[1].1)));
+        assert!(!beacon.verify(&public_sign_keys, F_SERVERS));
+
+        assert!(beacon.add(shares[1].clone()));
+        assert!(beacon.verify(&public_sign_keys, F_SERVERS));
+
+        // Whatever order the shares came in
+        let mut other = EpochBeacon::new(EPOCH);
+        other.add(shares[1].clone());
+        other.add(shares[0].clone());
+        assert_eq!(beacon.value(), other.value());
+
+        // Any servers will do, even without the first ones
+        let mut without_first = EpochBeacon::new(EPOCH);
+        without_first.add(shares[3].clone());
+        without_first.add(shares[2].clone());
+        assert!(without_first.verify(&public_sign_keys, F_SERVERS));
+        assert_ne!(beacon.value(), without_first.value());
+
+        other.add(shares[3].clone());
+        assert!(other.verify(&public_sign_keys, F_SERVERS));
+        assert_eq!(beacon, other.proposal(F_SERVERS));
+
+        let mut forged = beacon.clone();
+        let signature = forged.shares.remove(&1).unwrap();
+        forged.shares.insert(0, signature);
+        assert!(!forged.verify(&public_sign_keys, F_SERVERS));
+
+        let mut next = EpochBeacon::new(EPOCH + 1);
+        next.add(BeaconShare::new(0, EPOCH + 1, &keys[0].1));
+        next.add(BeaconShare::new(1, EPOCH + 1, &keys[1].1));
+        assert!(next.verify(&public_sign_keys, F_SERVERS));
+        assert_ne!(beacon.value(), next.value());
+    }
+}
@@ -0,0 +1,153 @@
+use std::{collections::BTreeMap, fs::OpenOptions, io::Write};
+
+use serde_derive::{Deserialize, Serialize};
+use sodiumoxide::crypto::sign;
+use color_eyre::eyre::Result;
+
+use crate::receipt::digest;
+
+// A server's word that it delivered this exact report of the client for the epoch
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DeliveryStatement {
+    pub server_id : usize,
+    pub idx : usize,
+    pub epoch : usize,
+    pub report : Vec<u8>, // digest of the signed report
+    pub signature : Vec<u8>,
+}
+
+impl DeliveryStatement {
+    pub fn new(server_id : usize, idx : usize, epoch : usize, signed_report : &[u8], sign_key : &sign::SecretKey) -> DeliveryStatement {
+        let report = digest(signed_report);
+        let signature = sign::sign_detached(&signed_content(idx, epoch, &report), sign_key).0.to_vec();
+        DeliveryStatement {
+            server_id,
+            idx,
+            epoch,
+            report,
+            signature,
+        }
+    }
+
+    pub fn verify(&self, public_key : &sign::PublicKey) -> bool {
+        verify_signature(&self.signature, &signed_content(self.idx, self.epoch, &self.report), public_key)
+    }
+}
+
+// Statements of several servers on the same report, convincing on its own once enough servers signed
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DeliveryCertificate {
+    pub idx : usize,
+    pub epoch : usize,
+    pub report : Vec<u8>, // digest of the signed report
+    pub signatures : BTreeMap<usize, Vec<u8>>, // server id -> signature
+}
+
+impl DeliveryCertificate {
+    pub fn new(statement : DeliveryStatement) -> DeliveryCertificate {
+        let mut signatures = BTreeMap::new();
+        signatures.insert(statement.server_id, statement.signature);
+        DeliveryCertificate {
+            idx : statement.idx,
+            epoch : statement.epoch,
+            report : statement.report,
+            signatures,
+        }
+    }
+
+    pub fn is_about(&self, statement : &DeliveryStatement) -> bool {
+        self.idx == statement.idx && self.epoch == statement.epoch && self.report == statement.report
+    }
+
+    // Returns false if the statement is about another report or the server had already signed
+    pub fn add(&mut self, statement : DeliveryStatement) -> bool {
+        if !self.is_about(&statement) || self.signatures.contains_key(&statement.server_id) {
+            return false;
+        }
+        self.signatures.insert(statement.server_id, statement.signature);
+        true
+    }
+
+    // Servers whose signature checks out, unknown server ids are not counted
+    pub fn signers(&self, public_sign_keys : &[sign::PublicKey]) -> usize {
+        let content = signed_content(self.idx, self.epoch, &self.report);
+        self.signatures.iter().filter(|(server_id, signature)|
+            match public_sign_keys.get(**server_id) {
+                Some(public_key) => verify_signature(signature, &content, public_key),
+                None => false,
+            }
+        ).count()
+    }
+
+    pub fn verify(&self, public_sign_keys : &[sign::PublicKey], necessary_res : usize) -> bool {
+        self.signers(public_sign_keys) > necessary_res
+    }
+
+    // Whether the certificate is about this exact report
+    pub fn certifies(&self, idx : usize, epoch : usize, signed_report : &[u8]) -> bool {
+        self.idx == idx && self.epoch == epoch && self.report == digest(signed_report)
+    }
+}
+
+fn signed_content(idx : usize, epoch : usize, report : &[u8]) -> Vec<u8> {
+    serde_json::to_vec(&("delivered", idx, epoch, report)).unwrap()
+}
+
+fn verify_signature(signature : &[u8], content : &[u8], public_key : &sign::PublicKey) -> bool {
+    match sign::Signature::from_slice(signature) {
+        Some(signature) => sign::verify_detached(&signature, content, public_key),
+        None => false,
+    }
+}
+
+// Certificates are kept one JSON per line, like receipts
+pub fn save_certificate(filename : &str, certificate : &DeliveryCertificate) -> Result<()> {
+    let mut line = serde_json::to_vec(certificate)?;
+    line.push(b'\n');
+
+    let mut file = OpenOptions::new().create(true).append(true).open(filename)?;
+    file.write_all(&line)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDX : usize = 7;
+    const EPOCH : usize = 3;
+    const NECESSARY_RES : usize = 2;
+
+    #[test]
+    fn verify_certificate() {
+        let keys : Vec<(sign::PublicKey, sign::SecretKey)> = (0..4).map(|_| sign::gen_keypair()).collect();
+        let public_sign_keys : Vec<sign::PublicKey> = keys.iter().map(|(public_key, _)| *public_key).collect();
+        let statements : Vec<DeliveryStatement> = keys.iter().enumerate()
+            .map(|(server_id, (_, sign_key))| DeliveryStatement::new(server_id, IDX, EPOCH, b"report", sign_key))
+            .collect();
+
+        assert!(statements[1].verify(&public_sign_keys[1]));
+        assert!(!statements[1].verify(&public_sign_keys[2]));
+
+        let mut certificate = DeliveryCertificate::new(statements[0].clone());
+        assert!(certificate.add(statements[1].clone()));
+        assert!(!certificate.add(statements[1].clone()));
+        assert!(!certificate.verify(&public_sign_keys, NECESSARY_RES));
+
+        assert!(!certificate.add(DeliveryStatement::new(2, IDX, EPOCH, b"other report", &keys[2].1)));
+        assert!(certificate.add(statements[2].clone()));
+        assert!(certificate.verify(&public_sign_keys, NECESSARY_RES));
+        assert!(certificate.certifies(IDX, EPOCH, b"report"));
+        assert!(!certificate.certifies(IDX, EPOCH, b"other report"));
+
+        // A signature under another server id does not count
+        let mut forged = certificate.clone();
+        let signature = forged.signatures.remove(&2).unwrap();
+        forged.signatures.insert(3, signature);
+        assert!(!forged.verify(&public_sign_keys, NECESSARY_RES));
+
+        let mut changed = certificate;
+        changed.epoch = EPOCH + 1;
+        assert_eq!(changed.signers(&public_sign_keys), 0);
+    }
+}
@@ -0,0 +1,107 @@
+use std::fmt;
+
+use crate::report::timestamp_now;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    Future,
+    Open, // the current epoch
+    Closing, // past, but still within the grace window
+    Sealed,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Phase::Future => write!(f, "not open yet"),
+            Phase::Open => write!(f, "open"),
+            Phase::Closing => write!(f, "closing"),
+            Phase::Sealed => write!(f, "sealed"),
+        }
+    }
+}
+
+// Epoch e runs from start + e * duration, its reports are taken until grace more epochs went by
+#[derive(Debug, Clone, Copy)]
+pub struct EpochClock {
+    start : u64, // seconds since the unix epoch
+    duration : u64, // seconds
+    grace : usize, // epochs
+}
+
+impl EpochClock {
+    pub fn new(start : u64, duration : u64, grace : usize) -> EpochClock {
+        EpochClock {
+            start,
+            duration : duration.max(1),
+            grace,
+        }
+    }
+
+    // Seconds since the unix epoch at which the epoch opens
+    pub fn start_of(&self, epoch : usize) -> u64 {
+        self.start + epoch as u64 * self.duration
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.current_at(timestamp_now())
+    }
+
+    fn current_at(&self, now : u64) -> Option<usize> {
+        if now < self.start {
+            return None;
+        }
+        Some(((now - self.start) / self.duration) as usize)
+    }
+
+    pub fn phase(&self, epoch : usize) -> Phase {
+        self.phase_at(epoch, timestamp_now())
+    }
+
+    fn phase_at(&self, epoch : usize, now : u64) -> Phase {
+        match self.current_at(now) {
+            Some(current) if epoch == current => Phase::Open,
+            Some(current) if epoch > current => Phase::Future,
+            Some(current) if current - epoch <= self.grace => Phase::Closing,
+            Some(_) => Phase::Sealed,
+            None => Phase::Future,
+        }
+    }
+
+    pub fn accepts(&self, epoch : usize) -> bool {
+        matches!(self.phase(epoch), Phase::Open | Phase::Closing)
+    }
+
+    // Epochs before this one are sealed
+    pub fn sealed_before(&self) -> usize {
+        self.current().map_or(0, |current| current.saturating_sub(self.grace))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START : u64 = 1_000;
+    const DURATION : u64 = 15;
+    const GRACE : usize = 1;
+
+    #[test]
+    fn epoch_phases() {
+        let clock = EpochClock::new(START, DURATION, GRACE);
+        let now = START + 3 * DURATION + 2;
+
+        assert_eq!(None, clock.current_at(START - 1));
+        assert_eq!(Some(0), clock.current_at(START));
+        assert_eq!(Some(3), clock.current_at(now));
+        assert_eq!(Some(3), clock.current_at(clock.start_of(3)));
+        assert_eq!(Some(2), clock.current_at(clock.start_of(3) - 1));
+
+        assert_eq!(Phase::Future, clock.phase_at(0, START - 1));
+        assert_eq!(Phase::Future, clock.phase_at(4, now));
+        assert_eq!(Phase::Open, clock.phase_at(3, now));
+        assert_eq!(Phase::Closing, clock.phase_at(2, now));
+        assert_eq!(Phase::Sealed, clock.phase_at(1, now));
+        assert_eq!(Phase::Future, clock.phase_at(usize::MAX, now));
+    }
+}
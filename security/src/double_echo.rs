@@ -1,3 +1,6 @@
+use std::collections::HashSet;
+
+use serde::de::DeserializeOwned;
 use serde_derive::{Deserialize, Serialize};
 use sodiumoxide::crypto::sign;
 use sodiumoxide::crypto::box_;
@@ -6,30 +9,42 @@ use sodiumoxide::crypto::sealedbox;
 use color_eyre::eyre::Result;
 use eyre::eyre;
 
+// What is being broadcast, each topic is agreed on separately
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Topic {
+    Report, // signed report, sent by the client with id client_id
+    Beacon, // beacon proposal, sent by the server with id client_id
+    Erasure, // erasure request, signed by the client with id client_id
+    Infection, // infection flag of the user with id client_id, signed by an HA operator
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Write{
     pub report : Vec<u8>,
     pub client_id : usize,
     pub epoch : usize,
     echo : bool,
+    pub topic : Topic,
 }
 
 impl Write {
-    pub fn new_echo(report : Vec<u8>, client_id : usize, epoch : usize) -> Write{
+    pub fn new_echo(topic : Topic, report : Vec<u8>, client_id : usize, epoch : usize) -> Write{
         Write{
             report,
             client_id,
             epoch,
             echo : true,
+            topic,
         }
     }
 
-    pub fn new_ready(report : Vec<u8>, client_id : usize, epoch : usize) -> Write{
+    pub fn new_ready(topic : Topic, report : Vec<u8>, client_id : usize, epoch : usize) -> Write{
         Write{
             report,
             client_id,
             epoch,
             echo : false,
+            topic,
         }
     }
 
@@ -118,3 +133,135 @@ pub fn success_echo(
         false
     }
 }
+
+
+/**
+ * Catch up
+ */
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeliveredRequest {
+    pub known : HashSet<(usize, usize)>, // (client id, epoch) already delivered
+}
+
+impl DeliveredRequest {
+    pub fn new(known : HashSet<(usize, usize)>) -> DeliveredRequest {
+        DeliveredRequest {
+            known,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeliveredResponse {
+    pub reports : Vec<(usize, usize, Vec<u8>)>, // client id, epoch, signed report
+}
+
+impl DeliveredResponse {
+    pub fn new(reports : Vec<(usize, usize, Vec<u8>)>) -> DeliveredResponse {
+        DeliveredResponse {
+            reports,
+        }
+    }
+}
+
+pub fn encode_delivered_request(
+    signsk : &sign::SecretKey,
+    theirpk : &box_::PublicKey,
+    delivered : &DeliveredRequest,
+    server_id : usize,
+) -> (Vec<u8>, Vec<u8>, secretbox::Key) {
+
+    let plaintext = serde_json::to_vec(delivered).unwrap();
+    let signtext = sign::sign(&plaintext, signsk);
+
+    let key = secretbox::gen_key();
+    let box_nonce = secretbox::gen_nonce();
+
+    let enc_known = secretbox::seal(&signtext,&box_nonce, &key);
+
+    let info = EchoInfo::new(server_id, key.clone(), box_nonce);
+    let textinfo = serde_json::to_vec(&info).unwrap();
+
+    (sealedbox::seal(&textinfo, theirpk), enc_known, key)
+}
+
+pub fn decode_delivered_request(
+    signpk : &sign::PublicKey,
+    sim_key : &secretbox::Key,
+    cipher_known : &[u8],
+    nonce : &secretbox::Nonce,
+) -> Result<DeliveredRequest> {
+
+    let signed_request = secretbox::open(cipher_known, nonce, sim_key).map_err(|_| eyre!("decode_delivered_request: Unable to open secretbox"))?;
+    let decoded_request = sign::verify(&signed_request, signpk).map_err(|_| eyre!("decode_delivered_request: Unable to verify signature"))?;
+
+    let request = serde_json::from_slice(&decoded_request)?;
+
+    Ok(request)
+}
+
+pub fn encode_delivered_response(
+    key : &secretbox::Key,
+    reports : Vec<(usize, usize, Vec<u8>)>,
+) -> (Vec<u8>, secretbox::Nonce) {
+
+    let nonce = secretbox::gen_nonce();
+
+    let delivered = DeliveredResponse::new(reports);
+    let plaintext = serde_json::to_vec(&delivered).unwrap();
+    (secretbox::seal(&plaintext, &nonce, key), nonce)
+}
+
+pub fn decode_delivered_response(
+    key : &secretbox::Key,
+    nonce : &[u8],
+    cyphertext : &[u8],
+) -> Result<DeliveredResponse> {
+    if let Some(nonce) = secretbox::Nonce::from_slice(nonce) {
+        let decoded_response = secretbox::open(cyphertext, &nonce, key).map_err(|_| eyre!("decode_delivered_response: Unable to open secretbox"))?;
+        let response = serde_json::from_slice(&decoded_response)?;
+        Ok(response)
+    } else {
+        Err(eyre!("Decode of delivered response failed."))
+    }
+}
+
+/**
+ * Server to server messages, sealed and signed the same way whatever they carry
+ */
+pub fn encode_server_message<T : serde::Serialize>(
+    signsk : &sign::SecretKey,
+    theirpk : &box_::PublicKey,
+    message : &T,
+    server_id : usize,
+) -> (Vec<u8>, Vec<u8>, secretbox::Key) {
+
+    let plaintext = serde_json::to_vec(message).unwrap();
+    let signtext = sign::sign(&plaintext, signsk);
+
+    let key = secretbox::gen_key();
+    let box_nonce = secretbox::gen_nonce();
+
+    let enc_message = secretbox::seal(&signtext,&box_nonce, &key);
+
+    let info = EchoInfo::new(server_id, key.clone(), box_nonce);
+    let textinfo = serde_json::to_vec(&info).unwrap();
+
+    (sealedbox::seal(&textinfo, theirpk), enc_message, key)
+}
+
+pub fn decode_server_message<T : DeserializeOwned>(
+    signpk : &sign::PublicKey,
+    sim_key : &secretbox::Key,
+    cipher_message : &[u8],
+    nonce : &secretbox::Nonce,
+) -> Result<T> {
+
+    let signed_message = secretbox::open(cipher_message, nonce, sim_key).map_err(|_| eyre!("decode_server_message: Unable to open secretbox"))?;
+    let decoded_message = sign::verify(&signed_message, signpk).map_err(|_| eyre!("decode_server_message: Unable to verify signature"))?;
+
+    let message = serde_json::from_slice(&decoded_message)?;
+
+    Ok(message)
+}
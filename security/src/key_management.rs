@@ -1,4 +1,4 @@
-use std::{collections::HashMap, io::{Read, Write}};
+use std::{collections::{BTreeMap, HashMap}, fmt, io::{Read, Write}, str::FromStr};
 use std::fs;
 
 use std::fs::File;
@@ -32,20 +32,80 @@ impl ClientKeys {
     pub fn public_key(&self) -> &sign::PublicKey { &self.public_key }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HARole {
+    TrajectoryReader, // individual locations, trajectories, contacts and aggregates
+    AggregateOnly, // heatmaps
+    InfectionFlagger, // flags infected users
+    Admin, // everything, including the blacklist
+}
+
+impl HARole {
+    pub fn all() -> [HARole; 4] {
+        [HARole::TrajectoryReader, HARole::AggregateOnly, HARole::InfectionFlagger, HARole::Admin]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            HARole::TrajectoryReader => "trajectory_reader",
+            HARole::AggregateOnly => "aggregate_only",
+            HARole::InfectionFlagger => "infection_flagger",
+            HARole::Admin => "admin",
+        }
+    }
+
+    // Whether an operator with this role can do what requires `access`
+    pub fn allows(&self, access : HARole) -> bool {
+        match self {
+            HARole::Admin => true,
+            HARole::TrajectoryReader => matches!(access, HARole::TrajectoryReader | HARole::AggregateOnly),
+            role => *role == access,
+        }
+    }
+}
+
+impl fmt::Display for HARole {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:}", self.name())
+    }
+}
+
+impl FromStr for HARole {
+    type Err = eyre::Report;
+
+    fn from_str(name : &str) -> Result<HARole> {
+        HARole::all().iter().copied().find(|role| role.name() == name).ok_or_else(|| eyre!("Unknown HA role '{:}'", name))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HAOperator {
+    pub public_key : sign::PublicKey,
+    pub role : HARole,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct HAClientKeys {
+    operator : usize,
+    role : HARole,
     private_key : sign::SecretKey,
-    client_keys : HashMap<usize, sign::PublicKey>,
+    client_keys : HashMap<usize, sign::PublicKey>, // only for roles that read reports
 }
 
 impl HAClientKeys {
-    fn new(private_key : sign::SecretKey, client_keys : HashMap<usize, sign::PublicKey>,) -> HAClientKeys {
+    fn new(operator : usize, role : HARole, private_key : sign::SecretKey, client_keys : HashMap<usize, sign::PublicKey>,) -> HAClientKeys {
         HAClientKeys {
+            operator,
+            role,
             private_key,
             client_keys,
         }
     }
 
+    #[allow(dead_code)]
+    pub fn operator(&self) -> usize { self.operator }
+    #[allow(dead_code)]
+    pub fn role(&self) -> HARole { self.role }
     #[allow(dead_code)]
     pub fn sign_key(&self) -> &sign::SecretKey { &self.private_key }
     #[allow(dead_code)]
@@ -69,7 +129,7 @@ pub struct ServerPublicKey {
 }
 
 impl ServerPublicKey {
-    fn new(public_keys : Vec<box_::PublicKey>, pub_sign_keys : Vec<sign::PublicKey>,) -> ServerPublicKey {
+    pub fn new(public_keys : Vec<box_::PublicKey>, pub_sign_keys : Vec<sign::PublicKey>,) -> ServerPublicKey {
         ServerPublicKey {
             public_keys,
             pub_sign_keys,
@@ -106,23 +166,23 @@ pub struct ServerKeys {
     sign_key : sign::SecretKey,
     public_key : box_::PublicKey,
     client_keys : HashMap<usize, sign::PublicKey>,
-    ha_public_key : sign::PublicKey,
+    ha_operators : BTreeMap<usize, HAOperator>, // operator id -> key and role
 }
 
 impl ServerKeys{
-    fn new(
+    pub fn new(
         client_keys : HashMap<usize, sign::PublicKey>,
         private_key : box_::SecretKey,
         sign_key : sign::SecretKey,
         public_key : box_::PublicKey,
-        ha_key : sign::PublicKey
+        ha_operators : BTreeMap<usize, HAOperator>,
     ) -> ServerKeys {
         ServerKeys {
             private_key,
             sign_key,
             public_key,
             client_keys,
-            ha_public_key : ha_key,
+            ha_operators,
         }
     }
 
@@ -133,12 +193,17 @@ impl ServerKeys{
     #[allow(dead_code)]
     pub fn public_key(&self) -> &box_::PublicKey { &self.public_key }
     #[allow(dead_code)]
-    pub fn ha_public_key(&self) -> &sign::PublicKey { &self.ha_public_key }
+    pub fn ha_operators(&self) -> &BTreeMap<usize, HAOperator> { &self.ha_operators }
 
     #[allow(dead_code)]
     pub fn client_sign_key(&self, idx : usize) -> Option<&sign::PublicKey> {
         self.client_keys.get(&idx)
     }
+
+    #[allow(dead_code)]
+    pub fn client_sign_keys(&self) -> &HashMap<usize, sign::PublicKey> {
+        &self.client_keys
+    }
 }
 
 pub fn save_keys(n_clients : usize, n_servers : usize, keys_dir : String) -> Result<()> {
@@ -163,7 +228,10 @@ pub fn save_keys(n_clients : usize, n_servers : usize, keys_dir : String) -> Res
         save_client_keys(&keys_dir, idx, c_k)?;
     }
 
+    // Operator 0 is the first admin, others are added later
     let (ha_pk, ha_sk) = sign::gen_keypair();
+    let mut ha_operators = BTreeMap::new();
+    ha_operators.insert(0, HAOperator { public_key : ha_pk, role : HARole::Admin });
 
     for server_idx in 0..n_servers {
 
@@ -181,18 +249,69 @@ pub fn save_keys(n_clients : usize, n_servers : usize, keys_dir : String) -> Res
                 serversk,
                 server_sign,
                 servers_public_keys[server_idx],
-                ha_pk)
+                ha_operators.clone())
             )?;
     }
 
 
-    save_ha_client_keys(&keys_dir, HAClientKeys::new(ha_sk, clients_public_keys))?;
+    save_ha_client_keys(&keys_dir, HAClientKeys::new(0, HARole::Admin, ha_sk, clients_public_keys))?;
     save_servers_public_keys(&keys_dir, ServerPublicKey::new(servers_public_keys, servers_pub_sign_keys))?;
 
     Ok(())
 
 }
 
+fn retrieve_all_server_keys(keys_dir : &str) -> Result<Vec<ServerKeys>> {
+    let n_servers = retrieve_servers_public_keys(keys_dir)?.public_keys().len();
+    (0..n_servers).map(|server_idx| retrieve_server_keys(keys_dir, server_idx)).collect()
+}
+
+// Registers a new operator on every server, servers pick it up when restarted
+pub fn add_ha_operator(keys_dir : &str, role : HARole) -> Result<usize> {
+    let servers = retrieve_all_server_keys(keys_dir)?;
+    let operator = servers.iter().filter_map(|server| server.ha_operators.keys().max()).max().map_or(0, |last| last + 1);
+
+    let (ha_pk, ha_sk) = sign::gen_keypair();
+    let client_keys = match servers.first() {
+        Some(server) if role.allows(HARole::TrajectoryReader) => server.client_keys.clone(),
+        _ => HashMap::new(),
+    };
+
+    for (server_idx, mut server) in servers.into_iter().enumerate() {
+        server.ha_operators.insert(operator, HAOperator { public_key : ha_pk, role });
+        save_server_keys(keys_dir, server_idx, server)?;
+    }
+    save_ha_client_keys(keys_dir, HAClientKeys::new(operator, role, ha_sk, client_keys))?;
+
+    Ok(operator)
+}
+
+pub fn remove_ha_operator(keys_dir : &str, operator : usize) -> Result<()> {
+    let servers = retrieve_all_server_keys(keys_dir)?;
+    let operators = list_ha_operators(keys_dir)?;
+
+    if !operators.iter().any(|(idx, _)| *idx == operator) {
+        return Err(eyre!("Unknown HA operator {:}", operator));
+    }
+    if operators.iter().all(|(idx, role)| *idx == operator || *role != HARole::Admin) {
+        return Err(eyre!("Refusing to remove the last admin operator"));
+    }
+
+    for (server_idx, mut server) in servers.into_iter().enumerate() {
+        server.ha_operators.remove(&operator);
+        save_server_keys(keys_dir, server_idx, server)?;
+    }
+    let _ = fs::remove_file(format!("{:}/ha_client_{:02}.keys", keys_dir, operator));
+    let _ = fs::remove_file(format!("{:}/pass/ha_client_{:02}.keys", keys_dir, operator));
+
+    Ok(())
+}
+
+pub fn list_ha_operators(keys_dir : &str) -> Result<Vec<(usize, HARole)>> {
+    let server = retrieve_server_keys(keys_dir, 0)?;
+    Ok(server.ha_operators.iter().map(|(idx, operator)| (*idx, operator.role)).collect())
+}
+
 fn save_client_keys(keys_dir : &str, idx : usize, client : ClientKeys) -> Result<()> {
     let file = File::create(format!("{:}/client_{:04}.keys", keys_dir, idx))?;
     let pass_file = File::create(format!("{:}/pass/client_{:04}.keys", keys_dir, idx))?;
@@ -240,8 +359,8 @@ fn save_servers_public_keys(keys_dir : &str, server_pub : ServerPublicKey) -> Re
 }
 
 fn save_ha_client_keys(keys_dir : &str, ha_keys : HAClientKeys) -> Result<()> {
-    let file = File::create(format!("{:}/ha_client.keys", keys_dir))?;
-    let pass_file = File::create(format!("{:}/pass/ha_client.keys", keys_dir))?;
+    let file = File::create(format!("{:}/ha_client_{:02}.keys", keys_dir, ha_keys.operator))?;
+    let pass_file = File::create(format!("{:}/pass/ha_client_{:02}.keys", keys_dir, ha_keys.operator))?;
 
     let pass = Pass::new(
         secretbox::gen_key(),
@@ -314,9 +433,9 @@ pub fn retrieve_servers_public_keys(keys_dir : &str) -> Result<ServerPublicKey>
 }
 
 #[allow(dead_code)]
-pub fn retrieve_ha_client_keys(keys_dir : &str) -> Result<HAClientKeys> {
-    let file = File::open(format!("{:}/ha_client.keys", keys_dir))?;
-    let pass_file = File::open(format!("{:}/pass/ha_client.keys", keys_dir))?;
+pub fn retrieve_ha_client_keys(keys_dir : &str, operator : usize) -> Result<HAClientKeys> {
+    let file = File::open(format!("{:}/ha_client_{:02}.keys", keys_dir, operator))?;
+    let pass_file = File::open(format!("{:}/pass/ha_client_{:02}.keys", keys_dir, operator))?;
 
     let reader_pass = BufReader::new(pass_file);
 
@@ -333,4 +452,20 @@ pub fn retrieve_ha_client_keys(keys_dir : &str) -> Result<HAClientKeys> {
                 )? ),
         Err(_) => Err(eyre!("retrieve_client_keys: unhable to decode keys")),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles() {
+        assert!(HARole::Admin.allows(HARole::InfectionFlagger));
+        assert!(HARole::TrajectoryReader.allows(HARole::AggregateOnly));
+        assert!(!HARole::AggregateOnly.allows(HARole::TrajectoryReader));
+        assert!(!HARole::InfectionFlagger.allows(HARole::Admin));
+
+        assert_eq!(HARole::AggregateOnly, "aggregate_only".parse().unwrap());
+        assert!("root".parse::<HARole>().is_err());
+    }
+}
@@ -3,5 +3,13 @@ pub mod report;
 pub mod key_management;
 pub mod status;
 pub mod double_echo;
+pub mod receipt;
+pub mod certificate;
+pub mod merkle;
+pub mod beacon;
+pub mod clock;
+pub mod audit;
 
-pub const DIFICULTY : u128 = u128::max_value() - u128::max_value() / 10; // Increase to 500_000 for a real aplication, Average 500k hashes
\ No newline at end of file
+pub const DIFICULTY : u128 = u128::max_value() - u128::max_value() / 10; // Increase to 500_000 for a real aplication, Average 500k hashes
+
+pub const NONCE_WINDOW : u64 = 300; // Seconds a request stays fresh, after that its nonce can be forgotten
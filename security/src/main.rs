@@ -3,6 +3,8 @@ mod key_management;
 use structopt::StructOpt;
 use color_eyre::eyre::Result;
 
+use key_management::HARole;
+
 #[derive(StructOpt)]
 #[structopt(name = "Security", about = "Creates the key pair")]
 struct Opt {
@@ -15,6 +17,18 @@ struct Opt {
 
     #[structopt(name = "keys", long, default_value = "security/keys")]
     keys_dir : String,
+
+    /// Adds an HA operator with this role (trajectory_reader, aggregate_only, infection_flagger or admin) to the existing keys
+    #[structopt(name = "add-operator", long)]
+    add_operator : Option<HARole>,
+
+    /// Removes an HA operator from the existing keys
+    #[structopt(name = "remove-operator", long)]
+    remove_operator : Option<usize>,
+
+    /// Lists the HA operators of the existing keys
+    #[structopt(name = "list-operators", long)]
+    list_operators : bool,
 }
 
 fn main() -> Result<()> {
@@ -22,5 +36,18 @@ fn main() -> Result<()> {
 
     let opt = Opt::from_args();
 
-    key_management::save_keys(opt.n_clients, opt.n_servers, opt.keys_dir)
-}
\ No newline at end of file
+    if let Some(role) = opt.add_operator {
+        let operator = key_management::add_ha_operator(&opt.keys_dir, role)?;
+        println!("Added HA operator {:} as {:}", operator, role);
+    } else if let Some(operator) = opt.remove_operator {
+        key_management::remove_ha_operator(&opt.keys_dir, operator)?;
+        println!("Removed HA operator {:}", operator);
+    } else if opt.list_operators {
+        for (operator, role) in key_management::list_ha_operators(&opt.keys_dir)? {
+            println!("{:}: {:}", operator, role);
+        }
+    } else {
+        return key_management::save_keys(opt.n_clients, opt.n_servers, opt.keys_dir);
+    }
+    Ok(())
+}
@@ -0,0 +1,226 @@
+use serde_derive::{Deserialize, Serialize};
+use sodiumoxide::crypto::{hash::sha256, sign};
+
+use crate::receipt::digest;
+
+const LEAF : u8 = 0;
+const NODE : u8 = 1;
+
+// Leaves are ordered by user id, so every server builds the same tree from the same reports
+pub fn leaf_hash(idx : usize, signed_report : &[u8]) -> Vec<u8> {
+    let mut content = vec![LEAF];
+    content.extend(serde_json::to_vec(&(idx, digest(signed_report))).unwrap());
+    sha256::hash(&content).0.to_vec()
+}
+
+fn node_hash(left : &[u8], right : &[u8]) -> Vec<u8> {
+    let mut content = vec![NODE];
+    content.extend_from_slice(left);
+    content.extend_from_slice(right);
+    sha256::hash(&content).0.to_vec()
+}
+
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    levels : Vec<Vec<Vec<u8>>>, // leaves first, root last
+}
+
+impl MerkleTree {
+    // A node without a sibling moves up unchanged, it is never paired with itself
+    pub fn new(leaves : Vec<Vec<u8>>) -> MerkleTree {
+        let mut levels = vec![leaves];
+        while levels.last().unwrap().len() > 1 {
+            let next = levels.last().unwrap().chunks(2).map(|pair|
+                match pair {
+                    [left, right] => node_hash(left, right),
+                    [single] => single.clone(),
+                    _ => unreachable!(),
+                }
+            ).collect();
+            levels.push(next);
+        }
+        MerkleTree { levels }
+    }
+
+    pub fn root(&self) -> Vec<u8> {
+        match self.levels.last().unwrap().first() {
+            Some(root) => root.clone(),
+            None => sha256::hash(b"").0.to_vec(), // epoch without reports
+        }
+    }
+
+    pub fn leaves(&self) -> usize { self.levels[0].len() }
+
+    pub fn proof(&self, position : usize) -> Option<InclusionProof> {
+        if position >= self.leaves() {
+            return None;
+        }
+        let mut siblings = vec![];
+        let mut index = position;
+        for level in self.levels.iter().take(self.levels.len() - 1) {
+            if let Some(sibling) = level.get(index ^ 1) {
+                siblings.push(sibling.clone());
+            }
+            index /= 2;
+        }
+        Some(InclusionProof {
+            position,
+            leaves : self.leaves(),
+            siblings,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct InclusionProof {
+    pub position : usize,
+    pub leaves : usize,
+    pub siblings : Vec<Vec<u8>>, // from the leaf up
+}
+
+impl InclusionProof {
+    pub fn root_from(&self, leaf : &[u8]) -> Option<Vec<u8>> {
+        if self.position >= self.leaves {
+            return None;
+        }
+        let mut siblings = self.siblings.iter();
+        let mut hash = leaf.to_vec();
+        let (mut index, mut width) = (self.position, self.leaves);
+        while width > 1 {
+            if index ^ 1 < width {
+                let sibling = siblings.next()?;
+                hash = if index % 2 == 0 { node_hash(&hash, sibling) } else { node_hash(sibling, &hash) };
+            }
+            index /= 2;
+            width = width / 2 + width % 2;
+        }
+        match siblings.next() {
+            None => Some(hash),
+            Some(_) => None,
+        }
+    }
+
+    pub fn verify(&self, leaf : &[u8], root : &[u8]) -> bool {
+        matches!(self.root_from(leaf), Some(hash) if hash == root)
+    }
+}
+
+// What a server committed to for an epoch once it closed
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EpochRoot {
+    pub server_id : usize,
+    pub epoch : usize,
+    pub root : Vec<u8>,
+    pub leaves : usize,
+    pub signature : Vec<u8>,
+}
+
+impl EpochRoot {
+    pub fn new(server_id : usize, epoch : usize, tree : &MerkleTree, sign_key : &sign::SecretKey) -> EpochRoot {
+        let mut root = EpochRoot {
+            server_id,
+            epoch,
+            root : tree.root(),
+            leaves : tree.leaves(),
+            signature : vec![],
+        };
+        root.signature = sign::sign_detached(&root.signed_content(), sign_key).0.to_vec();
+        root
+    }
+
+    fn signed_content(&self) -> Vec<u8> {
+        serde_json::to_vec(&("root", self.server_id, self.epoch, &self.root, self.leaves)).unwrap()
+    }
+
+    pub fn verify(&self, public_key : &sign::PublicKey) -> bool {
+        match sign::Signature::from_slice(&self.signature) {
+            Some(signature) => sign::verify_detached(&signature, &self.signed_content(), public_key),
+            None => false,
+        }
+    }
+
+    // Same reports, whoever signed
+    pub fn agrees_with(&self, other : &EpochRoot) -> bool {
+        self.epoch == other.epoch && self.root == other.root && self.leaves == other.leaves
+    }
+}
+
+// Shows a report is among those the server committed to for its epoch
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Inclusion {
+    pub root : EpochRoot,
+    pub proof : InclusionProof,
+}
+
+impl Inclusion {
+    pub fn verify(&self, public_key : &sign::PublicKey, idx : usize, epoch : usize, signed_report : &[u8]) -> bool {
+        self.root.epoch == epoch
+            && self.proof.leaves == self.root.leaves
+            && self.proof.verify(&leaf_hash(idx, signed_report), &self.root.root)
+            && self.root.verify(public_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER_ID : usize = 1;
+    const EPOCH : usize = 4;
+
+    fn leaves(n : usize) -> Vec<Vec<u8>> {
+        (0..n).map(|idx| leaf_hash(idx, format!("report {:}", idx).as_bytes())).collect()
+    }
+
+    #[test]
+    fn prove_every_leaf() {
+        for n in 1..10 {
+            let tree = MerkleTree::new(leaves(n));
+            for (position, leaf) in leaves(n).iter().enumerate() {
+                let proof = tree.proof(position).unwrap();
+                assert!(proof.verify(leaf, &tree.root()), "leaf {:} of {:}", position, n);
+                assert!(!proof.verify(&leaf_hash(n, b"other"), &tree.root()));
+            }
+            assert!(tree.proof(n).is_none());
+        }
+    }
+
+    #[test]
+    fn reject_moved_proof() {
+        let tree = MerkleTree::new(leaves(5));
+        let leaf = &leaves(5)[2];
+
+        let mut moved = tree.proof(2).unwrap();
+        moved.position = 3;
+        assert!(!moved.verify(leaf, &tree.root()));
+
+        let mut longer = tree.proof(2).unwrap();
+        longer.siblings.push(leaf.clone());
+        assert!(!longer.verify(leaf, &tree.root()));
+
+        let mut shorter = tree.proof(2).unwrap();
+        shorter.siblings.pop();
+        assert!(!shorter.verify(leaf, &tree.root()));
+
+        assert_ne!(MerkleTree::new(vec![]).root(), MerkleTree::new(leaves(1)).root());
+    }
+
+    #[test]
+    fn verify_inclusion() {
+        let (public_key, sign_key) = sign::gen_keypair();
+        let tree = MerkleTree::new(leaves(3));
+        let inclusion = Inclusion {
+            root : EpochRoot::new(SERVER_ID, EPOCH, &tree, &sign_key),
+            proof : tree.proof(1).unwrap(),
+        };
+
+        assert!(inclusion.verify(&public_key, 1, EPOCH, b"report 1"));
+        assert!(!inclusion.verify(&public_key, 1, EPOCH, b"report 2"));
+        assert!(!inclusion.verify(&public_key, 1, EPOCH + 1, b"report 1"));
+        assert!(!inclusion.verify(&sign::gen_keypair().0, 1, EPOCH, b"report 1"));
+
+        let other = EpochRoot::new(SERVER_ID + 1, EPOCH, &tree, &sign::gen_keypair().1);
+        assert!(inclusion.root.agrees_with(&other));
+        assert!(!inclusion.root.agrees_with(&EpochRoot::new(SERVER_ID + 1, EPOCH, &MerkleTree::new(leaves(4)), &sign_key)));
+    }
+}
@@ -3,21 +3,23 @@ use sodiumoxide::crypto::sign::{self, PublicKey, SecretKey};
 use color_eyre::eyre::{Result, Context};
 use eyre::eyre;
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Hash, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Hash, Eq)]
 pub struct Proof {
     idx_req : usize,
     idx_ass : usize,
     epoch : usize,
     loc_ass : (usize,usize),
+    beacon : Vec<u8>, // of the epoch, so the proof can't be signed before it starts
 }
 
 impl Proof {
-    pub fn new(epoch : usize, idx_req : usize, idx_ass : usize, loc_ass : (usize, usize)) -> Proof {
+    pub fn new(epoch : usize, idx_req : usize, idx_ass : usize, loc_ass : (usize, usize), beacon : Vec<u8>) -> Proof {
         Proof {
             idx_req,
             idx_ass,
             epoch,
             loc_ass,
+            beacon,
         }
     }
 
@@ -25,6 +27,7 @@ impl Proof {
     pub fn idx_req(&self) -> usize { self.idx_req }
     pub fn idx_ass(&self) -> usize { self.idx_ass }
     pub fn loc_ass(&self) -> (usize, usize) { self.loc_ass }
+    pub fn beacon(&self) -> &[u8] { &self.beacon }
 }
 
 pub fn sign_proof(oursk : &SecretKey, proof : Proof) -> Vec<u8>{
@@ -51,20 +54,22 @@ mod tests {
     const IDX_REQ : usize = 5;
     const IDX_ASS : usize = 16;
     const LOC_ASS : (usize, usize) = (3, 6);
+    const BEACON : &[u8] = b"beacon";
 
     #[test]
     fn create_proof() {
-        let proof = Proof::new(EPOCH, IDX_REQ, IDX_ASS, LOC_ASS);
+        let proof = Proof::new(EPOCH, IDX_REQ, IDX_ASS, LOC_ASS, BEACON.to_vec());
         assert_eq!(EPOCH, proof.epoch());
         assert_eq!(IDX_REQ, proof.idx_req());
         assert_eq!(IDX_ASS, proof.idx_ass());
         assert_eq!(LOC_ASS, proof.loc_ass());
+        assert_eq!(BEACON, proof.beacon());
     }
 
     #[test]
     fn sign_and_confirm_prood() {
-        let proof = Proof::new(EPOCH, IDX_REQ, IDX_ASS, LOC_ASS);
-        let proof_copy = Proof::new(EPOCH, IDX_REQ, IDX_ASS, LOC_ASS);
+        let proof = Proof::new(EPOCH, IDX_REQ, IDX_ASS, LOC_ASS, BEACON.to_vec());
+        let proof_copy = Proof::new(EPOCH, IDX_REQ, IDX_ASS, LOC_ASS, BEACON.to_vec());
 
         let (pk, sk) = sign::gen_keypair();
 
@@ -77,7 +82,7 @@ mod tests {
 
     #[test]
     fn sign_and_fail_check_proof() {
-        let proof = Proof::new(EPOCH, IDX_REQ, IDX_ASS, LOC_ASS);
+        let proof = Proof::new(EPOCH, IDX_REQ, IDX_ASS, LOC_ASS, BEACON.to_vec());
 
         let (_, sk) = sign::gen_keypair();
         let (bad_pk, _) = sign::gen_keypair();
@@ -0,0 +1,185 @@
+use std::{fs::{self, File, OpenOptions}, io::{BufRead, BufReader, Write}, path::Path};
+
+use serde_derive::{Deserialize, Serialize};
+use sodiumoxide::crypto::{hash::sha256, secretbox, sign};
+use color_eyre::eyre::Result;
+use eyre::eyre;
+
+use crate::report::timestamp_now;
+
+// What a server answered to a request, signed so anyone holding the server's public key can check it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Receipt {
+    pub server_id : usize,
+    pub idx : usize, // user the request was about, or the HA operator who sent the query
+    pub epoch : usize, // epoch the request was about, the first one of a range
+    pub request : Vec<u8>, // digest of the signed request
+    pub response : Vec<u8>, // digest of the answer
+    pub timestamp : u64,
+    pub signature : Vec<u8>,
+}
+
+impl Receipt {
+    pub fn new<T : serde::Serialize>(
+        server_id : usize,
+        idx : usize,
+        epoch : usize,
+        request : &[u8],
+        response : &T,
+        sign_key : &sign::SecretKey,
+    ) -> Receipt {
+        let mut receipt = Receipt {
+            server_id,
+            idx,
+            epoch,
+            request : digest(request),
+            response : digest(&serde_json::to_vec(response).unwrap()),
+            timestamp : timestamp_now(),
+            signature : vec![],
+        };
+        receipt.signature = sign::sign_detached(&receipt.signed_content(), sign_key).0.to_vec();
+        receipt
+    }
+
+    fn signed_content(&self) -> Vec<u8> {
+        serde_json::to_vec(&(self.server_id, self.idx, self.epoch, &self.request, &self.response, self.timestamp)).unwrap()
+    }
+
+    pub fn verify(&self, public_key : &sign::PublicKey) -> bool {
+        match sign::Signature::from_slice(&self.signature) {
+            Some(signature) => sign::verify_detached(&signature, &self.signed_content(), public_key),
+            None => false,
+        }
+    }
+
+    // Whether the receipt is about this exact request and answer
+    pub fn covers<T : serde::Serialize>(&self, request : &[u8], response : &T) -> bool {
+        self.request == digest(request) && self.response == digest(&serde_json::to_vec(response).unwrap())
+    }
+
+    pub fn confirms<T : serde::Serialize>(
+        &self,
+        public_key : &sign::PublicKey,
+        idx : usize,
+        epoch : usize,
+        request : &[u8],
+        response : &T,
+    ) -> bool {
+        self.idx == idx && self.epoch == epoch && self.covers(request, response) && self.verify(public_key)
+    }
+}
+
+// An answer along with the receipt that covers it
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Receipted<T> {
+    pub response : T,
+    pub receipt : Receipt,
+}
+
+impl<T : serde::Serialize> Receipted<T> {
+    pub fn new(
+        server_id : usize,
+        idx : usize,
+        epoch : usize,
+        request : &[u8],
+        response : T,
+        sign_key : &sign::SecretKey,
+    ) -> Receipted<T> {
+        let receipt = Receipt::new(server_id, idx, epoch, request, &response, sign_key);
+        Receipted {
+            response,
+            receipt,
+        }
+    }
+
+    // The signed request already says who and which epochs it was about
+    pub fn confirms(&self, public_key : &sign::PublicKey, request : &[u8]) -> bool {
+        self.receipt.covers(request, &self.response) && self.receipt.verify(public_key)
+    }
+}
+
+// Answer acknowledged by a receipt that only confirms the request was accepted
+pub const ACCEPTED : &str = "accepted";
+
+pub fn digest(bytes : &[u8]) -> Vec<u8> {
+    sha256::hash(bytes).0.to_vec()
+}
+
+pub fn encode_receipt(
+    key : &secretbox::Key,
+    receipt : &Receipt,
+) -> (Vec<u8>, secretbox::Nonce) {
+
+    let nonce = secretbox::gen_nonce();
+
+    let plaintext = serde_json::to_vec(receipt).unwrap();
+    (secretbox::seal(&plaintext, &nonce, key), nonce)
+}
+
+pub fn decode_receipt(
+    key : &secretbox::Key,
+    nonce : &[u8],
+    cyphertext : &[u8],
+) -> Result<Receipt> {
+    if let Some(nonce) = secretbox::Nonce::from_slice(nonce) {
+        let decoded_receipt = secretbox::open(cyphertext, &nonce, key).map_err(|_| eyre!("decode_receipt: Unable to open secretbox"))?;
+        Ok(serde_json::from_slice(&decoded_receipt)?)
+    } else {
+        Err(eyre!("Decode of receipt failed."))
+    }
+}
+
+// Receipts are kept one JSON per line, appended as they arrive
+pub fn save_receipt(filename : &str, receipt : &Receipt) -> Result<()> {
+    let mut line = serde_json::to_vec(receipt)?;
+    line.push(b'\n');
+
+    if let Some(dir) = Path::new(filename).parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(filename)?;
+    file.write_all(&line)?;
+    Ok(())
+}
+
+pub fn retrieve_receipts(filename : &str) -> Result<Vec<Receipt>> {
+    let mut receipts = vec![];
+    for line in BufReader::new(File::open(filename)?).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            receipts.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(receipts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER_ID : usize = 2;
+    const IDX : usize = 7;
+    const EPOCH : usize = 3;
+
+    #[test]
+    fn verify_receipt() {
+        let (public_key, sign_key) = sign::gen_keypair();
+        let receipt = Receipt::new(SERVER_ID, IDX, EPOCH, b"request", &ACCEPTED, &sign_key);
+
+        assert!(receipt.verify(&public_key));
+        assert!(receipt.covers(b"request", &ACCEPTED));
+        assert!(!receipt.covers(b"other request", &ACCEPTED));
+        assert!(!receipt.verify(&sign::gen_keypair().0));
+        assert!(receipt.confirms(&public_key, IDX, EPOCH, b"request", &ACCEPTED));
+        assert!(!receipt.confirms(&public_key, IDX, EPOCH + 1, b"request", &ACCEPTED));
+
+        let mut changed = receipt.clone();
+        changed.epoch = EPOCH + 1;
+        assert!(!changed.verify(&public_key));
+
+        let receipted = Receipted::new(SERVER_ID, IDX, EPOCH, b"request", vec![EPOCH], &sign_key);
+        assert!(receipted.confirms(&public_key, b"request"));
+        assert!(!receipted.confirms(&public_key, b"other request"));
+        assert!(!Receipted { response : vec![EPOCH + 1], receipt : receipted.receipt.clone() }.confirms(&public_key, b"request"));
+    }
+}
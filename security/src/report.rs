@@ -6,9 +6,11 @@ use sodiumoxide::crypto::sealedbox;
 use color_eyre::eyre::Result;
 use eyre::eyre;
 
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use pow::Pow;
 
-use crate::DIFICULTY;
+use crate::{DIFICULTY, NONCE_WINDOW};
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct Report {
@@ -41,6 +43,7 @@ pub struct ReportInfo {
     idx : usize,
     key : secretbox::Key,
     nonce : secretbox::Nonce,
+    timestamp : u64,
 }
 
 impl ReportInfo {
@@ -49,14 +52,31 @@ impl ReportInfo {
             idx,
             key,
             nonce,
+            timestamp : timestamp_now(),
         }
     }
 
     pub fn idx(&self) -> usize { self.idx }
     pub fn key(&self) -> &secretbox::Key { &self.key }
     pub fn nonce(&self) -> &secretbox::Nonce { &self.nonce }
+    pub fn timestamp(&self) -> u64 { self.timestamp }
+
+    // The timestamp travels inside the sealed box so it can't be changed on a replay
+    pub fn is_fresh(&self) -> bool {
+        let now = timestamp_now();
+        self.timestamp + NONCE_WINDOW >= now && self.timestamp <= now + NONCE_WINDOW
+    }
 }
 
+pub fn timestamp_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+
+// Signing is deterministic, so these are the bytes the servers store and certify
+pub fn sign_report(signsk : &sign::SecretKey, report : &Report) -> Vec<u8> {
+    sign::sign(&serde_json::to_vec(report).unwrap(), signsk)
+}
 
 pub fn encode_report(
     signsk : &sign::SecretKey,
@@ -65,8 +85,7 @@ pub fn encode_report(
     idx : usize
 ) -> (Vec<u8>, Vec<u8>, secretbox::Key, Vec<u8>) {
 
-    let plaintext = serde_json::to_vec(report).unwrap();
-    let signtext = sign::sign(&plaintext, signsk);
+    let signtext = sign_report(signsk, report);
 
     let key = secretbox::gen_key();
     let box_nonce = secretbox::gen_nonce();
@@ -207,6 +226,20 @@ mod tests {
         assert!(dec_report.is_err());
     }
 
+    #[test]
+    fn fresh_report_info() {
+        let info = ReportInfo::new(IDX_REQ, secretbox::gen_key(), secretbox::gen_nonce());
+
+        assert!(info.is_fresh());
+
+        let old_info = ReportInfo {
+            timestamp : timestamp_now() - NONCE_WINDOW - 1,
+            ..info
+        };
+
+        assert!(!old_info.is_fresh());
+    }
+
     #[test]
     fn encode_decode_report_info_fail() {
         let idxs_ass : Vec<usize> = vec![1, 3, 7];
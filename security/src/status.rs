@@ -1,16 +1,16 @@
 use std::collections::HashSet;
 
 use pow::Pow;
+use serde::de::DeserializeOwned;
 use serde_derive::{Deserialize, Serialize};
 use sodiumoxide::crypto::sign;
 use sodiumoxide::crypto::box_;
 use sodiumoxide::crypto::secretbox;
 use sodiumoxide::crypto::sealedbox;
 use color_eyre::eyre::Result;
-use crate::{DIFICULTY, proof::{self, Proof}, report::ReportInfo};
+use crate::{DIFICULTY, beacon::EpochBeacon, certificate::DeliveryCertificate, merkle::{EpochRoot, Inclusion}, proof, receipt::Receipt, report::{self, ReportInfo}};
 use eyre::eyre;
 
-
 pub fn decode_info(
     oursk : &box_::SecretKey,
     ourpk : &box_::PublicKey,
@@ -23,110 +23,123 @@ pub fn decode_info(
     Ok(info)
 }
 
-/**
- * Obtain Location
+/*
+ * Requests and Responses
  */
 
-#[derive(Debug,Serialize,Deserialize)]
-pub struct LocationReportRequest {
-    idx : usize,
-    epoch : usize,
-}
-
-impl LocationReportRequest {
-    pub fn new(idx : usize, epoch : usize) -> LocationReportRequest {
-        LocationReportRequest {
-            idx,
-            epoch,
-        }
-    }
-
-    pub fn epoch(&self) -> usize { self.epoch }
-    pub fn idx(&self) -> usize { self.idx }
+// Signing is deterministic, so these are also the bytes the receipt of the request is about
+pub fn sign_request<T : serde::Serialize>(signsk : &sign::SecretKey, request : &T) -> Vec<u8> {
+    sign::sign(&serde_json::to_vec(request).unwrap(), signsk)
 }
 
-
-#[derive(Debug,Serialize,Deserialize)]
-pub struct LocationReportResponse {
-    pub report : Vec<u8>,
-}
-
-impl LocationReportResponse {
-    pub fn new(report : Vec<u8>) -> LocationReportResponse {
-        LocationReportResponse {
-            report,
-        }
-    }
-}
-
-pub fn encode_location_report(
+// Requests are signed by their author and sealed with a fresh key, which only the server can learn from the info
+pub fn encode_request<T : serde::Serialize>(
     signsk : &sign::SecretKey,
     theirpk : &box_::PublicKey,
-    loc_report : &LocationReportRequest,
+    request : &T,
     idx : usize
 ) -> (Vec<u8>, Vec<u8>, secretbox::Key, Vec<u8>) {
 
-    let plaintext = serde_json::to_vec(loc_report).unwrap();
-    let signtext = sign::sign(&plaintext, signsk);
+    let signtext = sign_request(signsk, request);
 
     let key = secretbox::gen_key();
     let box_nonce = secretbox::gen_nonce();
 
-    let enc_report = secretbox::seal(&signtext,&box_nonce, &key);
+    let enc_request = secretbox::seal(&signtext,&box_nonce, &key);
 
     let info = ReportInfo::new(idx, key.clone(), box_nonce);
     let textinfo = serde_json::to_vec(&info).unwrap();
 
-    let encoded_textinfo =sealedbox::seal(&textinfo, theirpk);
+    let encoded_textinfo = sealedbox::seal(&textinfo, theirpk);
 
     let pw = Pow::prove_work(&encoded_textinfo, DIFICULTY).unwrap();
     let vec_pw  = serde_json::to_vec(&pw).unwrap();
 
-    (encoded_textinfo, enc_report, key, vec_pw)
+    (encoded_textinfo, enc_request, key, vec_pw)
 }
 
-pub fn decode_loc_report(
+// Along with the request comes its signed plaintext, for the receipt
+pub fn decode_request<T : DeserializeOwned>(
     signpk : &sign::PublicKey,
     sim_key : &secretbox::Key,
-    cipherreport : &Vec<u8>,
+    cipherrequest : &[u8],
     nonce : &secretbox::Nonce,
-) -> Result<LocationReportRequest> {
+) -> Result<(T, Vec<u8>)> {
 
-    let decoded_report = secretbox::open(cipherreport, nonce, sim_key).map_err(|_| eyre!("decode_loc_report: Unable to open secretbox"))?;
-    let report = sign::verify(&decoded_report,signpk).map_err(|_| eyre!("decode_loc_report: Unable to verify signature"))?;
+    let decoded_request = secretbox::open(cipherrequest, nonce, sim_key).map_err(|_| eyre!("decode_request: Unable to open secretbox"))?;
+    let request = sign::verify(&decoded_request,signpk).map_err(|_| eyre!("decode_request: Unable to verify signature"))?;
 
-    let report = serde_json::from_slice(&report)?;
-
-    Ok(report)
+    Ok((serde_json::from_slice(&request)?, decoded_request))
 }
 
-//
-
-pub fn encode_loc_response(
+pub fn encode_response<T : serde::Serialize>(
     key : &secretbox::Key,
-    report : Vec<u8>,
+    response : &T,
 ) -> (Vec<u8>, secretbox::Nonce) {
 
     let nonce = secretbox::gen_nonce();
 
-    let loc = LocationReportResponse::new(report);
-    let plaintext = serde_json::to_vec(&loc).unwrap();
+    let plaintext = serde_json::to_vec(response).unwrap();
     (secretbox::seal(&plaintext, &nonce, key), nonce)
 }
 
-pub fn decode_response_location(
+pub fn decode_response<T : DeserializeOwned>(
     key : &secretbox::Key,
-    nonce :&Vec<u8>,
-    cyphertext : &Vec<u8>,
-) -> Result<LocationReportResponse> {
+    nonce : &[u8],
+    cyphertext : &[u8],
+) -> Result<T> {
     if let Some(nonce) = secretbox::Nonce::from_slice(nonce) {
-        let decoded_response = secretbox::open(cyphertext, &nonce, key).map_err(|_| eyre!("decode_response_location: Unable to open secretbox"))?;
-        let response = serde_json::from_slice(&decoded_response)?;
-        Ok(response)
+        let decoded_response = secretbox::open(cyphertext, &nonce, key).map_err(|_| eyre!("decode_response: Unable to open secretbox"))?;
+        Ok(serde_json::from_slice(&decoded_response)?)
     } else {
-        Err(eyre!("Decode of location response failed."))
+        Err(eyre!("decode_response: Invalid nonce"))
+    }
+}
+
+/**
+ * Obtain Location
+ */
+
+#[derive(Debug,Serialize,Deserialize)]
+pub struct LocationReportRequest {
+    idx : usize,
+    epoch : usize,
+}
+
+impl LocationReportRequest {
+    pub fn new(idx : usize, epoch : usize) -> LocationReportRequest {
+        LocationReportRequest {
+            idx,
+            epoch,
+        }
     }
+
+    pub fn epoch(&self) -> usize { self.epoch }
+    pub fn idx(&self) -> usize { self.idx }
 }
+
+// The receipt covers (report, disputed)
+#[derive(Debug,Serialize,Deserialize)]
+pub struct LocationReportResponse {
+    pub report : Vec<u8>,
+    pub disputed : bool,
+    pub receipt : Receipt,
+    pub certificate : Option<DeliveryCertificate>, // none until enough servers said they delivered
+    pub inclusion : Option<Inclusion>, // none until the epoch closes
+}
+
+impl LocationReportResponse {
+    pub fn new(report : Vec<u8>, disputed : bool, receipt : Receipt, certificate : Option<DeliveryCertificate>, inclusion : Option<Inclusion>) -> LocationReportResponse {
+        LocationReportResponse {
+            report,
+            disputed,
+            receipt,
+            certificate,
+            inclusion,
+        }
+    }
+}
+
 /**
  * Obtain Users at Location
  */
@@ -149,198 +162,559 @@ impl UsersAtLocationRequest {
     pub fn epoch(&self) -> usize { self.epoch }
 }
 
-
 #[derive(Debug,Serialize,Deserialize)]
 pub struct UsersAtLocationResponse {
     pub idxs_reports : Vec<(usize, Vec<u8>)>,
+    pub disputed : Vec<usize>,
 }
 
 impl UsersAtLocationResponse {
-    pub fn new(idxs_reports : Vec<(usize, Vec<u8>)>) -> UsersAtLocationResponse {
+    pub fn new(idxs_reports : Vec<(usize, Vec<u8>)>, disputed : Vec<usize>) -> UsersAtLocationResponse {
         UsersAtLocationResponse {
             idxs_reports ,
+            disputed,
         }
     }
 }
 
-pub fn encode_users_at_location_report(
-    signsk : &sign::SecretKey,
-    theirpk : &box_::PublicKey,
-    users_at_loc : &UsersAtLocationRequest,
-    idx : usize
-) -> (Vec<u8>, Vec<u8>, secretbox::Key, Vec<u8>) {
+/**
+ * Obtain Users in Region
+ */
 
-    let plaintext = serde_json::to_vec(users_at_loc).unwrap();
-    let signtext = sign::sign(&plaintext, signsk);
+#[derive(Debug,Serialize,Deserialize)]
+pub struct UsersInRegionRequest {
+    epochs : (usize, usize), // inclusive
+    from : (usize, usize),
+    to : (usize, usize),
+}
 
-    let key = secretbox::gen_key();
-    let box_nonce = secretbox::gen_nonce();
+impl UsersInRegionRequest {
+    pub fn new(epochs : (usize, usize), from : (usize, usize), to : (usize, usize)) -> UsersInRegionRequest {
+        UsersInRegionRequest {
+            epochs,
+            from,
+            to,
+        }
+    }
 
-    let enc_report = secretbox::seal(&signtext,&box_nonce, &key);
+    pub fn epochs(&self) -> (usize, usize) { self.epochs }
+    pub fn from(&self) -> (usize, usize) { self.from }
+    pub fn to(&self) -> (usize, usize) { self.to }
 
-    let info = ReportInfo::new(idx, key.clone(), box_nonce);
-    let textinfo = serde_json::to_vec(&info).unwrap();
+    pub fn contains(&self, epoch : usize, (x, y) : (usize, usize)) -> bool {
+        self.epochs.0 <= epoch && epoch <= self.epochs.1
+            && self.from.0 <= x && x <= self.to.0
+            && self.from.1 <= y && y <= self.to.1
+    }
+}
 
-    let encoded_textinfo = sealedbox::seal(&textinfo, theirpk);
+#[derive(Debug,Serialize,Deserialize)]
+pub struct UsersInRegionResponse {
+    pub reports : Vec<(usize, usize, Vec<u8>)>, // epoch, user id, signed report
+    pub disputed : Vec<(usize, usize)>, // epoch, user id
+}
 
-    let pw = Pow::prove_work(&encoded_textinfo, DIFICULTY).unwrap();
-    let vec_pw  = serde_json::to_vec(&pw).unwrap();
+impl UsersInRegionResponse {
+    pub fn new(reports : Vec<(usize, usize, Vec<u8>)>, disputed : Vec<(usize, usize)>) -> UsersInRegionResponse {
+        UsersInRegionResponse {
+            reports,
+            disputed,
+        }
+    }
+}
+
+/**
+ * Obtain Trajectory
+ */
 
-    (encoded_textinfo, enc_report, key, vec_pw)
+#[derive(Debug,Serialize,Deserialize)]
+pub struct TrajectoryRequest {
+    idx : usize,
+    epochs : (usize, usize), // inclusive
 }
 
-pub fn decode_users_at_loc_report(
-    signpk : &sign::PublicKey,
-    sim_key : &secretbox::Key,
-    cipherreport : &Vec<u8>,
-    nonce : &secretbox::Nonce,
-) -> Result<UsersAtLocationRequest> {
+impl TrajectoryRequest {
+    pub fn new(idx : usize, epochs : (usize, usize)) -> TrajectoryRequest {
+        TrajectoryRequest {
+            idx,
+            epochs,
+        }
+    }
 
-    let decoded_report = secretbox::open(cipherreport, nonce, sim_key).map_err(|_| eyre!("decode_users_at_loc_report: Unable to open secretbox"))?;
-    let report = sign::verify(&decoded_report,signpk).map_err(|_| eyre!("decode_users_at_loc: Unable to verify signature"))?;
+    pub fn idx(&self) -> usize { self.idx }
+    pub fn epochs(&self) -> (usize, usize) { self.epochs }
+}
 
-    let report = serde_json::from_slice(&report)?;
+#[derive(Debug,Serialize,Deserialize)]
+pub struct TrajectoryResponse {
+    pub reports : Vec<(usize, Vec<u8>)>, // epoch, signed report
+    pub disputed : Vec<usize>, // epochs
+}
 
-    Ok(report)
+impl TrajectoryResponse {
+    pub fn new(reports : Vec<(usize, Vec<u8>)>, disputed : Vec<usize>) -> TrajectoryResponse {
+        TrajectoryResponse {
+            reports,
+            disputed,
+        }
+    }
 }
 
+/**
+ * Obtain Contacts
+ */
 
+#[derive(Debug,Serialize,Deserialize)]
+pub struct ContactsRequest {
+    idx : usize,
+    epochs : (usize, usize), // inclusive
+}
 
-pub fn encode_users_at_loc_response(
-    key : &secretbox::Key,
-    idxs_reports : Vec<(usize, Vec<u8>)>,
-) -> (Vec<u8>, secretbox::Nonce) {
+impl ContactsRequest {
+    pub fn new(idx : usize, epochs : (usize, usize)) -> ContactsRequest {
+        ContactsRequest {
+            idx,
+            epochs,
+        }
+    }
 
-    let nonce = secretbox::gen_nonce();
+    pub fn idx(&self) -> usize { self.idx }
+    pub fn epochs(&self) -> (usize, usize) { self.epochs }
+}
 
-    let loc = UsersAtLocationResponse::new(idxs_reports);
-    let plaintext = serde_json::to_vec(&loc).unwrap();
-    (secretbox::seal(&plaintext, &nonce, key), nonce)
+#[derive(Debug,Serialize,Deserialize,PartialEq)]
+pub struct ContactsAtEpoch {
+    pub epoch : usize,
+    pub report : Vec<u8>, // signed report of the user
+    pub contacts : Vec<(usize, Vec<u8>)>, // contact id, signed report
 }
 
+#[derive(Debug,Serialize,Deserialize)]
+pub struct ContactsResponse {
+    pub contacts : Vec<ContactsAtEpoch>,
+}
 
-pub fn decode_users_at_loc_response(
-    key : &secretbox::Key,
-    nonce :&Vec<u8>,
-    cyphertext : &Vec<u8>,
-) -> Result<UsersAtLocationResponse> {
-    if let Some(nonce) = secretbox::Nonce::from_slice(nonce) {
-        let decoded_response = secretbox::open(cyphertext, &nonce, key).map_err(|_| eyre!("decode_users_at_loc_response: Unable to open secretbox"))?;
-        let response = serde_json::from_slice(&decoded_response)?;
-        Ok(response)
-    } else {
-        Err(eyre!("Decode of users at location response failed."))
+impl ContactsResponse {
+    pub fn new(contacts : Vec<ContactsAtEpoch>) -> ContactsResponse {
+        ContactsResponse {
+            contacts,
+        }
     }
 }
 
 /**
- * Request My Proofs
+ * Obtain Exposure Chains
  */
 
+#[derive(Debug,Serialize,Deserialize)]
+pub struct ExposureRequest {
+    idx : usize,
+    epochs : (usize, usize), // inclusive
+    hops : usize,
+}
+
+impl ExposureRequest {
+    pub fn new(idx : usize, epochs : (usize, usize), hops : usize) -> ExposureRequest {
+        ExposureRequest {
+            idx,
+            epochs,
+            hops,
+        }
+    }
+
+    pub fn idx(&self) -> usize { self.idx }
+    pub fn epochs(&self) -> (usize, usize) { self.epochs }
+    pub fn hops(&self) -> usize { self.hops }
+}
+
+// One contact of a chain: `from` was next to `to` during `epoch`
+#[derive(Debug,Clone,Serialize,Deserialize,PartialEq,Eq)]
+pub struct ExposureLink {
+    pub from : usize,
+    pub to : usize,
+    pub epoch : usize,
+    pub from_cell : (usize, usize),
+    pub to_cell : (usize, usize),
+}
 
 #[derive(Debug,Serialize,Deserialize)]
-pub struct MyProofsRequest {
-    pub epochs : HashSet<usize>,
+pub struct ExposureResponse {
+    pub chains : Vec<Vec<ExposureLink>>,
 }
 
-impl MyProofsRequest {
-    pub fn new(epochs : HashSet<usize>) -> MyProofsRequest {
-        MyProofsRequest {
+impl ExposureResponse {
+    pub fn new(chains : Vec<Vec<ExposureLink>>) -> ExposureResponse {
+        ExposureResponse {
+            chains,
+        }
+    }
+}
+
+/**
+ * Flag Infected
+ */
+
+#[derive(Debug,Serialize,Deserialize)]
+pub struct InfectionRequest {
+    idx : usize,
+    epoch : usize, // infected from this epoch on
+}
+
+impl InfectionRequest {
+    pub fn new(idx : usize, epoch : usize) -> InfectionRequest {
+        InfectionRequest {
+            idx,
+            epoch,
+        }
+    }
+
+    pub fn idx(&self) -> usize { self.idx }
+    pub fn epoch(&self) -> usize { self.epoch }
+}
+
+/**
+ * Check Exposure
+ */
+
+// Only the number of epochs is sent, the window ends at the current epoch on the server's clock
+#[derive(Debug,Serialize,Deserialize)]
+pub struct CheckExposureRequest {
+    last_epochs : usize,
+}
+
+impl CheckExposureRequest {
+    pub fn new(last_epochs : usize) -> CheckExposureRequest {
+        CheckExposureRequest {
+            last_epochs,
+        }
+    }
+
+    pub fn last_epochs(&self) -> usize { self.last_epochs }
+}
+
+// The only look-backs a user may check, so the epoch of a contact can't be narrowed down window by window
+pub const EXPOSURE_WINDOWS : [usize; 3] = [7, 14, 28];
+
+// Who the flagged user was and where the contact happened is never sent
+#[derive(Debug,Serialize,Deserialize)]
+pub struct CheckExposureResponse {
+    pub exposed : bool,
+}
+
+impl CheckExposureResponse {
+    pub fn new(exposed : bool) -> CheckExposureResponse {
+        CheckExposureResponse {
+            exposed,
+        }
+    }
+}
+
+/**
+ * Obtain Heatmap
+ */
+
+#[derive(Debug,Serialize,Deserialize)]
+pub struct HeatmapRequest {
+    epochs : (usize, usize), // inclusive
+    k : usize, // cells with fewer users are suppressed
+    epsilon : Option<f64>, // privacy budget of the noise, none for exact counts
+}
+
+impl HeatmapRequest {
+    pub fn new(epochs : (usize, usize), k : usize, epsilon : Option<f64>) -> HeatmapRequest {
+        HeatmapRequest {
             epochs,
+            k,
+            epsilon,
         }
     }
+
+    pub fn epochs(&self) -> (usize, usize) { self.epochs }
+    pub fn k(&self) -> usize { self.k }
+    pub fn epsilon(&self) -> Option<f64> { self.epsilon }
 }
 
+// Noise is drawn once per epochs and epsilon, so only a few epsilons are served to keep it from being averaged out
+pub const HEATMAP_EPSILONS : [f64; 3] = [0.1, 0.5, 1.0];
+
 #[derive(Debug,Serialize,Deserialize)]
-pub struct MyProofsResponse {
-    pub proofs : Vec<Vec<u8>>
+pub struct HeatmapResponse {
+    pub cells : Vec<((usize, usize), usize)>, // only the cells that were not suppressed
 }
 
-impl MyProofsResponse {
-    pub fn new(proofs : Vec<Vec<u8>>) -> MyProofsResponse {
-        MyProofsResponse {
-            proofs
+impl HeatmapResponse {
+    pub fn new(cells : Vec<((usize, usize), usize)>) -> HeatmapResponse {
+        HeatmapResponse {
+            cells,
         }
     }
 }
 
-pub fn encode_my_proofs_request(
-    signsk : &sign::SecretKey,
-    theirpk : &box_::PublicKey,
-    my_proofs : &MyProofsRequest,
-    idx : usize
-) -> (Vec<u8>, Vec<u8>, secretbox::Key, Vec<u8>) {
+/**
+ * Obtain Roots
+ */
 
-    let plaintext = serde_json::to_vec(my_proofs).unwrap();
-    let signtext = sign::sign(&plaintext, signsk);
+#[derive(Debug,Serialize,Deserialize)]
+pub struct RootsRequest {
+    epoch : usize,
+}
 
-    let key = secretbox::gen_key();
-    let box_nonce = secretbox::gen_nonce();
+impl RootsRequest {
+    pub fn new(epoch : usize) -> RootsRequest {
+        RootsRequest {
+            epoch,
+        }
+    }
 
-    let enc_epochs = secretbox::seal(&signtext,&box_nonce, &key);
+    pub fn epoch(&self) -> usize { self.epoch }
+}
 
-    let info = ReportInfo::new(idx, key.clone(), box_nonce);
-    let textinfo = serde_json::to_vec(&info).unwrap();
+// What a server committed to for the epoch and what the others told it they did
+#[derive(Debug,Serialize,Deserialize)]
+pub struct RootsResponse {
+    pub root : Option<EpochRoot>, // none while the epoch is open, or if it took no reports
+    pub peer_roots : Vec<EpochRoot>,
+    pub divergent : Vec<usize>, // servers that committed to other reports than this one
+}
 
-    let encoded_textinfo = sealedbox::seal(&textinfo, theirpk);
+impl RootsResponse {
+    pub fn new(root : Option<EpochRoot>, peer_roots : Vec<EpochRoot>, divergent : Vec<usize>) -> RootsResponse {
+        RootsResponse {
+            root,
+            peer_roots,
+            divergent,
+        }
+    }
+}
 
-    let pw = Pow::prove_work(&encoded_textinfo, DIFICULTY).unwrap();
-    let vec_pw  = serde_json::to_vec(&pw).unwrap();
+/**
+ * Obtain My Accesses
+ */
 
-    (encoded_textinfo, enc_epochs, key, vec_pw)
+#[derive(Debug,Serialize,Deserialize)]
+pub struct MyAccessesRequest {
+    epochs : (usize, usize), // inclusive
 }
 
-pub fn decode_my_proofs_request(
-    signpk : &sign::PublicKey,
-    sim_key : &secretbox::Key,
-    cipherreport : &Vec<u8>,
-    nonce : &secretbox::Nonce,
-) -> Result<MyProofsRequest> {
+impl MyAccessesRequest {
+    pub fn new(epochs : (usize, usize)) -> MyAccessesRequest {
+        MyAccessesRequest {
+            epochs,
+        }
+    }
 
-    let decoded_report = secretbox::open(cipherreport, nonce, sim_key).map_err(|_| eyre!("decode_my_proofs_request: Unable to open secretbox"))?;
-    let report = sign::verify(&decoded_report,signpk).map_err(|_| eyre!("decode_my_proofs: Unable to verify signature"))?;
+    pub fn epochs(&self) -> (usize, usize) { self.epochs }
+}
 
-    let report = serde_json::from_slice(&report)?;
+// One HA query that read the user's data, the query itself is not revealed
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DataAccess {
+    pub timestamp : u64,
+    pub epochs : Vec<usize>,
+}
 
-    Ok(report)
+#[derive(Debug,Serialize,Deserialize)]
+pub struct MyAccessesResponse {
+    pub accesses : Vec<DataAccess>,
 }
 
-//
+impl MyAccessesResponse {
+    pub fn new(accesses : Vec<DataAccess>) -> MyAccessesResponse {
+        MyAccessesResponse {
+            accesses,
+        }
+    }
+}
 
-pub fn encode_my_proofs_response(
-    key : &secretbox::Key,
-    proofs : Vec<Vec<u8>>,
-) -> (Vec<u8>, secretbox::Nonce) {
+/**
+ * Obtain My Data
+ */
 
-    let nonce = secretbox::gen_nonce();
+// Nothing to choose, the user always gets everything the server holds about them
+#[derive(Debug,Default,Serialize,Deserialize)]
+pub struct UserDataRequest {}
 
-    let loc = MyProofsResponse::new(proofs);
-    let plaintext = serde_json::to_vec(&loc).unwrap();
-    (secretbox::seal(&plaintext, &nonce, key), nonce)
+impl UserDataRequest {
+    pub fn new() -> UserDataRequest {
+        UserDataRequest {}
+    }
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct UserData {
+    pub reports : Vec<(usize, Vec<u8>, bool)>, // epoch, signed report, disputed
+    pub proofs_given : Vec<(usize, usize, Vec<u8>)>, // epoch, requester, signed proof
+    pub proofs_received : Vec<(usize, usize, Vec<u8>)>, // epoch, witness, signed proof
+    pub blacklist : Option<BlacklistEntry>,
+    pub infected_from : Option<usize>,
+    pub erased_before : usize, // epochs before this one were erased at the user's request
+}
 
-pub fn decode_my_proofs_response(
-    key : &secretbox::Key,
-    public_key : &sign::PublicKey,
-    nonce :&Vec<u8>,
-    cyphertext : &Vec<u8>,
-) -> Result<Vec<Proof>> {
-    if let Some(nonce) = secretbox::Nonce::from_slice(nonce) {
-        let decoded_response = secretbox::open(cyphertext, &nonce, key).map_err(|_| eyre!("decode_my_proofs_response: Unable to open secretbox"))?;
-        let response : MyProofsResponse = serde_json::from_slice(&decoded_response)?;
-        let mut result = vec![];
-
-        for proof in response.proofs.iter() {
-            if let Ok(x) = proof::verify_proof(public_key, proof) {
-                result.push(x);
-            }
-            else {
-                return Err(eyre!("Decode of users at location response failed."))
-            }
+/**
+ * Obtain Beacon
+ */
+
+#[derive(Debug,Serialize,Deserialize)]
+pub struct BeaconRequest {
+    pub epoch : usize,
+}
+
+impl BeaconRequest {
+    pub fn new(epoch : usize) -> BeaconRequest {
+        BeaconRequest {
+            epoch,
+        }
+    }
+}
+
+// None until the epoch opened and the servers agreed on f_servers + 1 shares
+#[derive(Debug,Serialize,Deserialize)]
+pub struct BeaconResponse {
+    pub beacon : Option<EpochBeacon>,
+}
+
+/**
+ * Request Erasure
+ */
+
+// Signed on its own so servers can pass it on and every server can check it was the user who asked
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ErasureRequest {
+    pub idx : usize,
+    pub before : usize, // epochs before this one are erased
+    pub signature : Vec<u8>,
+}
+
+impl ErasureRequest {
+    pub fn new(idx : usize, before : usize, signsk : &sign::SecretKey) -> ErasureRequest {
+        let signature = sign::sign_detached(&ErasureRequest::signed_content(idx, before), signsk);
+        ErasureRequest {
+            idx,
+            before,
+            signature : signature.0.to_vec(),
+        }
+    }
+
+    fn signed_content(idx : usize, before : usize) -> Vec<u8> {
+        serde_json::to_vec(&("erasure", idx, before)).unwrap()
+    }
+
+    pub fn verify(&self, public_key : &sign::PublicKey) -> bool {
+        match sign::Signature::from_slice(&self.signature) {
+            Some(signature) => sign::verify_detached(&signature, &ErasureRequest::signed_content(self.idx, self.before), public_key),
+            None => false,
+        }
+    }
+}
+
+/**
+ * Request My Proofs
+ */
+
+#[derive(Debug,Serialize,Deserialize)]
+pub struct MyProofsRequest {
+    pub epochs : HashSet<usize>,
+}
+
+impl MyProofsRequest {
+    pub fn new(epochs : HashSet<usize>) -> MyProofsRequest {
+        MyProofsRequest {
+            epochs,
         }
-        Ok(result)
-    } else {
-        Err(eyre!("Decode of users at location response failed."))
     }
 }
+
+#[derive(Debug,Serialize,Deserialize)]
+pub struct MyProofsResponse {
+    pub proofs : Vec<Vec<u8>>
+}
+
+impl MyProofsResponse {
+    pub fn new(proofs : Vec<Vec<u8>>) -> MyProofsResponse {
+        MyProofsResponse {
+            proofs
+        }
+    }
+}
+
+/**
+ * Blacklist
+ */
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BlacklistEntry {
+    pub idx : usize,
+    pub epoch : usize,
+    pub reason : String,
+    pub timestamp : u64,
+    pub reports : (Vec<u8>, Vec<u8>), // the two conflicting signed reports
+}
+
+impl BlacklistEntry {
+    pub fn new(idx : usize, epoch : usize, reason : String, timestamp : u64, reports : (Vec<u8>, Vec<u8>)) -> BlacklistEntry {
+        BlacklistEntry {
+            idx,
+            epoch,
+            reason,
+            timestamp,
+            reports,
+        }
+    }
+
+    // Both reports must be signed by the user, for the same epoch and at different locations
+    pub fn verify(&self, public_key : &sign::PublicKey) -> bool {
+        match (report::verify_report(public_key, &self.reports.0), report::verify_report(public_key, &self.reports.1)) {
+            (Ok(first), Ok(second)) =>
+                first.idx() == self.idx && second.idx() == self.idx
+                && first.epoch() == self.epoch && second.epoch() == self.epoch
+                && first.loc() != second.loc(),
+            _ => false,
+        }
+    }
+}
+
+// Two proofs signed by the same witness placing itself at different locations in the same epoch
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FlaggedWitness {
+    pub idx : usize,
+    pub epoch : usize,
+    pub proofs : (Vec<u8>, Vec<u8>),
+}
+
+impl FlaggedWitness {
+    pub fn new(idx : usize, epoch : usize, proofs : (Vec<u8>, Vec<u8>)) -> FlaggedWitness {
+        FlaggedWitness {
+            idx,
+            epoch,
+            proofs,
+        }
+    }
+
+    pub fn verify(&self, public_key : &sign::PublicKey) -> bool {
+        match (proof::verify_proof(public_key, &self.proofs.0), proof::verify_proof(public_key, &self.proofs.1)) {
+            (Ok(first), Ok(second)) =>
+                first.idx_ass() == self.idx && second.idx_ass() == self.idx
+                && first.epoch() == self.epoch && second.epoch() == self.epoch
+                && first.loc_ass() != second.loc_ass(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum BlacklistRequest {
+    List,
+    Inspect(usize),
+    Clear(usize),
+    Flags(usize), // epochs in which the witness contradicted itself
+    Unflag(usize),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum BlacklistResponse {
+    Listed(Vec<(usize, usize, String, u64)>), // user id, epoch, reason, timestamp
+    Entry(Option<BlacklistEntry>),
+    Cleared(bool),
+    Flags(Vec<FlaggedWitness>),
+}
+
@@ -0,0 +1,83 @@
+// Keys, beacons and reports shared by the tests of the server, the auditor and the exporter
+
+use std::collections::{BTreeMap, HashMap};
+
+use sodiumoxide::crypto::{box_, sign};
+use security::{beacon::BeaconShare, key_management::{ServerKeys, ServerPublicKey}, proof::{Proof, sign_proof}, report::Report};
+
+use crate::storage::Timeline;
+
+pub const FILENAME : &str = "storage/test.txt";
+
+pub type SignKeys = (HashMap<usize, sign::PublicKey>, HashMap<usize, sign::SecretKey>);
+
+// Sign keys of users 0..n
+pub fn keys(n : usize) -> SignKeys {
+    let mut public_keys = HashMap::new();
+    let mut secret_keys = HashMap::new();
+    for idx in 0..n {
+        let (pk, sk) = sign::gen_keypair();
+        public_keys.insert(idx, pk);
+        secret_keys.insert(idx, sk);
+    }
+    (public_keys, secret_keys)
+}
+
+// Keys of n servers knowing the given clients, along with the first server's own
+pub fn server_keys(n : usize, client_keys : HashMap<usize, sign::PublicKey>) -> (ServerKeys, ServerPublicKey) {
+    let (public_key, private_key) = box_::gen_keypair();
+    let (sign_public_key, sign_key) = sign::gen_keypair();
+    let mut public_keys = vec![public_key];
+    let mut sign_public_keys = vec![sign_public_key];
+    for _ in 1..n {
+        public_keys.push(box_::gen_keypair().0);
+        sign_public_keys.push(sign::gen_keypair().0);
+    }
+    (ServerKeys::new(client_keys, private_key, sign_key, public_key, BTreeMap::new()), ServerPublicKey::new(public_keys, sign_public_keys))
+}
+
+// A file of its own in the temporary directory, for tests that save the storage
+pub fn temp_filename(name : &str) -> String {
+    std::env::temp_dir().join(format!("{:}_{:}.txt", name, std::process::id())).to_str().unwrap().to_string()
+}
+
+// Storage that delivered a complete beacon for the epoch, along with the beacon's value
+pub fn beacon_storage(size : usize, epoch : usize, f_servers : usize) -> (Timeline, Vec<u8>) {
+    beacon_storage_at(FILENAME.to_string(), size, epoch, f_servers)
+}
+
+pub fn beacon_storage_at(filename : String, size : usize, epoch : usize, f_servers : usize) -> (Timeline, Vec<u8>) {
+    let storage = Timeline::new(size, filename);
+    for server_id in 0..=f_servers {
+        storage.add_beacon_share(BeaconShare::new(server_id, epoch, &sign::gen_keypair().1));
+    }
+    let beacon = storage.get_beacon_shares(epoch).unwrap();
+    storage.add_agreed_beacon(0, beacon.clone());
+    (storage, beacon.value())
+}
+
+// Proofs signed by each witness for idx's report, placing itself at the given location
+pub fn proofs(
+    secret_keys : &HashMap<usize, sign::SecretKey>,
+    epoch : usize,
+    idx : usize,
+    witnesses : &[(usize, (usize, usize))],
+    beacon : &[u8],
+) -> Vec<(Proof, Vec<u8>)> {
+    witnesses.iter().map(|&(witness, loc_ass)| {
+        let proof = Proof::new(epoch, idx, witness, loc_ass, beacon.to_vec());
+        let signed_proof = sign_proof(&secret_keys[&witness], proof.clone());
+        (proof, signed_proof)
+    }).collect()
+}
+
+pub fn report(
+    secret_keys : &HashMap<usize, sign::SecretKey>,
+    epoch : usize,
+    (idx, loc) : (usize, (usize, usize)),
+    witnesses : &[(usize, (usize, usize))],
+    beacon : &[u8],
+) -> Report {
+    let proofs = proofs(secret_keys, epoch, idx, witnesses, beacon).into_iter().map(|(_, signed_proof)| signed_proof).collect();
+    Report::new(epoch, loc, idx, witnesses.iter().map(|(witness, _)| *witness).collect(), proofs)
+}
@@ -0,0 +1,4 @@
+pub mod server;
+pub mod storage;
+#[cfg(any(test, feature = "fixtures"))]
+pub mod fixtures;
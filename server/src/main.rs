@@ -1,6 +1,3 @@
-mod server;
-mod storage;
-
 use color_eyre::eyre::Result;
 use structopt::StructOpt;
 use tonic::transport::Uri;
@@ -8,6 +5,9 @@ use tonic::transport::Uri;
 use std::{fs, sync::Arc};
 
 use security::key_management::{retrieve_server_keys, retrieve_servers_public_keys};
+use security::clock::EpochClock;
+use server::server::{self as location_server, double_echo_report::DoubleEchoConfig, validator::{ReportValidator, Rule}};
+use server::storage;
 
 #[derive(StructOpt)]
 #[structopt(name = "Server", about = "(Highly) Dependable Location Tracker")]
@@ -30,6 +30,27 @@ struct Opt {
 
     #[structopt(name = "n_servers", long, default_value = "1")]
     n_servers : usize,
+
+    #[structopt(name = "retention", long, help = "Number of epochs kept in storage (keeps everything if absent)")]
+    retention : Option<usize>,
+
+    #[structopt(name = "archive", long, help = "Directory where purged epochs are archived (discarded if absent)")]
+    archive_dir : Option<String>,
+
+    #[structopt(name = "epoch_start", long, help = "Unix time at which epoch 0 starts, the same for every server and client")]
+    epoch_start : u64,
+
+    #[structopt(name = "epoch_duration", long, default_value = "15", help = "Seconds each epoch lasts")]
+    epoch_duration : u64,
+
+    #[structopt(name = "grace", long, default_value = "1", help = "Epochs a past epoch still takes reports before it is sealed")]
+    grace : usize,
+
+    #[structopt(name = "heatmap_min_k", long, default_value = "3", help = "Fewest users a heatmap request may ask to show a cell")]
+    heatmap_min_k : usize,
+
+    #[structopt(name = "rules", long, use_delimiter = true, help = "Report validation rules to apply, comma separated (all if absent)")]
+    rules : Vec<Rule>,
 }
 
 #[tokio::main]
@@ -43,6 +64,9 @@ async fn main() -> Result<()> {
     sodiumoxide::init().expect("Unable to make sodiumoxide thread safe");
 
     fs::create_dir_all(&opt.storage_dir)?;
+    if let Some(archive_dir) = &opt.archive_dir {
+        fs::create_dir_all(archive_dir)?;
+    }
 
     let storage_file = format!("{:}{:}.txt", &opt.storage_dir, opt.server_id);
 
@@ -55,19 +79,35 @@ async fn main() -> Result<()> {
     let server_keys = Arc::new(retrieve_server_keys(&opt.keys_dir, opt.server_id)?);
     let server_pkeys = Arc::new(retrieve_servers_public_keys(&opt.keys_dir)?);
 
+    let audit_file = format!("{:}{:}_audit.log", &opt.storage_dir, opt.server_id);
+    let audit = Arc::new(storage::audit::AuditLog::open(audit_file, opt.server_id, server_keys.sign_key().clone())?);
+
     let f_servers = (opt.n_servers - 1) / 3;
     let necessary_res= f_servers + opt.n_servers / 2;
 
-    server::start_server(
-        opt.server_id,
-        format!("[::1]:500{:02}", opt.server_id),
-        storage,
-        server_keys,
-        opt.f_line,
-        get_servers_url(opt.n_servers, opt.server_id),
+    let clock = EpochClock::new(opt.epoch_start, opt.epoch_duration, opt.grace);
+    let rules = if opt.rules.is_empty() { Rule::all() } else { opt.rules };
+    println!("Validating reports with rules: {:}", rules.iter().map(|rule| rule.name()).collect::<Vec<_>>().join(", "));
+
+    let config = DoubleEchoConfig {
+        server_id : opt.server_id,
+        server_urls : Arc::new(get_servers_url(opt.n_servers, opt.server_id)),
         necessary_res,
         f_servers,
+        server_keys,
         server_pkeys,
+        validator : ReportValidator::new(rules, opt.f_line, f_servers, Some(clock)),
+        retention : opt.retention,
+        archive_dir : opt.archive_dir,
+        clock,
+    };
+
+    location_server::start_server(
+        format!("[::1]:500{:02}", opt.server_id),
+        config,
+        storage,
+        audit,
+        opt.heatmap_min_k,
     ).await?;
 
     Ok(())
@@ -0,0 +1,28 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::time::sleep;
+
+use security::clock::EpochClock;
+
+use super::double_echo_report::DoubleEcho;
+
+const SHARE_INTERVAL : u64 = 1_000; // ms
+
+// Shares this server's part of the beacon of every epoch still taking reports, as soon as it opens
+pub async fn beacon_publisher(
+    double_echo : Arc<DoubleEcho>,
+    clock : EpochClock,
+) {
+    loop {
+        if let Some(current) = clock.current() {
+            for epoch in clock.sealed_before()..=current {
+                match double_echo.publish_share(epoch).await {
+                    Ok(true) => println!("Shared the beacon of epoch {:}", epoch),
+                    Ok(false) => {}
+                    Err(err) => println!("Unable to share the beacon of epoch {:}: {:}", epoch, err),
+                }
+            }
+        }
+        sleep(Duration::from_millis(SHARE_INTERVAL)).await;
+    }
+}
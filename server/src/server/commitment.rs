@@ -0,0 +1,29 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::time::sleep;
+
+use crate::storage::Timeline;
+
+use super::double_echo_report::DoubleEcho;
+
+const SEAL_INTERVAL : u64 = 5_000; // ms
+
+// Seals the epochs past the grace window once caught up with the other servers, committing to the reports they took
+pub async fn epoch_sealer(
+    double_echo : Arc<DoubleEcho>,
+    storage : Arc<Timeline>,
+) {
+    loop {
+        sleep(Duration::from_millis(SEAL_INTERVAL)).await;
+
+        let before = double_echo.sealable_before();
+        if before == 0 || storage.is_closed(before - 1) {
+            continue;
+        }
+
+        match double_echo.close_before(before).await {
+            Ok(closed) => println!("Sealed epochs before {:}, committed to the reports of {:}", before, closed),
+            Err(err) => println!("Unable to seal epochs before {:}: {:}", before, err),
+        }
+    }
+}
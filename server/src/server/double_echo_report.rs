@@ -10,16 +10,36 @@ use eyre::eyre;
 use color_eyre::eyre::Result;
 use sodiumoxide::crypto::{box_, secretbox, sign};
 use tokio::time::sleep;
-use tonic::{Request, Response, Status, transport::Uri};
-use security::{double_echo::{self, Write, success_echo, decode_echo_info, decode_echo_request}, key_management::{ServerKeys, ServerPublicKey}, proof::verify_proof, report::{Report, verify_report}};
+use serde::de::DeserializeOwned;
+use tonic::{Request, Response, Status, transport::{Channel, Uri}};
+use security::{double_echo::{self, EchoInfo, Write, Topic, success_echo, decode_echo_info, decode_echo_request, decode_server_message}, key_management::{HARole, ServerKeys, ServerPublicKey}, report::{Report, verify_report}};
+use security::double_echo::{DeliveredRequest, decode_delivered_request, encode_delivered_response, decode_delivered_response};
+use security::certificate::{DeliveryCertificate, DeliveryStatement};
+use security::merkle::EpochRoot;
+use security::audit::AuditHead;
+use security::beacon::{BeaconShare, EpochBeacon};
+use security::status::{BlacklistEntry, ErasureRequest, InfectionRequest};
 use protos::double_echo_broadcast::{EchoWriteRequest, EchoWriteResponse, double_echo_broadcast_client::DoubleEchoBroadcastClient, double_echo_broadcast_server::{DoubleEchoBroadcast}};
+use protos::double_echo_broadcast::{RequestDeliveredRequest, RequestDeliveredResponse, ReportMisbehaviourRequest, ReportMisbehaviourResponse};
+use protos::double_echo_broadcast::{ShareStatementRequest, ShareStatementResponse};
+use protos::double_echo_broadcast::{ShareRootRequest, ShareRootResponse, ShareBeaconRequest, ShareBeaconResponse};
+use protos::double_echo_broadcast::{ShareAuditHeadRequest, ShareAuditHeadResponse};
 
-use crate::storage::{Timeline, save_storage};
+use crate::storage::{Archive, Timeline, save_storage};
+use super::validator::{Rejection, ReportValidator, Rule};
+use super::retention::erase_archived;
+use security::clock::{EpochClock, Phase};
+
+const SPREAD_ATTEMPTS : usize = 10;
+const CATCH_UP_INTERVAL : u64 = 5_000; // ms
+
+type Votes = HashMap<usize, HashMap<Vec<u8>, HashSet<usize>>>; // epoch -> m -> server id
+type Vouchers = HashMap<(usize, usize), HashMap<Vec<u8>, HashSet<usize>>>; // (client id, epoch) -> signed report -> servers that delivered it
 
 struct Logic {
     n_servers : usize,
-    echos  : RwLock<HashMap<usize, HashMap<Vec<u8>, HashSet<usize>>>>, // client id -> m -> server id
-    readys : RwLock<HashMap<usize, HashMap<Vec<u8>, HashSet<usize>>>>, // client id -> m -> server id
+    echos  : RwLock<HashMap<usize, Votes>>, // client id -> votes
+    readys : RwLock<HashMap<usize, Votes>>, // client id -> votes
     sent_echo  : RwLock<HashMap<usize, HashSet<usize>>>, // client id -> epoch
     sent_ready : RwLock<HashMap<usize, HashSet<usize>>>, // client id -> epoch
     delivered  : RwLock< (
@@ -89,10 +109,11 @@ impl Logic {
         let sender = delivered.1.remove(&client_id);
 
         if start {
-            let mut client_echos = self.echos.write().unwrap();
-            let mut client_readys = self.readys.write().unwrap();
-            client_echos.insert(client_id, HashMap::new());
-            client_readys.insert(client_id, HashMap::new());
+            for votes in [&self.echos, &self.readys].iter() {
+                if let Some(client_votes) = votes.write().unwrap().get_mut(&client_id) {
+                    client_votes.remove(&epoch);
+                }
+            }
         }
         (start, sender)
     }
@@ -129,87 +150,69 @@ impl Logic {
         }
     }
 
-    fn has_echo_message(&self, client_id : usize, message : &Vec<u8>) -> bool{
-        let client_msgs = self.echos.read().unwrap();
-        match client_msgs.get(&client_id) {
-            Some(msgs) => msgs.contains_key(message),
-            None => false,
+    fn purge_before(&self, before : usize) {
+        for sent in [&self.sent_echo, &self.sent_ready].iter() {
+            let mut sent = sent.write().unwrap();
+            for epochs in sent.values_mut() {
+                epochs.retain(|&epoch| epoch >= before);
+            }
+            sent.retain(|_, epochs| !epochs.is_empty());
         }
-    }
 
-    fn add_server_to_echo_msg(&self, client_id : usize, server_id : usize, message : &Vec<u8>) -> usize {
-        let mut client_msgs = self.echos.write().unwrap();
-        match client_msgs.get_mut(&client_id) {
-            Some(msgs) => {
+        // Messages that never gathered enough votes would otherwise stay forever
+        for votes in [&self.echos, &self.readys].iter() {
+            let mut votes = votes.write().unwrap();
+            for client_votes in votes.values_mut() {
+                client_votes.retain(|&epoch, _| epoch >= before);
+            }
+            votes.retain(|_, client_votes| !client_votes.is_empty());
+        }
 
-                for set in msgs.values() {
-                    if set.contains(&server_id) {
-                        return set.len();
-                    }
-                }
+        let mut delivered = self.delivered.write().unwrap();
+        for epochs in delivered.0.values_mut() {
+            epochs.retain(|&epoch| epoch >= before);
+        }
+        delivered.0.retain(|_, epochs| !epochs.is_empty());
+    }
 
-                match msgs.get_mut(message) {
-                    Some(set) => { set.insert(server_id); set.len() }
-                    None => {
-                        let mut y = HashSet::new();
-                        y.insert(server_id);
-                        msgs.insert(message.to_vec(), y);
-                        1
-                    }
-                }
+    fn has_echo_message(&self, client_id : usize, epoch : usize, message : &Vec<u8>) -> bool{
+        has_message(&self.echos, client_id, epoch, message)
+    }
 
-            }
-            None => {
-                let mut x = HashMap::new();
-                let mut y = HashSet::new();
-                y.insert(server_id);
-                x.insert(message.to_vec(), y);
-                client_msgs.insert(client_id, x);
-                1
-            },
-        }
+    fn add_server_to_echo_msg(&self, client_id : usize, epoch : usize, server_id : usize, message : &Vec<u8>) -> usize {
+        add_server_to_msg(&self.echos, client_id, epoch, server_id, message)
     }
 
-    fn has_ready_message(&self, client_id : usize, message : &Vec<u8>) -> bool {
-        let client_msgs = self.readys.read().unwrap();
-        match client_msgs.get(&client_id) {
-            Some(msgs) => msgs.contains_key(message),
-            None => false,
-        }
+    fn has_ready_message(&self, client_id : usize, epoch : usize, message : &Vec<u8>) -> bool {
+        has_message(&self.readys, client_id, epoch, message)
     }
 
-    fn add_server_to_ready_msg(&self, client_id : usize, server_id : usize, message : &Vec<u8>) -> usize {
-        let mut client_msgs = self.readys.write().unwrap();
-        match client_msgs.get_mut(&client_id) {
-            Some(msgs) => {
+    fn add_server_to_ready_msg(&self, client_id : usize, epoch : usize, server_id : usize, message : &Vec<u8>) -> usize {
+        add_server_to_msg(&self.readys, client_id, epoch, server_id, message)
+    }
+}
 
-                for set in msgs.values() {
-                    if set.contains(&server_id) {
-                        return set.len();
-                    }
-                }
+fn has_message(votes : &RwLock<HashMap<usize, Votes>>, client_id : usize, epoch : usize, message : &Vec<u8>) -> bool {
+    match votes.read().unwrap().get(&client_id).and_then(|client_votes| client_votes.get(&epoch)) {
+        Some(msgs) => msgs.contains_key(message),
+        None => false,
+    }
+}
 
-                match msgs.get_mut(message) {
-                    Some(set) => { set.insert(server_id); set.len() }
-                    None => {
-                        let mut y = HashSet::new();
-                        y.insert(server_id);
-                        msgs.insert(message.to_vec(), y);
-                        1
-                    }
-                }
+// Each server votes once per client and epoch, returns how many servers voted for the message
+fn add_server_to_msg(votes : &RwLock<HashMap<usize, Votes>>, client_id : usize, epoch : usize, server_id : usize, message : &Vec<u8>) -> usize {
+    let mut votes = votes.write().unwrap();
+    let msgs = votes.entry(client_id).or_default().entry(epoch).or_default();
 
-            }
-            None => {
-                let mut x = HashMap::new();
-                let mut y = HashSet::new();
-                y.insert(server_id);
-                x.insert(message.to_vec(), y);
-                client_msgs.insert(client_id, x);
-                1
-            },
+    for set in msgs.values() {
+        if set.contains(&server_id) {
+            return set.len();
         }
     }
+
+    let set = msgs.entry(message.to_vec()).or_default();
+    set.insert(server_id);
+    set.len()
 }
 
 pub struct DoubleEcho {
@@ -220,33 +223,52 @@ pub struct DoubleEcho {
     server_keys : Arc<ServerKeys>,
     server_pkeys : Arc<ServerPublicKey>,
     storage : Arc<Timeline>,
-    f_line : usize,
-    logic : Logic
+    validator : ReportValidator,
+    retention : Option<usize>,
+    archive_dir : Option<String>,
+    clock : EpochClock,
+    caught_up_before : RwLock<usize>, // epochs before this one were sealed on the clock when a catch up last completed
+    logic : Logic,
+    beacon_logic : Logic,
+    erasure_logic : Logic,
+    infection_logic : Logic,
+}
+
+// Who the server is, who its peers are and how it judges and keeps reports
+pub struct DoubleEchoConfig {
+    pub server_id : usize,
+    pub server_urls : Arc<Vec<(usize, Uri)>>,
+    pub necessary_res : usize,
+    pub f_servers : usize,
+    pub server_keys : Arc<ServerKeys>,
+    pub server_pkeys : Arc<ServerPublicKey>,
+    pub validator : ReportValidator,
+    pub retention : Option<usize>,
+    pub archive_dir : Option<String>,
+    pub clock : EpochClock,
 }
 
 impl DoubleEcho {
-    pub fn new(
-        server_id : usize,
-        server_urls : Arc<Vec<(usize, Uri)>>,
-        necessary_res : usize,
-        f_servers : usize,
-        server_keys : Arc<ServerKeys>,
-        server_pkeys : Arc<ServerPublicKey>,
-        f_line : usize,
-        storage : Arc<Timeline>
-) -> DoubleEcho {
-        let n_servers = server_urls.len() + 1;
+    pub fn new(config : DoubleEchoConfig, storage : Arc<Timeline>) -> DoubleEcho {
+        let n_servers = config.server_urls.len() + 1;
 
         DoubleEcho {
-            server_id,
-            server_urls,
-            necessary_res,
-            f_servers,
-            server_keys,
-            server_pkeys,
+            server_id : config.server_id,
+            server_urls : config.server_urls,
+            necessary_res : config.necessary_res,
+            f_servers : config.f_servers,
+            server_keys : config.server_keys,
+            server_pkeys : config.server_pkeys,
             storage,
-            f_line,
+            validator : config.validator,
+            retention : config.retention,
+            archive_dir : config.archive_dir,
+            clock : config.clock,
+            caught_up_before : RwLock::new(0),
             logic : Logic::new(n_servers),
+            beacon_logic : Logic::new(n_servers),
+            erasure_logic : Logic::new(n_servers),
+            infection_logic : Logic::new(n_servers),
         }
     }
 
@@ -265,52 +287,51 @@ impl DoubleEcho {
         }
     }
 
-    fn check_valid_location_report(&self, req_idx : usize, report : &Report) -> bool { //signed report
-        if req_idx != report.idx() { return false; }
+    fn check_valid_location_report(&self, req_idx : usize, report : &Report) -> Result<(), Rejection> { //signed report
+        self.validator.validate(self.server_keys.client_sign_keys(), &self.storage, req_idx, report)
+    }
 
-        let (epoch, (pos_x, pos_y)) = (report.epoch(), report.loc());
+    // Report rules a client's submission must pass before being echoed, or even answered
+    pub fn check_report_rules(&self, req_idx : usize, report : &Report, rules : &[Rule]) -> Result<(), Rejection> {
+        self.validator.check_report(&self.storage, req_idx, report, rules)
+    }
 
-        if !self.storage.valid_pos(pos_x, pos_y) {
-            return false;
-        }
+    pub fn server_id(&self) -> usize { self.server_id }
 
-        let ((lower_x, lower_y), (upper_x, upper_y)) = self.storage.valid_neighbour(pos_x, pos_y);
-        let mut counter = 0;
+    pub fn clock(&self) -> &EpochClock { &self.clock }
 
-        for (idx, proof) in report.proofs() {
-            if let Some(sign_key) = self.server_keys.client_sign_key(*idx) {
-                if let Ok(proof) = verify_proof(&sign_key, &proof) {
-                    let (x, y)  = proof.loc_ass();
-                    if lower_x <= x && x <= upper_x
-                        && lower_y <= y && y <= upper_y
-                        && epoch == proof.epoch()
-                        && req_idx == proof.idx_req()
-                        && *idx == proof.idx_ass() {
-                        counter += 1;
-                    }
-                }
-            }
-            if counter > self.f_line {
-                break;
-            }
+    // An epoch is only sealed once a catch up started after the clock sealed it, so its root misses none of the reports delivered elsewhere
+    pub fn sealable_before(&self) -> usize {
+        std::cmp::min(self.clock.sealed_before(), *self.caught_up_before.read().unwrap())
+    }
+
+    fn is_valid_server_id(&self, server_id : usize) -> bool {
+        server_id < self.logic.n_servers
+    }
+
+    fn logic(&self, topic : Topic) -> &Logic {
+        match topic {
+            Topic::Report => &self.logic,
+            Topic::Beacon => &self.beacon_logic,
+            Topic::Erasure => &self.erasure_logic,
+            Topic::Infection => &self.infection_logic,
         }
-        counter > self.f_line
     }
 
-    fn correctly_ass_proofs(&self, report : &Report) -> Vec<(usize, usize, Vec<u8>)> { //signed report
-        let mut proofs = vec![];
-        for (idx, ass_proof) in report.proofs() {
-            if let Some(sign_key) = self.server_keys.client_sign_key(*idx) {
-                if let Ok(p) = verify_proof(&sign_key, &ass_proof) {
-                    proofs.push((*idx, p.epoch(), ass_proof.clone()))
+    // Checks a message before taking part in its broadcast
+    fn check_write(&self, write : &Write) -> Result<()> {
+        match write.topic {
+            Topic::Report => {
+                let report = self.get_report_from_signed(&write.report, write.client_id)?;
+                if write.epoch != report.epoch() {
+                    return Err(eyre!("Not a correct report"));
                 }
+                self.check_valid_location_report(write.client_id, &report).map_err(|rejection| eyre!("{:}", rejection))
             }
+            Topic::Beacon => self.check_beacon_proposal(&write.report, write.client_id, write.epoch).map(|_| ()),
+            Topic::Erasure => self.check_erasure(&write.report, write.client_id, write.epoch).map(|_| ()),
+            Topic::Infection => self.check_infection(&write.report, write.client_id, write.epoch),
         }
-        proofs
-    }
-
-    fn is_valid_server_id(&self, server_id : usize) -> bool {
-        server_id < self.logic.n_servers
     }
 
     // LOGIC
@@ -322,8 +343,8 @@ impl DoubleEcho {
         report : Report,
     ) -> Result<()> {
 
-        if !self.check_valid_location_report(client_id, &report) {
-            return Err(eyre!("Not a valid report"));
+        if let Err(rejection) = self.check_valid_location_report(client_id, &report) {
+            return Err(eyre!("{:}", rejection));
         }
 
         let reciever = match self.logic.has_been_delivered_or_add_notify(client_id, report.epoch()) {
@@ -332,7 +353,7 @@ impl DoubleEcho {
         };
 
         if self.logic.start_echo(client_id, report.epoch()) {
-            self.echo_fase(message, client_id, report.epoch());
+            self.echo_fase(Topic::Report, message, client_id, report.epoch());
         }
 
         match reciever.await {
@@ -349,13 +370,14 @@ impl DoubleEcho {
 
     fn echo_fase(
         &self,
+        topic : Topic,
         message : &Vec<u8>,
         client_id : usize,
         epoch : usize,
     ) {
-        let echo_write = Write::new_echo(message.clone(), client_id, epoch);
+        let echo_write = Write::new_echo(topic, message.clone(), client_id, epoch);
 
-        self.logic.add_server_to_echo_msg(client_id, self.server_id, message);
+        self.logic(topic).add_server_to_echo_msg(client_id, epoch, self.server_id, message);
         tokio::spawn(fase(
             self.server_id,
             echo_write,
@@ -370,13 +392,14 @@ impl DoubleEcho {
 
     fn ready_fase(
         &self,
+        topic : Topic,
         message : &Vec<u8>,
         client_id : usize,
         epoch : usize,
     ) {
-        let ready_write = Write::new_ready(message.clone(), client_id, epoch);
+        let ready_write = Write::new_ready(topic, message.clone(), client_id, epoch);
 
-        self.logic.add_server_to_ready_msg(client_id, self.server_id, message);
+        self.logic(topic).add_server_to_ready_msg(client_id, epoch, self.server_id, message);
         tokio::spawn(fase(
             self.server_id,
             ready_write,
@@ -388,20 +411,568 @@ impl DoubleEcho {
         ));
     }
 
-    async fn deliver(
-        &self,
-        message : &Vec<u8>,
-        client_id : usize,
-    ) -> Result<()> {
-        let report = self.get_report_from_signed(message, client_id)?;
+    async fn store(
+        &self,
+        message : &Vec<u8>,
+        client_id : usize,
+    ) -> Result<()> {
+        let report = self.get_report_from_signed(message, client_id)?;
+        let blacklisted = self.storage.is_blacklisted(client_id);
+
+        match self.storage.add_user_location_at_epoch(report.epoch(), report.loc(), client_id, message.clone()) {
+            Ok(_) => {
+                for witness in self.storage.add_proofs(self.validator.signed_proofs(self.server_keys.client_sign_keys(), &report)) {
+                    println!("Witness {:} vouched for contradictory locations", witness);
+                    self.dispute_supported_by(witness);
+                }
+                // The client may have vouched elsewhere before reporting, and its witnesses may have reported elsewhere
+                let f_line = self.validator.f_line();
+                log_disputed(self.storage.dispute_contradicted_by(client_id, report.epoch(), f_line));
+                log_disputed(self.storage.dispute_unsupported(vec![(report.epoch(), client_id)].into_iter().collect(), f_line));
+            }
+            Err(_) => {
+                if !blacklisted && self.storage.is_blacklisted(client_id) {
+                    self.on_blacklisted(client_id).await?;
+                }
+                return Err(eyre!("Unable to add report"));
+            }
+        }
+        Ok(())
+    }
+
+    async fn deliver(
+        &self,
+        message : &Vec<u8>,
+        client_id : usize,
+        epoch : usize,
+    ) -> Result<()> {
+        // Servers only echo reports the clock accepts, a delivery past that would leave this one's root apart
+        if !self.clock.accepts(epoch) {
+            return Err(eyre!("Epoch {:} is {:}", epoch, self.clock.phase(epoch)));
+        }
+        self.store(message, client_id).await?;
+        self.certify(message, client_id, epoch);
+
+        match save_storage(self.storage.filename(), &self.storage).await {
+            Ok(_) => Ok(()),
+            Err(_) => Err(eyre!("Unable to permanently save information.")),
+        }
+    }
+
+    async fn deliver_topic(
+        &self,
+        topic : Topic,
+        message : &Vec<u8>,
+        client_id : usize,
+        epoch : usize,
+    ) -> Result<()> {
+        match topic {
+            Topic::Report => self.deliver(message, client_id, epoch).await,
+            Topic::Beacon => self.deliver_beacon(message, client_id, epoch).await,
+            Topic::Erasure => self.deliver_erasure(message, client_id, epoch).await,
+            Topic::Infection => self.deliver_infection(message, client_id, epoch).await,
+        }
+    }
+
+    // RETENTION
+
+    pub async fn purge_before(&self, before : usize) -> Result<Archive> {
+        let archive = self.storage.purge_before(before);
+        self.logic.purge_before(before);
+        self.beacon_logic.purge_before(before);
+        self.erasure_logic.purge_before(before);
+        self.infection_logic.purge_before(before);
+
+        if !archive.is_empty() && save_storage(self.storage.filename(), &self.storage).await.is_err() {
+            return Err(eyre!("Unable to permanently save information."));
+        }
+        Ok(archive)
+    }
+
+    // MISBEHAVIOUR
+
+    pub async fn on_blacklisted(&self, client_id : usize) -> Result<()> {
+        self.dispute_supported_by(client_id);
+        self.broadcast_misbehaviour(client_id);
+
+        match save_storage(self.storage.filename(), &self.storage).await {
+            Ok(_) => Ok(()),
+            Err(_) => Err(eyre!("Unable to permanently save information.")),
+        }
+    }
+
+    // The ban or flag is lifted here only, the other servers keep the evidence they were sent
+    pub async fn on_cleared(&self, client_id : usize) -> Result<()> {
+        for (epoch, idx) in self.storage.undispute_supported_by(client_id, self.validator.f_line()) {
+            println!("Report of client {:} at epoch {:} is no longer disputed", idx, epoch);
+        }
+
+        match save_storage(self.storage.filename(), &self.storage).await {
+            Ok(_) => Ok(()),
+            Err(_) => Err(eyre!("Unable to permanently save information.")),
+        }
+    }
+
+    fn dispute_supported_by(&self, witness : usize) {
+        log_disputed(self.storage.dispute_supported_by(witness, self.validator.f_line()));
+    }
+
+    // Sends the message to every other server in the background, retrying those that do not acknowledge it
+    fn share_with_servers<T : ServerMessage>(&self, message : T) {
+        tokio::spawn(spread(
+            self.server_id,
+            message,
+            HashSet::new(),
+            SPREAD_ATTEMPTS,
+            self.server_urls.clone(),
+            self.server_keys.clone(),
+            self.server_pkeys.clone(),
+        ));
+    }
+
+    fn broadcast_misbehaviour(&self, client_id : usize) {
+        if let Some(evidence) = self.storage.get_blacklist_entry(client_id) {
+            self.share_with_servers(evidence);
+        }
+    }
+
+    // Returns true if the evidence was new to this server
+    async fn accept_misbehaviour(&self, evidence : BlacklistEntry) -> Result<bool> {
+        match self.server_keys.client_sign_key(evidence.idx) {
+            Some(key) if evidence.verify(key) => {}
+            _ => return Err(eyre!("Evidence against client {:} does not verify", evidence.idx)),
+        }
+
+        let client_id = evidence.idx;
+        if !self.storage.add_blacklist_entry(evidence) {
+            return Ok(false);
+        }
+        self.dispute_supported_by(client_id);
+
+        match save_storage(self.storage.filename(), &self.storage).await {
+            Ok(_) => Ok(true),
+            Err(_) => Err(eyre!("Unable to permanently save information.")),
+        }
+    }
+
+    // ERASURE
+
+    // Waits until enough servers agreed on the erasure, each one erases once it is delivered
+    pub async fn confirm_erasure(&self, erasure : &ErasureRequest) -> Result<()> {
+        let message = serde_json::to_vec(erasure).unwrap();
+        self.check_erasure(&message, erasure.idx, erasure.before)?;
+
+        let reciever = match self.erasure_logic.has_been_delivered_or_add_notify(erasure.idx, erasure.before) {
+            None => return Ok(()),
+            Some(reciever) => reciever,
+        };
+
+        if self.erasure_logic.start_echo(erasure.idx, erasure.before) {
+            self.echo_fase(Topic::Erasure, &message, erasure.idx, erasure.before);
+        }
+
+        match reciever.await {
+            Ok(0) => Ok(()),
+            _ => Err(eyre!("Failed erasure")),
+        }
+    }
+
+    fn check_erasure(&self, message : &[u8], idx : usize, before : usize) -> Result<ErasureRequest> {
+        let erasure = match serde_json::from_slice::<ErasureRequest>(message) {
+            Ok(erasure) if erasure.idx == idx && erasure.before == before => erasure,
+            _ => return Err(eyre!("Not an erasure request of client {:}", idx)),
+        };
+        match self.server_keys.client_sign_key(erasure.idx) {
+            Some(key) if erasure.verify(key) => {}
+            _ => return Err(eyre!("Erasure request of client {:} does not verify", erasure.idx)),
+        }
+
+        // Same cut as the garbage collector, newer epochs may still be needed
+        let erasable = match (self.retention, self.clock.current()) {
+            (Some(retention), Some(current)) => current.saturating_sub(retention),
+            (Some(_), None) => 0,
+            (None, _) => return Err(eyre!("Every epoch is retained, so none is past the retention period")),
+        };
+        if erasure.before > erasable {
+            return Err(eyre!("Epochs from {:} on are still within the retention period", erasable));
+        }
+        Ok(erasure)
+    }
+
+    async fn deliver_erasure(&self, message : &[u8], idx : usize, before : usize) -> Result<()> {
+        let erasure = self.check_erasure(message, idx, before)?;
+        if !self.storage.erase_user(erasure.idx, erasure.before) {
+            return Ok(()); // Already erased
+        }
+        println!("Erased client {:} before epoch {:}", erasure.idx, erasure.before);
+        if let Some(dir) = &self.archive_dir {
+            erase_archived(self.server_id, dir, erasure.idx, erasure.before)?;
+        }
+
+        match save_storage(self.storage.filename(), &self.storage).await {
+            Ok(_) => Ok(()),
+            Err(_) => Err(eyre!("Unable to permanently save information.")),
+        }
+    }
+
+    // INFECTION
+
+    // Waits until enough servers agreed on the flag, signed is the request as the HA operator signed it
+    pub async fn confirm_infection(&self, idx : usize, epoch : usize, signed : &[u8]) -> Result<()> {
+        self.check_infection(signed, idx, epoch)?;
+
+        let reciever = match self.infection_logic.has_been_delivered_or_add_notify(idx, epoch) {
+            None => return Ok(()),
+            Some(reciever) => reciever,
+        };
+
+        if self.infection_logic.start_echo(idx, epoch) {
+            self.echo_fase(Topic::Infection, &signed.to_vec(), idx, epoch);
+        }
+
+        match reciever.await {
+            Ok(0) => Ok(()),
+            _ => Err(eyre!("Failed flag")),
+        }
+    }
+
+    fn check_infection(&self, signed : &[u8], idx : usize, epoch : usize) -> Result<()> {
+        if self.server_keys.client_sign_key(idx).is_none() {
+            return Err(eyre!("Unknown user {:}", idx));
+        }
+        let flagged = self.server_keys.ha_operators().values()
+            .filter(|operator| operator.role.allows(HARole::InfectionFlagger))
+            .filter_map(|operator| sign::verify(signed, &operator.public_key).ok())
+            .any(|plaintext| matches!(serde_json::from_slice::<InfectionRequest>(&plaintext), Ok(request) if request.idx() == idx && request.epoch() == epoch));
+        if !flagged {
+            return Err(eyre!("Infection flag of user {:} is not signed by an infection flagger", idx));
+        }
+        Ok(())
+    }
+
+    async fn deliver_infection(&self, signed : &[u8], idx : usize, epoch : usize) -> Result<()> {
+        self.check_infection(signed, idx, epoch)?;
+        self.storage.flag_infected(idx, epoch);
+
+        match save_storage(self.storage.filename(), &self.storage).await {
+            Ok(_) => Ok(()),
+            Err(_) => Err(eyre!("Unable to permanently save information.")),
+        }
+    }
+
+    // CERTIFICATES
+
+    // Signs that the report was delivered and lets the other servers know
+    fn certify(
+        &self,
+        message : &[u8],
+        client_id : usize,
+        epoch : usize,
+    ) {
+        let statement = DeliveryStatement::new(self.server_id, client_id, epoch, message, self.server_keys.sign_key());
+        self.storage.add_delivery_statement(statement.clone());
+
+        self.share_with_servers(statement);
+    }
+
+    // Returns true if the statement was new to this server
+    async fn accept_statement(&self, statement : DeliveryStatement) -> Result<bool> {
+        if !self.is_valid_server_id(statement.server_id) || !statement.verify(self.server_pkeys.public_sign_key(statement.server_id)) {
+            return Err(eyre!("Statement of server {:} does not verify", statement.server_id));
+        }
+
+        let (idx, epoch) = (statement.idx, statement.epoch);
+        let was_complete = self.delivery_certificate(idx, epoch).is_some();
+        if !self.storage.add_delivery_statement(statement) {
+            return Ok(false);
+        }
+
+        // Saved once the certificate completes, partial ones go out with the next save
+        if was_complete || self.delivery_certificate(idx, epoch).is_none() {
+            return Ok(true);
+        }
+        match save_storage(self.storage.filename(), &self.storage).await {
+            Ok(_) => Ok(true),
+            Err(_) => Err(eyre!("Unable to permanently save information.")),
+        }
+    }
+
+    pub fn delivery_certificate(&self, client_id : usize, epoch : usize) -> Option<DeliveryCertificate> {
+        self.storage.get_certificate(client_id, epoch, self.necessary_res)
+    }
+
+    // COMMITMENTS
+
+    pub async fn close_before(&self, before : usize) -> Result<usize> {
+        let roots = self.storage.close_before(before, self.server_id, self.server_keys.sign_key());
+        if roots.is_empty() {
+            return Ok(0);
+        }
+        if save_storage(self.storage.filename(), &self.storage).await.is_err() {
+            return Err(eyre!("Unable to permanently save information."));
+        }
+
+        for root in roots.iter() {
+            self.report_divergence(root.epoch);
+            self.share_with_servers(root.clone());
+        }
+        Ok(roots.len())
+    }
+
+    // Returns true if the root was new to this server
+    async fn accept_root(&self, root : EpochRoot) -> Result<bool> {
+        if !self.is_valid_server_id(root.server_id) || !root.verify(self.server_pkeys.public_sign_key(root.server_id)) {
+            return Err(eyre!("Root of server {:} does not verify", root.server_id));
+        }
+
+        let epoch = root.epoch;
+        if !self.storage.add_peer_root(root) {
+            return Ok(false);
+        }
+        self.report_divergence(epoch);
+
+        match save_storage(self.storage.filename(), &self.storage).await {
+            Ok(_) => Ok(true),
+            Err(_) => Err(eyre!("Unable to permanently save information.")),
+        }
+    }
+
+    fn report_divergence(&self, epoch : usize) {
+        for server_id in self.storage.divergent_servers(epoch) {
+            println!("Epoch {:}: server {:} committed to different reports", epoch, server_id);
+        }
+    }
+
+    // AUDIT
+
+    // Every other server keeps the latest head, so the log cannot lose entries from its end unnoticed
+    pub fn publish_audit_head(&self, head : AuditHead) {
+        self.share_with_servers(head);
+    }
+
+    // Returns true if the head was further along than the one kept for its server
+    async fn accept_audit_head(&self, head : AuditHead) -> Result<bool> {
+        if !self.is_valid_server_id(head.server_id) || !head.verify(self.server_pkeys.public_sign_key(head.server_id)) {
+            return Err(eyre!("Audit head of server {:} does not verify", head.server_id));
+        }
+
+        if let Some(kept) = self.storage.get_audit_head(head.server_id) {
+            if kept.seq == head.seq && kept.hash != head.hash {
+                println!("Server {:} gave two different audit entries {:}", head.server_id, head.seq);
+            }
+        }
+        if !self.storage.add_audit_head(head) {
+            return Ok(false);
+        }
+
+        match save_storage(self.storage.filename(), &self.storage).await {
+            Ok(_) => Ok(true),
+            Err(_) => Err(eyre!("Unable to permanently save information.")),
+        }
+    }
+
+    // BEACON
+
+    // Every server releases its share once the epoch opens, never before
+    pub async fn publish_share(&self, epoch : usize) -> Result<bool> {
+        if self.clock.phase(epoch) == Phase::Future || self.storage.has_beacon_share(epoch, self.server_id) {
+            return Ok(false);
+        }
+
+        let share = BeaconShare::new(self.server_id, epoch, self.server_keys.sign_key());
+        if !self.storage.add_beacon_share(share.clone()) {
+            return Ok(false);
+        }
+        if save_storage(self.storage.filename(), &self.storage).await.is_err() {
+            return Err(eyre!("Unable to permanently save information."));
+        }
+
+        self.share_with_servers(share);
+        self.propose_beacon(epoch);
+        Ok(true)
+    }
+
+    // Returns true if the share was new to this server
+    async fn accept_share(&self, share : BeaconShare) -> Result<bool> {
+        if !self.is_valid_server_id(share.server_id) || !share.verify(self.server_pkeys.public_sign_key(share.server_id)) {
+            return Err(eyre!("Beacon share of server {:} does not verify", share.server_id));
+        }
+        // A share sent early is not kept either, or clients could learn the beacon before the epoch
+        if self.clock.phase(share.epoch) == Phase::Future {
+            return Err(eyre!("Epoch {:} is {:}", share.epoch, Phase::Future));
+        }
+
+        let epoch = share.epoch;
+        if !self.storage.add_beacon_share(share) {
+            return Ok(false);
+        }
+        if save_storage(self.storage.filename(), &self.storage).await.is_err() {
+            return Err(eyre!("Unable to permanently save information."));
+        }
+        self.propose_beacon(epoch);
+        Ok(true)
+    }
+
+    // Once f_servers + 1 shares are in, broadcasts them so every server delivers the same proposals
+    fn propose_beacon(&self, epoch : usize) {
+        let proposal = match self.storage.get_beacon_shares(epoch) {
+            Some(shares) if shares.is_complete(self.f_servers) => shares.proposal(self.f_servers),
+            _ => return,
+        };
+        if self.beacon_logic.start_echo(self.server_id, epoch) {
+            self.echo_fase(Topic::Beacon, &serde_json::to_vec(&proposal).unwrap(), self.server_id, epoch);
+        }
+    }
+
+    fn check_beacon_proposal(&self, message : &[u8], server_id : usize, epoch : usize) -> Result<EpochBeacon> {
+        if !self.is_valid_server_id(server_id) {
+            return Err(eyre!("Unable to find server {:} keys", server_id));
+        }
+        match serde_json::from_slice::<EpochBeacon>(message) {
+            Ok(beacon) if beacon.epoch == epoch && beacon.verify(self.server_pkeys.public_sign_keys(), self.f_servers) => Ok(beacon),
+            _ => Err(eyre!("Beacon proposal of server {:} for epoch {:} does not verify", server_id, epoch)),
+        }
+    }
+
+    async fn deliver_beacon(&self, message : &[u8], server_id : usize, epoch : usize) -> Result<()> {
+        let beacon = self.check_beacon_proposal(message, server_id, epoch)?;
+        if !self.storage.add_agreed_beacon(server_id, beacon) {
+            return Ok(());
+        }
+
+        match save_storage(self.storage.filename(), &self.storage).await {
+            Ok(_) => Ok(()),
+            Err(_) => Err(eyre!("Unable to permanently save information.")),
+        }
+    }
+
+    pub fn beacon(&self, epoch : usize) -> Option<EpochBeacon> {
+        if self.clock.phase(epoch) == Phase::Future {
+            return None;
+        }
+        self.storage.get_beacon(epoch).filter(|beacon| beacon.is_complete(self.f_servers))
+    }
+
+    // CATCH UP
+
+    pub async fn catch_up(&self) -> Result<usize> {
+        // No server delivers a report of these epochs any more, whatever the others delivered they hold by now
+        let sealed_before = self.clock.sealed_before();
+        if self.server_urls.is_empty() {
+            self.caught_up(sealed_before);
+            return Ok(0);
+        }
+
+        let request = DeliveredRequest::new(self.storage.get_delivered());
+
+        let mut responses : FuturesUnordered<_> =
+            self.server_urls.iter().map(
+                |(id, url)|
+                    request_delivered(
+                        url,
+                        self.server_id,
+                        &request,
+                        self.server_keys.sign_key(),
+                        *id,
+                        self.server_pkeys.public_key(*id),
+                    )
+            ).collect();
+
+        let mut vouchers : Vouchers = HashMap::new();
+        let mut answered = 0;
+
+        while let Some(res) = responses.next().await {
+            if let Ok((server_id, reports)) = res {
+                answered += 1;
+                for (client_id, epoch, message) in reports {
+                    vouchers.entry((client_id, epoch))
+                        .or_default()
+                        .entry(message)
+                        .or_default()
+                        .insert(server_id);
+                }
+            }
+        }
+
+        if answered <= self.f_servers {
+            return Err(eyre!("Not enough servers answered the catch up ({:}/{:})", answered, self.f_servers + 1));
+        }
+
+        let recovered = self.recover(&vouchers).await?;
+        self.caught_up(sealed_before);
+        Ok(recovered)
+    }
+
+    fn caught_up(&self, sealed_before : usize) {
+        let mut caught_up_before = self.caught_up_before.write().unwrap();
+        *caught_up_before = std::cmp::max(*caught_up_before, sealed_before);
+    }
+
+    // Delivers the reports more than f_servers servers vouched for, returns how many were new
+    async fn recover(&self, vouchers : &Vouchers) -> Result<usize> {
+        let mut recovered = 0;
+        for ((client_id, epoch), messages) in vouchers.iter() {
+            // At least one correct server must have delivered the report
+            for (message, servers) in messages.iter().filter(|(_, servers)| servers.len() > self.f_servers) {
+                match self.get_report_from_signed(message, *client_id) {
+                    Ok(report) => {
+                        // Epochs sealed on the clock are still recovered, they are not closed here before a catch up
+                        if report.epoch() != *epoch || self.clock.phase(*epoch) == Phase::Future {
+                            continue;
+                        }
+                        // Judged without the clock, the reports were accepted in time elsewhere
+                        if let Err(rejection) = self.validator.without_clock().validate(self.server_keys.client_sign_keys(), &self.storage, *client_id, &report) {
+                            println!("Catch up: report of client {:} at epoch {:} vouched by {:?} is not valid: {:}", client_id, epoch, servers, rejection);
+                            continue;
+                        }
+                    }
+                    Err(_) => continue,
+                }
+
+                match self.logic.start_deliver(*client_id, *epoch) {
+                    (false, _) => {} // noop
+                    (true, sender) => {
+                        let stored = self.store(message, *client_id).await;
+                        if stored.is_ok() {
+                            self.certify(message, *client_id, *epoch);
+                            recovered += 1;
+                        }
+                        if let Some(sender) = sender {
+                            let _x = sender.send(if stored.is_ok() { 0 } else { 1 });
+                        }
+                    }
+                }
+                break;
+            }
+        }
+
+        if recovered > 0 && save_storage(self.storage.filename(), &self.storage).await.is_err() {
+            return Err(eyre!("Unable to permanently save information."));
+        }
+        Ok(recovered)
+    }
+}
+
+fn log_disputed(disputed : Vec<(usize, usize)>) { // (epoch, user id)
+    for (epoch, idx) in disputed {
+        println!("Report of client {:} at epoch {:} is now disputed", idx, epoch);
+    }
+}
 
-        match self.storage.add_user_location_at_epoch(report.epoch(), report.loc(), client_id, message.clone()) {
-            Ok(_) => self.storage.add_proofs(self.correctly_ass_proofs(&report)),
-            Err(_) => return Err(eyre!("Unhable to add report")),
-        }
-        match save_storage(self.storage.filename(), &self.storage).await {
-            Ok(_) => Ok(()),
-            Err(_) => Err(eyre!("Unable to permanently save information.")),
+// Keeps catching up, a partition may hide deliveries from a server that never went down
+pub async fn catch_up(double_echo : Arc<DoubleEcho>) {
+    loop {
+        match double_echo.catch_up().await {
+            Ok(recovered) => {
+                if recovered > 0 {
+                    println!("Catch up finished: recovered {:} reports", recovered);
+                }
+                sleep(Duration::from_millis(CATCH_UP_INTERVAL)).await;
+            }
+            Err(err) => {
+                println!("Catch up failed: {:}", err);
+                sleep(Duration::from_millis(1000)).await;
+            }
         }
     }
 }
@@ -463,6 +1034,52 @@ async fn fase(
     }
 }
 
+#[async_recursion]
+async fn spread<T>(
+    server_id : usize,
+    message : T,
+    mut ack : HashSet<usize>,
+    attempts : usize,
+    server_urls : Arc<Vec<(usize, Uri)>>,
+    server_keys : Arc<ServerKeys>,
+    server_pkeys : Arc<ServerPublicKey>,
+) where T : ServerMessage {
+    let mut responses : FuturesUnordered<_> =
+        server_urls.iter().filter(
+            |(id, _)|    !ack.contains(id)
+        ).map(
+            |(id, url)|
+                share(
+                    url,
+                    server_id,
+                    &message,
+                    server_keys.sign_key(),
+                    *id,
+                    server_pkeys.public_key(*id),
+                )
+        ).collect();
+
+    while let Some(res) = responses.next().await {
+        if let Ok(id) = res {
+            ack.insert(id);
+        }
+    }
+    drop(responses);
+
+    if ack.len() < server_urls.len() && attempts > 1 {
+        sleep(Duration::from_millis(1000)).await;
+        spread(
+            server_id,
+            message,
+            ack,
+            attempts - 1,
+            server_urls,
+            server_keys,
+            server_pkeys,
+        ).await
+    }
+}
+
 /*
 CLIENT
 */
@@ -503,6 +1120,147 @@ pub async fn echo(
     }
 }
 
+pub async fn request_delivered(
+    url : &Uri,
+    server_id : usize,
+    delivered : &DeliveredRequest,
+    sign_key : &sign::SecretKey,
+    dest_id : usize,
+    server_key : &box_::PublicKey,
+) -> Result<(usize, Vec<(usize, usize, Vec<u8>)>)> {
+    let (info, known, key) = double_echo::encode_delivered_request(sign_key, server_key, delivered, server_id);
+    let mut client = DoubleEchoBroadcastClient::connect(url.clone()).await?;
+
+    let request =
+        tonic::Request::new( RequestDeliveredRequest{
+            known,
+            info,
+        });
+
+    match client.request_delivered(request).await {
+        Ok(response) => {
+            let response = response.get_ref();
+            match decode_delivered_response(&key, &response.nonce, &response.reports) {
+                Ok(delivered) => Ok((dest_id, delivered.reports)),
+                Err(_) => Err(eyre!("request_delivered unable to validate server response")),
+            }
+        }
+        Err(status) => {
+            Err(eyre!("Request delivered failed with code {:?} and message {:?}.",
+                            status.code(), status.message()))
+        }
+    }
+}
+
+// A message one server sends to all the others, sealed with encode_server_message
+#[tonic::async_trait]
+trait ServerMessage : serde::Serialize + Clone + Send + Sync + 'static {
+    const NAME : &'static str;
+
+    // Calls the matching rpc and returns the nonce and sealed ok of the response
+    async fn send(
+        client : &mut DoubleEchoBroadcastClient<Channel>,
+        info : Vec<u8>,
+        message : Vec<u8>,
+    ) -> Result<(Vec<u8>, Vec<u8>), Status>;
+}
+
+#[tonic::async_trait]
+impl ServerMessage for BlacklistEntry {
+    const NAME : &'static str = "report_misbehaviour";
+
+    async fn send(
+        client : &mut DoubleEchoBroadcastClient<Channel>,
+        info : Vec<u8>,
+        evidence : Vec<u8>,
+    ) -> Result<(Vec<u8>, Vec<u8>), Status> {
+        let response = client.report_misbehaviour(ReportMisbehaviourRequest{ evidence, info }).await?.into_inner();
+        Ok((response.nonce, response.ok))
+    }
+}
+
+#[tonic::async_trait]
+impl ServerMessage for DeliveryStatement {
+    const NAME : &'static str = "share_statement";
+
+    async fn send(
+        client : &mut DoubleEchoBroadcastClient<Channel>,
+        info : Vec<u8>,
+        statement : Vec<u8>,
+    ) -> Result<(Vec<u8>, Vec<u8>), Status> {
+        let response = client.share_statement(ShareStatementRequest{ statement, info }).await?.into_inner();
+        Ok((response.nonce, response.ok))
+    }
+}
+
+#[tonic::async_trait]
+impl ServerMessage for EpochRoot {
+    const NAME : &'static str = "share_root";
+
+    async fn send(
+        client : &mut DoubleEchoBroadcastClient<Channel>,
+        info : Vec<u8>,
+        root : Vec<u8>,
+    ) -> Result<(Vec<u8>, Vec<u8>), Status> {
+        let response = client.share_root(ShareRootRequest{ root, info }).await?.into_inner();
+        Ok((response.nonce, response.ok))
+    }
+}
+
+#[tonic::async_trait]
+impl ServerMessage for AuditHead {
+    const NAME : &'static str = "share_audit_head";
+
+    async fn send(
+        client : &mut DoubleEchoBroadcastClient<Channel>,
+        info : Vec<u8>,
+        head : Vec<u8>,
+    ) -> Result<(Vec<u8>, Vec<u8>), Status> {
+        let response = client.share_audit_head(ShareAuditHeadRequest{ head, info }).await?.into_inner();
+        Ok((response.nonce, response.ok))
+    }
+}
+
+#[tonic::async_trait]
+impl ServerMessage for BeaconShare {
+    const NAME : &'static str = "share_beacon";
+
+    async fn send(
+        client : &mut DoubleEchoBroadcastClient<Channel>,
+        info : Vec<u8>,
+        share : Vec<u8>,
+    ) -> Result<(Vec<u8>, Vec<u8>), Status> {
+        let response = client.share_beacon(ShareBeaconRequest{ share, info }).await?.into_inner();
+        Ok((response.nonce, response.ok))
+    }
+}
+
+async fn share<T : ServerMessage>(
+    url : &Uri,
+    server_id : usize,
+    message : &T,
+    sign_key : &sign::SecretKey,
+    dest_id : usize,
+    server_key : &box_::PublicKey,
+) -> Result<usize> {
+    let (info, message, key) = double_echo::encode_server_message(sign_key, server_key, message, server_id);
+    let mut client = DoubleEchoBroadcastClient::connect(url.clone()).await?;
+
+    match T::send(&mut client, info, message).await {
+        Ok((nonce, ok)) => {
+            if success_echo(&key, &nonce, &ok) {
+                Ok(dest_id)
+            } else {
+                Err(eyre!("{:} unable to validate server response", T::NAME))
+            }
+        }
+        Err(status) => {
+            Err(eyre!("{:} failed with code {:?} and message {:?}.",
+                            T::NAME, status.code(), status.message()))
+        }
+    }
+}
+
 /*
 SERVER
 */
@@ -519,6 +1277,37 @@ impl MyDoubleEchoWrite {
             echo,
         }
     }
+
+    // Opens a message sealed with encode_server_message by one of the other servers, errors are denials
+    fn open<T : DeserializeOwned>(&self, info : &Vec<u8>, sealed : &[u8], what : &str) -> Result<(EchoInfo, T), String> {
+        let info = match decode_echo_info(
+            self.echo.server_keys.private_key(),
+            self.echo.server_keys.public_key(),
+            info) {
+            Ok(info) => info,
+            Err(_) => return Err("Unable to decrypt sealed container".to_string()),
+        };
+
+        if !self.echo.is_valid_server_id(info.server_id) {
+            return Err(format!("Unable to find server {:} keys", info.server_id));
+        };
+
+        match decode_server_message(
+            self.echo.server_pkeys.public_sign_key(info.server_id),
+            &info.key,
+            sealed,
+            &info.nonce,
+        ) {
+            Ok(message) => Ok((info, message)),
+            Err(_) => Err(format!("Unable to decrypt {:}", what)),
+        }
+    }
+}
+
+// Empty reply sealed with the key of the sender, checked with success_echo
+fn acknowledge(info : &EchoInfo) -> (Vec<u8>, Vec<u8>) {
+    let nonce = secretbox::gen_nonce();
+    (nonce.0.to_vec(), secretbox::seal(b"", &nonce, &info.key))
 }
 
 #[tonic::async_trait]
@@ -535,7 +1324,7 @@ impl DoubleEchoBroadcast for MyDoubleEchoWrite {
             &request.info) {
             info
         } else {
-            return Err(Status::permission_denied("Unhable to decrypt sealed container"));
+            return Err(Status::permission_denied("Unable to decrypt sealed container"));
         };
 
         if !self.echo.is_valid_server_id(info.server_id) {
@@ -556,71 +1345,83 @@ impl DoubleEchoBroadcast for MyDoubleEchoWrite {
 
         let message = &write.report;
 
+        // Erasures and infection flags are about past epochs, which may be long sealed
+        if matches!(write.topic, Topic::Report | Topic::Beacon) {
+            if !self.echo.storage.is_retained(write.epoch) {
+                return Err(Status::out_of_range(format!("Epoch {:} is no longer retained", write.epoch)));
+            }
+            match self.echo.clock.phase(write.epoch) {
+                Phase::Open | Phase::Closing => {}
+                phase => return Err(Status::out_of_range(format!("Epoch {:} is {:}", write.epoch, phase))),
+            }
+        }
+
+        let logic = self.echo.logic(write.topic);
+
         if write.is_echo() {
 
-            if !self.echo.logic.has_echo_message(write.client_id, message) {
-                match self.echo.get_report_from_signed(message, write.client_id) {
-                    Ok(report) => {
-                        if !self.echo.check_valid_location_report(write.client_id, &report)
-                            && write.epoch == report.epoch() {
-                            return Err(Status::aborted("Not a correct report"));
-                        }
-                        if self.echo.logic.has_been_delivered(write.client_id, write.epoch) {
-                            let nonce = secretbox::gen_nonce();
-                            return Ok( Response::new( EchoWriteResponse{
-                                nonce : nonce.0.to_vec(),
-                                ok : secretbox::seal(b"", &nonce, &info.key),
-                            }));
-                        }
-                    }
-                    Err(err) => return Err(Status::aborted(err.to_string())),
+            if !logic.has_echo_message(write.client_id, write.epoch, message) {
+                if let Err(err) = self.echo.check_write(&write) {
+                    return Err(Status::aborted(err.to_string()));
+                }
+                if logic.has_been_delivered(write.client_id, write.epoch) {
+                    let nonce = secretbox::gen_nonce();
+                    return Ok( Response::new( EchoWriteResponse{
+                        nonce : nonce.0.to_vec(),
+                        ok : secretbox::seal(b"", &nonce, &info.key),
+                    }));
                 }
             }
 
-            if self.echo.logic.add_server_to_echo_msg(write.client_id, info.server_id, message) > self.echo.necessary_res {
-                if self.echo.logic.start_ready(write.client_id, write.epoch) {
-                    self.echo.ready_fase(message, write.client_id, write.epoch);
+            // Clients send their reports to every server, other messages reach the rest through the echos
+            let relay = match write.topic {
+                Topic::Report => false,
+                Topic::Beacon => info.server_id == write.client_id, // only from the proposing server
+                Topic::Erasure => true, // signed by the client, whoever passed it on
+                Topic::Infection => true, // signed by the HA operator, whoever passed it on
+            };
+            if relay && logic.start_echo(write.client_id, write.epoch) {
+                self.echo.echo_fase(write.topic, message, write.client_id, write.epoch);
+            }
+
+            if logic.add_server_to_echo_msg(write.client_id, write.epoch, info.server_id, message) > self.echo.necessary_res {
+                if logic.start_ready(write.client_id, write.epoch) {
+                    self.echo.ready_fase(write.topic, message, write.client_id, write.epoch);
                 }
             }
 
         } else { // READY
 
-            if !self.echo.logic.has_ready_message(write.client_id, message) {
-                match self.echo.get_report_from_signed(message, write.client_id) {
-                    Ok(report) => {
-                        if !self.echo.check_valid_location_report(write.client_id, &report)
-                            && write.epoch == report.epoch() {
-                            return Err(Status::aborted("Not a correct report"));
-                        }
-                        if self.echo.logic.has_been_delivered(write.client_id, write.epoch) {
-                            let nonce = secretbox::gen_nonce();
-                            return Ok( Response::new( EchoWriteResponse{
-                                nonce : nonce.0.to_vec(),
-                                ok : secretbox::seal(b"", &nonce, &info.key),
-                            }));
-                        }
-                    }
-                    Err(err) => return Err(Status::aborted(err.to_string())),
+            if !logic.has_ready_message(write.client_id, write.epoch, message) {
+                if let Err(err) = self.echo.check_write(&write) {
+                    return Err(Status::aborted(err.to_string()));
+                }
+                if logic.has_been_delivered(write.client_id, write.epoch) {
+                    let nonce = secretbox::gen_nonce();
+                    return Ok( Response::new( EchoWriteResponse{
+                        nonce : nonce.0.to_vec(),
+                        ok : secretbox::seal(b"", &nonce, &info.key),
+                    }));
                 }
             }
 
-            let n = self.echo.logic.add_server_to_ready_msg(write.client_id, info.server_id, message);
+            let n = logic.add_server_to_ready_msg(write.client_id, write.epoch, info.server_id, message);
 
             if n > self.echo.f_servers {
-                if self.echo.logic.start_ready(write.client_id, write.epoch) {
-                    self.echo.ready_fase(message, write.client_id, write.epoch);
+                if logic.start_ready(write.client_id, write.epoch) {
+                    self.echo.ready_fase(write.topic, message, write.client_id, write.epoch);
                 }
             }
             if n > self.echo.necessary_res {
-                match self.echo.logic.start_deliver(write.client_id, write.epoch) {
+                match logic.start_deliver(write.client_id, write.epoch) {
                     (false, _) => {} // noop
                     (true, None) => {
-                        if let Err(err) = self.echo.deliver(message, write.client_id).await {
+                        if let Err(err) = self.echo.deliver_topic(write.topic, message, write.client_id, write.epoch).await {
                             return Err(Status::aborted(err.to_string()));
                         }
                     }
                     (true, Some(sender)) => {
-                        if let Err(err) = self.echo.deliver(message, write.client_id).await {
+                        if let Err(err) = self.echo.deliver_topic(write.topic, message, write.client_id, write.epoch).await {
                             let _x = sender.send(1);
                             return Err(Status::aborted(err.to_string()));
                         } else {
@@ -638,12 +1439,140 @@ impl DoubleEchoBroadcast for MyDoubleEchoWrite {
             ok : secretbox::seal(b"", &nonce, &info.key),
         }))
     }
+
+    async fn request_delivered(
+        &self,
+        request : Request<RequestDeliveredRequest>,
+    ) ->  Result<Response<RequestDeliveredResponse>, Status> {
+        let request = request.get_ref();
+
+        let info = if let Ok(info) = decode_echo_info(
+            self.echo.server_keys.private_key(),
+            self.echo.server_keys.public_key(),
+            &request.info) {
+            info
+        } else {
+            return Err(Status::permission_denied("Unable to decrypt sealed container"));
+        };
+
+        if !self.echo.is_valid_server_id(info.server_id) {
+            return Err(Status::permission_denied(format!("Unable to find server {:} keys", info.server_id)));
+        };
+
+        let delivered = match decode_delivered_request(
+            self.echo.server_pkeys.public_sign_key(info.server_id),
+            &info.key,
+            &request.known,
+            &info.nonce,
+        ) {
+            Ok(delivered) => delivered,
+            Err(_) => return Err(Status::permission_denied("Unable to decrypt delivered request"))
+        };
+
+        let (reports, nonce) = encode_delivered_response(&info.key, self.echo.storage.get_reports_not_in(&delivered.known));
+
+        Ok( Response::new( RequestDeliveredResponse{
+            nonce : nonce.0.to_vec(),
+            reports,
+        }))
+    }
+
+    async fn report_misbehaviour(
+        &self,
+        request : Request<ReportMisbehaviourRequest>,
+    ) ->  Result<Response<ReportMisbehaviourResponse>, Status> {
+        let request = request.get_ref();
+
+        let (info, evidence) = self.open::<BlacklistEntry>(&request.info, &request.evidence, "misbehaviour report").map_err(Status::permission_denied)?;
+
+        let client_id = evidence.idx;
+        match self.echo.accept_misbehaviour(evidence).await {
+            Ok(true) => {
+                println!("Client {:} blacklisted on evidence from server {:}", client_id, info.server_id);
+                // Pass it on in case the reporting server did not reach everyone
+                self.echo.broadcast_misbehaviour(client_id);
+            }
+            Ok(false) => {} // Already known
+            Err(err) => return Err(Status::invalid_argument(err.to_string())),
+        }
+
+        let (nonce, ok) = acknowledge(&info);
+        Ok( Response::new( ReportMisbehaviourResponse{ nonce, ok }))
+    }
+
+    async fn share_statement(
+        &self,
+        request : Request<ShareStatementRequest>,
+    ) ->  Result<Response<ShareStatementResponse>, Status> {
+        let request = request.get_ref();
+
+        let (info, statement) = self.open(&request.info, &request.statement, "statement").map_err(Status::permission_denied)?;
+
+        // No need to pass it on, the signing server sends it to everyone itself
+        if let Err(err) = self.echo.accept_statement(statement).await {
+            return Err(Status::permission_denied(err.to_string()));
+        }
+
+        let (nonce, ok) = acknowledge(&info);
+        Ok( Response::new( ShareStatementResponse{ nonce, ok }))
+    }
+
+    async fn share_root(
+        &self,
+        request : Request<ShareRootRequest>,
+    ) ->  Result<Response<ShareRootResponse>, Status> {
+        let request = request.get_ref();
+
+        let (info, root) = self.open(&request.info, &request.root, "root").map_err(Status::permission_denied)?;
+
+        if let Err(err) = self.echo.accept_root(root).await {
+            return Err(Status::permission_denied(err.to_string()));
+        }
+
+        let (nonce, ok) = acknowledge(&info);
+        Ok( Response::new( ShareRootResponse{ nonce, ok }))
+    }
+
+    async fn share_beacon(
+        &self,
+        request : Request<ShareBeaconRequest>,
+    ) ->  Result<Response<ShareBeaconResponse>, Status> {
+        let request = request.get_ref();
+
+        let (info, share) = self.open(&request.info, &request.share, "beacon share").map_err(Status::permission_denied)?;
+
+        // Early shares are refused, the sender tries again later
+        if let Err(err) = self.echo.accept_share(share).await {
+            return Err(Status::permission_denied(err.to_string()));
+        }
+
+        let (nonce, ok) = acknowledge(&info);
+        Ok( Response::new( ShareBeaconResponse{ nonce, ok }))
+    }
+
+    async fn share_audit_head(
+        &self,
+        request : Request<ShareAuditHeadRequest>,
+    ) ->  Result<Response<ShareAuditHeadResponse>, Status> {
+        let request = request.get_ref();
+
+        let (info, head) = self.open(&request.info, &request.head, "audit head").map_err(Status::permission_denied)?;
+
+        if let Err(err) = self.echo.accept_audit_head(head).await {
+            return Err(Status::permission_denied(err.to_string()));
+        }
+
+        let (nonce, ok) = acknowledge(&info);
+        Ok( Response::new( ShareAuditHeadResponse{ nonce, ok }))
+    }
 }
 
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use security::report::sign_report;
+    use crate::fixtures;
     const N_SERVERS : usize = 5;
     const SERVER_ID : usize = 3;
     const OTHER_SERVER_ID : usize = 1;
@@ -795,13 +1724,36 @@ mod tests {
 
     }
 
+    #[test]
+    fn purge_before() {
+        let logic = Logic::new(N_SERVERS);
+
+        let msg = b"msg".to_vec();
+
+        logic.start_echo(CLIENT_ID, EPOCH);
+        logic.start_ready(CLIENT_ID, OTHER_EPOCH);
+        logic.start_deliver(CLIENT_ID, EPOCH);
+        logic.start_deliver(CLIENT_ID, OTHER_EPOCH);
+        logic.add_server_to_echo_msg(OTHER_CLIENT_ID, EPOCH, SERVER_ID, &msg);
+        logic.add_server_to_ready_msg(OTHER_CLIENT_ID, OTHER_EPOCH, SERVER_ID, &msg);
+
+        logic.purge_before(OTHER_EPOCH);
+
+        assert!(logic.sent_echo.read().unwrap().is_empty());
+        assert!(logic.echos.read().unwrap().is_empty());
+        assert!(logic.has_ready_message(OTHER_CLIENT_ID, OTHER_EPOCH, &msg));
+        assert!(!logic.start_ready(CLIENT_ID, OTHER_EPOCH));
+        assert!(!logic.has_been_delivered(CLIENT_ID, EPOCH));
+        assert!(logic.has_been_delivered(CLIENT_ID, OTHER_EPOCH));
+    }
+
     #[test]
     fn add_server_to_echo_msg() {
         let logic = Logic::new(N_SERVERS);
 
         let msg = b"msg".to_vec();
 
-        assert_eq!(1, logic.add_server_to_echo_msg(CLIENT_ID, SERVER_ID, &msg));
+        assert_eq!(1, logic.add_server_to_echo_msg(CLIENT_ID, EPOCH, SERVER_ID, &msg));
     }
 
     #[test]
@@ -810,8 +1762,8 @@ mod tests {
 
         let msg = b"msg".to_vec();
 
-        assert_eq!(1, logic.add_server_to_echo_msg(CLIENT_ID, SERVER_ID, &msg));
-        assert_eq!(2, logic.add_server_to_echo_msg(CLIENT_ID, OTHER_SERVER_ID, &msg));
+        assert_eq!(1, logic.add_server_to_echo_msg(CLIENT_ID, EPOCH, SERVER_ID, &msg));
+        assert_eq!(2, logic.add_server_to_echo_msg(CLIENT_ID, EPOCH, OTHER_SERVER_ID, &msg));
     }
 
     #[test]
@@ -820,8 +1772,22 @@ mod tests {
 
         let msg = b"msg".to_vec();
 
-        assert_eq!(1, logic.add_server_to_echo_msg(CLIENT_ID, SERVER_ID, &msg));
-        assert_eq!(1, logic.add_server_to_echo_msg(OTHER_CLIENT_ID, OTHER_SERVER_ID, &msg));
+        assert_eq!(1, logic.add_server_to_echo_msg(CLIENT_ID, EPOCH, SERVER_ID, &msg));
+        assert_eq!(1, logic.add_server_to_echo_msg(OTHER_CLIENT_ID, EPOCH, OTHER_SERVER_ID, &msg));
+    }
+
+    #[test]
+    fn add_server_to_echo_msg_off_diff_epochs() {
+        let logic = Logic::new(N_SERVERS);
+
+        let msg = b"msg".to_vec();
+
+        assert_eq!(1, logic.add_server_to_echo_msg(CLIENT_ID, EPOCH, SERVER_ID, &msg));
+        assert_eq!(1, logic.add_server_to_echo_msg(CLIENT_ID, OTHER_EPOCH, SERVER_ID, &msg));
+
+        logic.start_deliver(CLIENT_ID, EPOCH);
+        assert!(!logic.has_echo_message(CLIENT_ID, EPOCH, &msg));
+        assert!(logic.has_echo_message(CLIENT_ID, OTHER_EPOCH, &msg));
     }
 
     #[test]
@@ -830,9 +1796,9 @@ mod tests {
 
         let msg = b"msg".to_vec();
 
-        logic.add_server_to_echo_msg(CLIENT_ID, SERVER_ID, &msg);
+        logic.add_server_to_echo_msg(CLIENT_ID, EPOCH, SERVER_ID, &msg);
 
-        assert!(logic.has_echo_message(CLIENT_ID, &msg));
+        assert!(logic.has_echo_message(CLIENT_ID, EPOCH, &msg));
     }
 
     #[test]
@@ -841,9 +1807,9 @@ mod tests {
 
         let msg = b"msg".to_vec();
 
-        logic.add_server_to_echo_msg(OTHER_CLIENT_ID, SERVER_ID, &msg);
+        logic.add_server_to_echo_msg(OTHER_CLIENT_ID, EPOCH, SERVER_ID, &msg);
 
-        assert!(!logic.has_echo_message(CLIENT_ID, &msg));
+        assert!(!logic.has_echo_message(CLIENT_ID, EPOCH, &msg));
     }
 
     #[test]
@@ -852,7 +1818,7 @@ mod tests {
 
         let msg = b"msg".to_vec();
 
-        assert_eq!(1, logic.add_server_to_ready_msg(CLIENT_ID, SERVER_ID, &msg));
+        assert_eq!(1, logic.add_server_to_ready_msg(CLIENT_ID, EPOCH, SERVER_ID, &msg));
     }
 
     #[test]
@@ -861,8 +1827,8 @@ mod tests {
 
         let msg = b"msg".to_vec();
 
-        assert_eq!(1, logic.add_server_to_ready_msg(CLIENT_ID, SERVER_ID, &msg));
-        assert_eq!(2, logic.add_server_to_ready_msg(CLIENT_ID, OTHER_SERVER_ID, &msg));
+        assert_eq!(1, logic.add_server_to_ready_msg(CLIENT_ID, EPOCH, SERVER_ID, &msg));
+        assert_eq!(2, logic.add_server_to_ready_msg(CLIENT_ID, EPOCH, OTHER_SERVER_ID, &msg));
     }
 
     #[test]
@@ -871,8 +1837,8 @@ mod tests {
 
         let msg = b"msg".to_vec();
 
-        assert_eq!(1, logic.add_server_to_ready_msg(CLIENT_ID, SERVER_ID, &msg));
-        assert_eq!(1, logic.add_server_to_ready_msg(OTHER_CLIENT_ID, OTHER_SERVER_ID, &msg));
+        assert_eq!(1, logic.add_server_to_ready_msg(CLIENT_ID, EPOCH, SERVER_ID, &msg));
+        assert_eq!(1, logic.add_server_to_ready_msg(OTHER_CLIENT_ID, EPOCH, OTHER_SERVER_ID, &msg));
     }
 
     #[test]
@@ -881,9 +1847,9 @@ mod tests {
 
         let msg = b"msg".to_vec();
 
-        logic.add_server_to_ready_msg(CLIENT_ID, SERVER_ID, &msg);
+        logic.add_server_to_ready_msg(CLIENT_ID, EPOCH, SERVER_ID, &msg);
 
-        assert!(logic.has_ready_message(CLIENT_ID, &msg));
+        assert!(logic.has_ready_message(CLIENT_ID, EPOCH, &msg));
     }
 
     #[test]
@@ -892,8 +1858,90 @@ mod tests {
 
         let msg = b"msg".to_vec();
 
-        logic.add_server_to_ready_msg(OTHER_CLIENT_ID, SERVER_ID, &msg);
+        logic.add_server_to_ready_msg(OTHER_CLIENT_ID, EPOCH, SERVER_ID, &msg);
+
+        assert!(!logic.has_ready_message(CLIENT_ID, EPOCH, &msg));
+    }
+
+    // A server back from a crash, which sealed the epochs before EPOCH before going down, with a clock past them all
+    fn restarted(name : &str) -> (DoubleEcho, Arc<Timeline>, HashMap<usize, sign::SecretKey>, Vec<u8>) {
+        let (public_keys, secret_keys) = fixtures::keys(3);
+        let (storage, beacon) = fixtures::beacon_storage_at(fixtures::temp_filename(name), 10, EPOCH, 1);
+        storage.close_before(EPOCH, SERVER_ID, &sign::gen_keypair().1);
+        let storage = Arc::new(storage);
+
+        let clock = EpochClock::new(1, 1, 1);
+        let (server_keys, server_pkeys) = fixtures::server_keys(N_SERVERS, public_keys);
+        let echo = DoubleEcho::new(DoubleEchoConfig {
+            server_id : SERVER_ID,
+            server_urls : Arc::new(vec![]),
+            necessary_res : 2,
+            f_servers : 1,
+            server_keys : Arc::new(server_keys),
+            server_pkeys : Arc::new(server_pkeys),
+            validator : ReportValidator::new(Rule::all(), 1, 1, Some(clock)),
+            retention : None,
+            archive_dir : None,
+            clock,
+        }, storage.clone());
+        (echo, storage, secret_keys, beacon)
+    }
+
+    #[tokio::test]
+    async fn recover_sealed_epoch() {
+        let (echo, storage, secret_keys, beacon) = restarted("recover_sealed_epoch");
+        let report = fixtures::report(&secret_keys, EPOCH, (0, (3, 3)), &[(1, (3, 3)), (2, (4, 4))], &beacon);
+        let message = sign_report(&secret_keys[&0], &report);
+
+        // Nothing is sealed here before catching up, however far the clock went
+        assert_eq!(0, echo.sealable_before());
+
+        let mut vouchers : Vouchers = HashMap::new();
+        vouchers.entry((0, EPOCH)).or_default().insert(message.clone(), vec![1, 2].into_iter().collect());
+        vouchers.entry((1, EPOCH)).or_default().insert(b"report".to_vec(), vec![1].into_iter().collect());
+        assert_eq!(1, echo.recover(&vouchers).await.unwrap());
+        assert_eq!(Some(message), storage.get_user_report_at_epoch(EPOCH, 0));
+
+        assert_eq!(0, echo.catch_up().await.unwrap());
+        assert_eq!(echo.clock().sealed_before(), echo.sealable_before());
+        echo.close_before(echo.sealable_before()).await.unwrap();
+        assert_eq!(1, storage.get_root(EPOCH).unwrap().leaves);
+
+        std::fs::remove_file(storage.filename()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn catch_up_after_partition() {
+        let (echo, storage, secret_keys, beacon) = restarted("catch_up_after_partition");
+        let epoch_report = |idx, witnesses : &[(usize, (usize, usize))]| {
+            let report = fixtures::report(&secret_keys, EPOCH, (idx, (3, 3)), witnesses, &beacon);
+            sign_report(&secret_keys[&idx], &report)
+        };
+        assert_eq!(0, echo.catch_up().await.unwrap());
+
+        // Up all along, but cut off while the others delivered these
+        let mut vouchers : Vouchers = HashMap::new();
+        vouchers.entry((0, EPOCH)).or_default().insert(epoch_report(0, &[(1, (3, 3)), (2, (4, 4))]), vec![1, 2].into_iter().collect());
+        assert_eq!(1, echo.recover(&vouchers).await.unwrap());
+
+        // Later rounds only take what is still missing
+        vouchers.entry((1, EPOCH)).or_default().insert(epoch_report(1, &[(0, (3, 3)), (2, (4, 4))]), vec![1, 3].into_iter().collect());
+        assert_eq!(1, echo.recover(&vouchers).await.unwrap());
+        assert_eq!(0, echo.recover(&vouchers).await.unwrap());
+        assert!(storage.get_user_report_at_epoch(EPOCH, 1).is_some());
 
-        assert!(!logic.has_ready_message(CLIENT_ID, &msg));
+        std::fs::remove_file(storage.filename()).unwrap();
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn no_sealing_while_partitioned() {
+        let (echo, storage, _, _) = restarted("no_sealing_while_partitioned");
+        let unreachable = vec![(1, "http://[::1]:1".parse().unwrap()), (2, "http://[::1]:2".parse().unwrap())];
+        let partitioned = DoubleEcho { server_urls : Arc::new(unreachable), ..echo };
+
+        // Without an answer from f_servers + 1 servers nothing counts as caught up
+        assert!(partitioned.catch_up().await.is_err());
+        assert_eq!(0, partitioned.sealable_before());
+        assert!(storage.get_root(EPOCH).is_none());
+    }
+}
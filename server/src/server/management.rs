@@ -1,6 +1,6 @@
 use color_eyre::eyre::Result;
 use dashmap::DashSet;
-use security::{key_management::ServerKeys, report::confirm_proof_of_work};
+use security::{key_management::{HARole, ServerKeys}, report::confirm_proof_of_work};
 
 use std::sync::Arc;
 
@@ -9,23 +9,54 @@ use tonic::{Request, Response, Status};
 use protos::location_master::location_master_server::LocationMaster;
 use protos::location_master::{ObtainLocationReportRequest, ObtainLocationReportResponse,
     ObtainUsersAtLocationRequest, ObtainUsersAtLocationResponse};
+use protos::location_master::{AdministerBlacklistRequest, AdministerBlacklistResponse};
+use protos::location_master::{ObtainUsersInRegionRequest, ObtainUsersInRegionResponse};
+use protos::location_master::{ObtainTrajectoryRequest, ObtainTrajectoryResponse};
+use protos::location_master::{ObtainContactsRequest, ObtainContactsResponse};
+use protos::location_master::{ObtainExposureChainsRequest, ObtainExposureChainsResponse};
+use protos::location_master::{FlagInfectedRequest, FlagInfectedResponse};
+use protos::location_master::{ObtainHeatmapRequest, ObtainHeatmapResponse};
+use protos::location_master::{ObtainRootsRequest, ObtainRootsResponse};
 
-use crate::storage::Timeline;
+use crate::storage::{Timeline, exposure, heatmap, audit::AuditLog};
 
-use security::report::decode_info;
-use security::status::{decode_loc_report, encode_loc_response, decode_users_at_loc_report, encode_users_at_loc_response};
+use super::double_echo_report::DoubleEcho;
+
+use security::report::{ReportInfo, decode_info};
+use security::receipt::{ACCEPTED, Receipt, Receipted, encode_receipt};
+use security::status::{decode_request, encode_response, LocationReportRequest, LocationReportResponse, UsersAtLocationRequest, UsersAtLocationResponse};
+use security::status::{BlacklistRequest, BlacklistResponse, UsersInRegionRequest, UsersInRegionResponse, TrajectoryRequest, TrajectoryResponse};
+use security::status::{ContactsRequest, ContactsResponse, ExposureRequest, ExposureResponse, InfectionRequest, HeatmapRequest, HeatmapResponse, HEATMAP_EPSILONS};
+use security::status::{RootsRequest, RootsResponse};
+
+use serde::{Serialize, de::DeserializeOwned};
+use sodiumoxide::crypto::{hash::sha256, secretbox, sign};
 
 pub struct MyLocationMaster {
+    server_id : usize,
+    necessary_res : usize,
     storage : Arc<Timeline>,
     server_keys : Arc<ServerKeys>,
+    echo : Arc<DoubleEcho>,
+    audit : Arc<AuditLog>,
+    heatmap_min_k : usize,
+    heatmap_seed : Vec<u8>,
     pows : DashSet<Vec<u8>>,
 }
 
 impl MyLocationMaster {
-    pub fn new(storage : Arc<Timeline>, server_keys : Arc<ServerKeys>,) -> MyLocationMaster {
+    pub fn new(server_id : usize, necessary_res : usize, storage : Arc<Timeline>, server_keys : Arc<ServerKeys>, echo : Arc<DoubleEcho>, audit : Arc<AuditLog>, heatmap_min_k : usize) -> MyLocationMaster {
+        // Secret to the server, so the heatmap noise can't be predicted
+        let heatmap_seed = sha256::hash(&serde_json::to_vec(&("heatmap", &server_keys.sign_key().0[..])).unwrap()).0.to_vec();
         MyLocationMaster {
+            server_id,
+            necessary_res,
             storage,
             server_keys,
+            echo,
+            audit,
+            heatmap_min_k,
+            heatmap_seed,
             pows : DashSet::new(),
         }
     }
@@ -41,6 +72,57 @@ impl MyLocationMaster {
         }
         Ok(())
     }
+
+    // Decodes the request with the key of the HA operator that signed it, if its role allows the access
+    fn decode_as_operator<T>(&self, access : HARole, decode : impl Fn(&sign::PublicKey) -> Result<T>) -> Result<(usize, T), String> {
+        for (idx, operator) in self.server_keys.ha_operators() {
+            if let Ok(request) = decode(&operator.public_key) {
+                if operator.role.allows(access) {
+                    return Ok((*idx, request));
+                }
+                return Err(format!("HA operator {:} is {:} but {:} is required", idx, operator.role, access));
+            }
+        }
+        Err("Unable to decrypt request".to_string())
+    }
+
+    // Proof of work, sealed info, freshness and nonce, checked before the request of an operator is opened
+    // Also returns the signed request, which is what receipts are about
+    fn decode_request<T : DeserializeOwned>(&self, access : HARole, pow : &Vec<u8>, ha_info : &Vec<u8>, request : &[u8]) -> Result<(ReportInfo, usize, T, Vec<u8>), Status> {
+        self.check_proof_of_work(pow, ha_info)?;
+
+        let info = decode_info(self.server_keys.private_key(), self.server_keys.public_key(), ha_info)
+            .map_err(|_| Status::permission_denied("Unable to decrypt sealed container"))?;
+
+        if !info.is_fresh() {
+            return Err(Status::deadline_exceeded("Request is no longer fresh"));
+        }
+
+        if !self.storage.valid_ha_nonce(info.nonce(), info.timestamp()) {
+            return Err(Status::already_exists("nonce already exists"));
+        }
+
+        let (operator, (request, signed)) = self.decode_as_operator(access, |key| decode_request(key, info.key(), request, info.nonce()))
+            .map_err(Status::permission_denied)?;
+
+        if !self.storage.add_ha_nonce(*info.nonce(), info.timestamp()) {
+            return Err(Status::permission_denied("nonce already exists"));
+        }
+
+        Ok((info, operator, request, signed))
+    }
+
+    // Receipts name the operator who asked, the users the query touched are only kept in the audit log
+    fn receipted<T : Serialize>(&self, operator : usize, epoch : usize, request : &[u8], response : T, key : &secretbox::Key) -> (Vec<u8>, secretbox::Nonce) {
+        encode_response(key, &Receipted::new(self.server_id, operator, epoch, request, response, self.server_keys.sign_key()))
+    }
+
+    // Nothing is answered unless the query was recorded
+    fn audited(&self, operator : usize, query : String, touched : Vec<(usize, usize)>, result_size : usize) -> Result<(), String> {
+        let head = self.audit.record(operator, query, touched, result_size).map_err(|_| "Unable to record the query in the audit log.".to_string())?;
+        self.echo.publish_audit_head(head);
+        Ok(())
+    }
 }
 
 #[tonic::async_trait]
@@ -51,38 +133,17 @@ impl LocationMaster for MyLocationMaster {
     ) -> Result<Response<ObtainLocationReportResponse>, Status> {
         let request = request.get_ref();
 
-        self.check_proof_of_work(&request.pow, &request.info)?;
-
-        let info = if let Ok(info) = decode_info(
-            self.server_keys.private_key(),
-            self.server_keys.public_key(),
-            &request.info) {
-            info
-        } else {
-            return Err(Status::permission_denied("Unhable to decrept sealed container"));
-        };
-
-        if !self.storage.valid_ha_nonce(info.nonce()) {
-            return Err(Status::already_exists("nonce already exists"));
-        }
-
-        let loc_req = match decode_loc_report(
-            self.server_keys.ha_public_key(),
-            info.key(),
-            &request.user,
-            info.nonce(),
-        ) {
-            Ok(location_request) => {
-                if !self.storage.add_ha_nonce(info.nonce().clone()) {
-                    return  Err(Status::permission_denied("nonce already exists"));
-                }
-                location_request
-            }
-            Err(_) => return  Err(Status::permission_denied("Unable to decrypt report"))
-        };
-        match self.storage.get_user_report_at_epoch(loc_req.epoch(), loc_req.idx()) {
+        let (info, operator, loc_req, signed) : (_, _, LocationReportRequest, _) = self.decode_request(HARole::TrajectoryReader, &request.pow, &request.info, &request.user)?;
+        let report = self.storage.get_user_report_at_epoch(loc_req.epoch(), loc_req.idx());
+        self.audited(operator, format!("location of user {:} at epoch {:}", loc_req.idx(), loc_req.epoch()),
+            vec![(loc_req.idx(), loc_req.epoch())], report.iter().count()).map_err(Status::internal)?;
+        match report {
             Some(report) =>  {
-                let (location, nonce) = encode_loc_response(info.key(), report.clone());
+                let disputed = self.storage.is_disputed(loc_req.epoch(), loc_req.idx());
+                let receipt = Receipt::new(self.server_id, loc_req.idx(), loc_req.epoch(), &signed, &(&report, disputed), self.server_keys.sign_key());
+                let certificate = self.storage.get_certificate(loc_req.idx(), loc_req.epoch(), self.necessary_res);
+                let inclusion = self.storage.get_inclusion(loc_req.idx(), loc_req.epoch());
+                let (location, nonce) = encode_response(info.key(), &LocationReportResponse::new(report, disputed, receipt, certificate, inclusion));
                 Ok( Response::new(ObtainLocationReportResponse {
                     nonce : nonce.0.to_vec(),
                     location,
@@ -99,38 +160,16 @@ impl LocationMaster for MyLocationMaster {
 
         let request = request.get_ref();
 
-        self.check_proof_of_work(&request.pow, &request.info)?;
-
-        let info = if let Ok(info) = decode_info(
-            self.server_keys.private_key(),
-            self.server_keys.public_key(),
-            &request.info) {
-            info
-        } else {
-            return Err(Status::permission_denied("Unhable to decrept sealed container"));
-        };
-
-        if !self.storage.valid_ha_nonce(info.nonce()) {
-            return Err(Status::already_exists("nonce already exists"));
-        }
-
-        let loc_req = match decode_users_at_loc_report(
-            self.server_keys.ha_public_key(),
-            info.key(),
-            &request.place,
-            info.nonce(),
-        ) {
-            Ok(location_request) => {
-                if !self.storage.add_ha_nonce(info.nonce().clone()) {
-                    return  Err(Status::permission_denied("nonce already exists"));
-                }
-                location_request
-            }
-            Err(_) => return  Err(Status::permission_denied("Unable to decrypt report"))
-        };
-        match self.storage.get_users_at_epoch_at_location(loc_req.epoch(), loc_req.pos()) {
+        let (info, operator, loc_req, signed) : (_, _, UsersAtLocationRequest, _) = self.decode_request(HARole::TrajectoryReader, &request.pow, &request.info, &request.place)?;
+        let users = self.storage.get_users_at_epoch_at_location(loc_req.epoch(), loc_req.pos());
+        let touched : Vec<_> = users.iter().flatten().map(|(idx, _)| (*idx, loc_req.epoch())).collect();
+        self.audited(operator, format!("users at {:?} at epoch {:}", loc_req.pos(), loc_req.epoch()), touched.clone(), touched.len()).map_err(Status::internal)?;
+        match users {
             Some(idxs_reports) =>  {
-                let (idxs, nonce) = encode_users_at_loc_response(info.key(), idxs_reports);
+                let disputed = self.storage.get_disputed_at_epoch(loc_req.epoch()).into_iter().filter(
+                    |idx| idxs_reports.iter().any(|(user, _)| user == idx)
+                ).collect();
+                let (idxs, nonce) = self.receipted(operator, loc_req.epoch(), &signed, UsersAtLocationResponse::new(idxs_reports, disputed), info.key());
                 Ok( Response::new(ObtainUsersAtLocationResponse {
                     nonce : nonce.0.to_vec(),
                     idxs,
@@ -139,4 +178,248 @@ impl LocationMaster for MyLocationMaster {
             None => Err(Status::not_found(format!("Location {:?} at epoch {:} not found.", loc_req.pos(), loc_req.epoch()))),
         }
     }
-}
\ No newline at end of file
+
+    async fn obtain_users_in_region(
+        &self,
+        request : Request<ObtainUsersInRegionRequest>
+    ) ->Result<Response<ObtainUsersInRegionResponse>, Status> {
+
+        let request = request.get_ref();
+
+        let (info, operator, region_req, signed) : (_, _, UsersInRegionRequest, _) = self.decode_request(HARole::TrajectoryReader, &request.pow, &request.info, &request.region)?;
+
+        let ((first, last), from, to) = (region_req.epochs(), region_req.from(), region_req.to());
+        if first > last || from.0 > to.0 || from.1 > to.1 || !self.storage.valid_pos(to.0, to.1) {
+            return Err(Status::invalid_argument(format!("Not a valid region {:?}-{:?} during epochs {:}..{:}", from, to, first, last)));
+        }
+
+        let mut reports = vec![];
+        let mut disputed = vec![];
+        for (epoch, idx, report, is_disputed) in self.storage.get_users_in_region((first, last), from, to) {
+            if is_disputed {
+                disputed.push((epoch, idx));
+            }
+            reports.push((epoch, idx, report));
+        }
+        let touched = reports.iter().map(|(epoch, idx, _)| (*idx, *epoch)).collect();
+        self.audited(operator, format!("users in {:?}-{:?} during epochs {:}..{:}", from, to, first, last), touched, reports.len()).map_err(Status::internal)?;
+
+        let (reports, nonce) = self.receipted(operator, first, &signed, UsersInRegionResponse::new(reports, disputed), info.key());
+        Ok( Response::new(ObtainUsersInRegionResponse {
+            nonce : nonce.0.to_vec(),
+            reports,
+        }))
+    }
+
+    async fn obtain_trajectory(
+        &self,
+        request : Request<ObtainTrajectoryRequest>
+    ) ->Result<Response<ObtainTrajectoryResponse>, Status> {
+
+        let request = request.get_ref();
+
+        let (info, operator, trajectory_req, signed) : (_, _, TrajectoryRequest, _) = self.decode_request(HARole::TrajectoryReader, &request.pow, &request.info, &request.trajectory)?;
+
+        let (first, last) = trajectory_req.epochs();
+        if first > last {
+            return Err(Status::invalid_argument(format!("Not a valid epoch range {:}..{:}", first, last)));
+        }
+
+        let mut reports = vec![];
+        let mut disputed = vec![];
+        for (epoch, report, is_disputed) in self.storage.get_trajectory(trajectory_req.idx(), (first, last)) {
+            if is_disputed {
+                disputed.push(epoch);
+            }
+            reports.push((epoch, report));
+        }
+        let touched = reports.iter().map(|(epoch, _)| (trajectory_req.idx(), *epoch)).collect();
+        self.audited(operator, format!("trajectory of user {:} during epochs {:}..{:}", trajectory_req.idx(), first, last), touched, reports.len()).map_err(Status::internal)?;
+
+        let (trajectory, nonce) = self.receipted(operator, first, &signed, TrajectoryResponse::new(reports, disputed), info.key());
+        Ok( Response::new(ObtainTrajectoryResponse {
+            nonce : nonce.0.to_vec(),
+            trajectory,
+        }))
+    }
+
+    async fn obtain_contacts(
+        &self,
+        request : Request<ObtainContactsRequest>
+    ) ->Result<Response<ObtainContactsResponse>, Status> {
+
+        let request = request.get_ref();
+
+        let (info, operator, contacts_req, signed) : (_, _, ContactsRequest, _) = self.decode_request(HARole::TrajectoryReader, &request.pow, &request.info, &request.contacts)?;
+
+        let (first, last) = contacts_req.epochs();
+        if first > last {
+            return Err(Status::invalid_argument(format!("Not a valid epoch range {:}..{:}", first, last)));
+        }
+
+        let contacts = self.storage.get_contacts(contacts_req.idx(), (first, last));
+        let touched = contacts.iter().flat_map(|at_epoch|
+            std::iter::once(contacts_req.idx()).chain(at_epoch.contacts.iter().map(|(idx, _)| *idx)).map(move |idx| (idx, at_epoch.epoch))
+        ).collect();
+        self.audited(operator, format!("contacts of user {:} during epochs {:}..{:}", contacts_req.idx(), first, last),
+            touched, contacts.iter().map(|at_epoch| at_epoch.contacts.len()).sum()).map_err(Status::internal)?;
+        let (contacts, nonce) = self.receipted(operator, first, &signed, ContactsResponse::new(contacts), info.key());
+        Ok( Response::new(ObtainContactsResponse {
+            nonce : nonce.0.to_vec(),
+            contacts,
+        }))
+    }
+
+    async fn obtain_exposure_chains(
+        &self,
+        request : Request<ObtainExposureChainsRequest>
+    ) ->Result<Response<ObtainExposureChainsResponse>, Status> {
+
+        let request = request.get_ref();
+
+        let (info, operator, exposure_req, signed) : (_, _, ExposureRequest, _) = self.decode_request(HARole::TrajectoryReader, &request.pow, &request.info, &request.exposure)?;
+
+        let (first, last) = exposure_req.epochs();
+        if first > last {
+            return Err(Status::invalid_argument(format!("Not a valid epoch range {:}..{:}", first, last)));
+        }
+
+        let chains = exposure::exposure_chains(&self.storage, exposure_req.idx(), (first, last), exposure_req.hops());
+        let touched = chains.iter().flatten().flat_map(|link| vec![(link.from, link.epoch), (link.to, link.epoch)]).collect();
+        self.audited(operator, format!("exposure chains of user {:} during epochs {:}..{:} within {:} hops", exposure_req.idx(), first, last, exposure_req.hops()), touched, chains.len()).map_err(Status::internal)?;
+        let (chains, nonce) = self.receipted(operator, first, &signed, ExposureResponse::new(chains), info.key());
+        Ok( Response::new(ObtainExposureChainsResponse {
+            nonce : nonce.0.to_vec(),
+            chains,
+        }))
+    }
+
+    async fn flag_infected(
+        &self,
+        request : Request<FlagInfectedRequest>
+    ) ->Result<Response<FlagInfectedResponse>, Status> {
+
+        let request = request.get_ref();
+
+        let (info, operator, infection_req, signed) : (_, _, InfectionRequest, _) = self.decode_request(HARole::InfectionFlagger, &request.pow, &request.info, &request.infection)?;
+
+        if self.server_keys.client_sign_key(infection_req.idx()).is_none() {
+            return Err(Status::invalid_argument(format!("Unknown user {:}", infection_req.idx())));
+        }
+
+        self.audited(operator, format!("flag user {:} infected from epoch {:}", infection_req.idx(), infection_req.epoch()),
+            vec![(infection_req.idx(), infection_req.epoch())], 1).map_err(Status::internal)?;
+        // Acknowledged only once delivered, so every correct server flags the user
+        if let Err(err) = self.echo.confirm_infection(infection_req.idx(), infection_req.epoch(), &signed).await {
+            return Err(Status::aborted(err.to_string()));
+        }
+
+        let receipt = Receipt::new(self.server_id, infection_req.idx(), infection_req.epoch(), &signed, &ACCEPTED, self.server_keys.sign_key());
+        let (ok, nonce) = encode_receipt(info.key(), &receipt);
+        Ok( Response::new(FlagInfectedResponse {
+            nonce : nonce.0.to_vec(),
+            ok,
+        }))
+    }
+
+    async fn obtain_heatmap(
+        &self,
+        request : Request<ObtainHeatmapRequest>
+    ) ->Result<Response<ObtainHeatmapResponse>, Status> {
+
+        let request = request.get_ref();
+
+        let (info, operator, heatmap_req, signed) : (_, _, HeatmapRequest, _) = self.decode_request(HARole::AggregateOnly, &request.pow, &request.info, &request.heatmap)?;
+
+        let (first, last) = heatmap_req.epochs();
+        if first > last {
+            return Err(Status::invalid_argument(format!("Not a valid epoch range {:}..{:}", first, last)));
+        }
+        if heatmap_req.k() < self.heatmap_min_k {
+            return Err(Status::invalid_argument(format!("K must be at least {:}", self.heatmap_min_k)));
+        }
+        if matches!(heatmap_req.epsilon(), Some(epsilon) if !HEATMAP_EPSILONS.contains(&epsilon)) {
+            return Err(Status::invalid_argument(format!("Epsilon must be one of {:?}", HEATMAP_EPSILONS)));
+        }
+
+        let cells = heatmap::heatmap(&self.storage, (first, last), heatmap_req.k(), heatmap_req.epsilon(), &self.heatmap_seed);
+        self.audited(operator, format!("heatmap during epochs {:}..{:} with k {:} and epsilon {:?}", first, last, heatmap_req.k(), heatmap_req.epsilon()), vec![], cells.len()).map_err(Status::internal)?;
+        let (heatmap, nonce) = self.receipted(operator, first, &signed, HeatmapResponse::new(cells), info.key());
+        Ok( Response::new(ObtainHeatmapResponse {
+            nonce : nonce.0.to_vec(),
+            heatmap,
+        }))
+    }
+
+    async fn obtain_roots(
+        &self,
+        request : Request<ObtainRootsRequest>
+    ) ->Result<Response<ObtainRootsResponse>, Status> {
+
+        let request = request.get_ref();
+
+        // Roots only hash the reports, so anyone allowed aggregates may compare them
+        let (info, operator, roots_req, signed) : (_, _, RootsRequest, _) = self.decode_request(HARole::AggregateOnly, &request.pow, &request.info, &request.request)?;
+
+        let epoch = roots_req.epoch();
+        let roots_res = RootsResponse::new(self.storage.get_root(epoch), self.storage.get_peer_roots(epoch), self.storage.divergent_servers(epoch));
+        self.audited(operator, format!("roots of epoch {:}", epoch), vec![], roots_res.peer_roots.len()).map_err(Status::internal)?;
+        let (roots, nonce) = self.receipted(operator, epoch, &signed, roots_res, info.key());
+        Ok( Response::new(ObtainRootsResponse {
+            nonce : nonce.0.to_vec(),
+            roots,
+        }))
+    }
+
+    async fn administer_blacklist(
+        &self,
+        request : Request<AdministerBlacklistRequest>
+    ) ->Result<Response<AdministerBlacklistResponse>, Status> {
+
+        let request = request.get_ref();
+
+        let (info, operator, blacklist_req, signed) : (_, _, BlacklistRequest, _) = self.decode_request(HARole::Admin, &request.pow, &request.info, &request.request)?;
+
+        // The epochs of the user's entries read or cleared, the whole list only names who is banned
+        let touched : Vec<(usize, usize)> = match blacklist_req {
+            BlacklistRequest::List => vec![],
+            BlacklistRequest::Inspect(idx) | BlacklistRequest::Clear(idx) =>
+                self.storage.get_blacklist_entry(idx).iter().map(|entry| (idx, entry.epoch)).collect(),
+            BlacklistRequest::Flags(idx) | BlacklistRequest::Unflag(idx) =>
+                self.storage.get_flagged_witness(idx).iter().map(|flag| (idx, flag.epoch)).collect(),
+        };
+
+        let blacklist_res = match blacklist_req {
+            BlacklistRequest::List => BlacklistResponse::Listed(self.storage.get_blacklist()),
+            BlacklistRequest::Inspect(idx) => BlacklistResponse::Entry(self.storage.get_blacklist_entry(idx)),
+            BlacklistRequest::Clear(idx) => {
+                let cleared = self.storage.clear_blacklist_entry(idx);
+                if cleared && self.echo.on_cleared(idx).await.is_err() {
+                    return Err(Status::internal("Unable to permanently save information."));
+                }
+                BlacklistResponse::Cleared(cleared)
+            }
+            BlacklistRequest::Flags(idx) => BlacklistResponse::Flags(self.storage.get_flagged_witness(idx)),
+            BlacklistRequest::Unflag(idx) => {
+                let cleared = self.storage.clear_flagged_witness(idx);
+                if cleared && self.echo.on_cleared(idx).await.is_err() {
+                    return Err(Status::internal("Unable to permanently save information."));
+                }
+                BlacklistResponse::Cleared(cleared)
+            }
+        };
+
+        let result_size = match &blacklist_res {
+            BlacklistResponse::Listed(entries) => entries.len(),
+            BlacklistResponse::Entry(entry) => entry.iter().count(),
+            BlacklistResponse::Cleared(cleared) => *cleared as usize,
+            BlacklistResponse::Flags(flags) => flags.len(),
+        };
+        self.audited(operator, format!("blacklist {:?}", blacklist_req), touched, result_size).map_err(Status::internal)?;
+        let (response, nonce) = self.receipted(operator, 0, &signed, blacklist_res, info.key());
+        Ok( Response::new(AdministerBlacklistResponse {
+            nonce : nonce.0.to_vec(),
+            response,
+        }))
+    }
+}
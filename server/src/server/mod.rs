@@ -1,42 +1,40 @@
 pub mod validating;
 pub mod management;
 pub mod double_echo_report;
+pub mod retention;
+pub mod commitment;
+pub mod beacon;
+pub mod validator;
 
 use std::sync::Arc;
 
 use color_eyre::eyre::Result;
-use tonic::transport::{Server, Uri};
+use tonic::transport::Server;
 use protos::{double_echo_broadcast::double_echo_broadcast_server::DoubleEchoBroadcastServer, location_storage::location_storage_server::LocationStorageServer};
 use protos::location_master::location_master_server::LocationMasterServer;
 
-use crate::storage::Timeline;
-use security::key_management::{ServerKeys, ServerPublicKey};
+use crate::storage::{Timeline, audit::AuditLog};
+use double_echo_report::DoubleEchoConfig;
 
 pub async fn start_server(
-    server_id : usize,
     addr : String,
+    config : DoubleEchoConfig,
     storage : Arc<Timeline>,
-    server_keys : Arc<ServerKeys>,
-    f_line : usize,
-    server_urls :  Vec<(usize, Uri)>,
-    necessary_res : usize,
-    f_servers : usize,
-    server_pkeys : Arc<ServerPublicKey>,
+    audit : Arc<AuditLog>,
+    heatmap_min_k : usize,
 ) -> Result<()> {
 
     let addr = addr.parse()?;
-    let double_echo = Arc::new(double_echo_report::DoubleEcho::new(
-                server_id,
-                Arc::new(server_urls),
-                necessary_res,
-                f_servers,
-                server_keys.clone(),
-                server_pkeys,
-                f_line,
-                storage.clone()
-            ));
-    let validater = validating::MyLocationStorage::new(storage.clone(), server_keys.clone(), double_echo.clone());
-    let manager = management::MyLocationMaster::new(storage.clone(), server_keys);
+    let (server_id, server_keys, necessary_res, clock) = (config.server_id, config.server_keys.clone(), config.necessary_res, config.clock);
+    let (retention, archive_dir) = (config.retention, config.archive_dir.clone());
+    let double_echo = Arc::new(double_echo_report::DoubleEcho::new(config, storage.clone()));
+    tokio::spawn(double_echo_report::catch_up(double_echo.clone()));
+    tokio::spawn(retention::garbage_collector(server_id, double_echo.clone(), storage.clone(), retention, archive_dir));
+    tokio::spawn(commitment::epoch_sealer(double_echo.clone(), storage.clone()));
+    tokio::spawn(beacon::beacon_publisher(double_echo.clone(), clock));
+
+    let validater = validating::MyLocationStorage::new(storage.clone(), server_keys.clone(), double_echo.clone(), audit.clone());
+    let manager = management::MyLocationMaster::new(server_id, necessary_res, storage.clone(), server_keys, double_echo.clone(), audit, heatmap_min_k);
     let echo = double_echo_report::MyDoubleEchoWrite::new(double_echo);
     println!("LocationStorageServer listening on {}", addr);
 
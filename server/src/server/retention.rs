@@ -0,0 +1,70 @@
+use std::{fs, sync::Arc, time::Duration};
+
+use color_eyre::eyre::Result;
+
+use tokio::time::sleep;
+
+use security::{NONCE_WINDOW, report::timestamp_now};
+
+use crate::storage::{Timeline, save_archive, retrieve_archive};
+
+use super::double_echo_report::DoubleEcho;
+
+const GC_INTERVAL : u64 = 10_000; // ms
+
+pub async fn garbage_collector(
+    server_id : usize,
+    double_echo : Arc<DoubleEcho>,
+    storage : Arc<Timeline>,
+    retention : Option<usize>,
+    archive_dir : Option<String>,
+) {
+    loop {
+        sleep(Duration::from_millis(GC_INTERVAL)).await;
+
+        // Requests older than the window are refused, so their nonces can't be replayed
+        storage.prune_nonces(timestamp_now().saturating_sub(NONCE_WINDOW));
+
+        // Driven by the clock, a report for a far future epoch must not purge everything else
+        let (retention, current) = match (retention, double_echo.clock().current()) {
+            (Some(retention), Some(current)) => (retention, current),
+            _ => continue,
+        };
+
+        let before = current.saturating_sub(retention);
+        if before == 0 || !storage.is_retained(before - 1) {
+            continue;
+        }
+
+        match double_echo.purge_before(before).await {
+            Ok(archive) => {
+                if let (Some(dir), false) = (&archive_dir, archive.is_empty()) {
+                    let archive_file = format!("{:}{:}_before_{:}.txt", dir, server_id, archive.before());
+                    if let Err(err) = save_archive(&archive_file, &archive) {
+                        println!("Unable to archive epochs before {:}: {:}", archive.before(), err);
+                    }
+                }
+                println!("Purged epochs before {:}", before);
+            }
+            Err(err) => println!("Unable to purge epochs before {:}: {:}", before, err),
+        }
+    }
+}
+
+// Erased users must also leave the epochs that were already archived
+pub fn erase_archived(server_id : usize, archive_dir : &str, idx : usize, before : usize) -> Result<()> {
+    let prefix = format!("{:}_before_", server_id);
+    for entry in fs::read_dir(archive_dir)? {
+        let path = entry?.path();
+        match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if name.starts_with(&prefix) => {}
+            _ => continue,
+        }
+        let archive_file = path.to_string_lossy().to_string();
+        let mut archive = retrieve_archive(&archive_file)?;
+        if archive.erase_user(idx, before) {
+            save_archive(&archive_file, &archive)?;
+        }
+    }
+    Ok(())
+}
@@ -4,26 +4,35 @@ use dashmap::DashSet;
 
 use std::sync::Arc;
 
-use crate::storage::Timeline;
+use crate::storage::{Timeline, audit::AuditLog};
 
 use tonic::{Request, Response, Status};
 
 use protos::location_storage::{RequestMyProofsRequest, RequestMyProofsResponse, location_storage_server::LocationStorage};
+use protos::location_storage::{CheckExposureRequest, CheckExposureResponse, ObtainMyAccessesRequest, ObtainMyAccessesResponse};
+use protos::location_storage::{ObtainMyDataRequest, ObtainMyDataResponse, RequestErasureRequest, RequestErasureResponse};
+use protos::location_storage::{ObtainBeaconRequest, ObtainBeaconResponse};
 use protos::location_storage::{SubmitLocationReportRequest, SubmitLocationReportResponse,
     ObtainLocationReportRequest, ObtainLocationReportResponse};
 
 use security::{key_management::ServerKeys, report::confirm_proof_of_work};
-use security::report::{decode_info, decode_report};
-use security::status::{decode_loc_report, encode_loc_response, decode_my_proofs_request, encode_my_proofs_response};
+use security::receipt::{ACCEPTED, Receipt, Receipted, encode_receipt};
+use security::report::{ReportInfo, decode_info, decode_report};
+use security::status::{decode_request, encode_response, LocationReportRequest, LocationReportResponse, MyProofsRequest, MyProofsResponse};
+use security::status::{CheckExposureRequest as ExposureCheck, CheckExposureResponse as ExposureCheckResponse, EXPOSURE_WINDOWS, MyAccessesRequest, MyAccessesResponse};
+use security::status::{UserDataRequest, ErasureRequest, BeaconRequest, BeaconResponse};
 
-use sodiumoxide::crypto::secretbox;
+use serde::{Serialize, de::DeserializeOwned};
+use sodiumoxide::crypto::{secretbox, sign};
 
 use super::double_echo_report::DoubleEcho;
+use super::validator::{Rejection, Rule};
 
 pub struct MyLocationStorage {
     storage : Arc<Timeline>,
     server_keys : Arc<ServerKeys>,
     echo : Arc<DoubleEcho>,
+    audit : Arc<AuditLog>,
     pows : DashSet<Vec<u8>>,
 }
 
@@ -32,12 +41,14 @@ impl MyLocationStorage {
         storage : Arc<Timeline>,
         server_keys : Arc<ServerKeys>,
         echo : Arc<DoubleEcho>,
+        audit : Arc<AuditLog>,
     ) -> MyLocationStorage {
 
         MyLocationStorage {
             storage,
             server_keys,
             echo,
+            audit,
             pows : DashSet::new(),
         }
     }
@@ -53,6 +64,60 @@ impl MyLocationStorage {
         }
         Ok(())
     }
+
+    // Proof of work, sealed info, freshness and nonce, checked before any request of a user is opened
+    fn check_info(&self, pow : &Vec<u8>, user_info : &Vec<u8>) -> Result<(ReportInfo, &sign::PublicKey), Status> {
+        self.check_proof_of_work(pow, user_info)?;
+
+        let info = decode_info(self.server_keys.private_key(), self.server_keys.public_key(), user_info)
+            .map_err(|_| Status::permission_denied("Unable to decrypt sealed container"))?;
+
+        let client_sign_key = match self.server_keys.client_sign_key(info.idx()) {
+            Some(ck) => ck,
+            None => return Err(Status::permission_denied(format!("Unable to find client {:} keys", info.idx()))),
+        };
+
+        if !info.is_fresh() {
+            return Err(Status::deadline_exceeded("Request is no longer fresh"));
+        }
+
+        if !self.storage.valid_nonce(info.idx(), info.nonce(), info.timestamp()) {
+            return Err(Status::already_exists("nonce already exists"));
+        }
+
+        Ok((info, client_sign_key))
+    }
+
+    fn use_nonce(&self, info : &ReportInfo) -> Result<(), Status> {
+        if !self.storage.add_nonce(info.idx(), *info.nonce(), info.timestamp()) {
+            return Err(Status::permission_denied("nonce already exists"));
+        }
+        Ok(())
+    }
+
+    // Also returns the signed request, which is what receipts are about
+    fn decode_request<T : DeserializeOwned>(&self, pow : &Vec<u8>, user_info : &Vec<u8>, request : &[u8]) -> Result<(ReportInfo, T, Vec<u8>), Status> {
+        let (info, client_sign_key) = self.check_info(pow, user_info)?;
+
+        let (request, signed) = decode_request(client_sign_key, info.key(), request, info.nonce())
+            .map_err(|_| Status::permission_denied("Unable to decrypt request"))?;
+        self.use_nonce(&info)?;
+
+        Ok((info, request, signed))
+    }
+
+    fn receipted<T : Serialize>(&self, idx : usize, epoch : usize, request : &[u8], response : T, key : &secretbox::Key) -> (Vec<u8>, secretbox::Nonce) {
+        encode_response(key, &Receipted::new(self.echo.server_id(), idx, epoch, request, response, self.server_keys.sign_key()))
+    }
+
+    fn accepted(&self, idx : usize, epoch : usize, request : &[u8], key : &secretbox::Key) -> Response<SubmitLocationReportResponse> {
+        let receipt = Receipt::new(self.echo.server_id(), idx, epoch, request, &ACCEPTED, self.server_keys.sign_key());
+        let (ok, nonce) = encode_receipt(key, &receipt);
+        Response::new(SubmitLocationReportResponse {
+            nonce : nonce.0.to_vec(),
+            ok,
+        })
+    }
 }
 
 #[tonic::async_trait]
@@ -64,26 +129,7 @@ impl LocationStorage for MyLocationStorage {
 
         let request = request.get_ref();
 
-        self.check_proof_of_work(&request.pow, &request.report_info)?;
-
-        let info = if let Ok(info) = decode_info(
-            self.server_keys.private_key(),
-            self.server_keys.public_key(),
-            &request.report_info) {
-            info
-        } else {
-            return Err(Status::permission_denied("Unhable to decrypt sealed container"));
-        };
-
-        let client_sign_key = if let Some(ck) = self.server_keys.client_sign_key(info.idx()) {
-            ck
-        } else {
-            return Err(Status::permission_denied(format!("Unable to find client {:} keys", info.idx())));
-        };
-
-        if !self.storage.valid_nonce(info.idx(), info.nonce()) {
-            return Err(Status::already_exists("nonce already exists"));
-        }
+        let (info, client_sign_key) = self.check_info(&request.pow, &request.report_info)?;
 
         let (report, signed_rep) = match decode_report(
             client_sign_key,
@@ -95,30 +141,28 @@ impl LocationStorage for MyLocationStorage {
                 if info.idx() != report.0.idx() {
                     return Err(Status::aborted("Info idx does not match report idx"));
                 }
-                if !self.storage.add_nonce(info.idx(), info.nonce().clone()) {
-                    return Err(Status::permission_denied("nonce already exists"));
-                }
+                self.use_nonce(&info)?;
                 report
             }
             Err(_) => return  Err(Status::permission_denied("Unable to decrypt report"))
         };
 
+        self.echo.check_report_rules(info.idx(), &report, &[Rule::Blacklisted]).map_err(rejected)?;
+
         if !self.storage.report_not_submitted_at_epoch(report.epoch(), info.idx()) {
-            let nonce = secretbox::gen_nonce();
-            return Ok(Response::new(SubmitLocationReportResponse {
-                nonce : nonce.0.to_vec(),
-                ok : secretbox::seal(b"", &nonce, info.key()),
-            }));
+            if self.storage.blacklist_if_conflicting(report.epoch(), report.loc(), info.idx(), &signed_rep) {
+                if self.echo.on_blacklisted(info.idx()).await.is_err() {
+                    return Err(Status::internal("Unable to permanently save information."));
+                }
+                return Err(Status::permission_denied("Two different positions submitted for the same epoch"));
+            }
+            return Ok(self.accepted(info.idx(), report.epoch(), &signed_rep, info.key()));
         }
 
+        let epoch = report.epoch();
+        self.echo.check_report_rules(info.idx(), &report, &[Rule::EpochWindow]).map_err(rejected)?;
         match self.echo.confirm_write(&signed_rep, report.idx(), report).await {
-            Ok(_) => {
-                let nonce = secretbox::gen_nonce();
-                Ok( Response::new(SubmitLocationReportResponse {
-                    nonce : nonce.0.to_vec(),
-                    ok : secretbox::seal(b"", &nonce, info.key()),
-                }))
-            }
+            Ok(_) => Ok(self.accepted(info.idx(), epoch, &signed_rep, info.key())),
             Err(err) => Err(Status::aborted(err.to_string())),
         }
     }
@@ -129,44 +173,14 @@ impl LocationStorage for MyLocationStorage {
     ) -> Result<Response<ObtainLocationReportResponse>, Status> {
         let request = request.get_ref();
 
-        self.check_proof_of_work(&request.pow, &request.user_info)?;
-
-        let info = if let Ok(info) = decode_info(
-            self.server_keys.private_key(),
-            self.server_keys.public_key(),
-            &request.user_info) {
-            info
-        } else {
-            return Err(Status::permission_denied("Unhable to decript sealed container"));
-        };
-
-        let client_sign_key = if let Some(ck) = self.server_keys.client_sign_key(info.idx()) {
-            ck
-        } else {
-            return Err(Status::permission_denied(format!("Unable to find client {:} keys", info.idx())));
-        };
-
-        if !self.storage.valid_nonce(info.idx(), info.nonce()) {
-            return Err(Status::already_exists("nonce already exists"));
-        }
-
-        let loc_req = match decode_loc_report(
-            client_sign_key,
-            info.key(),
-            &request.user,
-            info.nonce(),
-        ) {
-            Ok(location_request) => {
-                if !self.storage.add_nonce(info.idx(), info.nonce().clone()) {
-                    return  Err(Status::permission_denied("nonce already exists"));
-                }
-                location_request
-            }
-            Err(_) => return  Err(Status::permission_denied("Unable to decrypt report"))
-        };
+        let (info, loc_req, signed) : (_, LocationReportRequest, _) = self.decode_request(&request.pow, &request.user_info, &request.user)?;
         match self.storage.get_user_report_at_epoch(loc_req.epoch(), loc_req.idx()) {
             Some(report) =>  {
-                let (location, nonce) = encode_loc_response(info.key(), report.clone());
+                let disputed = self.storage.is_disputed(loc_req.epoch(), loc_req.idx());
+                let receipt = Receipt::new(self.echo.server_id(), loc_req.idx(), loc_req.epoch(), &signed, &(&report, disputed), self.server_keys.sign_key());
+                let certificate = self.echo.delivery_certificate(loc_req.idx(), loc_req.epoch());
+                let inclusion = self.storage.get_inclusion(loc_req.idx(), loc_req.epoch());
+                let (location, nonce) = encode_response(info.key(), &LocationReportResponse::new(report, disputed, receipt, certificate, inclusion));
                 Ok( Response::new(ObtainLocationReportResponse {
                     nonce : nonce.0.to_vec(),
                     location,
@@ -183,47 +197,123 @@ impl LocationStorage for MyLocationStorage {
     ) -> Result<Response<RequestMyProofsResponse>, Status> {
         let request = request.get_ref();
 
-        self.check_proof_of_work(&request.pow, &request.user_info)?;
+        let (info, proofs_req, signed) : (_, MyProofsRequest, _) = self.decode_request(&request.pow, &request.user_info, &request.epochs)?;
 
-        let info = if let Ok(info) = decode_info(
-            self.server_keys.private_key(),
-            self.server_keys.public_key(),
-            &request.user_info) {
-            info
-        } else {
-            return Err(Status::permission_denied("Unhable to decript sealed container"));
-        };
+        let first = proofs_req.epochs.iter().min().copied().unwrap_or(0);
+        let (proofs, nonce) = self.receipted(info.idx(), first, &signed, MyProofsResponse::new(self.storage.get_proofs(info.idx(), &proofs_req.epochs)), info.key());
 
-        let client_sign_key = if let Some(ck) = self.server_keys.client_sign_key(info.idx()) {
-            ck
-        } else {
-            return Err(Status::permission_denied(format!("Unable to find client {:} keys", info.idx())));
-        };
+        Ok( Response::new( RequestMyProofsResponse {
+            nonce : nonce.0.to_vec(),
+            proofs,
+        }))
+    }
 
-        if !self.storage.valid_nonce(info.idx(), info.nonce()) {
-            return Err(Status::already_exists("nonce already exists"));
+    async fn check_exposure(
+        &self,
+        request : Request<CheckExposureRequest>,
+    ) -> Result<Response<CheckExposureResponse>, Status> {
+        let request = request.get_ref();
+
+        let (info, check_req, signed) : (_, ExposureCheck, _) = self.decode_request(&request.pow, &request.user_info, &request.request)?;
+
+        if !EXPOSURE_WINDOWS.contains(&check_req.last_epochs()) {
+            return Err(Status::invalid_argument(format!("Exposure can only be checked for the last {:?} epochs", EXPOSURE_WINDOWS)));
         }
 
-        let proofs_req = match decode_my_proofs_request(
-            client_sign_key,
-            info.key(),
-            &request.epochs,
-            info.nonce(),
-        ) {
-            Ok(location_request) => {
-                if !self.storage.add_nonce(info.idx(), info.nonce().clone()) {
-                    return  Err(Status::permission_denied("nonce already exists"));
-                }
-                location_request
+        // Anchored on the clock, not on whatever epoch some client reported last
+        let exposed = match self.echo.clock().current() {
+            Some(current) => {
+                let first = (current + 1).saturating_sub(check_req.last_epochs());
+                self.storage.was_exposed(info.idx(), (first, current))
             }
-            Err(_) => return  Err(Status::permission_denied("Unable to decrypt report"))
+            None => false,
         };
 
-        let (proofs, nonce) = encode_my_proofs_response(info.key(), self.storage.get_proofs(info.idx(), &proofs_req.epochs));
+        let (exposed, nonce) = self.receipted(info.idx(), 0, &signed, ExposureCheckResponse::new(exposed), info.key());
+        Ok( Response::new( CheckExposureResponse {
+            nonce : nonce.0.to_vec(),
+            exposed,
+        }))
+    }
 
-        Ok( Response::new( RequestMyProofsResponse {
+    async fn obtain_my_accesses(
+        &self,
+        request : Request<ObtainMyAccessesRequest>,
+    ) -> Result<Response<ObtainMyAccessesResponse>, Status> {
+        let request = request.get_ref();
+
+        let (info, accesses_req, signed) : (_, MyAccessesRequest, _) = self.decode_request(&request.pow, &request.user_info, &request.request)?;
+
+        let (first, last) = accesses_req.epochs();
+        if first > last {
+            return Err(Status::invalid_argument(format!("Not a valid epoch range {:}..{:}", first, last)));
+        }
+
+        let (accesses, nonce) = self.receipted(info.idx(), first, &signed, MyAccessesResponse::new(self.audit.accesses(info.idx(), (first, last))), info.key());
+        Ok( Response::new( ObtainMyAccessesResponse {
             nonce : nonce.0.to_vec(),
-            proofs,
+            accesses,
+        }))
+    }
+    async fn obtain_my_data(
+        &self,
+        request : Request<ObtainMyDataRequest>,
+    ) -> Result<Response<ObtainMyDataResponse>, Status> {
+        let request = request.get_ref();
+
+        let (info, _, signed) : (_, UserDataRequest, _) = self.decode_request(&request.pow, &request.user_info, &request.request)?;
+
+        let (data, nonce) = self.receipted(info.idx(), 0, &signed, self.storage.get_user_data(info.idx()), info.key());
+        Ok( Response::new( ObtainMyDataResponse {
+            nonce : nonce.0.to_vec(),
+            data,
+        }))
+    }
+
+    async fn request_erasure(
+        &self,
+        request : Request<RequestErasureRequest>,
+    ) -> Result<Response<RequestErasureResponse>, Status> {
+        let request = request.get_ref();
+
+        let (info, erasure, signed) : (_, ErasureRequest, _) = self.decode_request(&request.pow, &request.user_info, &request.erasure)?;
+
+        if erasure.idx != info.idx() {
+            return Err(Status::permission_denied("Users can only erase their own data"));
+        }
+
+        if let Err(err) = self.echo.confirm_erasure(&erasure).await {
+            return Err(Status::failed_precondition(err.to_string()));
+        }
+
+        let receipt = Receipt::new(self.echo.server_id(), erasure.idx, erasure.before, &signed, &ACCEPTED, self.server_keys.sign_key());
+        let (ok, nonce) = encode_receipt(info.key(), &receipt);
+        Ok( Response::new( RequestErasureResponse {
+            nonce : nonce.0.to_vec(),
+            ok,
+        }))
+    }
+
+    async fn obtain_beacon(
+        &self,
+        request : Request<ObtainBeaconRequest>,
+    ) -> Result<Response<ObtainBeaconResponse>, Status> {
+        let request = request.get_ref();
+
+        let (info, beacon_req, signed) : (_, BeaconRequest, _) = self.decode_request(&request.pow, &request.user_info, &request.request)?;
+
+        let (beacon, nonce) = self.receipted(info.idx(), beacon_req.epoch, &signed, BeaconResponse { beacon : self.echo.beacon(beacon_req.epoch) }, info.key());
+        Ok( Response::new( ObtainBeaconResponse {
+            nonce : nonce.0.to_vec(),
+            beacon,
         }))
     }
-}
\ No newline at end of file
+}
+
+fn rejected(rejection : Rejection) -> Status {
+    match rejection.rule {
+        Rule::Blacklisted => Status::permission_denied(rejection.to_string()),
+        Rule::EpochWindow => Status::out_of_range(rejection.to_string()),
+        _ => Status::aborted(rejection.to_string()),
+    }
+}
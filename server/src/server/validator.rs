@@ -0,0 +1,406 @@
+use std::{collections::{HashMap, HashSet}, fmt, str::FromStr};
+
+use eyre::eyre;
+use color_eyre::eyre::Result;
+use sodiumoxide::crypto::sign;
+use security::{clock::EpochClock, proof::{Proof, verify_proof}, report::Report};
+
+use crate::storage::Timeline;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    // Report
+    Requester, // report belongs to the client submitting it
+    Position, // report is inside the grid
+    EpochWindow, // epoch is still retained, not closed and taken on the clock
+    Blacklisted, // requester is not blacklisted
+    // Proofs
+    Signature, // proof signed by the witness for this requester and epoch
+    Beacon, // proof carries a complete beacon agreed for its epoch, so it was not signed before
+    Proximity, // witness is next to the requester
+    WitnessReport, // witness is where it reported itself, if it did
+    BlacklistedWitness,
+    FlaggedWitness,
+    DistinctWitnesses, // each witness counts once
+    // Count
+    MinimumWitnesses, // failing proofs are ignored while more than f_line pass, without it any failing proof rejects the report
+}
+
+const REPORT_RULES : [Rule; 4] = [Rule::Requester, Rule::Position, Rule::EpochWindow, Rule::Blacklisted];
+const PROOF_RULES : [Rule; 7] = [Rule::Signature, Rule::Beacon, Rule::Proximity, Rule::WitnessReport, Rule::BlacklistedWitness, Rule::FlaggedWitness, Rule::DistinctWitnesses];
+
+impl Rule {
+    pub fn all() -> Vec<Rule> {
+        REPORT_RULES.iter().chain(PROOF_RULES.iter()).copied().chain(std::iter::once(Rule::MinimumWitnesses)).collect()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::Requester => "requester",
+            Rule::Position => "position",
+            Rule::EpochWindow => "epoch_window",
+            Rule::Blacklisted => "blacklisted",
+            Rule::Signature => "signature",
+            Rule::Beacon => "beacon",
+            Rule::Proximity => "proximity",
+            Rule::WitnessReport => "witness_report",
+            Rule::BlacklistedWitness => "blacklisted_witness",
+            Rule::FlaggedWitness => "flagged_witness",
+            Rule::DistinctWitnesses => "distinct_witnesses",
+            Rule::MinimumWitnesses => "minimum_witnesses",
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:}", self.name())
+    }
+}
+
+impl FromStr for Rule {
+    type Err = eyre::Report;
+
+    fn from_str(name : &str) -> Result<Rule> {
+        Rule::all().into_iter().find(|rule| rule.name() == name).ok_or_else(|| eyre!("Unknown validation rule '{:}'", name))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Rejection {
+    pub rule : Rule,
+    pub reason : String,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Rejected by rule {:}: {:}", self.rule, self.reason)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReportValidator {
+    rules : Vec<Rule>,
+    f_line : usize,
+    f_servers : usize,
+    clock : Option<EpochClock>, // none to judge reports whenever they were delivered
+}
+
+impl ReportValidator {
+    pub fn new(rules : Vec<Rule>, f_line : usize, f_servers : usize, clock : Option<EpochClock>) -> ReportValidator {
+        ReportValidator {
+            rules,
+            f_line,
+            f_servers,
+            clock,
+        }
+    }
+
+    // Same rules for reports other servers already delivered, which the clock may have sealed since
+    pub fn without_clock(&self) -> ReportValidator {
+        ReportValidator {
+            clock : None,
+            ..self.clone()
+        }
+    }
+
+    pub fn f_line(&self) -> usize { self.f_line }
+
+    fn enabled(&self, rule : Rule) -> bool {
+        self.rules.contains(&rule)
+    }
+
+    pub fn validate(
+        &self,
+        client_keys : &HashMap<usize, sign::PublicKey>,
+        storage : &Timeline,
+        req_idx : usize,
+        report : &Report,
+    ) -> Result<(), Rejection> {
+        self.check_report(storage, req_idx, report, &REPORT_RULES)?;
+
+        let minimum = self.enabled(Rule::MinimumWitnesses);
+        let mut witnesses = HashSet::new();
+        let mut failed : HashMap<Rule, usize> = HashMap::new();
+
+        for (idx, signed_proof) in report.proofs() {
+            match self.check_proof(client_keys, storage, report, *idx, signed_proof, &witnesses) {
+                Ok(()) => { witnesses.insert(*idx); }
+                // Without a count to meet, every proof must pass
+                Err(rule) if !minimum => return Err(Rejection { rule, reason : format!("proof of witness {:} rejected", idx) }),
+                Err(rule) => *failed.entry(rule).or_default() += 1,
+            }
+            if minimum && witnesses.len() > self.f_line {
+                return Ok(());
+            }
+        }
+
+        // Turning the rule off makes every proof count, it never lowers how many are needed
+        if witnesses.len() > self.f_line {
+            return Ok(());
+        }
+
+        let mut failed : Vec<String> = failed.into_iter().map(|(rule, n)| format!("{:} rejected {:}", rule, n)).collect();
+        failed.sort();
+        let mut reason = format!("{:} of {:} required proofs accepted", witnesses.len(), self.f_line + 1);
+        if !failed.is_empty() {
+            reason = format!("{:} ({:})", reason, failed.join(", "));
+        }
+        Err(Rejection {
+            rule : Rule::MinimumWitnesses,
+            reason,
+        })
+    }
+
+    // Runs the enabled report rules among the given ones, returns the first one the report fails
+    pub fn check_report(&self, storage : &Timeline, req_idx : usize, report : &Report, rules : &[Rule]) -> Result<(), Rejection> {
+        for rule in self.rules.iter().filter(|rule| REPORT_RULES.contains(rule) && rules.contains(rule)) {
+            self.check_report_rule(*rule, storage, req_idx, report).map_err(|reason| Rejection { rule : *rule, reason })?;
+        }
+        Ok(())
+    }
+
+    fn check_report_rule(&self, rule : Rule, storage : &Timeline, req_idx : usize, report : &Report) -> Result<(), String> {
+        let epoch = report.epoch();
+        match rule {
+            Rule::Requester if req_idx != report.idx() =>
+                Err(format!("report of client {:} submitted by client {:}", report.idx(), req_idx)),
+            Rule::Position if !storage.valid_pos(report.loc().0, report.loc().1) =>
+                Err(format!("position {:?} is outside the grid", report.loc())),
+            Rule::EpochWindow if !storage.is_retained(epoch) =>
+                Err(format!("epoch {:} is no longer retained", epoch)),
+            Rule::EpochWindow if storage.is_closed(epoch) =>
+                Err(format!("epoch {:} is closed", epoch)),
+            Rule::EpochWindow => match &self.clock {
+                Some(clock) if !clock.accepts(epoch) => Err(format!("epoch {:} is {:}, the current epoch is {:}",
+                    epoch, clock.phase(epoch), clock.current().map_or("none yet".to_string(), |current| current.to_string()))),
+                _ => Ok(()),
+            },
+            Rule::Blacklisted if storage.is_blacklisted(report.idx()) =>
+                Err(format!("client {:} is blacklisted", report.idx())),
+            _ => Ok(()),
+        }
+    }
+
+    // Returns the first proof rule the proof fails
+    fn check_proof(
+        &self,
+        client_keys : &HashMap<usize, sign::PublicKey>,
+        storage : &Timeline,
+        report : &Report,
+        idx : usize,
+        signed_proof : &Vec<u8>,
+        counted : &HashSet<usize>,
+    ) -> Result<(), Rule> {
+        let proof = match client_keys.get(&idx).map(|sign_key| verify_proof(sign_key, signed_proof)) {
+            Some(Ok(proof)) => proof,
+            _ => return Err(Rule::Signature),
+        };
+
+        for rule in self.rules.iter().filter(|rule| PROOF_RULES.contains(rule)) {
+            let ok = match rule {
+                Rule::Signature => bound_to(&proof, report, idx),
+                Rule::Beacon => storage.get_agreed_beacons(report.epoch()).iter()
+                    .any(|beacon| beacon.is_complete(self.f_servers) && beacon.value() == proof.beacon()),
+                Rule::Proximity => {
+                    let ((lower_x, lower_y), (upper_x, upper_y)) = storage.valid_neighbour(report.loc().0, report.loc().1);
+                    let (x, y) = proof.loc_ass();
+                    lower_x <= x && x <= upper_x && lower_y <= y && y <= upper_y
+                }
+                Rule::WitnessReport => match storage.get_user_location_at_epoch(report.epoch(), idx) {
+                    Some(own_loc) => own_loc == proof.loc_ass(),
+                    None => true,
+                },
+                Rule::BlacklistedWitness => !storage.is_blacklisted(idx),
+                Rule::FlaggedWitness => !storage.is_flagged_witness(idx, report.epoch()),
+                Rule::DistinctWitnesses => !counted.contains(&idx),
+                _ => true,
+            };
+            if !ok {
+                return Err(*rule);
+            }
+        }
+        Ok(())
+    }
+
+    // Proofs correctly signed by their witness for this report, whether or not they are accepted
+    pub fn signed_proofs(&self, client_keys : &HashMap<usize, sign::PublicKey>, report : &Report) -> Vec<(Proof, Vec<u8>)> {
+        let mut proofs = vec![];
+        for (idx, signed_proof) in report.proofs() {
+            if let Some(sign_key) = client_keys.get(idx) {
+                if let Ok(proof) = verify_proof(sign_key, signed_proof) {
+                    if bound_to(&proof, report, *idx) {
+                        proofs.push((proof, signed_proof.clone()));
+                    }
+                }
+            }
+        }
+        proofs
+    }
+}
+
+fn bound_to(proof : &Proof, report : &Report, idx : usize) -> bool {
+    proof.epoch() == report.epoch() && proof.idx_req() == report.idx() && proof.idx_ass() == idx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use security::{beacon::BeaconShare, report::timestamp_now};
+    use crate::fixtures::{self, keys, FILENAME};
+
+    const SIZE : usize = 10;
+    const EPOCH : usize = 5;
+    const IDX : usize = 0;
+    const LOC : (usize, usize) = (3, 3);
+    const F_LINE : usize = 1;
+    const F_SERVERS : usize = 1;
+
+    // Storage that delivered a complete beacon for EPOCH
+    fn storage() -> (Timeline, Vec<u8>) {
+        fixtures::beacon_storage(SIZE, EPOCH, F_SERVERS)
+    }
+
+    fn report(secret_keys : &HashMap<usize, sign::SecretKey>, beacon : &[u8], witnesses : &[(usize, (usize, usize))]) -> Report {
+        fixtures::report(secret_keys, EPOCH, (IDX, LOC), witnesses, beacon)
+    }
+
+    #[test]
+    fn accept_valid_report() {
+        let (public_keys, secret_keys) = keys(3);
+        let (storage, beacon) = storage();
+        let validator = ReportValidator::new(Rule::all(), F_LINE, F_SERVERS, None);
+
+        let report = report(&secret_keys, &beacon, &[(1, LOC), (2, (4, 4))]);
+
+        assert_eq!(Ok(()), validator.validate(&public_keys, &storage, IDX, &report));
+        assert_eq!(2, validator.signed_proofs(&public_keys, &report).len());
+    }
+
+    #[test]
+    fn reject_names_rule() {
+        let (public_keys, secret_keys) = keys(3);
+        let (storage, beacon) = storage();
+        let validator = ReportValidator::new(Rule::all(), F_LINE, F_SERVERS, None);
+
+        let far_away = report(&secret_keys, &beacon, &[(1, LOC), (2, (8, 8))]);
+        let rejection = validator.validate(&public_keys, &storage, IDX, &far_away).unwrap_err();
+        assert_eq!(Rule::MinimumWitnesses, rejection.rule);
+        assert!(rejection.reason.contains("proximity rejected 1"));
+
+        let same_witness = report(&secret_keys, &beacon, &[(1, LOC), (1, LOC)]);
+        let rejection = validator.validate(&public_keys, &storage, IDX, &same_witness).unwrap_err();
+        assert!(rejection.reason.contains("distinct_witnesses rejected 1"));
+
+        let rejection = validator.validate(&public_keys, &storage, IDX + 1, &far_away).unwrap_err();
+        assert_eq!(Rule::Requester, rejection.rule);
+    }
+
+    #[test]
+    fn reject_other_beacon() {
+        let (public_keys, secret_keys) = keys(3);
+        let (storage, _) = storage();
+        let validator = ReportValidator::new(Rule::all(), F_LINE, F_SERVERS, None);
+
+        // Signed ahead of time, without the beacon of the epoch
+        let presigned = report(&secret_keys, b"guess", &[(1, LOC), (2, (4, 4))]);
+        let rejection = validator.validate(&public_keys, &storage, IDX, &presigned).unwrap_err();
+        assert!(rejection.reason.contains("beacon rejected 2"));
+
+        let unknown = report(&secret_keys, &[], &[(1, LOC), (2, (4, 4))]);
+        let empty = Timeline::new(SIZE, FILENAME.to_string());
+        let rejection = validator.validate(&public_keys, &empty, IDX, &unknown).unwrap_err();
+        assert!(rejection.reason.contains("beacon rejected 2"));
+
+        // A single share was shared, but no complete beacon agreed on
+        let partial = Timeline::new(SIZE, FILENAME.to_string());
+        partial.add_beacon_share(BeaconShare::new(0, EPOCH, &sign::gen_keypair().1));
+        let beacon = partial.get_beacon_shares(EPOCH).unwrap();
+        partial.add_agreed_beacon(0, beacon.clone());
+        let early = report(&secret_keys, &beacon.value(), &[(1, LOC), (2, (4, 4))]);
+        let rejection = validator.validate(&public_keys, &partial, IDX, &early).unwrap_err();
+        assert!(rejection.reason.contains("beacon rejected 2"));
+    }
+
+    #[test]
+    fn configurable_rules() {
+        let (public_keys, secret_keys) = keys(3);
+        let (storage, beacon) = storage();
+
+        let far_away = report(&secret_keys, &beacon, &[(1, LOC), (2, (8, 8))]);
+        let rules = Rule::all().into_iter().filter(|&rule| rule != Rule::Proximity).collect();
+        let validator = ReportValidator::new(rules, F_LINE, F_SERVERS, None);
+
+        assert_eq!(Ok(()), validator.validate(&public_keys, &storage, IDX, &far_away));
+
+        assert_eq!(Rule::WitnessReport, "witness_report".parse().unwrap());
+        assert!("not_a_rule".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn proof_rules_without_minimum() {
+        let (public_keys, secret_keys) = keys(3);
+        let (storage, beacon) = storage();
+        let rules : Vec<Rule> = Rule::all().into_iter().filter(|&rule| rule != Rule::MinimumWitnesses).collect();
+        let validator = ReportValidator::new(rules.clone(), F_LINE, F_SERVERS, None);
+
+        // Every proof must pass
+        let near = report(&secret_keys, &beacon, &[(1, LOC), (2, (4, 4))]);
+        assert_eq!(Ok(()), validator.validate(&public_keys, &storage, IDX, &near));
+
+        let far_away = report(&secret_keys, &beacon, &[(1, LOC), (2, (8, 8))]);
+        let rejection = validator.validate(&public_keys, &storage, IDX, &far_away).unwrap_err();
+        assert_eq!(Rule::Proximity, rejection.rule);
+        assert!(rejection.reason.contains("witness 2"));
+
+        let presigned = report(&secret_keys, b"guess", &[(1, LOC), (2, (4, 4))]);
+        let rejection = validator.validate(&public_keys, &storage, IDX, &presigned).unwrap_err();
+        assert_eq!(Rule::Beacon, rejection.rule);
+
+        let rules = rules.into_iter().filter(|&rule| rule != Rule::Proximity).collect();
+        let validator = ReportValidator::new(rules, F_LINE, F_SERVERS, None);
+        assert_eq!(Ok(()), validator.validate(&public_keys, &storage, IDX, &far_away));
+    }
+
+    #[test]
+    fn minimum_kept_without_rule() {
+        let (public_keys, secret_keys) = keys(3);
+        let (storage, beacon) = storage();
+        let rules = Rule::all().into_iter().filter(|&rule| rule != Rule::MinimumWitnesses).collect();
+        let validator = ReportValidator::new(rules, F_LINE, F_SERVERS, None);
+
+        let unproven = report(&secret_keys, &beacon, &[]);
+        let rejection = validator.validate(&public_keys, &storage, IDX, &unproven).unwrap_err();
+        assert_eq!(Rule::MinimumWitnesses, rejection.rule);
+        assert_eq!("0 of 2 required proofs accepted", rejection.reason);
+
+        let single = report(&secret_keys, &beacon, &[(1, LOC)]);
+        let rejection = validator.validate(&public_keys, &storage, IDX, &single).unwrap_err();
+        assert_eq!(Rule::MinimumWitnesses, rejection.rule);
+    }
+
+    #[test]
+    fn epoch_window_follows_clock() {
+        let (public_keys, secret_keys) = keys(3);
+        let (storage, beacon) = storage();
+        let report = report(&secret_keys, &beacon, &[(1, LOC), (2, (4, 4))]);
+        let duration = 1_000;
+
+        let open = EpochClock::new(timestamp_now() - EPOCH as u64 * duration, duration, 1);
+        let validator = ReportValidator::new(Rule::all(), F_LINE, F_SERVERS, Some(open));
+        assert_eq!(Ok(()), validator.validate(&public_keys, &storage, IDX, &report));
+
+        let early = EpochClock::new(timestamp_now() + duration, duration, 1);
+        let validator = ReportValidator::new(Rule::all(), F_LINE, F_SERVERS, Some(early));
+        let rejection = validator.check_report(&storage, IDX, &report, &[Rule::EpochWindow]).unwrap_err();
+        assert_eq!(Rule::EpochWindow, rejection.rule);
+        assert!(rejection.reason.contains("not open yet"));
+        assert_eq!(Ok(()), validator.check_report(&storage, IDX, &report, &[Rule::Blacklisted]));
+
+        // Sealed epochs are still taken from the other servers
+        let sealed = EpochClock::new(1, 1, 1);
+        let validator = ReportValidator::new(Rule::all(), F_LINE, F_SERVERS, Some(sealed));
+        assert_eq!(Rule::EpochWindow, validator.validate(&public_keys, &storage, IDX, &report).unwrap_err().rule);
+        assert_eq!(Ok(()), validator.without_clock().validate(&public_keys, &storage, IDX, &report));
+    }
+}
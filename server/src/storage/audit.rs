@@ -0,0 +1,257 @@
+use std::{fs::{File, OpenOptions}, io::{BufRead, BufReader, Write}};
+use std::sync::RwLock;
+
+use serde_derive::{Deserialize, Serialize};
+use eyre::eyre;
+use color_eyre::eyre::Result;
+use sodiumoxide::crypto::{hash::sha256, sign};
+
+use security::{audit::AuditHead, report::timestamp_now, status::DataAccess};
+
+// One HA query, chained to the previous entry and signed by the server
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AuditEntry {
+    pub seq : usize,
+    pub operator : usize,
+    pub query : String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub touched : Vec<(usize, usize)>, // user, epoch
+    pub result_size : usize,
+    pub timestamp : u64,
+    pub prev_hash : Vec<u8>,
+    pub hash : Vec<u8>,
+    pub signature : Vec<u8>,
+}
+
+impl AuditEntry {
+    fn new(prev : Option<&AuditEntry>, operator : usize, query : String, touched : Vec<(usize, usize)>, result_size : usize, sign_key : &sign::SecretKey) -> AuditEntry {
+        let mut entry = AuditEntry {
+            seq : prev.map_or(0, |prev| prev.seq + 1),
+            operator,
+            query,
+            touched,
+            result_size,
+            timestamp : timestamp_now(),
+            prev_hash : prev.map_or_else(|| vec![0; sha256::DIGESTBYTES], |prev| prev.hash.clone()),
+            hash : vec![],
+            signature : vec![],
+        };
+        entry.hash = entry.digest();
+        entry.signature = sign::sign_detached(&entry.hash, sign_key).0.to_vec();
+        entry
+    }
+
+    // Entries that touched no user hash as they did before touched was recorded, so older logs still verify
+    fn digest(&self) -> Vec<u8> {
+        let content = if self.touched.is_empty() {
+            serde_json::to_vec(&(self.seq, self.operator, &self.query, self.result_size, self.timestamp, &self.prev_hash)).unwrap()
+        } else {
+            serde_json::to_vec(&(self.seq, self.operator, &self.query, &self.touched, self.result_size, self.timestamp, &self.prev_hash)).unwrap()
+        };
+        sha256::hash(&content).0.to_vec()
+    }
+}
+
+// Append only, one JSON entry per line
+pub struct AuditLog {
+    entries : RwLock<Vec<AuditEntry>>,
+    server_id : usize,
+    sign_key : sign::SecretKey,
+    filename : String,
+}
+
+impl AuditLog {
+    // Keeps the entries already in the file, if there is one
+    pub fn open(filename : String, server_id : usize, sign_key : sign::SecretKey) -> Result<AuditLog> {
+        let entries = match File::open(&filename) {
+            Ok(file) => read_entries(BufReader::new(file))?,
+            Err(_) => vec![],
+        };
+        Ok(AuditLog {
+            entries : RwLock::new(entries),
+            server_id,
+            sign_key,
+            filename,
+        })
+    }
+
+    // Returns the new head, for the other servers to keep
+    pub fn record(&self, operator : usize, query : String, touched : Vec<(usize, usize)>, result_size : usize) -> Result<AuditHead> {
+        let mut entries = self.entries.write().unwrap();
+
+        let entry = AuditEntry::new(entries.last(), operator, query, touched, result_size, &self.sign_key);
+
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.filename)?;
+        file.write_all(&line)?;
+        file.sync_data()?;
+
+        let head = AuditHead::new(self.server_id, entry.seq, entry.hash.clone(), &self.sign_key);
+        entries.push(entry);
+        Ok(head)
+    }
+
+    // Only when and which of the user's epochs, the query itself may reveal other users
+    pub fn accesses(&self, idx : usize, (first, last) : (usize, usize)) -> Vec<DataAccess> {
+        self.entries.read().unwrap().iter().filter_map(|entry| {
+            let mut epochs : Vec<usize> = entry.touched.iter()
+                .filter(|(user, epoch)| *user == idx && first <= *epoch && *epoch <= last)
+                .map(|(_, epoch)| *epoch).collect();
+            epochs.sort_unstable();
+            epochs.dedup();
+            if epochs.is_empty() {
+                None
+            } else {
+                Some(DataAccess { timestamp : entry.timestamp, epochs })
+            }
+        }).collect()
+    }
+}
+
+pub fn read_entries(reader : impl BufRead) -> Result<Vec<AuditEntry>> {
+    let mut entries = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            entries.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(entries)
+}
+
+pub fn retrieve_audit_log(filename : &str) -> Result<Vec<AuditEntry>> {
+    read_entries(BufReader::new(File::open(filename)?))
+}
+
+// Fails at the first entry that was changed, removed or not signed by the server
+pub fn verify_chain(entries : &[AuditEntry], public_key : &sign::PublicKey) -> Result<()> {
+    let mut prev_hash = vec![0; sha256::DIGESTBYTES];
+    for (seq, entry) in entries.iter().enumerate() {
+        if entry.seq != seq {
+            return Err(eyre!("Audit entry {:} is out of place, expected {:}", entry.seq, seq));
+        }
+        if entry.prev_hash != prev_hash || entry.hash != entry.digest() {
+            return Err(eyre!("Audit entry {:} does not match the chain", seq));
+        }
+        match sign::Signature::from_slice(&entry.signature) {
+            Some(signature) if sign::verify_detached(&signature, &entry.hash, public_key) => {}
+            _ => return Err(eyre!("Audit entry {:} has a bad signature", seq)),
+        }
+        prev_hash = entry.hash.clone();
+    }
+    Ok(())
+}
+
+// A head another server kept must still be in the log, or entries were dropped from its end
+pub fn verify_head(entries : &[AuditEntry], head : &AuditHead, public_key : &sign::PublicKey) -> Result<()> {
+    if !head.verify(public_key) {
+        return Err(eyre!("Audit head kept by the other server has a bad signature"));
+    }
+    match entries.get(head.seq) {
+        Some(entry) if entry.hash == head.hash => Ok(()),
+        Some(_) => Err(eyre!("Audit entry {:} differs from the one the other server was given", head.seq)),
+        None => Err(eyre!("Audit log ends at {:} entries, but the other server was given entry {:}", entries.len(), head.seq)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATOR : usize = 1;
+
+    fn chain(sign_key : &sign::SecretKey) -> Vec<AuditEntry> {
+        let first = AuditEntry::new(None, OPERATOR, "location of user 3 at epoch 2".to_string(), vec![(3, 2)], 1, sign_key);
+        let second = AuditEntry::new(Some(&first), OPERATOR, "heatmap".to_string(), vec![], 4, sign_key);
+        vec![first, second]
+    }
+
+    #[test]
+    fn verify_untouched_chain() {
+        let (public_key, sign_key) = sign::gen_keypair();
+        let entries = chain(&sign_key);
+
+        assert!(verify_chain(&entries, &public_key).is_ok());
+        assert!(verify_chain(&entries, &sign::gen_keypair().0).is_err());
+    }
+
+    #[test]
+    fn detect_tampering() {
+        let (public_key, sign_key) = sign::gen_keypair();
+
+        let mut changed = chain(&sign_key);
+        changed[0].result_size = 0;
+        assert!(verify_chain(&changed, &public_key).is_err());
+
+        let mut hidden = chain(&sign_key);
+        hidden[0].touched.clear();
+        assert!(verify_chain(&hidden, &public_key).is_err());
+
+        let mut removed = chain(&sign_key);
+        removed.remove(0);
+        assert!(verify_chain(&removed, &public_key).is_err());
+
+        // Rebuilding the chain needs the server's key
+        let (_, other_key) = sign::gen_keypair();
+        assert!(verify_chain(&chain(&other_key), &public_key).is_err());
+    }
+
+    #[test]
+    fn read_log_without_touched() {
+        let (public_key, sign_key) = sign::gen_keypair();
+        let entry = AuditEntry::new(None, OPERATOR, "heatmap".to_string(), vec![], 4, &sign_key);
+
+        // Same line and hash as before touched was recorded
+        let line = serde_json::to_string(&entry).unwrap();
+        assert!(!line.contains("touched"));
+        let content = serde_json::to_vec(&(entry.seq, entry.operator, &entry.query, entry.result_size, entry.timestamp, &entry.prev_hash)).unwrap();
+        assert_eq!(sha256::hash(&content).0.to_vec(), entry.hash);
+
+        let read = read_entries(format!("{:}\n", line).as_bytes()).unwrap();
+        assert_eq!(vec![entry], read);
+        assert!(verify_chain(&read, &public_key).is_ok());
+    }
+
+    #[test]
+    fn detect_truncation() {
+        let (public_key, sign_key) = sign::gen_keypair();
+        let filename = std::env::temp_dir().join(format!("audit_head_{:}.log", std::process::id()));
+        let log = AuditLog::open(filename.to_str().unwrap().to_string(), 0, sign_key).unwrap();
+
+        log.record(OPERATOR, "location of user 3 at epoch 2".to_string(), vec![(3, 2)], 1).unwrap();
+        let head = log.record(OPERATOR, "heatmap".to_string(), vec![], 4).unwrap();
+        assert_eq!(1, head.seq);
+
+        let mut entries = retrieve_audit_log(filename.to_str().unwrap()).unwrap();
+        assert!(verify_head(&entries, &head, &public_key).is_ok());
+        assert!(verify_head(&entries, &head, &sign::gen_keypair().0).is_err());
+
+        // Still a valid chain, but the head shows it was cut short
+        entries.pop();
+        assert!(verify_chain(&entries, &public_key).is_ok());
+        assert!(verify_head(&entries, &head, &public_key).is_err());
+
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn accesses_of_user() {
+        let (_, sign_key) = sign::gen_keypair();
+        let filename = std::env::temp_dir().join(format!("audit_{:}.log", std::process::id()));
+        let log = AuditLog::open(filename.to_str().unwrap().to_string(), 0, sign_key).unwrap();
+
+        log.record(OPERATOR, "trajectory of user 3 during epochs 0..5".to_string(), vec![(3, 1), (3, 2), (3, 4)], 3).unwrap();
+        log.record(OPERATOR, "users at (1, 1) at epoch 2".to_string(), vec![(3, 2), (3, 2), (5, 2)], 2).unwrap();
+        log.record(OPERATOR, "heatmap during epochs 0..5".to_string(), vec![], 4).unwrap();
+
+        let accesses = log.accesses(3, (2, 5));
+        assert_eq!(accesses.len(), 2);
+        assert_eq!(accesses[0].epochs, vec![2, 4]);
+        assert_eq!(accesses[1].epochs, vec![2]);
+        assert!(log.accesses(5, (3, 5)).is_empty());
+        assert!(log.accesses(7, (0, 5)).is_empty());
+
+        std::fs::remove_file(filename).unwrap();
+    }
+}
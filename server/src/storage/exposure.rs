@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+
+use security::status::ExposureLink;
+
+use super::Timeline;
+
+struct Exposure {
+    contagious_from : usize, // first epoch in which the user can expose others
+    hops : usize,
+    link : Option<ExposureLink>, // contact that exposed the user, none for the source
+}
+
+// Chains of undisputed contacts reaching each user exposed by `source` within `hops` contacts.
+// Contacts only propagate forward in time: a user exposed at epoch e exposes others from e + 1,
+// and each user keeps the earliest chain that reached them.
+pub fn exposure_chains(storage : &Timeline, source : usize, (first, last) : (usize, usize), hops : usize) -> Vec<Vec<ExposureLink>> {
+    let mut exposed : BTreeMap<usize, Exposure> = BTreeMap::new();
+    exposed.insert(source, Exposure { contagious_from : first, hops : 0, link : None });
+
+    for epoch in storage.get_epochs((first, last)) {
+        let carriers : Vec<usize> = exposed.iter().filter(
+            |(_, exposure)| exposure.contagious_from <= epoch && exposure.hops < hops
+        ).map(|(idx, _)| *idx).collect();
+
+        for carrier in carriers {
+            let from_cell = match storage.get_user_location_at_epoch(epoch, carrier) {
+                Some(from_cell) => from_cell,
+                None => continue,
+            };
+            let carrier_hops = exposed[&carrier].hops;
+            for to in storage.get_neighbours_at_epoch(epoch, carrier) {
+                if exposed.contains_key(&to) {
+                    continue;
+                }
+                if let Some(to_cell) = storage.get_user_location_at_epoch(epoch, to) {
+                    exposed.insert(to, Exposure {
+                        contagious_from : epoch + 1,
+                        hops : carrier_hops + 1,
+                        link : Some(ExposureLink { from : carrier, to, epoch, from_cell, to_cell }),
+                    });
+                }
+            }
+        }
+    }
+
+    exposed.keys().filter(|&&idx| idx != source).map(|idx| {
+        let mut chain = vec![];
+        let mut current = *idx;
+        while let Some(link) = &exposed[&current].link {
+            chain.push(link.clone());
+            current = link.from;
+        }
+        chain.reverse();
+        chain
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE : usize = 10;
+    const FILENAME : &str = "storage/test.txt";
+    const SOURCE : usize = 1;
+    const EPOCH : usize = 5;
+
+    fn add(storage : &Timeline, epoch : usize, loc : (usize, usize), idx : usize) {
+        assert!(storage.add_user_location_at_epoch(epoch, loc, idx, vec![]).is_ok());
+    }
+
+    #[test]
+    fn chains_follow_time_order() {
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+
+        // 1 meets 2, then 2 meets 3
+        add(&storage, EPOCH, (0, 0), SOURCE);
+        add(&storage, EPOCH, (1, 1), 2);
+        add(&storage, EPOCH + 1, (5, 5), 2);
+        add(&storage, EPOCH + 1, (5, 6), 3);
+        // 4 met 3 before 3 was exposed
+        add(&storage, EPOCH, (8, 8), 3);
+        add(&storage, EPOCH, (8, 9), 4);
+
+        let chains = exposure_chains(&storage, SOURCE, (EPOCH, EPOCH + 1), 2);
+        assert_eq!(vec![
+            vec![ExposureLink { from : SOURCE, to : 2, epoch : EPOCH, from_cell : (0, 0), to_cell : (1, 1) }],
+            vec![
+                ExposureLink { from : SOURCE, to : 2, epoch : EPOCH, from_cell : (0, 0), to_cell : (1, 1) },
+                ExposureLink { from : 2, to : 3, epoch : EPOCH + 1, from_cell : (5, 5), to_cell : (5, 6) },
+            ],
+        ], chains);
+
+        assert_eq!(1, exposure_chains(&storage, SOURCE, (EPOCH, EPOCH + 1), 1).len());
+        assert!(exposure_chains(&storage, SOURCE, (EPOCH + 1, EPOCH + 1), 2).is_empty());
+    }
+
+    #[test]
+    fn same_epoch_does_not_chain() {
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+
+        add(&storage, EPOCH, (0, 0), SOURCE);
+        add(&storage, EPOCH, (1, 1), 2);
+        add(&storage, EPOCH, (2, 2), 3);
+
+        let chains = exposure_chains(&storage, SOURCE, (EPOCH, EPOCH), 3);
+        assert_eq!(1, chains.len());
+        assert_eq!(2, chains[0][0].to);
+    }
+}
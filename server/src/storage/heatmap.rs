@@ -0,0 +1,97 @@
+use sodiumoxide::crypto::hash::sha256;
+
+use super::Timeline;
+
+// Distinct users per cell during the epochs, suppressing cells with fewer than k users.
+// Suppression looks at the exact count, so every cell shown holds at least k users. With
+// epsilon, Laplace noise is then added to the counts shown, hiding how many users above k
+// they hold. One user can change the count of at most one cell per epoch, so the noise
+// scale grows with the number of epochs covered. The noise is drawn from the server's seed,
+// so asking again for the same epochs gives the same answer instead of fresh noise to
+// average out.
+pub fn heatmap(storage : &Timeline, epochs : (usize, usize), k : usize, epsilon : Option<f64>, seed : &[u8]) -> Vec<((usize, usize), usize)> {
+    let sensitivity = storage.get_epochs(epochs).len().min(storage.size() * storage.size()).max(1) as f64;
+
+    storage.get_cell_counts(epochs).into_iter().filter(|(_, count)| *count >= k).map(|(cell, count)| {
+        match epsilon {
+            Some(epsilon) => {
+                let noise = laplace(sensitivity / epsilon, uniform(seed, epochs, epsilon, cell));
+                (cell, (count as f64 + noise).round().max(0.0) as usize)
+            }
+            None => (cell, count),
+        }
+    }).collect()
+}
+
+// uniform in (-0.5, 0.5), never reaching the ends
+fn uniform(seed : &[u8], epochs : (usize, usize), epsilon : f64, cell : (usize, usize)) -> f64 {
+    let digest = sha256::hash(&serde_json::to_vec(&("heatmap", seed, epochs, epsilon.to_bits(), cell)).unwrap());
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest.0[..8]);
+    ((u64::from_be_bytes(bytes) >> 11) as f64 + 0.5) / (1u64 << 53) as f64 - 0.5
+}
+
+fn laplace(scale : f64, u : f64) -> f64 {
+    -scale * u.signum() * (1.0 - 2.0 * u.abs()).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE : usize = 10;
+    const FILENAME : &str = "storage/test.txt";
+    const EPOCH : usize = 5;
+    const SEED : &[u8] = b"seed";
+
+    fn storage() -> Timeline {
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+        for idx in 0..3 {
+            assert!(storage.add_user_location_at_epoch(EPOCH, (1, 1), idx, vec![]).is_ok());
+            assert!(storage.add_user_location_at_epoch(EPOCH + 1, (1, 1), idx, vec![]).is_ok());
+        }
+        assert!(storage.add_user_location_at_epoch(EPOCH, (7, 7), 3, vec![]).is_ok());
+        storage
+    }
+
+    #[test]
+    fn suppress_small_cells() {
+        let storage = storage();
+
+        assert_eq!(vec![((1, 1), 3), ((7, 7), 1)], heatmap(&storage, (EPOCH, EPOCH + 1), 1, None, SEED));
+        assert_eq!(vec![((1, 1), 3)], heatmap(&storage, (EPOCH, EPOCH + 1), 2, None, SEED));
+        assert!(heatmap(&storage, (EPOCH, EPOCH + 1), 4, None, SEED).is_empty());
+    }
+
+    #[test]
+    fn same_noise_for_same_epochs() {
+        let storage = storage();
+
+        let noisy = heatmap(&storage, (EPOCH, EPOCH + 1), 0, Some(0.5), SEED);
+        assert_eq!(2, noisy.len());
+        assert_eq!(noisy, heatmap(&storage, (EPOCH, EPOCH + 1), 0, Some(0.5), SEED));
+
+        // Other seeds draw other noise
+        let answers : Vec<Vec<((usize, usize), usize)>> = (0..8u8)
+            .map(|seed| heatmap(&storage, (EPOCH, EPOCH + 1), 0, Some(0.5), &[seed]))
+            .collect();
+        assert!(answers.iter().any(|answer| *answer != noisy));
+    }
+
+    #[test]
+    fn suppress_noisy_counts() {
+        let storage = storage();
+
+        // With little noise the counts are the exact ones
+        assert_eq!(heatmap(&storage, (EPOCH, EPOCH + 1), 2, None, SEED), heatmap(&storage, (EPOCH, EPOCH + 1), 2, Some(1e9), SEED));
+
+        // Suppression looks at the exact count, whatever the noise: (7, 7) holds a single user
+        for seed in 0..64u8 {
+            for epsilon in [0.1, 0.5, 1.0].iter() {
+                let cells : Vec<(usize, usize)> = heatmap(&storage, (EPOCH, EPOCH + 1), 2, Some(*epsilon), &[seed])
+                    .into_iter().map(|(cell, _)| cell).collect();
+                assert_eq!(vec![(1, 1)], cells);
+            }
+        }
+    }
+}
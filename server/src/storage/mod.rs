@@ -1,2 +1,5 @@
 pub mod storage;
-pub use storage::*;
\ No newline at end of file
+pub use storage::*;
+pub mod exposure;
+pub mod heatmap;
+pub mod audit;
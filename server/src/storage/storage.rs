@@ -1,5 +1,5 @@
 use std::{fs::File, io::{BufReader, BufWriter}};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::RwLock;
 
 use serde_derive::{Deserialize, Serialize};
@@ -7,12 +7,21 @@ use eyre::{eyre, Context};
 use color_eyre::eyre::Result;
 use sodiumoxide::crypto::secretbox::Nonce;
 
+use security::{proof::Proof, report::timestamp_now, status::{BlacklistEntry, ContactsAtEpoch, FlaggedWitness, UserData}};
+use security::certificate::{DeliveryCertificate, DeliveryStatement};
+use security::merkle::{EpochRoot, Inclusion, MerkleTree, leaf_hash};
+use security::audit::AuditHead;
+use security::beacon::{BeaconShare, EpochBeacon};
+use sodiumoxide::crypto::sign;
+
 use atomicwrites::{AtomicFile, AllowOverwrite};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Report {
     loc : (usize, usize),
-    report : Vec<u8>
+    report : Vec<u8>,
+    #[serde(default)]
+    disputed : bool, // no longer supported by enough witnesses
 }
 
 impl Report {
@@ -20,10 +29,49 @@ impl Report {
     fn new(loc: (usize, usize), report: Vec<u8>) -> Report {
         Report {
             loc,
-            report
+            report,
+            disputed : false,
+        }
+    }
+}
+
+// A proof given by a witness, kept under (witness, epoch)
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Vouch {
+    requester : usize,
+    loc : (usize, usize), // location the witness signed for itself
+    proof : Vec<u8>,
+}
+
+impl Vouch {
+
+    fn new(requester : usize, loc : (usize, usize), proof : Vec<u8>) -> Vouch {
+        Vouch {
+            requester,
+            loc,
+            proof,
         }
     }
 }
+
+// The reports of a closed epoch, as the server committed to them
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Commitment {
+    leaves : Vec<Vec<u8>>, // ordered by user id
+    positions : HashMap<usize, usize>, // user id -> leaf, erased users keep their leaf but lose this
+    root : EpochRoot,
+}
+
+impl Commitment {
+    fn inclusion(&self, idx : usize) -> Option<Inclusion> {
+        let position = *self.positions.get(&idx)?;
+        Some(Inclusion {
+            root : self.root.clone(),
+            proof : MerkleTree::new(self.leaves.clone()).proof(position)?,
+        })
+    }
+}
+
 // pos_x -> pos_y -> user_id
 #[derive(Debug, Serialize, Deserialize)]
 struct Grid {
@@ -47,18 +95,90 @@ impl Grid {
     fn get_users_at_location(&self, pos_x : usize, pos_y : usize) -> Vec<usize> {
         self.grid[pos_x][pos_y].read().unwrap().iter().map(|&idx| idx).collect()
     }
+
+    // Only visits the cells inside the rectangle
+    fn get_users_in_region(&self, (from_x, from_y) : (usize, usize), (to_x, to_y) : (usize, usize)) -> Vec<usize> {
+        let mut users = vec![];
+        for column in self.grid.iter().take(to_x + 1).skip(from_x) {
+            for cell in column.iter().take(to_y + 1).skip(from_y) {
+                users.extend(cell.read().unwrap().iter());
+            }
+        }
+        users
+    }
 }
 
 
+// Data removed from storage once it falls out of the retention window
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Archive {
+    before : usize,
+    routes : HashMap<usize, HashMap<usize, Report>>, //epoch -> user id -> location/report
+    proofs : HashMap<usize, HashMap<usize, Vec<Vouch>>>, // user -> epoch -> proofs_given
+}
+
+impl Archive {
+    pub fn before(&self) -> usize { self.before }
+    pub fn is_empty(&self) -> bool { self.routes.is_empty() && self.proofs.is_empty() }
+
+    // Returns true if anything about the user was archived before the given epoch
+    pub fn erase_user(&mut self, idx : usize, before : usize) -> bool {
+        let mut erased = false;
+        for (_, users) in self.routes.iter_mut().filter(|(epoch, _)| **epoch < before) {
+            erased |= users.remove(&idx).is_some();
+        }
+        self.routes.retain(|_, users| !users.is_empty());
+
+        if let Some(u_proofs) = self.proofs.get_mut(&idx) {
+            let old_epochs : Vec<usize> = u_proofs.keys().filter(|&&epoch| epoch < before).copied().collect();
+            for epoch in old_epochs {
+                erased |= u_proofs.remove(&epoch).is_some();
+            }
+        }
+        for u_proofs in self.proofs.values_mut() {
+            for (_, e_proofs) in u_proofs.iter_mut().filter(|(epoch, _)| **epoch < before) {
+                let given = e_proofs.len();
+                e_proofs.retain(|vouch| vouch.requester != idx);
+                erased |= e_proofs.len() != given;
+            }
+            u_proofs.retain(|_, e_proofs| !e_proofs.is_empty());
+        }
+        self.proofs.retain(|_, u_proofs| !u_proofs.is_empty());
+        erased
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Timeline {
     routes : RwLock<HashMap<usize, RwLock<HashMap<usize, Report>>>>, //epoch -> user id -> location/report
-    proofs : RwLock<HashMap<usize, RwLock<HashMap<usize, Vec<Vec<u8>> >>>>, // user -> epoch -> proofs_given
-    timeline : RwLock<Vec<Grid>>,
+    proofs : RwLock<HashMap<usize, RwLock<HashMap<usize, Vec<Vouch> >>>>, // user -> epoch -> proofs_given
+    timeline : RwLock<BTreeMap<usize, Grid>>, // epoch -> grid, ordered for epoch range queries
     size : usize,
-    blacklist : RwLock<HashSet<usize>>,
-    nonces : RwLock<HashMap<usize, HashSet<Nonce>>>,
-    ha_nonces : RwLock<HashSet<Nonce>>,
+    blacklist : RwLock<HashMap<usize, BlacklistEntry>>, // user id -> proof of misbehaviour
+    #[serde(default)]
+    flagged : RwLock<HashMap<usize, BTreeMap<usize, FlaggedWitness>>>, // witness id -> epoch -> contradictory proofs
+    #[serde(default)]
+    infected : RwLock<HashMap<usize, usize>>, // user id -> epoch from which the user is infected
+    #[serde(default)]
+    erased : RwLock<HashMap<usize, usize>>, // user id -> epochs before this one were erased on request
+    #[serde(default)]
+    certificates : RwLock<HashMap<usize, HashMap<usize, Vec<DeliveryCertificate>>>>, // epoch -> user id -> certificates being collected, one per report
+    #[serde(default)]
+    commitments : RwLock<BTreeMap<usize, Commitment>>, // epoch -> reports committed to once it closed
+    #[serde(default)]
+    peer_roots : RwLock<HashMap<usize, BTreeMap<usize, EpochRoot>>>, // epoch -> server id -> root it published
+    #[serde(default)]
+    audit_heads : RwLock<HashMap<usize, AuditHead>>, // server id -> latest head of its audit log
+    #[serde(default)]
+    closed_before : RwLock<usize>, // epochs before this one no longer take reports
+    #[serde(default)]
+    beacons : RwLock<HashMap<usize, EpochBeacon>>, // epoch -> shares received so far
+    #[serde(default)]
+    agreed_beacons : RwLock<HashMap<usize, BTreeMap<usize, EpochBeacon>>>, // epoch -> server id -> proposal delivered through the double echo
+    nonces : RwLock<HashMap<usize, BTreeMap<u64, HashSet<Nonce>>>>, // user -> timestamp -> nonces
+    ha_nonces : RwLock<BTreeMap<u64, HashSet<Nonce>>>, // timestamp -> nonces
+    #[serde(default)]
+    purged_before : RwLock<usize>, // epochs before this one are no longer kept
     filename: String,
 }
 
@@ -67,11 +187,22 @@ impl Timeline {
         Timeline {
             routes : RwLock::new(HashMap::new()),
             proofs : RwLock::new(HashMap::new()),
-            timeline : RwLock::new(vec![]),
+            timeline : RwLock::new(BTreeMap::new()),
             size,
-            blacklist : RwLock::new(HashSet::new()),
+            blacklist : RwLock::new(HashMap::new()),
+            flagged : RwLock::new(HashMap::new()),
+            infected : RwLock::new(HashMap::new()),
+            erased : RwLock::new(HashMap::new()),
+            certificates : RwLock::new(HashMap::new()),
+            commitments : RwLock::new(BTreeMap::new()),
+            peer_roots : RwLock::new(HashMap::new()),
+            audit_heads : RwLock::new(HashMap::new()),
+            closed_before : RwLock::new(0),
+            beacons : RwLock::new(HashMap::new()),
+            agreed_beacons : RwLock::new(HashMap::new()),
             nonces : RwLock::new(HashMap::new()),
-            ha_nonces : RwLock::new(HashSet::new()),
+            ha_nonces : RwLock::new(BTreeMap::new()),
+            purged_before : RwLock::new(0),
             filename,
         }
     }
@@ -85,12 +216,23 @@ impl Timeline {
     }
 
     pub fn add_user_location_at_epoch(&self, epoch: usize, (pos_x, pos_y) : (usize, usize), idx: usize, report : Vec<u8>) -> Result<()>{
-        if self.blacklist.read().unwrap().contains(&idx) {
+        if self.is_blacklisted(idx) {
             return Err(eyre!("Malicious user detected!"));
         }
         if !self.valid_pos(pos_x, pos_y){
             return Err(eyre!("Invalid position"));
         }
+        if !self.is_retained(epoch) {
+            return Err(eyre!("Epoch {:} is no longer retained", epoch));
+        }
+        if self.is_erased(idx, epoch) {
+            return Err(eyre!("Epoch {:} of user {:} was erased", epoch, idx));
+        }
+        // Held until the report is in, so an epoch can't close halfway through
+        let closed_before = self.closed_before.read().unwrap();
+        if epoch < *closed_before {
+            return Err(eyre!("Epoch {:} is closed", epoch));
+        }
         {
             let report = Report::new((pos_x, pos_y), report);
             let mut routes = self.routes.write().unwrap();
@@ -98,7 +240,7 @@ impl Timeline {
                 let mut writable_user_pos = user_pos.write().unwrap();
                 if let Some(user_pos) =  writable_user_pos.get(&idx) {
                     if user_pos.loc != (pos_x, pos_y) {
-                        self.blacklist.write().unwrap().insert(idx);
+                        self.blacklist_user(idx, epoch, (user_pos.report.clone(), report.report));
                         return Err(eyre!("Two different positions submitted for the same epoch"));
                     }
                 } else {
@@ -111,35 +253,237 @@ impl Timeline {
             }
         }
         {
-            let mut vec = self.timeline.write().map_err(|_| eyre!("Unable to write"))?;
+            let mut grids = self.timeline.write().map_err(|_| eyre!("Unable to write"))?;
+            grids.entry(epoch).or_insert_with(|| Grid::new_empty(self.size)).add_user_location(pos_x, pos_y, idx);
+        }
+        drop(closed_before);
+        Ok(())
+    }
 
-            for _ in vec.len()..=epoch {
-                vec.push(Grid::new_empty(self.size));
+    // Returns true if the user already has a report for the epoch at another location
+    pub fn blacklist_if_conflicting(&self, epoch : usize, loc : (usize, usize), idx : usize, report : &[u8]) -> bool {
+        let routes = self.routes.read().unwrap();
+        if let Some(users) = routes.get(&epoch) {
+            if let Some(stored) = users.read().unwrap().get(&idx) {
+                if stored.loc != loc {
+                    self.blacklist_user(idx, epoch, (stored.report.clone(), report.to_vec()));
+                    return true;
+                }
             }
         }
-        let vec = self.timeline.read().map_err(|_| eyre!("Unable to read"))?;
-        vec[epoch].add_user_location(pos_x, pos_y, idx);
-        Ok(())
+        false
     }
 
-    pub fn add_proofs(&self, proofs : Vec<(usize, usize, Vec<u8>)>) {
-        let mut p = self.proofs.write().unwrap();
-        for (idx, epoch, proof) in proofs.into_iter() {
-            if let Some(u_proof) = p.get_mut(&idx) {
-                let mut u_proof = u_proof.write().unwrap();
-                if let Some(u_e_proof) = u_proof.get_mut(&epoch) {
-                    u_e_proof.push(proof);
-                } else {
-                    u_proof.insert(epoch, vec![proof]);
+    fn blacklist_user(&self, idx : usize, epoch : usize, reports : (Vec<u8>, Vec<u8>)) {
+        self.blacklist.write().unwrap().entry(idx).or_insert_with(|| BlacklistEntry::new(
+            idx,
+            epoch,
+            "Two different positions submitted for the same epoch".to_string(),
+            timestamp_now(),
+            reports,
+        ));
+    }
+
+    // Evidence must be verified before being added, returns true if the user was not blacklisted yet
+    pub fn add_blacklist_entry(&self, entry : BlacklistEntry) -> bool {
+        let mut blacklist = self.blacklist.write().unwrap();
+        if blacklist.contains_key(&entry.idx) {
+            return false;
+        }
+        blacklist.insert(entry.idx, entry);
+        true
+    }
+
+    pub fn is_blacklisted(&self, idx : usize) -> bool {
+        self.blacklist.read().unwrap().contains_key(&idx)
+    }
+
+    pub fn get_blacklist(&self) -> Vec<(usize, usize, String, u64)> { // user id, epoch, reason, timestamp
+        let mut entries : Vec<(usize, usize, String, u64)> = self.blacklist.read().unwrap().values().map(
+            |entry| (entry.idx, entry.epoch, entry.reason.clone(), entry.timestamp)
+        ).collect();
+        entries.sort_by_key(|entry| entry.0);
+        entries
+    }
+
+    pub fn get_blacklist_entry(&self, idx : usize) -> Option<BlacklistEntry> {
+        self.blacklist.read().unwrap().get(&idx).cloned()
+    }
+
+    pub fn clear_blacklist_entry(&self, idx : usize) -> bool {
+        self.blacklist.write().unwrap().remove(&idx).is_some()
+    }
+
+    pub fn flag_infected(&self, idx : usize, epoch : usize) {
+        self.infected.write().unwrap().insert(idx, epoch);
+    }
+
+    pub fn is_infected_at(&self, idx : usize, epoch : usize) -> bool {
+        matches!(self.infected.read().unwrap().get(&idx), Some(from) if *from <= epoch)
+    }
+
+    // Whether idx was next to a user infected at that epoch, only undisputed reports count
+    pub fn was_exposed(&self, idx : usize, epochs : (usize, usize)) -> bool {
+        if self.infected.read().unwrap().is_empty() {
+            return false;
+        }
+        self.get_epochs(epochs).into_iter().any(
+            |epoch| self.get_neighbours_at_epoch(epoch, idx).into_iter().any(|other| self.is_infected_at(other, epoch))
+        )
+    }
+
+    // Returns the witnesses flagged because of these proofs
+    pub fn add_proofs(&self, proofs : Vec<(Proof, Vec<u8>)>) -> Vec<usize> {
+        let mut newly_flagged = vec![];
+        {
+            let mut p = self.proofs.write().unwrap();
+            for (proof, signed_proof) in proofs.into_iter() {
+                let (idx, epoch) = (proof.idx_ass(), proof.epoch());
+                let mut u_proof = p.entry(idx).or_insert_with(|| RwLock::new(HashMap::new())).write().unwrap();
+                let u_e_proof = u_proof.entry(epoch).or_default();
+
+                if let Some(conflict) = u_e_proof.iter().find(|vouch| vouch.loc != proof.loc_ass()) {
+                    if self.flag_witness(idx, epoch, (conflict.proof.clone(), signed_proof.clone())) {
+                        newly_flagged.push(idx);
+                    }
                 }
+                u_e_proof.push(Vouch::new(proof.idx_req(), proof.loc_ass(), signed_proof));
+            }
+        }
+        newly_flagged
+    }
 
-            } else {
-                let mut u_e_proof = HashMap::new();
-                u_e_proof.insert(epoch, vec![proof]);
+    // Only the witness's proofs for that epoch are no longer trusted
+    fn flag_witness(&self, idx : usize, epoch : usize, proofs : (Vec<u8>, Vec<u8>)) -> bool {
+        let mut flagged = self.flagged.write().unwrap();
+        let epochs = flagged.entry(idx).or_default();
+        if epochs.contains_key(&epoch) {
+            return false;
+        }
+        epochs.insert(epoch, FlaggedWitness::new(idx, epoch, proofs));
+        true
+    }
+
+    // Re-evaluates every report the witness supported, returns the ones that became disputed
+    pub fn dispute_supported_by(&self, witness : usize, f_line : usize) -> Vec<(usize, usize)> { // (epoch, user id)
+        self.dispute_unsupported(self.supported_by(witness), f_line)
+    }
+
+    // Once the witness reported itself at loc, its proofs placing it elsewhere in that epoch no longer count
+    pub fn dispute_contradicted_by(&self, witness : usize, epoch : usize, f_line : usize) -> Vec<(usize, usize)> { // (epoch, user id)
+        let contradicted : HashSet<(usize, usize)> = match self.get_user_location_at_epoch(epoch, witness) {
+            Some(loc) => match self.proofs.read().unwrap().get(&witness) {
+                Some(u_proofs) => match u_proofs.read().unwrap().get(&epoch) {
+                    Some(e_proofs) => e_proofs.iter().filter(|vouch| vouch.loc != loc).map(|vouch| (epoch, vouch.requester)).collect(),
+                    None => return vec![],
+                },
+                None => return vec![],
+            },
+            None => return vec![],
+        };
+        self.dispute_unsupported(contradicted, f_line)
+    }
 
-                p.insert(idx, RwLock::new(u_e_proof));
+    // Disputes the reports left with f_line or fewer witnesses whose proofs still hold
+    pub fn dispute_unsupported(&self, reports : HashSet<(usize, usize)>, f_line : usize) -> Vec<(usize, usize)> { // (epoch, user id)
+        let mut disputed = vec![];
+        for (epoch, requester) in reports {
+            if self.is_disputed(epoch, requester) || self.support(epoch, requester) > f_line {
+                continue;
+            }
+            if let Some(users) = self.routes.read().unwrap().get(&epoch) {
+                if let Some(report) = users.write().unwrap().get_mut(&requester) {
+                    report.disputed = true;
+                    disputed.push((epoch, requester));
+                }
             }
         }
+        disputed
+    }
+
+    // Once a witness is trusted again, the reports it supported that regained enough support are no longer disputed
+    pub fn undispute_supported_by(&self, witness : usize, f_line : usize) -> Vec<(usize, usize)> { // (epoch, user id)
+        let mut undisputed = vec![];
+        for (epoch, requester) in self.supported_by(witness) {
+            if !self.is_disputed(epoch, requester) || self.support(epoch, requester) <= f_line {
+                continue;
+            }
+            if let Some(users) = self.routes.read().unwrap().get(&epoch) {
+                if let Some(report) = users.write().unwrap().get_mut(&requester) {
+                    report.disputed = false;
+                    undisputed.push((epoch, requester));
+                }
+            }
+        }
+        undisputed
+    }
+
+    fn supported_by(&self, witness : usize) -> HashSet<(usize, usize)> { // (epoch, user id)
+        match self.proofs.read().unwrap().get(&witness) {
+            Some(u_proofs) => u_proofs.read().unwrap().iter().flat_map(
+                |(epoch, e_proofs)| e_proofs.iter().map(move |vouch| (*epoch, vouch.requester))
+            ).collect(),
+            None => HashSet::new(),
+        }
+    }
+
+    // Number of witnesses whose proofs still hold for the user's report
+    fn support(&self, epoch : usize, idx : usize) -> usize {
+        let (x, y) = match self.get_user_location_at_epoch(epoch, idx) {
+            Some(loc) => loc,
+            None => return 0,
+        };
+        let ((lower_x, lower_y), (upper_x, upper_y)) = self.valid_neighbour(x, y);
+
+        let mut vouches = vec![];
+        for (witness, u_proofs) in self.proofs.read().unwrap().iter() {
+            if let Some(e_proofs) = u_proofs.read().unwrap().get(&epoch) {
+                vouches.extend(e_proofs.iter().filter(|vouch| vouch.requester == idx).map(|vouch| (*witness, vouch.loc)));
+            }
+        }
+
+        let mut witnesses = HashSet::new();
+        for (witness, (x, y)) in vouches {
+            if lower_x <= x && x <= upper_x
+                && lower_y <= y && y <= upper_y
+                && !self.is_blacklisted(witness)
+                && !self.is_flagged_witness(witness, epoch) {
+                match self.get_user_location_at_epoch(epoch, witness) {
+                    Some(own_loc) if own_loc != (x, y) => {}
+                    _ => { witnesses.insert(witness); }
+                }
+            }
+        }
+        witnesses.len()
+    }
+
+    pub fn is_disputed(&self, epoch : usize, idx : usize) -> bool {
+        match self.routes.read().unwrap().get(&epoch) {
+            Some(users) => matches!(users.read().unwrap().get(&idx), Some(report) if report.disputed),
+            None => false,
+        }
+    }
+
+    pub fn get_disputed_at_epoch(&self, epoch : usize) -> Vec<usize> {
+        match self.routes.read().unwrap().get(&epoch) {
+            Some(users) => users.read().unwrap().iter().filter(|(_, report)| report.disputed).map(|(idx, _)| *idx).collect(),
+            None => vec![],
+        }
+    }
+
+    pub fn is_flagged_witness(&self, idx : usize, epoch : usize) -> bool {
+        matches!(self.flagged.read().unwrap().get(&idx), Some(epochs) if epochs.contains_key(&epoch))
+    }
+
+    pub fn get_flagged_witness(&self, idx : usize) -> Vec<FlaggedWitness> {
+        match self.flagged.read().unwrap().get(&idx) {
+            Some(epochs) => epochs.values().cloned().collect(),
+            None => vec![],
+        }
+    }
+
+    pub fn clear_flagged_witness(&self, idx : usize) -> bool {
+        self.flagged.write().unwrap().remove(&idx).is_some()
     }
 
     pub fn get_proofs(&self, idx : usize, epochs : &HashSet<usize>) -> Vec<Vec<u8>> { // Assumes vec is a set
@@ -150,13 +494,33 @@ impl Timeline {
             for epoch in epochs {
                 let e_proofs = u_proofs.read().unwrap();
                 if let Some(e_proofs) = e_proofs.get(epoch) {
-                    proofs.extend(e_proofs.iter().map(|proof| proof.clone()));
+                    proofs.extend(e_proofs.iter().map(|vouch| vouch.proof.clone()));
                 }
             }
         }
         proofs
     }
 
+    pub fn get_reports(&self) -> Vec<(usize, usize, (usize, usize), Vec<u8>)> { // epoch, user id, location, report
+        let mut reports = vec![];
+        for (epoch, users) in self.routes.read().unwrap().iter() {
+            for (idx, report) in users.read().unwrap().iter() {
+                reports.push((*epoch, *idx, report.loc, report.report.clone()));
+            }
+        }
+        reports
+    }
+
+    pub fn get_all_proofs(&self) -> Vec<(usize, usize, Vec<u8>)> { // witness id, epoch, proof
+        let mut proofs = vec![];
+        for (idx, u_proofs) in self.proofs.read().unwrap().iter() {
+            for (epoch, e_proofs) in u_proofs.read().unwrap().iter() {
+                proofs.extend(e_proofs.iter().map(|vouch| (*idx, *epoch, vouch.proof.clone())));
+            }
+        }
+        proofs
+    }
+
     pub fn valid_pos(&self, x : usize, y : usize) -> bool {
         x < self.size && y < self.size
     }
@@ -170,13 +534,16 @@ impl Timeline {
     }
 
     pub fn get_users_at_epoch_at_location(&self, epoch: usize, (pos_x, pos_y) : (usize, usize)) -> Option<Vec<(usize, Vec<u8>)>> {
-        let vec = self.timeline.read().unwrap();
+        let grids = self.timeline.read().unwrap();
 
-        if vec.len() > epoch && self.valid_pos(pos_x, pos_y) {
+        if let (Some(grid), true) = (grids.get(&epoch), self.valid_pos(pos_x, pos_y)) {
             let mut idxs_reports = vec![];
             let epoch_map = self.routes.read().unwrap();
-            let epoch_map = epoch_map.get(&epoch).unwrap().read().unwrap();
-            for idx in vec[epoch].get_users_at_location(pos_x, pos_y) {
+            let epoch_map = match epoch_map.get(&epoch) {
+                Some(epoch_map) => epoch_map.read().unwrap(),
+                None => return Some(idxs_reports),
+            };
+            for idx in grid.get_users_at_location(pos_x, pos_y) {
                 if let Some(report) = epoch_map.get(&idx) {
                     idxs_reports.push((idx, report.report.clone()));
                 } else {
@@ -189,6 +556,112 @@ impl Timeline {
         }
     }
 
+    pub fn get_users_in_region(
+        &self,
+        (first, last) : (usize, usize),
+        from : (usize, usize),
+        to : (usize, usize),
+    ) -> Vec<(usize, usize, Vec<u8>, bool)> { // epoch, user id, report, disputed
+        if first > last || from.0 > to.0 || from.1 > to.1 || !self.valid_pos(to.0, to.1) {
+            return vec![];
+        }
+
+        let found : Vec<(usize, Vec<usize>)> = self.timeline.read().unwrap().range(first..=last).map(
+            |(epoch, grid)| (*epoch, grid.get_users_in_region(from, to))
+        ).collect();
+
+        let routes = self.routes.read().unwrap();
+        let mut reports = vec![];
+        for (epoch, users) in found {
+            if let Some(epoch_map) = routes.get(&epoch) {
+                let epoch_map = epoch_map.read().unwrap();
+                for idx in users {
+                    if let Some(report) = epoch_map.get(&idx) {
+                        reports.push((epoch, idx, report.report.clone(), report.disputed));
+                    }
+                }
+            }
+        }
+        reports
+    }
+
+    pub fn get_trajectory(&self, idx : usize, (first, last) : (usize, usize)) -> Vec<(usize, Vec<u8>, bool)> { // epoch, report, disputed
+        let mut trajectory = vec![];
+        for (epoch, users) in self.routes.read().unwrap().iter().filter(|(&epoch, _)| first <= epoch && epoch <= last) {
+            if let Some(report) = users.read().unwrap().get(&idx) {
+                trajectory.push((*epoch, report.report.clone(), report.disputed));
+            }
+        }
+        trajectory.sort_by_key(|(epoch, _, _)| *epoch);
+        trajectory
+    }
+
+    // Users whose undisputed location was within the proximity radius of idx's undisputed location
+    pub fn get_neighbours_at_epoch(&self, epoch : usize, idx : usize) -> Vec<usize> {
+        if self.is_disputed(epoch, idx) {
+            return vec![];
+        }
+        let (from, to) = match self.get_user_location_at_epoch(epoch, idx) {
+            Some((x, y)) => self.valid_neighbour(x, y),
+            None => return vec![],
+        };
+        let neighbours = match self.timeline.read().unwrap().get(&epoch) {
+            Some(grid) => grid.get_users_in_region(from, to),
+            None => return vec![],
+        };
+        neighbours.into_iter().filter(|&other| other != idx && !self.is_disputed(epoch, other)).collect()
+    }
+
+    pub fn get_contacts(&self, idx : usize, epochs : (usize, usize)) -> Vec<ContactsAtEpoch> {
+        let mut contacts = vec![];
+        for (epoch, report, disputed) in self.get_trajectory(idx, epochs) {
+            if disputed {
+                continue;
+            }
+            let mut at_epoch : Vec<(usize, Vec<u8>)> = self.get_neighbours_at_epoch(epoch, idx).into_iter().filter_map(
+                |other| self.get_user_report_at_epoch(epoch, other).map(|other_report| (other, other_report))
+            ).collect();
+            if !at_epoch.is_empty() {
+                at_epoch.sort_by_key(|(other, _)| *other);
+                contacts.push(ContactsAtEpoch { epoch, report, contacts : at_epoch });
+            }
+        }
+        contacts
+    }
+
+    pub fn get_cell_counts(&self, (first, last) : (usize, usize)) -> BTreeMap<(usize, usize), usize> { // distinct users per cell
+        let mut users : BTreeMap<(usize, usize), HashSet<usize>> = BTreeMap::new();
+        if first <= last {
+            for grid in self.timeline.read().unwrap().range(first..=last).map(|(_, grid)| grid) {
+                for (x, column) in grid.grid.iter().enumerate() {
+                    for (y, cell) in column.iter().enumerate() {
+                        let cell = cell.read().unwrap();
+                        if !cell.is_empty() {
+                            users.entry((x, y)).or_default().extend(cell.iter());
+                        }
+                    }
+                }
+            }
+        }
+        users.into_iter().map(|(cell, users)| (cell, users.len())).collect()
+    }
+
+    pub fn get_epochs(&self, (first, last) : (usize, usize)) -> Vec<usize> {
+        if first > last {
+            return vec![];
+        }
+        self.timeline.read().unwrap().range(first..=last).map(|(epoch, _)| *epoch).collect()
+    }
+
+    pub fn get_user_location_at_epoch(&self, epoch: usize, idx: usize) -> Option<(usize, usize)> {
+        if let Some(user_loc ) = self.routes.read().unwrap().get(&epoch) {
+            if let Some(position) = user_loc.read().unwrap().get(&idx){
+                return Some(position.loc);
+            }
+        }
+        None
+    }
+
     pub fn get_user_report_at_epoch(&self, epoch: usize, idx: usize) -> Option<Vec<u8>> {
         if let Some(user_loc ) = self.routes.read().unwrap().get(&epoch) {
             if let Some(position) = user_loc.read().unwrap().get(&idx){
@@ -198,37 +671,407 @@ impl Timeline {
         None
     }
 
-    pub fn valid_nonce(&self, idx : usize, nonce : &Nonce) -> bool {
+    pub fn get_delivered(&self) -> HashSet<(usize, usize)> { // (user id, epoch)
+        let mut delivered = HashSet::new();
+        for (epoch, users) in self.routes.read().unwrap().iter() {
+            for idx in users.read().unwrap().keys() {
+                delivered.insert((*idx, *epoch));
+            }
+        }
+        delivered
+    }
+
+    pub fn get_reports_not_in(&self, known : &HashSet<(usize, usize)>) -> Vec<(usize, usize, Vec<u8>)> {
+        let mut reports = vec![];
+        for (epoch, users) in self.routes.read().unwrap().iter() {
+            for (idx, report) in users.read().unwrap().iter() {
+                if !known.contains(&(*idx, *epoch)) {
+                    reports.push((*idx, *epoch, report.report.clone()));
+                }
+            }
+        }
+        reports
+    }
+
+    // A replayed request carries the same sealed timestamp, so only that bucket needs checking
+    pub fn valid_nonce(&self, idx : usize, nonce : &Nonce, timestamp : u64) -> bool {
         let nonces = self.nonces.read().unwrap();
         if let Some(user_nonces) = nonces.get(&idx) {
-            !user_nonces.contains(&nonce)
+            match user_nonces.get(&timestamp) {
+                Some(ts_nonces) => !ts_nonces.contains(nonce),
+                None => true,
+            }
         } else {
             true
         }
     }
 
-    pub fn add_nonce(&self, idx : usize, nonce : Nonce) -> bool {
+    pub fn add_nonce(&self, idx : usize, nonce : Nonce, timestamp : u64) -> bool {
         let mut nonces = self.nonces.write().unwrap();
-        if let Some(user_nonces) = nonces.get_mut(&idx){
-            user_nonces.insert(nonce)
-        } else { //RwLock bc of insert (before if else)
-           let mut user_nonce= HashSet::new();
-           user_nonce.insert(nonce);
-           nonces.insert(idx, user_nonce);
-           true
-        }
+        nonces.entry(idx).or_default().entry(timestamp).or_default().insert(nonce)
     }
 
-    pub fn valid_ha_nonce(&self, nonce : &Nonce) -> bool {
+    pub fn valid_ha_nonce(&self, nonce : &Nonce, timestamp : u64) -> bool {
         let nonces = self.ha_nonces.read().unwrap();
-        !nonces.contains(&nonce)
+        match nonces.get(&timestamp) {
+            Some(ts_nonces) => !ts_nonces.contains(nonce),
+            None => true,
+        }
     }
 
-    pub fn add_ha_nonce(&self, nonce : Nonce) -> bool {
+    pub fn add_ha_nonce(&self, nonce : Nonce, timestamp : u64) -> bool {
         let mut nonces = self.ha_nonces.write().unwrap();
-        nonces.insert(nonce)
+        nonces.entry(timestamp).or_default().insert(nonce)
+    }
+
+    // Only safe for timestamps that are no longer accepted as fresh
+    pub fn prune_nonces(&self, before : u64) {
+        let mut nonces = self.nonces.write().unwrap();
+        for user_nonces in nonces.values_mut() {
+            *user_nonces = user_nonces.split_off(&before);
+        }
+        nonces.retain(|_, user_nonces| !user_nonces.is_empty());
+
+        let mut ha_nonces = self.ha_nonces.write().unwrap();
+        *ha_nonces = ha_nonces.split_off(&before);
+    }
+
+    pub fn is_erased(&self, idx : usize, epoch : usize) -> bool {
+        matches!(self.erased.read().unwrap().get(&idx), Some(before) if epoch < *before)
+    }
+
+    // Returns false if the user had already asked for at least these epochs to be erased
+    pub fn erase_user(&self, idx : usize, before : usize) -> bool {
+        {
+            let mut erased = self.erased.write().unwrap();
+            if matches!(erased.get(&idx), Some(erased_before) if *erased_before >= before) {
+                return false;
+            }
+            erased.insert(idx, before);
+        }
+        {
+            // Same order as get_users_at_epoch_at_location, timeline before routes
+            let grids = self.timeline.read().unwrap();
+            let routes = self.routes.read().unwrap();
+            for (epoch, users) in routes.iter().filter(|(epoch, _)| **epoch < before) {
+                if let Some(report) = users.write().unwrap().remove(&idx) {
+                    if let Some(grid) = grids.get(epoch) {
+                        grid.grid[report.loc.0][report.loc.1].write().unwrap().remove(&idx);
+                    }
+                }
+            }
+        }
+
+        let proofs = self.proofs.read().unwrap();
+        for (witness, u_proofs) in proofs.iter() {
+            let mut u_proofs = u_proofs.write().unwrap();
+            for (_, e_proofs) in u_proofs.iter_mut().filter(|(epoch, _)| **epoch < before) {
+                if *witness == idx {
+                    e_proofs.clear();
+                } else {
+                    e_proofs.retain(|vouch| vouch.requester != idx);
+                }
+            }
+            u_proofs.retain(|_, e_proofs| !e_proofs.is_empty());
+        }
+
+        for (_, users) in self.certificates.write().unwrap().iter_mut().filter(|(epoch, _)| **epoch < before) {
+            users.remove(&idx);
+        }
+        // The leaf stays so the published root still holds for everyone else
+        for (_, commitment) in self.commitments.write().unwrap().range_mut(..before) {
+            commitment.positions.remove(&idx);
+        }
+        true
+    }
+
+    // Returns false if the statement was already known, a server only counts once per user and epoch
+    pub fn add_delivery_statement(&self, statement : DeliveryStatement) -> bool {
+        if !self.is_retained(statement.epoch) || self.is_erased(statement.idx, statement.epoch) {
+            return false;
+        }
+        let mut certificates = self.certificates.write().unwrap();
+        let candidates = certificates.entry(statement.epoch).or_default().entry(statement.idx).or_default();
+        if candidates.iter().any(|certificate| certificate.signatures.contains_key(&statement.server_id)) {
+            return false;
+        }
+        match candidates.iter_mut().find(|certificate| certificate.is_about(&statement)) {
+            Some(certificate) => certificate.add(statement),
+            None => {
+                candidates.push(DeliveryCertificate::new(statement));
+                true
+            }
+        }
+    }
+
+    pub fn is_closed(&self, epoch : usize) -> bool {
+        epoch < *self.closed_before.read().unwrap()
+    }
+
+    // Commits to the reports of every retained epoch before the given one that took any, returns the new roots
+    pub fn close_before(&self, before : usize, server_id : usize, sign_key : &sign::SecretKey) -> Vec<EpochRoot> {
+        let mut closed_before = self.closed_before.write().unwrap();
+        if before <= *closed_before {
+            return vec![];
+        }
+        let first = std::cmp::max(*closed_before, *self.purged_before.read().unwrap());
+        *closed_before = before;
+
+        let routes = self.routes.read().unwrap();
+        let mut commitments = self.commitments.write().unwrap();
+        let mut roots = vec![];
+        for (epoch, users) in routes.iter().filter(|(epoch, _)| first <= **epoch && **epoch < before) {
+            let epoch = *epoch;
+            let mut reports : Vec<(usize, Vec<u8>)> = users.read().unwrap().iter().map(|(idx, report)| (*idx, leaf_hash(*idx, &report.report))).collect();
+            reports.sort();
+
+            let positions = reports.iter().enumerate().map(|(position, (idx, _))| (*idx, position)).collect();
+            let leaves : Vec<Vec<u8>> = reports.into_iter().map(|(_, leaf)| leaf).collect();
+            let root = EpochRoot::new(server_id, epoch, &MerkleTree::new(leaves.clone()), sign_key);
+
+            roots.push(root.clone());
+            commitments.insert(epoch, Commitment { leaves, positions, root });
+        }
+        roots.sort_by_key(|root| root.epoch);
+        roots
+    }
+
+    pub fn get_inclusion(&self, idx : usize, epoch : usize) -> Option<Inclusion> {
+        self.commitments.read().unwrap().get(&epoch)?.inclusion(idx)
+    }
+
+    pub fn get_root(&self, epoch : usize) -> Option<EpochRoot> {
+        self.commitments.read().unwrap().get(&epoch).map(|commitment| commitment.root.clone())
+    }
+
+    // Returns false if the server had already published a root for the epoch
+    pub fn add_peer_root(&self, root : EpochRoot) -> bool {
+        if !self.is_retained(root.epoch) {
+            return false;
+        }
+        let mut peer_roots = self.peer_roots.write().unwrap();
+        let roots = peer_roots.entry(root.epoch).or_default();
+        if roots.contains_key(&root.server_id) {
+            return false;
+        }
+        roots.insert(root.server_id, root);
+        true
+    }
+
+    pub fn get_peer_roots(&self, epoch : usize) -> Vec<EpochRoot> {
+        match self.peer_roots.read().unwrap().get(&epoch) {
+            Some(peer_roots) => peer_roots.values().cloned().collect(),
+            None => vec![],
+        }
     }
 
+    // Returns false if an audit log head at least as far along was already kept for the server
+    pub fn add_audit_head(&self, head : AuditHead) -> bool {
+        let mut heads = self.audit_heads.write().unwrap();
+        if matches!(heads.get(&head.server_id), Some(kept) if kept.seq >= head.seq) {
+            return false;
+        }
+        heads.insert(head.server_id, head);
+        true
+    }
+
+    pub fn get_audit_head(&self, server_id : usize) -> Option<AuditHead> {
+        self.audit_heads.read().unwrap().get(&server_id).cloned()
+    }
+
+    // Servers that committed to other reports than this one did for the epoch, a closed epoch without a root took none
+    pub fn divergent_servers(&self, epoch : usize) -> Vec<usize> {
+        if !self.is_closed(epoch) {
+            return vec![];
+        }
+        let root = self.get_root(epoch);
+        match self.peer_roots.read().unwrap().get(&epoch) {
+            Some(peer_roots) => peer_roots.values()
+                .filter(|peer_root| match &root {
+                    Some(root) => !peer_root.agrees_with(root),
+                    None => peer_root.leaves > 0,
+                })
+                .map(|peer_root| peer_root.server_id)
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    pub fn get_roots(&self) -> Vec<EpochRoot> {
+        self.commitments.read().unwrap().values().map(|commitment| commitment.root.clone()).collect()
+    }
+
+    // Recomputes each root from the committed leaves, and checks every kept report has its leaf
+    pub fn check_commitments(&self) -> Vec<(usize, Option<usize>)> { // epoch, user id whose report is not committed
+        let routes = self.routes.read().unwrap();
+        let mut failures = vec![];
+        for (epoch, commitment) in self.commitments.read().unwrap().iter() {
+            let tree = MerkleTree::new(commitment.leaves.clone());
+            if tree.root() != commitment.root.root || tree.leaves() != commitment.root.leaves {
+                failures.push((*epoch, None));
+            }
+            if let Some(users) = routes.get(epoch) {
+                for (idx, report) in users.read().unwrap().iter() {
+                    match commitment.positions.get(idx) {
+                        Some(position) if commitment.leaves.get(*position) == Some(&leaf_hash(*idx, &report.report)) => {}
+                        _ => failures.push((*epoch, Some(*idx))),
+                    }
+                }
+            }
+        }
+        failures
+    }
+
+    // Returns false if the server had already shared for the epoch
+    pub fn add_beacon_share(&self, share : BeaconShare) -> bool {
+        if !self.is_retained(share.epoch) {
+            return false;
+        }
+        let epoch = share.epoch;
+        self.beacons.write().unwrap().entry(epoch).or_insert_with(|| EpochBeacon::new(epoch)).add(share)
+    }
+
+    pub fn has_beacon_share(&self, epoch : usize, server_id : usize) -> bool {
+        match self.beacons.read().unwrap().get(&epoch) {
+            Some(beacon) => beacon.shares.contains_key(&server_id),
+            None => false,
+        }
+    }
+
+    // Whatever was shared so far, to propose once there are enough shares
+    pub fn get_beacon_shares(&self, epoch : usize) -> Option<EpochBeacon> {
+        self.beacons.read().unwrap().get(&epoch).cloned()
+    }
+
+    // Returns false if a proposal of the server had already been delivered for the epoch
+    pub fn add_agreed_beacon(&self, server_id : usize, beacon : EpochBeacon) -> bool {
+        if !self.is_retained(beacon.epoch) {
+            return false;
+        }
+        let mut agreed_beacons = self.agreed_beacons.write().unwrap();
+        let proposals = agreed_beacons.entry(beacon.epoch).or_default();
+        if proposals.contains_key(&server_id) {
+            return false;
+        }
+        proposals.insert(server_id, beacon);
+        true
+    }
+
+    // Every correct server ends up delivering the same proposals, the one of the lowest server id is handed out
+    pub fn get_beacon(&self, epoch : usize) -> Option<EpochBeacon> {
+        self.agreed_beacons.read().unwrap().get(&epoch)?.values().next().cloned()
+    }
+
+    pub fn get_agreed_beacons(&self, epoch : usize) -> Vec<EpochBeacon> {
+        match self.agreed_beacons.read().unwrap().get(&epoch) {
+            Some(proposals) => proposals.values().cloned().collect(),
+            None => vec![],
+        }
+    }
+
+    // Only once more than necessary_res servers stated they delivered the same report
+    pub fn get_certificate(&self, idx : usize, epoch : usize, necessary_res : usize) -> Option<DeliveryCertificate> {
+        self.certificates.read().unwrap().get(&epoch)?.get(&idx)?.iter()
+            .find(|certificate| certificate.signatures.len() > necessary_res)
+            .cloned()
+    }
+
+    pub fn get_user_data(&self, idx : usize) -> UserData {
+        let mut data = UserData::default();
+
+        for (epoch, users) in self.routes.read().unwrap().iter() {
+            if let Some(report) = users.read().unwrap().get(&idx) {
+                data.reports.push((*epoch, report.report.clone(), report.disputed));
+            }
+        }
+        for (witness, u_proofs) in self.proofs.read().unwrap().iter() {
+            for (epoch, e_proofs) in u_proofs.read().unwrap().iter() {
+                for vouch in e_proofs.iter() {
+                    if *witness == idx {
+                        data.proofs_given.push((*epoch, vouch.requester, vouch.proof.clone()));
+                    } else if vouch.requester == idx {
+                        data.proofs_received.push((*epoch, *witness, vouch.proof.clone()));
+                    }
+                }
+            }
+        }
+        data.reports.sort();
+        data.proofs_given.sort();
+        data.proofs_received.sort();
+
+        data.blacklist = self.get_blacklist_entry(idx);
+        data.infected_from = self.infected.read().unwrap().get(&idx).copied();
+        data.erased_before = self.erased.read().unwrap().get(&idx).copied().unwrap_or(0);
+        data
+    }
+
+    pub fn is_retained(&self, epoch : usize) -> bool {
+        epoch >= *self.purged_before.read().unwrap()
+    }
+
+    pub fn latest_epoch(&self) -> Option<usize> {
+        self.routes.read().unwrap().keys().max().copied()
+    }
+
+    pub fn purge_before(&self, before : usize) -> Archive {
+        let mut purged_before = self.purged_before.write().unwrap();
+        let mut archive = Archive {
+            before,
+            routes : HashMap::new(),
+            proofs : HashMap::new(),
+        };
+        if before <= *purged_before {
+            return archive;
+        }
+        *purged_before = before;
+
+        {
+            let mut routes = self.routes.write().unwrap();
+            let old_epochs : Vec<usize> = routes.keys().filter(|&&epoch| epoch < before).copied().collect();
+            for epoch in old_epochs {
+                if let Some(users) = routes.remove(&epoch) {
+                    archive.routes.insert(epoch, users.into_inner().unwrap());
+                }
+            }
+        }
+        {
+            let mut grids = self.timeline.write().unwrap();
+            *grids = grids.split_off(&before);
+        }
+        // Certificates and commitments are only served along with the reports they are about
+        self.certificates.write().unwrap().retain(|epoch, _| *epoch >= before);
+        {
+            let mut commitments = self.commitments.write().unwrap();
+            *commitments = commitments.split_off(&before);
+        }
+        self.peer_roots.write().unwrap().retain(|epoch, _| *epoch >= before);
+        self.beacons.write().unwrap().retain(|epoch, _| *epoch >= before);
+        self.agreed_beacons.write().unwrap().retain(|epoch, _| *epoch >= before);
+        {
+            let mut flagged = self.flagged.write().unwrap();
+            for epochs in flagged.values_mut() {
+                *epochs = epochs.split_off(&before);
+            }
+            flagged.retain(|_, epochs| !epochs.is_empty());
+        }
+
+        let mut proofs = self.proofs.write().unwrap();
+        for (idx, u_proofs) in proofs.iter_mut() {
+            let mut u_proofs = u_proofs.write().unwrap();
+            let old_epochs : Vec<usize> = u_proofs.keys().filter(|&&epoch| epoch < before).copied().collect();
+            for epoch in old_epochs {
+                if let Some(e_proofs) = u_proofs.remove(&epoch) {
+                    archive.proofs.entry(*idx).or_default().insert(epoch, e_proofs);
+                }
+            }
+        }
+        proofs.retain(|_, u_proofs| !u_proofs.read().unwrap().is_empty());
+
+        archive
+    }
+
+    pub fn size(&self) -> usize { self.size }
+
     pub fn filename(&self) -> &str { &self.filename }
 }
 
@@ -240,6 +1083,23 @@ pub async fn save_storage(filename : &str, timeline : &Timeline) -> Result<()> {
     Ok(())
 }
 
+pub fn save_archive(filename : &str, archive : &Archive) -> Result<()> {
+    let atomic_file = AtomicFile::new(filename, AllowOverwrite);
+
+    atomic_file.write(|f| serde_json::to_writer(BufWriter::new(f), archive) )?;
+
+    Ok(())
+}
+
+pub fn retrieve_archive(filename : &str) -> Result<Archive> {
+    let file = File::open(filename)?;
+    let reader = BufReader::new(file);
+
+    serde_json::from_reader(reader).wrap_err_with(
+        || format!("Failed to parse struct Archive from file '{:}'", filename)
+    )
+}
+
 pub fn retrieve_storage(file_name : &str) -> Result<Timeline> {
     let file = File::open(file_name)?;
     let reader = BufReader::new(file);
@@ -264,6 +1124,7 @@ mod tests {
     const IDX : usize = 785;
     const IDX_OTHER : usize = 50;
     const EPOCH_2 : usize = 8;
+    const TIMESTAMP : u64 = 1_000;
 
     #[test]
     fn new_grid() {
@@ -331,7 +1192,7 @@ mod tests {
     fn add_proof_of_epoch() {
         let storage = Timeline::new(SIZE, FILENAME.to_string());
 
-        let proof_1 = (IDX, EPOCH,   b"id:0 | epoch:0".to_vec());
+        let proof_1 = (Proof::new(EPOCH, IDX_OTHER, IDX, (POS_X, POS_Y), vec![]),   b"id:0 | epoch:0".to_vec());
 
         storage.add_proofs(vec![ proof_1.clone() ]);
 
@@ -342,15 +1203,15 @@ mod tests {
 
         assert_eq!(1, proofs.len());
 
-        assert_eq!(proof_1.2, proofs[0]);
+        assert_eq!(proof_1.1, proofs[0]);
     }
 
     #[test]
     fn add_proofs_of_diff_epoch() {
         let storage = Timeline::new(SIZE, FILENAME.to_string());
 
-        let proof_1 = (IDX, EPOCH, b"id:0 | epoch:0".to_vec());
-        let proof_2 = (IDX, EPOCH_2, b"id:0 | epoch:1".to_vec());
+        let proof_1 = (Proof::new(EPOCH, IDX_OTHER, IDX, (POS_X, POS_Y), vec![]), b"id:0 | epoch:0".to_vec());
+        let proof_2 = (Proof::new(EPOCH_2, IDX_OTHER, IDX, (POS_X, POS_Y), vec![]), b"id:0 | epoch:1".to_vec());
 
         storage.add_proofs(vec![ proof_1.clone(), proof_2.clone() ]);
 
@@ -367,7 +1228,7 @@ mod tests {
     fn add_not_my_proof() {
         let storage = Timeline::new(SIZE, FILENAME.to_string());
 
-        let proof_other = (IDX_OTHER, EPOCH, b"id:1 | epoch:0".to_vec());
+        let proof_other = (Proof::new(EPOCH, IDX, IDX_OTHER, (POS_X, POS_Y), vec![]), b"id:1 | epoch:0".to_vec());
 
         storage.add_proofs(vec![ proof_other.clone() ]);
 
@@ -380,6 +1241,172 @@ mod tests {
     }
 
 
+    #[test]
+    fn flag_contradictory_witness() {
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX, b"report".to_vec()).is_ok());
+        assert!(storage.add_user_location_at_epoch(EPOCH_2, (POS_X, POS_Y), IDX, b"report".to_vec()).is_ok());
+
+        let flagged = storage.add_proofs(vec![
+            (Proof::new(EPOCH, IDX, IDX_OTHER, (POS_X, POS_Y), vec![]), b"first".to_vec()),
+            (Proof::new(EPOCH_2, IDX, IDX_OTHER, (POS_X, POS_Y), vec![]), b"other epoch".to_vec()),
+        ]);
+        assert!(flagged.is_empty());
+
+        let flagged = storage.add_proofs(vec![ (Proof::new(EPOCH, IDX, IDX_OTHER, (DIFF_POS_X, POS_Y), vec![]), b"second".to_vec()) ]);
+        assert_eq!(vec![IDX_OTHER], flagged);
+        assert!(storage.is_flagged_witness(IDX_OTHER, EPOCH));
+        assert!(!storage.is_flagged_witness(IDX_OTHER, EPOCH_2));
+        assert_eq!(vec![FlaggedWitness::new(IDX_OTHER, EPOCH, (b"first".to_vec(), b"second".to_vec()))], storage.get_flagged_witness(IDX_OTHER));
+        assert!(storage.add_proofs(vec![ (Proof::new(EPOCH, IDX, IDX_OTHER, (DIFF_POS_X + 1, POS_Y), vec![]), b"third".to_vec()) ]).is_empty());
+
+        // Only the report of the epoch the witness contradicted itself in loses its support
+        let f_line = 0;
+        assert_eq!(vec![(EPOCH, IDX)], storage.dispute_supported_by(IDX_OTHER, f_line));
+        assert!(!storage.is_disputed(EPOCH_2, IDX));
+
+        assert!(storage.clear_flagged_witness(IDX_OTHER));
+        assert!(!storage.clear_flagged_witness(IDX_OTHER));
+        assert!(!storage.is_flagged_witness(IDX_OTHER, EPOCH));
+        assert_eq!(vec![(EPOCH, IDX)], storage.undispute_supported_by(IDX_OTHER, f_line));
+    }
+
+    #[test]
+    fn witness_report_contradicts_proof() {
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+        let f_line = 1;
+        let third = IDX_OTHER + 1;
+
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX, b"report".to_vec()).is_ok());
+        storage.add_proofs(vec![
+            (Proof::new(EPOCH, IDX, IDX_OTHER, (POS_X, POS_Y), vec![]), b"proof".to_vec()),
+            (Proof::new(EPOCH, IDX, third, (POS_X + 1, POS_Y), vec![]), b"proof".to_vec()),
+        ]);
+        assert!(storage.dispute_contradicted_by(IDX_OTHER, EPOCH, f_line).is_empty());
+
+        // Reporting where it vouched keeps the report supported
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X + 1, POS_Y), third, b"report".to_vec()).is_ok());
+        assert!(storage.dispute_contradicted_by(third, EPOCH, f_line).is_empty());
+
+        assert!(storage.add_user_location_at_epoch(EPOCH, (DIFF_POS_X, POS_Y), IDX_OTHER, b"report".to_vec()).is_ok());
+        assert_eq!(Some((DIFF_POS_X, POS_Y)), storage.get_user_location_at_epoch(EPOCH, IDX_OTHER));
+        assert_eq!(vec![(EPOCH, IDX)], storage.dispute_contradicted_by(IDX_OTHER, EPOCH, f_line));
+        assert!(storage.is_disputed(EPOCH, IDX));
+    }
+
+    #[test]
+    fn proof_contradicts_witness_report() {
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+        let f_line = 0;
+
+        assert!(storage.add_user_location_at_epoch(EPOCH, (DIFF_POS_X, POS_Y), IDX_OTHER, b"report".to_vec()).is_ok());
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX, b"report".to_vec()).is_ok());
+        storage.add_proofs(vec![ (Proof::new(EPOCH, IDX, IDX_OTHER, (POS_X, POS_Y), vec![]), b"proof".to_vec()) ]);
+
+        let reports = vec![(EPOCH, IDX)].into_iter().collect();
+        assert_eq!(vec![(EPOCH, IDX)], storage.dispute_unsupported(reports, f_line));
+        assert!(!storage.is_disputed(EPOCH, IDX_OTHER));
+    }
+
+    #[test]
+    fn dispute_when_witness_blacklisted() {
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+        let f_line = 1;
+        let third = IDX_OTHER + 1;
+
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX, b"report".to_vec()).is_ok());
+        storage.add_proofs(vec![
+            (Proof::new(EPOCH, IDX, IDX_OTHER, (POS_X, POS_Y), vec![]), b"proof".to_vec()),
+            (Proof::new(EPOCH, IDX, third, (POS_X + 1, POS_Y), vec![]), b"proof".to_vec()),
+        ]);
+        assert!(storage.dispute_supported_by(IDX_OTHER, f_line).is_empty());
+
+        let entry = BlacklistEntry::new(IDX_OTHER, EPOCH, "reason".to_string(), TIMESTAMP, (b"first".to_vec(), b"second".to_vec()));
+        assert!(storage.add_blacklist_entry(entry));
+
+        assert_eq!(vec![(EPOCH, IDX)], storage.dispute_supported_by(IDX_OTHER, f_line));
+        assert!(storage.is_disputed(EPOCH, IDX));
+        assert_eq!(vec![IDX], storage.get_disputed_at_epoch(EPOCH));
+        assert!(storage.dispute_supported_by(IDX_OTHER, f_line).is_empty());
+
+        assert!(storage.undispute_supported_by(IDX_OTHER, f_line).is_empty());
+        assert!(storage.clear_blacklist_entry(IDX_OTHER));
+        assert_eq!(vec![(EPOCH, IDX)], storage.undispute_supported_by(IDX_OTHER, f_line));
+        assert!(!storage.is_disputed(EPOCH, IDX));
+        assert!(storage.get_disputed_at_epoch(EPOCH).is_empty());
+    }
+
+    #[test]
+    fn users_in_region() {
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX, b"inside".to_vec()).is_ok());
+        assert!(storage.add_user_location_at_epoch(EPOCH, (DIFF_POS_X, POS_Y), IDX_OTHER, b"outside".to_vec()).is_ok());
+        assert!(storage.add_user_location_at_epoch(EPOCH_2, (POS_X + 1, POS_Y - 1), IDX_OTHER, b"inside".to_vec()).is_ok());
+        assert!(storage.add_user_location_at_epoch(EPOCH_2 + 1, (POS_X, POS_Y), IDX, b"too late".to_vec()).is_ok());
+
+        let mut found = storage.get_users_in_region((EPOCH, EPOCH_2), (POS_X, POS_Y - 1), (POS_X + 1, POS_Y));
+        found.sort();
+
+        assert_eq!(vec![
+            (EPOCH, IDX, b"inside".to_vec(), false),
+            (EPOCH_2, IDX_OTHER, b"inside".to_vec(), false),
+        ], found);
+
+        assert!(storage.get_users_in_region((EPOCH_2, EPOCH), (0, 0), (SIZE - 1, SIZE - 1)).is_empty());
+        assert!(storage.get_users_in_region((EPOCH, EPOCH), (0, 0), (SIZE, SIZE)).is_empty());
+    }
+
+    #[test]
+    fn trajectory() {
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+
+        assert!(storage.add_user_location_at_epoch(EPOCH_2, (DIFF_POS_X, POS_Y), IDX, b"second".to_vec()).is_ok());
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX, b"first".to_vec()).is_ok());
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX_OTHER, b"other".to_vec()).is_ok());
+        assert!(storage.add_user_location_at_epoch(EPOCH_2 + 1, (POS_X, POS_Y), IDX, b"too late".to_vec()).is_ok());
+
+        assert_eq!(vec![
+            (EPOCH, b"first".to_vec(), false),
+            (EPOCH_2, b"second".to_vec(), false),
+        ], storage.get_trajectory(IDX, (EPOCH, EPOCH_2)));
+        assert!(storage.get_trajectory(IDX, (0, EPOCH - 1)).is_empty());
+    }
+
+    #[test]
+    fn contacts() {
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX, b"user".to_vec()).is_ok());
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X + 1, POS_Y + 1), IDX_OTHER, b"near".to_vec()).is_ok());
+        assert!(storage.add_user_location_at_epoch(EPOCH, (DIFF_POS_X, POS_Y), IDX_OTHER + 1, b"far".to_vec()).is_ok());
+        assert!(storage.add_user_location_at_epoch(EPOCH_2, (POS_X, POS_Y), IDX, b"alone".to_vec()).is_ok());
+
+        assert_eq!(vec![IDX_OTHER], storage.get_neighbours_at_epoch(EPOCH, IDX));
+        assert_eq!(vec![ContactsAtEpoch {
+            epoch : EPOCH,
+            report : b"user".to_vec(),
+            contacts : vec![(IDX_OTHER, b"near".to_vec())],
+        }], storage.get_contacts(IDX, (EPOCH, EPOCH_2)));
+    }
+
+    #[test]
+    fn exposure_to_infected() {
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX, vec![]).is_ok());
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X + 1, POS_Y), IDX_OTHER, vec![]).is_ok());
+        assert!(!storage.was_exposed(IDX, (EPOCH, EPOCH_2)));
+
+        storage.flag_infected(IDX_OTHER, EPOCH + 1);
+        assert!(!storage.was_exposed(IDX, (EPOCH, EPOCH_2)));
+
+        storage.flag_infected(IDX_OTHER, EPOCH);
+        assert!(storage.was_exposed(IDX, (EPOCH, EPOCH_2)));
+        assert!(!storage.was_exposed(IDX, (EPOCH + 1, EPOCH_2)));
+    }
+
     #[test]
     fn double_report_at_same_epoch_diff_pos() {
         let storage = Timeline::new(SIZE, FILENAME.to_string());
@@ -398,18 +1425,311 @@ mod tests {
         assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX, "report".as_bytes().to_vec()).is_ok());
     }
 
+    #[test]
+    fn delivered_reports_not_known() {
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX, b"report".to_vec()).is_ok());
+        assert!(storage.add_user_location_at_epoch(EPOCH_2, (POS_X, POS_Y), IDX_OTHER, b"other".to_vec()).is_ok());
+
+        let delivered = storage.get_delivered();
+        assert_eq!(2, delivered.len());
+        assert!(delivered.contains(&(IDX, EPOCH)));
+
+        let mut known = HashSet::new();
+        known.insert((IDX, EPOCH));
+
+        let reports = storage.get_reports_not_in(&known);
+        assert_eq!(vec![(IDX_OTHER, EPOCH_2, b"other".to_vec())], reports);
+    }
+
+    #[test]
+    fn blacklist_keeps_evidence() {
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX, b"first".to_vec()).is_ok());
+        assert!(storage.add_user_location_at_epoch(EPOCH, (DIFF_POS_X, POS_Y), IDX, b"second".to_vec()).is_err());
+
+        assert!(storage.is_blacklisted(IDX));
+        assert_eq!(1, storage.get_blacklist().len());
+
+        let entry = storage.get_blacklist_entry(IDX).unwrap();
+        assert_eq!(EPOCH, entry.epoch);
+        assert_eq!((b"first".to_vec(), b"second".to_vec()), entry.reports);
+
+        assert!(storage.add_user_location_at_epoch(EPOCH_2, (POS_X, POS_Y), IDX, b"report".to_vec()).is_err());
+
+        assert!(storage.clear_blacklist_entry(IDX));
+        assert!(!storage.clear_blacklist_entry(IDX));
+        assert!(storage.add_user_location_at_epoch(EPOCH_2, (POS_X, POS_Y), IDX, b"report".to_vec()).is_ok());
+    }
+
+    #[test]
+    fn add_blacklist_entry() {
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+
+        let entry = BlacklistEntry::new(IDX, EPOCH, "reason".to_string(), TIMESTAMP, (b"first".to_vec(), b"second".to_vec()));
+
+        assert!(storage.add_blacklist_entry(entry.clone()));
+        assert!(!storage.add_blacklist_entry(entry.clone()));
+        assert_eq!(Some(entry), storage.get_blacklist_entry(IDX));
+    }
+
+    #[test]
+    fn blacklist_if_conflicting() {
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX, b"first".to_vec()).is_ok());
+
+        assert!(!storage.blacklist_if_conflicting(EPOCH, (POS_X, POS_Y), IDX, b"first"));
+        assert!(!storage.is_blacklisted(IDX));
+
+        assert!(storage.blacklist_if_conflicting(EPOCH, (DIFF_POS_X, POS_Y), IDX, b"second"));
+        assert!(storage.is_blacklisted(IDX));
+    }
+
     #[test]
     fn test_nonce() {
         let nonce : secretbox::Nonce = secretbox::gen_nonce();
 
         let storage = Timeline::new(SIZE, FILENAME.to_string());
 
-        assert!(storage.valid_nonce(IDX, &nonce));
+        assert!(storage.valid_nonce(IDX, &nonce, TIMESTAMP));
+
+        assert!(storage.add_nonce(IDX, nonce, TIMESTAMP));
+
+        assert!(!storage.valid_nonce(IDX, &nonce, TIMESTAMP));
+
+        assert!(!storage.add_nonce(IDX, nonce, TIMESTAMP));
+    }
+
+    #[test]
+    fn prune_nonces() {
+        let nonce : secretbox::Nonce = secretbox::gen_nonce();
+        let other_nonce : secretbox::Nonce = secretbox::gen_nonce();
+
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+
+        assert!(storage.add_nonce(IDX, nonce, TIMESTAMP));
+        assert!(storage.add_nonce(IDX, other_nonce, TIMESTAMP + 10));
+        assert!(storage.add_ha_nonce(nonce, TIMESTAMP));
+
+        storage.prune_nonces(TIMESTAMP + 1);
+
+        assert!(storage.valid_nonce(IDX, &nonce, TIMESTAMP));
+        assert!(!storage.valid_nonce(IDX, &other_nonce, TIMESTAMP + 10));
+        assert!(storage.valid_ha_nonce(&nonce, TIMESTAMP));
+    }
+
+    #[test]
+    fn purge_old_epochs() {
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX, b"report".to_vec()).is_ok());
+        assert!(storage.add_user_location_at_epoch(EPOCH_2, (POS_X, POS_Y), IDX, b"report".to_vec()).is_ok());
+        storage.add_proofs(vec![
+            (Proof::new(EPOCH, IDX_OTHER, IDX, (POS_X, POS_Y), vec![]), b"id:0 | epoch:0".to_vec()),
+            (Proof::new(EPOCH_2, IDX_OTHER, IDX, (POS_X, POS_Y), vec![]), b"id:0 | epoch:1".to_vec()),
+            (Proof::new(EPOCH, IDX_OTHER, IDX, (DIFF_POS_X, POS_Y), vec![]), b"id:0 | epoch:0 elsewhere".to_vec()),
+        ]);
+        assert!(storage.is_flagged_witness(IDX, EPOCH));
 
-        assert!(storage.add_nonce(IDX, nonce));
+        let archive = storage.purge_before(EPOCH_2);
 
-        assert!(!storage.valid_nonce(IDX, &nonce));
+        assert_eq!(1, archive.routes.len());
+        assert_eq!(1, archive.proofs[&IDX].len());
+        assert!(storage.get_flagged_witness(IDX).is_empty());
 
-        assert!(!storage.add_nonce(IDX, nonce));
+        assert!(storage.get_user_report_at_epoch(EPOCH, IDX).is_none());
+        assert!(storage.get_users_at_epoch_at_location(EPOCH, (POS_X, POS_Y)).is_none());
+        assert!(storage.get_user_report_at_epoch(EPOCH_2, IDX).is_some());
+        assert_eq!(Some(EPOCH_2), storage.latest_epoch());
+
+        assert!(!storage.is_retained(EPOCH));
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX_OTHER, b"report".to_vec()).is_err());
     }
-}
\ No newline at end of file
+    #[test]
+    fn user_data() {
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX, b"report".to_vec()).is_ok());
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX_OTHER, b"other".to_vec()).is_ok());
+        storage.add_proofs(vec![
+            (Proof::new(EPOCH, IDX_OTHER, IDX, (POS_X, POS_Y), vec![]), b"given".to_vec()),
+            (Proof::new(EPOCH, IDX, IDX_OTHER, (POS_X, POS_Y), vec![]), b"received".to_vec()),
+        ]);
+        storage.flag_infected(IDX, EPOCH);
+
+        let data = storage.get_user_data(IDX);
+        assert_eq!(vec![(EPOCH, b"report".to_vec(), false)], data.reports);
+        assert_eq!(vec![(EPOCH, IDX_OTHER, b"given".to_vec())], data.proofs_given);
+        assert_eq!(vec![(EPOCH, IDX_OTHER, b"received".to_vec())], data.proofs_received);
+        assert_eq!(None, data.blacklist);
+        assert_eq!(Some(EPOCH), data.infected_from);
+    }
+
+    #[test]
+    fn erase_user() {
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX, b"report".to_vec()).is_ok());
+        assert!(storage.add_user_location_at_epoch(EPOCH_2, (POS_X, POS_Y), IDX, b"report".to_vec()).is_ok());
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX_OTHER, b"other".to_vec()).is_ok());
+        storage.add_proofs(vec![
+            (Proof::new(EPOCH, IDX_OTHER, IDX, (POS_X, POS_Y), vec![]), b"given".to_vec()),
+            (Proof::new(EPOCH, IDX, IDX_OTHER, (POS_X, POS_Y), vec![]), b"received".to_vec()),
+            (Proof::new(EPOCH_2, IDX_OTHER, IDX, (POS_X, POS_Y), vec![]), b"kept".to_vec()),
+        ]);
+
+        assert!(storage.erase_user(IDX, EPOCH_2));
+        assert!(!storage.erase_user(IDX, EPOCH));
+
+        let data = storage.get_user_data(IDX);
+        assert_eq!(vec![(EPOCH_2, b"report".to_vec(), false)], data.reports);
+        assert_eq!(vec![(EPOCH_2, IDX_OTHER, b"kept".to_vec())], data.proofs_given);
+        assert!(data.proofs_received.is_empty());
+        assert_eq!(EPOCH_2, data.erased_before);
+
+        let users = storage.get_users_at_epoch_at_location(EPOCH, (POS_X, POS_Y)).unwrap();
+        assert_eq!(vec![IDX_OTHER], users.iter().map(|(idx, _)| *idx).collect::<Vec<_>>());
+        assert!(storage.get_user_report_at_epoch(EPOCH, IDX_OTHER).is_some());
+
+        // Catching up from another server must not bring it back
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX, b"report".to_vec()).is_err());
+    }
+
+    #[test]
+    fn erase_archived_user() {
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX, b"report".to_vec()).is_ok());
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX_OTHER, b"other".to_vec()).is_ok());
+        storage.add_proofs(vec![(Proof::new(EPOCH, IDX, IDX_OTHER, (POS_X, POS_Y), vec![]), b"received".to_vec())]);
+
+        let mut archive = storage.purge_before(EPOCH_2);
+
+        assert!(!archive.erase_user(IDX, EPOCH));
+        assert!(archive.erase_user(IDX, EPOCH_2));
+        assert_eq!(1, archive.routes[&EPOCH].len());
+        assert!(archive.proofs.is_empty());
+        assert!(!archive.erase_user(IDX, EPOCH_2));
+    }
+
+    #[test]
+    fn delivery_certificates() {
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+        let (_, sign_key) = sodiumoxide::crypto::sign::gen_keypair();
+        let statement = |server_id, report : &[u8]| DeliveryStatement::new(server_id, IDX, EPOCH, report, &sign_key);
+
+        assert!(storage.add_delivery_statement(statement(0, b"report")));
+        assert!(storage.add_delivery_statement(statement(1, b"report")));
+        assert!(!storage.add_delivery_statement(statement(1, b"report")));
+        assert!(storage.get_certificate(IDX, EPOCH, 2).is_none());
+
+        // A server that signed another report for the same epoch only counts once
+        assert!(storage.add_delivery_statement(statement(2, b"other")));
+        assert!(!storage.add_delivery_statement(statement(2, b"report")));
+        assert!(storage.get_certificate(IDX, EPOCH, 2).is_none());
+
+        assert!(storage.add_delivery_statement(statement(3, b"report")));
+        let certificate = storage.get_certificate(IDX, EPOCH, 2).unwrap();
+        assert!(certificate.certifies(IDX, EPOCH, b"report"));
+        assert_eq!(vec![0, 1, 3], certificate.signatures.keys().copied().collect::<Vec<_>>());
+
+        assert!(storage.erase_user(IDX, EPOCH_2));
+        assert!(storage.get_certificate(IDX, EPOCH, 0).is_none());
+        assert!(!storage.add_delivery_statement(statement(0, b"report")));
+    }
+
+    #[test]
+    fn epoch_commitments() {
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+        let (public_key, sign_key) = sign::gen_keypair();
+
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX, b"report".to_vec()).is_ok());
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), IDX_OTHER, b"other".to_vec()).is_ok());
+        assert!(storage.get_inclusion(IDX, EPOCH).is_none());
+
+        // Only epochs that took reports get a root, however far the cut is
+        let roots = storage.close_before(EPOCH + 1, 0, &sign_key);
+        assert_eq!(vec![EPOCH], roots.iter().map(|root| root.epoch).collect::<Vec<_>>());
+        assert!(storage.close_before(EPOCH, 0, &sign_key).is_empty());
+        assert!(storage.is_closed(EPOCH));
+        assert!(!storage.is_closed(EPOCH_2));
+        assert!(storage.add_user_location_at_epoch(EPOCH, (POS_X, POS_Y), 1, b"late".to_vec()).is_err());
+        assert!(storage.check_commitments().is_empty());
+
+        let inclusion = storage.get_inclusion(IDX, EPOCH).unwrap();
+        assert_eq!(2, inclusion.root.leaves);
+        assert!(inclusion.verify(&public_key, IDX, EPOCH, b"report"));
+        assert!(!inclusion.verify(&public_key, IDX, EPOCH, b"other"));
+
+        // A peer that kept other reports for the epoch shows up as divergent
+        let other_root = EpochRoot::new(1, EPOCH, &MerkleTree::new(vec![leaf_hash(IDX, b"report")]), &sign_key);
+        assert!(storage.add_peer_root(other_root.clone()));
+        assert!(!storage.add_peer_root(other_root));
+        let mut same_root = storage.get_root(EPOCH).unwrap();
+        same_root.server_id = 2;
+        assert!(storage.add_peer_root(same_root));
+        assert_eq!(vec![1], storage.divergent_servers(EPOCH));
+        assert_eq!(vec![1, 2], storage.get_peer_roots(EPOCH).iter().map(|root| root.server_id).collect::<Vec<_>>());
+
+        // So does one that took reports in an epoch that took none here
+        assert!(storage.get_root(EPOCH - 1).is_none());
+        assert!(storage.add_peer_root(EpochRoot::new(1, EPOCH - 1, &MerkleTree::new(vec![leaf_hash(IDX, b"report")]), &sign_key)));
+        assert!(storage.add_peer_root(EpochRoot::new(2, EPOCH - 1, &MerkleTree::new(vec![]), &sign_key)));
+        assert_eq!(vec![1], storage.divergent_servers(EPOCH - 1));
+
+        // Erasure hides the user's leaf without breaking the others' proofs
+        assert!(storage.erase_user(IDX, EPOCH + 1));
+        assert!(storage.get_inclusion(IDX, EPOCH).is_none());
+        assert!(storage.get_inclusion(IDX_OTHER, EPOCH).unwrap().verify(&public_key, IDX_OTHER, EPOCH, b"other"));
+        assert!(storage.check_commitments().is_empty());
+
+        assert!(storage.add_user_location_at_epoch(EPOCH_2, (POS_X, POS_Y), IDX, b"later".to_vec()).is_ok());
+        let roots = storage.close_before(usize::MAX, 0, &sign_key);
+        assert_eq!(vec![EPOCH_2], roots.iter().map(|root| root.epoch).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn keep_latest_audit_head() {
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+        let (_, sign_key) = sign::gen_keypair();
+
+        assert!(storage.get_audit_head(1).is_none());
+        assert!(storage.add_audit_head(AuditHead::new(1, 3, b"third".to_vec(), &sign_key)));
+        assert!(!storage.add_audit_head(AuditHead::new(1, 2, b"second".to_vec(), &sign_key)));
+        assert!(!storage.add_audit_head(AuditHead::new(1, 3, b"third".to_vec(), &sign_key)));
+        assert!(storage.add_audit_head(AuditHead::new(2, 0, b"first".to_vec(), &sign_key)));
+        assert_eq!(3, storage.get_audit_head(1).unwrap().seq);
+    }
+
+    #[test]
+    fn beacon_shares() {
+        let storage = Timeline::new(SIZE, FILENAME.to_string());
+        let (_, sign_key) = sign::gen_keypair();
+
+        assert!(storage.get_beacon_shares(EPOCH).is_none());
+        assert!(storage.add_beacon_share(BeaconShare::new(0, EPOCH, &sign_key)));
+        assert!(!storage.add_beacon_share(BeaconShare::new(0, EPOCH, &sign_key)));
+        assert!(storage.add_beacon_share(BeaconShare::new(1, EPOCH, &sign_key)));
+        assert!(storage.has_beacon_share(EPOCH, 1));
+        assert!(!storage.has_beacon_share(EPOCH_2, 1));
+        assert_eq!(2, storage.get_beacon_shares(EPOCH).unwrap().shares.len());
+
+        // Shares alone are not the beacon until a proposal is delivered
+        assert!(storage.get_beacon(EPOCH).is_none());
+        let proposal = storage.get_beacon_shares(EPOCH).unwrap().proposal(1);
+        assert!(storage.add_agreed_beacon(3, proposal.clone()));
+        assert!(!storage.add_agreed_beacon(3, proposal.clone()));
+        assert!(storage.add_agreed_beacon(2, EpochBeacon::new(EPOCH)));
+        assert_eq!(Some(EpochBeacon::new(EPOCH)), storage.get_beacon(EPOCH));
+        assert_eq!(2, storage.get_agreed_beacons(EPOCH).len());
+
+        storage.purge_before(EPOCH + 1);
+        assert!(storage.get_beacon_shares(EPOCH).is_none());
+        assert!(storage.get_beacon(EPOCH).is_none());
+        assert!(!storage.add_beacon_share(BeaconShare::new(2, EPOCH, &sign_key)));
+        assert!(!storage.add_agreed_beacon(3, proposal));
+    }
+}